@@ -31,7 +31,11 @@ async fn main() {
     let mut builder = Builder::default();
     builder.log_with(noria::logger_pls());
     builder.set_persistence(persistence_params);
-    builder.set_memory_limit(100 * 1024, Duration::from_millis(1000));
+    builder.set_memory_limit(
+        100 * 1024,
+        Duration::from_millis(1000),
+        noria::EvictionKind::Lru,
+    );
 
     // TODO: This should be removed when the `it_works_with_reads_before_writes`
     // test passes again.
@@ -213,6 +213,16 @@ impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
         inner.locals.insert(key, chan);
     }
 
+    pub fn remove<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut inner = self.inner.write().unwrap();
+        inner.addrs.remove(key);
+        inner.locals.remove(key);
+    }
+
     pub fn has<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
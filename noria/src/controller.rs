@@ -1,8 +1,9 @@
 use crate::consensus::{self, Authority};
 use crate::debug::stats;
-use crate::table::{Table, TableBuilder, TableRpc};
+use crate::table::{Change, ChangeOffset, Table, TableBuilder, TableRpc};
+use crate::transaction::Transaction;
 use crate::view::{View, ViewBuilder, ViewRpc};
-use crate::ActivationResult;
+use crate::{ActivationResult, RecipeDiff, RecipePlan, RecipeVersion};
 use failure::{self, ResultExt};
 use futures_util::future;
 use petgraph::graph::NodeIndex;
@@ -388,6 +389,10 @@ impl<A: Authority + 'static> ControllerHandle<A> {
 
     /// Extend the existing recipe with the given set of queries.
     ///
+    /// The additions may also include `ALTER TABLE` statements that add, drop or rename columns
+    /// of existing tables, or add indices to them. Existing rows get the default value of any
+    /// column added, and the extension fails if a query reads a column that is dropped or renamed.
+    ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn extend_recipe(
         &mut self,
@@ -406,6 +411,69 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         self.rpc("install_recipe", new_recipe, "failed to install recipe")
     }
 
+    /// Work out what extending the existing recipe with the given set of queries would do to the
+    /// data-flow graph, without changing the graph.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn plan_recipe(
+        &mut self,
+        recipe_addition: &str,
+    ) -> impl Future<Output = Result<RecipePlan, failure::Error>> {
+        self.rpc(
+            "plan_recipe",
+            (recipe_addition, false),
+            "failed to plan recipe extension",
+        )
+    }
+
+    /// Work out what replacing the existing recipe with this one would do to the data-flow graph,
+    /// without changing the graph.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn plan_recipe_installation(
+        &mut self,
+        new_recipe: &str,
+    ) -> impl Future<Output = Result<RecipePlan, failure::Error>> {
+        self.rpc(
+            "plan_recipe",
+            (new_recipe, true),
+            "failed to plan recipe installation",
+        )
+    }
+
+    /// List the versions of the recipe that have been activated, oldest first. Only the 100 most
+    /// recent versions are kept.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn recipe_versions(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<RecipeVersion>, failure::Error>> {
+        self.rpc("recipe_versions", (), "failed to list recipe versions")
+    }
+
+    /// Compare the recipe at version `from` with the recipe at version `to`.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn diff_recipes(
+        &mut self,
+        from: usize,
+        to: usize,
+    ) -> impl Future<Output = Result<RecipeDiff, failure::Error>> {
+        self.rpc("diff_recipes", (from, to), "failed to diff recipes")
+    }
+
+    /// Install the recipe at an earlier version again, replacing the current recipe.
+    ///
+    /// The rollback is a migration like any other, and so yields a new recipe version.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn rollback_recipe(
+        &mut self,
+        version: usize,
+    ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
+        self.rpc("rollback_recipe", version, "failed to roll back recipe")
+    }
+
     /// Fetch a graphviz description of the dataflow graph.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
@@ -434,4 +502,99 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         // TODO: this should likely take a view name, and we should verify that it's a Reader.
         self.rpc("remove_node", view, "failed to remove node")
     }
+
+    /// Start a transaction, through which writes to any number of base tables can be made that
+    /// readers will observe all at once.
+    ///
+    /// Nothing is sent to Noria until the transaction is committed.
+    pub fn transaction(&self) -> Transaction<A> {
+        Transaction::new(self.clone())
+    }
+
+    /// Make the given base table keep a change feed of the writes it accepts from now on.
+    ///
+    /// The feed is stored on disk alongside the table, so this fails if the deployment only keeps
+    /// base tables in memory. Enabling the feed of a table that already keeps one does nothing.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn enable_table_changes(
+        &mut self,
+        table: &str,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "enable_table_changes",
+            table,
+            "failed to enable table changes",
+        )
+    }
+
+    /// Read the changes that the given base table has accepted, starting at `from`.
+    ///
+    /// Returns at most `limit` changes from each shard of the table, along with the offset to read
+    /// the changes that follow from. Pass in `ChangeOffset::default()` to start with the oldest
+    /// changes that are still available. The table must keep a change feed (see
+    /// `Self::enable_table_changes`).
+    ///
+    /// Each shard's feed survives restarts of the worker that holds it, but only the most recent
+    /// changes are kept. Reading fails if `from` refers to changes that have been trimmed from the
+    /// feed since, so changes are never skipped without notice.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn table_changes(
+        &mut self,
+        table: &str,
+        from: ChangeOffset,
+        limit: usize,
+    ) -> impl Future<Output = Result<(Vec<Change>, ChangeOffset), failure::Error>> {
+        self.rpc(
+            "table_changes",
+            (table, from, limit),
+            "failed to read table changes",
+        )
+    }
+
+    /// Write a snapshot of the deployment to the directory `path` on the controller's machine.
+    ///
+    /// The snapshot holds the recipe, the security configuration, and the contents of every base
+    /// table as of a single point in time across all the base tables. Writes are held back while
+    /// the base tables take an in-memory copy of their rows, which are then written out a chunk at
+    /// a time. A fresh deployment can be restored from the snapshot by starting `noria-server`
+    /// with `--restore`.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn snapshot(&mut self, path: &str) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("snapshot", path, "failed to take snapshot")
+    }
+
+    /// List the workers in the deployment.
+    ///
+    /// Each worker is identified by its address, and listed along with whether it is healthy and
+    /// how long ago the controller last heard from it.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn instances(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<(SocketAddr, bool, Duration)>, failure::Error>> {
+        self.rpc("instances", (), "failed to list workers")
+    }
+
+    /// Move all domains off the worker at `addr`, and then remove it from the deployment.
+    ///
+    /// The state of the moved domains is rebuilt on the remaining workers through replay. Base
+    /// tables on the worker are moved by copying their rows to the controller and loading them
+    /// into the tables once they have been re-created on another worker, so the controller must
+    /// have room for them in memory. Writes to those tables that arrive while they are being moved
+    /// are never applied, and fail once the old tables are shut down. `Table` handles for the moved
+    /// tables stop working, and must be fetched again with `Self::table`.
+    ///
+    /// This fails if no other worker can take over. If moving the domains fails, the worker stays
+    /// part of the deployment.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn decommission_worker(
+        &mut self,
+        addr: SocketAddr,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("decommission_worker", addr, "failed to decommission worker")
+    }
 }
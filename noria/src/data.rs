@@ -717,6 +717,22 @@ pub enum TableOperation {
         /// The key used to identify the row to update.
         key: Vec<DataType>,
     },
+    /// Delete all rows whose `column` holds `value`.
+    DeleteWhere {
+        /// The column to match on.
+        column: usize,
+        /// The value that matching rows hold in `column`.
+        value: DataType,
+    },
+    /// Update all rows whose `column` holds `value`.
+    UpdateWhere {
+        /// The column to match on.
+        column: usize,
+        /// The value that matching rows hold in `column`.
+        value: DataType,
+        /// The modifications to make to each column of the matching rows.
+        set: Vec<Modification>,
+    },
 }
 
 impl TableOperation {
@@ -36,6 +36,9 @@ pub struct NodeStats {
     pub process_ptime: u64,
     /// Total memory size of this node's state.
     pub mem_size: u64,
+    /// Number of rows in this node's state. Readers do not count their rows, and report zero.
+    #[serde(default)]
+    pub rows: u64,
     /// The materialization type of this node's state.
     pub materialized: MaterializationStatus,
     /// The value returned from Ingredient::probe.
@@ -207,6 +207,7 @@ use tokio_tower::multiplex;
 mod controller;
 mod data;
 mod table;
+mod transaction;
 mod view;
 
 #[doc(hidden)]
@@ -297,14 +298,15 @@ impl<T> From<T> for Tagged<T> {
 
 pub use crate::controller::{ControllerDescriptor, ControllerHandle};
 pub use crate::data::{DataType, Modification, Operation, TableOperation};
-pub use crate::table::Table;
-pub use crate::view::View;
+pub use crate::table::{Change, ChangeOffset, Table};
+pub use crate::transaction::Transaction;
+pub use crate::view::{Delta, KeyRange, PageCursor, Subscription, View};
 
 #[doc(hidden)]
 pub use crate::table::Input;
 
 #[doc(hidden)]
-pub use crate::view::{ReadQuery, ReadReply, ReadReplyBatch};
+pub use crate::view::{PageRequest, ReadQuery, ReadReply, ReadReplyBatch};
 
 #[doc(hidden)]
 pub mod builders {
@@ -328,6 +330,76 @@ pub struct ActivationResult {
     pub expressions_removed: usize,
 }
 
+/// Describes what a recipe change would do to the data-flow graph, without applying it.
+///
+/// New nodes are identified by the indices they would get if the change were applied next. The
+/// ingress, egress and sharding nodes that connect domains are not included, as they are only
+/// added once the change is applied.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RecipePlan {
+    /// Nodes that the change would add.
+    pub new_nodes: Vec<PlannedNode>,
+    /// Existing nodes that the change's new queries would build on or read from.
+    pub reused_nodes: Vec<PlannedNode>,
+    /// Existing nodes that the change would remove.
+    pub removed_nodes: Vec<PlannedNode>,
+    /// Nodes that would be materialized by the change.
+    pub new_materializations: Vec<PlannedMaterialization>,
+    /// Indices that would be added to existing materializations, by node and key columns.
+    pub new_indices: Vec<(NodeIndex, Vec<usize>)>,
+}
+
+/// A data-flow node that a recipe change would affect.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlannedNode {
+    /// The node's index.
+    pub node: NodeIndex,
+    /// The node's name.
+    pub name: String,
+    /// A description of what the node does.
+    pub description: String,
+}
+
+/// A materialization that a recipe change would add.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlannedMaterialization {
+    /// The materialized node.
+    pub node: NodeIndex,
+    /// The key columns of each of the materialization's indices.
+    pub indices: Vec<Vec<usize>>,
+    /// Whether the materialization would be partial, and only filled as reads miss in it.
+    pub partial: bool,
+    /// The materialized ancestors whose state would be replayed to fill the materialization
+    /// when the change is applied. Empty for partial materializations.
+    pub replay_from: Vec<NodeIndex>,
+    /// Roughly how many rows would be replayed to fill the materialization, from the current
+    /// sizes of the ancestors in `replay_from`. Zero for partial materializations, which start out
+    /// empty.
+    pub estimated_rows: u64,
+    /// Roughly how many bytes of state the rows in `estimated_rows` take up in those ancestors.
+    pub estimated_bytes: u64,
+}
+
+/// A version of the recipe that has been activated in a Noria deployment.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecipeVersion {
+    /// The recipe's version number.
+    pub version: usize,
+    /// The text of the recipe, which yields this version again when installed.
+    pub recipe: String,
+    /// When the version was activated.
+    pub activated: std::time::SystemTime,
+}
+
+/// The expressions that differ between two versions of a recipe.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RecipeDiff {
+    /// Expressions that are only in the version compared to.
+    pub added: Vec<String>,
+    /// Expressions that are only in the version compared against.
+    pub removed: Vec<String>,
+}
+
 #[doc(hidden)]
 #[inline]
 pub fn shard_by(dt: &DataType, shards: usize) -> usize {
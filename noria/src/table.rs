@@ -1,14 +1,15 @@
 use crate::channel::CONNECTION_FROM_BASE;
 use crate::data::*;
 use crate::internal::*;
+use crate::view::Delta;
 use crate::LocalOrNot;
 use crate::{Tagged, Tagger};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
     future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
-    stream::TryStreamExt,
+    stream::StreamExt, stream::TryStreamExt,
 };
-use nom_sql::CreateTableStatement;
+use nom_sql::{CreateTableStatement, TableKey};
 use petgraph::graph::NodeIndex;
 use std::collections::HashMap;
 use std::future::Future;
@@ -25,6 +26,12 @@ use tower_limit::concurrency::ConcurrencyLimit;
 use tower_service::Service;
 use vec_map::VecMap;
 
+/// The number of rows sent to a base table in each batch of `Table::bulk_load`.
+const BULK_LOAD_BATCH: usize = 10_000;
+
+/// The number of batches of a bulk load that may be outstanding at any one time.
+const BULK_LOAD_IN_FLIGHT: usize = 16;
+
 type Transport = AsyncBincodeStream<
     tokio::net::TcpStream,
     Tagged<()>,
@@ -289,6 +296,14 @@ pub enum TableError {
     )]
     WrongKeyColumnCount(usize, usize),
 
+    /// An update tried to modify a column of the table's primary key.
+    #[fail(display = "cannot modify primary key column {}", _0)]
+    PrimaryKeyModification(usize),
+
+    /// An operation tried to match rows on a column that the table has no index on.
+    #[fail(display = "cannot match rows on unindexed column {}", _0)]
+    UnindexedColumn(usize),
+
     /// The underlying connection to Noria produced an error.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -300,11 +315,47 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
     }
 }
 
+/// A batch of writes that a base table accepted, as it applied them.
+///
+/// Returned by [`ControllerHandle::table_changes`](crate::ControllerHandle::table_changes).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    /// The shard of the table that accepted the writes.
+    pub shard: usize,
+    /// The position of the change in its shard's change feed.
+    ///
+    /// The changes to each shard are numbered consecutively from zero, in the order they were
+    /// applied.
+    pub seq: u64,
+    /// The rows that the writes added and removed.
+    ///
+    /// Deletes and updates are resolved against the rows that were in the table at the time, so
+    /// an update shows up as the removal of the old row and the addition of the new one.
+    pub records: Vec<Delta>,
+}
+
+/// A position in the change feed of a table, from which reading continues.
+///
+/// Holds the sequence number of the next change to read from each shard of the table, by shard
+/// index. A missing entry (as in the default offset) refers to the oldest change that is still
+/// available from that shard.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeOffset(pub Vec<Option<u64>>);
+
+impl ChangeOffset {
+    /// The sequence number of the next change to read from the given shard, if known.
+    pub fn shard(&self, shard: usize) -> Option<u64> {
+        self.0.get(shard).cloned().flatten()
+    }
+}
+
 #[doc(hidden)]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Input {
     pub dst: LocalNodeIndex,
     pub data: Vec<TableOperation>,
+    /// Whether this input is part of a bulk load. A bulk input without data ends the load.
+    pub bulk: bool,
 }
 
 impl fmt::Debug for Input {
@@ -312,6 +363,7 @@ impl fmt::Debug for Input {
         fmt.debug_struct("Input")
             .field("dst", &self.dst)
             .field("data", &self.data)
+            .field("bulk", &self.bulk)
             .finish()
     }
 }
@@ -432,6 +484,162 @@ impl fmt::Debug for Table {
 }
 
 impl Table {
+    /// Check that rows can be matched on column `col`, which they can if the table's primary key
+    /// or one of its indices is on `col` alone.
+    ///
+    /// Tables that were not created from SQL don't know their indices, so the base table checks
+    /// for itself, and ignores operations that match on unindexed columns.
+    fn check_indexed(&self, col: usize) -> Result<(), TableError> {
+        if col >= self.columns.len() {
+            return Err(TableError::WrongColumnCount(self.columns.len(), col + 1));
+        }
+        let schema = match self.schema {
+            Some(ref schema) => schema,
+            None => return Ok(()),
+        };
+
+        let name = &self.columns[col];
+        let indexed = schema.keys.iter().flatten().any(|k| match *k {
+            TableKey::PrimaryKey(ref cs)
+            | TableKey::UniqueKey(_, ref cs)
+            | TableKey::Key(_, ref cs) => cs.len() == 1 && cs[0].name == *name,
+            TableKey::FulltextKey(..) => false,
+        });
+        if indexed {
+            Ok(())
+        } else {
+            Err(TableError::UnindexedColumn(col))
+        }
+    }
+
+    /// Check that the operations in `i` make sense for this table.
+    fn check_input(&self, i: &Input) -> Result<(), TableError> {
+        let ncols = self.columns.len() + self.dropped.len();
+        // NOTE: the operations have had the dropped columns injected by now
+        let check_set = |set: &[Modification]| {
+            if set.len() > ncols {
+                // NOTE: < is okay to allow dropping tailing no-ops
+                return Err(TableError::WrongColumnCount(ncols, set.len()));
+            }
+            if self.key_is_primary {
+                if let Some(&k) = self
+                    .key
+                    .iter()
+                    .find(|&&k| set.get(k).map(|m| *m != Modification::None) == Some(true))
+                {
+                    return Err(TableError::PrimaryKeyModification(k));
+                }
+            }
+            Ok(())
+        };
+        for op in &i.data {
+            match op {
+                TableOperation::Insert(ref row) => {
+                    if row.len() != ncols {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                }
+                TableOperation::Delete { ref key } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                }
+                TableOperation::InsertOrUpdate {
+                    ref row,
+                    ref update,
+                } => {
+                    if row.len() != ncols {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                    check_set(update)?;
+                }
+                TableOperation::Update { ref set, ref key } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                    check_set(set)?;
+                }
+                TableOperation::DeleteWhere { column, .. } => {
+                    if column >= ncols {
+                        return Err(TableError::WrongColumnCount(ncols, column + 1));
+                    }
+                }
+                TableOperation::UpdateWhere {
+                    column, ref set, ..
+                } => {
+                    if column >= ncols {
+                        return Err(TableError::WrongColumnCount(ncols, column + 1));
+                    }
+                    check_set(set)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Split `ops` up by the shard of the table they concern.
+    fn shard_writes(&self, ops: Vec<TableOperation>) -> Vec<Vec<TableOperation>> {
+        if self.key.is_empty() {
+            unreachable!("sharded base without a key?");
+        }
+        if self.key.len() != 1 {
+            // base sharded by complex key
+            unimplemented!();
+        }
+        let key_col = self.key[0];
+
+        let mut shard_writes = vec![Vec::new(); self.shards.len()];
+        for r in ops {
+            let shard = {
+                let key = match r {
+                    TableOperation::Insert(ref r) => &r[key_col],
+                    TableOperation::Delete { ref key } => &key[0],
+                    TableOperation::Update { ref key, .. } => &key[0],
+                    TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
+                    TableOperation::DeleteWhere { column, ref value }
+                    | TableOperation::UpdateWhere {
+                        column, ref value, ..
+                    } if column == key_col => value,
+                    TableOperation::DeleteWhere { .. } | TableOperation::UpdateWhere { .. } => {
+                        // matching rows may live in any shard
+                        for shard in &mut shard_writes {
+                            shard.push(r.clone());
+                        }
+                        continue;
+                    }
+                };
+                crate::shard_by(key, self.shards.len())
+            };
+            shard_writes[shard].push(r);
+        }
+        shard_writes
+    }
+
+    /// Prepare `ops` to be applied to this table as part of a transaction.
+    ///
+    /// Returns the table's base node, along with the input for each of its shards.
+    pub(crate) fn transaction_inputs(
+        &self,
+        ops: Vec<TableOperation>,
+    ) -> Result<(NodeIndex, Vec<Input>), TableError> {
+        let i = self.prep_records(ops);
+        self.check_input(&i)?;
+        if self.shards.len() == 1 {
+            return Ok((self.ni, vec![i]));
+        }
+
+        let inputs = self
+            .shard_writes(i.data)
+            .into_iter()
+            .map(|data| Input {
+                dst: self.node,
+                data,
+                bulk: false,
+            })
+            .collect();
+        Ok((self.ni, inputs))
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn input(
         &mut self,
@@ -446,54 +654,7 @@ impl Table {
             None
         };
 
-        // NOTE: this is really just a try block
-        let immediate_err = || {
-            let ncols = self.columns.len() + self.dropped.len();
-            for op in &i.data {
-                match op {
-                    TableOperation::Insert(ref row) => {
-                        if row.len() != ncols {
-                            return Err(TableError::WrongColumnCount(ncols, row.len()));
-                        }
-                    }
-                    TableOperation::Delete { ref key } => {
-                        if key.len() != self.key.len() {
-                            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-                        }
-                    }
-                    TableOperation::InsertOrUpdate {
-                        ref row,
-                        ref update,
-                    } => {
-                        if row.len() != ncols {
-                            return Err(TableError::WrongColumnCount(ncols, row.len()));
-                        }
-                        if update.len() > self.columns.len() {
-                            // NOTE: < is okay to allow dropping tailing no-ops
-                            return Err(TableError::WrongColumnCount(
-                                self.columns.len(),
-                                update.len(),
-                            ));
-                        }
-                    }
-                    TableOperation::Update { ref set, ref key } => {
-                        if key.len() != self.key.len() {
-                            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-                        }
-                        if set.len() > self.columns.len() {
-                            // NOTE: < is okay to allow dropping tailing no-ops
-                            return Err(TableError::WrongColumnCount(
-                                self.columns.len(),
-                                set.len(),
-                            ));
-                        }
-                    }
-                }
-            }
-            Ok(())
-        };
-
-        if let Err(e) = immediate_err() {
+        if let Err(e) = self.check_input(&i) {
             return future::Either::Left(async move { Err(e) });
         }
 
@@ -510,45 +671,29 @@ impl Table {
                 self.shards[0].call(request).map_err(TableError::from),
             ))
         } else {
-            if self.key.is_empty() {
-                unreachable!("sharded base without a key?");
-            }
-            if self.key.len() != 1 {
-                // base sharded by complex key
-                unimplemented!();
-            }
-            let key_col = self.key[0];
+            // the end of a bulk load concerns every shard
+            let finish_bulk = i.bulk && i.data.is_empty();
 
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("shard request");
-            let mut shard_writes = vec![Vec::new(); self.shards.len()];
-            for r in i.data.drain(..) {
-                let shard = {
-                    let key = match r {
-                        TableOperation::Insert(ref r) => &r[key_col],
-                        TableOperation::Delete { ref key } => &key[0],
-                        TableOperation::Update { ref key, .. } => &key[0],
-                        TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
-                    };
-                    crate::shard_by(key, self.shards.len())
-                };
-                shard_writes[shard].push(r);
-            }
+            let mut shard_writes = self.shard_writes(std::mem::take(&mut i.data));
 
             let wait_for = FuturesUnordered::new();
             for (s, rs) in shard_writes.drain(..).enumerate() {
-                if !rs.is_empty() {
+                if !rs.is_empty() || finish_bulk {
                     let p = if self.dst_is_local {
                         unsafe {
                             LocalOrNot::for_local_transfer(Input {
                                 dst: i.dst,
                                 data: rs,
+                                bulk: i.bulk,
                             })
                         }
                     } else {
                         LocalOrNot::new(Input {
                             dst: i.dst,
                             data: rs,
+                            bulk: i.bulk,
                         })
                     };
                     let request = Tagged::from(p);
@@ -630,21 +775,68 @@ impl Table {
         self.schema.as_ref()
     }
 
+    /// Maps the index of one of the table's columns to its index among all the columns of the
+    /// base table, which still include the dropped ones.
+    fn base_column(&self, mut col: usize) -> usize {
+        for (dropped, _) in &self.dropped {
+            if dropped <= col {
+                col += 1;
+            } else {
+                break;
+            }
+        }
+        col
+    }
+
+    /// Inserts no-op modifications for the dropped columns into `set`.
+    fn inject_dropped_set(&self, set: &mut Vec<Modification>) {
+        for (dropped, _) in &self.dropped {
+            if dropped > set.len() {
+                // trailing no-ops may be left out
+                break;
+            }
+            set.insert(dropped, Modification::None);
+        }
+    }
+
     fn inject_dropped_cols(&self, r: &mut TableOperation) {
+        if self.dropped.is_empty() {
+            return;
+        }
+
+        match *r {
+            TableOperation::Insert(ref mut row) => self.inject_dropped_row(row),
+            TableOperation::InsertOrUpdate {
+                ref mut row,
+                ref mut update,
+            } => {
+                self.inject_dropped_row(row);
+                self.inject_dropped_set(update);
+            }
+            // keys are given by value, and dropped columns are never part of the key
+            TableOperation::Delete { .. } => {}
+            TableOperation::Update { ref mut set, .. } => self.inject_dropped_set(set),
+            TableOperation::DeleteWhere { ref mut column, .. } => {
+                *column = self.base_column(*column);
+            }
+            TableOperation::UpdateWhere {
+                ref mut column,
+                ref mut set,
+                ..
+            } => {
+                *column = self.base_column(*column);
+                self.inject_dropped_set(set);
+            }
+        }
+    }
+
+    fn inject_dropped_row(&self, r: &mut Vec<DataType>) {
         use std::mem;
         let ndropped = self.dropped.len();
         if ndropped != 0 {
             // inject defaults for dropped columns
             let dropped = self.dropped.iter().rev();
 
-            // get a handle to the underlying data vector
-            let r = match *r {
-                TableOperation::Insert(ref mut row)
-                | TableOperation::InsertOrUpdate { ref mut row, .. } => row,
-                _ => unimplemented!("we need to shift the update/delete cols!"),
-            };
-            // TODO: what about updates? do we need to rewrite the set vector?
-
             // we want to be a bit careful here to avoid shifting elements multiple times. we
             // do this by moving from the back, and swapping the tail element to the end of the
             // vector until we hit each index.
@@ -724,6 +916,7 @@ impl Table {
         Input {
             dst: self.node,
             data: ops,
+            bulk: false,
         }
     }
 
@@ -758,6 +951,61 @@ impl Table {
             .await
     }
 
+    /// Insert a large number of rows into this base table in large batches.
+    ///
+    /// This is a batched insert, not a separate ingestion path: every batch is sent to the base
+    /// table as a regular input, and flows through the data-flow graph like any other write, so
+    /// downstream materializations are built in the same pass as the base table. What it saves
+    /// over `perform_all` is per-write overhead. Batches skip group commit, several are kept in
+    /// flight at once, and a persistent base table writes each one with a single RocksDB
+    /// `WriteBatch` that bypasses the write-ahead log. Rows are not written as pre-sorted SST files
+    /// and ingested, since they arrive in no particular key order.
+    ///
+    /// Because the write-ahead log is skipped, the inserted rows are made durable only once all of
+    /// them have been sent and the base table has flushed its memtables. If the load fails
+    /// part-way through, or the server crashes before it finishes, some of the rows may have been
+    /// inserted and others not.
+    ///
+    /// This is meant for the initial import of data, and other writes to the table during the
+    /// load may see (and interleave with) a partially loaded table.
+    pub async fn bulk_load<I, V>(&mut self, rows: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Vec<DataType>>,
+    {
+        let mut in_flight = FuturesUnordered::new();
+        let mut rows = rows.into_iter().peekable();
+        while rows.peek().is_some() {
+            let batch: Vec<_> = rows
+                .by_ref()
+                .take(BULK_LOAD_BATCH)
+                .map(|r| TableOperation::Insert(r.into()))
+                .collect();
+            let mut i = self.prep_records(batch);
+            i.bulk = true;
+
+            if in_flight.len() >= BULK_LOAD_IN_FLIGHT {
+                if let Some(r) = in_flight.next().await {
+                    r?;
+                }
+            }
+            future::poll_fn(|cx| self.poll_ready(cx)).await?;
+            in_flight.push(self.input(i));
+        }
+        while let Some(r) = in_flight.next().await {
+            r?;
+        }
+
+        let finish = Input {
+            dst: self.node,
+            data: Vec::new(),
+            bulk: true,
+        };
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.input(finish).await?;
+        Ok(())
+    }
+
     /// Delete the row with the given key from this base table.
     pub async fn delete<I>(&mut self, key: I) -> Result<(), TableError>
     where
@@ -770,7 +1018,8 @@ impl Table {
     /// Update the row with the given key in this base table.
     ///
     /// `u` is a set of column-modification pairs, where for each pair `(i, m)`, the modification
-    /// `m` will be applied to column `i` of the record with key `key`.
+    /// `m` will be applied to column `i` of the record with key `key`. Updates may not modify the
+    /// table's primary key.
     pub async fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<(), TableError>
     where
         V: IntoIterator<Item = (usize, Modification)>,
@@ -792,6 +1041,61 @@ impl Table {
             .await
     }
 
+    /// Delete all rows whose column `col` holds `value` from this base table.
+    ///
+    /// Matching rows are found by the base table itself, using an index on `col`. The index must
+    /// be declared when the table is set up, as its primary key, with a `KEY` or `UNIQUE KEY` in
+    /// `CREATE TABLE`, or with `ALTER TABLE ... ADD INDEX`. Matching on any other column fails with
+    /// `TableError::UnindexedColumn`.
+    pub async fn delete_where<V>(&mut self, col: usize, value: V) -> Result<(), TableError>
+    where
+        V: Into<DataType>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "delete operations can only be applied to base nodes with key columns"
+        );
+        self.check_indexed(col)?;
+
+        self.quick_n_dirty(vec![TableOperation::DeleteWhere {
+            column: col,
+            value: value.into(),
+        }])
+        .await
+    }
+
+    /// Update all rows whose column `col` holds `value` in this base table.
+    ///
+    /// `u` is a set of column-modification pairs, as documented in `Table::update`. Updates may
+    /// not modify the table's primary key. Rows are matched as in `Table::delete_where`, so `col`
+    /// must be indexed.
+    pub async fn update_where<D, V>(&mut self, col: usize, value: D, u: V) -> Result<(), TableError>
+    where
+        D: Into<DataType>,
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "update operations can only be applied to base nodes with key columns"
+        );
+        self.check_indexed(col)?;
+
+        let mut set = vec![Modification::None; self.columns.len()];
+        for (coli, m) in u {
+            if coli >= self.columns.len() {
+                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
+            }
+            set[coli] = m;
+        }
+
+        self.quick_n_dirty(vec![TableOperation::UpdateWhere {
+            column: col,
+            value: value.into(),
+            set,
+        }])
+        .await
+    }
+
     /// Perform a insert-or-update on this base table.
     ///
     /// If a row already exists for the key in `insert`, the existing row will instead be updated
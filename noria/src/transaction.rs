@@ -0,0 +1,85 @@
+use crate::consensus::Authority;
+use crate::controller::ControllerHandle;
+use crate::data::{DataType, TableOperation};
+use crate::table::{Input, Table, TableError};
+use petgraph::graph::NodeIndex;
+
+/// A set of writes to one or more base tables that readers observe all at once.
+///
+/// A `Transaction` is created with `ControllerHandle::transaction`, and holds on to the writes
+/// made through it until it is committed. Committing applies all of them, and views keep exposing
+/// what they held before the transaction until every write in it that affects them has arrived.
+/// Writes to the same table are applied in the order they were made.
+///
+/// Reads that miss in a partially materialized view while a transaction is being applied may
+/// still observe some of its writes without the others.
+/// If the writes don't all reach a view within the server's transaction timeout, for example
+/// because the controller failed while applying them, the view stops waiting and exposes those
+/// that did. Views that none of the writes reach are never held back.
+pub struct Transaction<A>
+where
+    A: 'static + Authority,
+{
+    handle: ControllerHandle<A>,
+    writes: Vec<(NodeIndex, Vec<Input>)>,
+}
+
+impl<A: Authority + 'static> Transaction<A> {
+    pub(crate) fn new(handle: ControllerHandle<A>) -> Self {
+        Transaction {
+            handle,
+            writes: Vec::new(),
+        }
+    }
+
+    /// Insert a single row of data into `table` as part of this transaction.
+    pub fn insert<V>(&mut self, table: &Table, u: V) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.perform_all(table, vec![TableOperation::Insert(u.into())])
+    }
+
+    /// Delete the row with the given key from `table` as part of this transaction.
+    pub fn delete<I>(&mut self, table: &Table, key: I) -> Result<(), TableError>
+    where
+        I: Into<Vec<DataType>>,
+    {
+        self.perform_all(table, vec![TableOperation::Delete { key: key.into() }])
+    }
+
+    /// Perform multiple operations on `table` as part of this transaction.
+    pub fn perform_all<I, V>(&mut self, table: &Table, i: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let (base, inputs) =
+            table.transaction_inputs(i.into_iter().map(Into::into).collect::<Vec<_>>())?;
+        match self.writes.iter_mut().find(|(b, _)| *b == base) {
+            Some((_, shards)) => {
+                for (shard, input) in shards.iter_mut().zip(inputs) {
+                    shard.data.extend(input.data);
+                }
+            }
+            None => self.writes.push((base, inputs)),
+        }
+        Ok(())
+    }
+
+    /// Apply the writes of this transaction.
+    ///
+    /// Once this resolves, the writes have been applied to their base tables. Like other writes,
+    /// they may take a little while longer to be reflected in views.
+    pub async fn commit(self) -> Result<(), failure::Error> {
+        let Transaction { mut handle, writes } = self;
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        handle.ready().await?;
+        handle
+            .rpc("transaction", writes, "failed to commit transaction")
+            .await
+    }
+}
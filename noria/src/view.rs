@@ -5,13 +5,15 @@ use futures_util::{
     future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
     stream::StreamExt, stream::TryStreamExt,
 };
-use nom_sql::ColumnSpecification;
+use nom_sql::{ColumnSpecification, OrderType};
 use petgraph::graph::NodeIndex;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
+use std::ops::{Bound, RangeBounds};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use tokio_tower::multiplex;
@@ -101,6 +103,17 @@ pub enum ViewError {
     /// The given view is not yet available.
     #[fail(display = "the view is not yet available")]
     NotYetAvailable,
+    /// The subscription is no longer active, most likely because the view was removed.
+    #[fail(display = "the subscription was closed")]
+    SubscriptionClosed,
+    /// The subscription fell too far behind the view, and was dropped. Subscribe again to pick up
+    /// from the current results.
+    #[fail(display = "the subscription fell behind")]
+    SubscriptionLagged,
+    /// The view can't answer range lookups for keys it does not already hold, since it is
+    /// partially materialized, and filled through a union or a sharded path.
+    #[fail(display = "range lookups are not supported by this view")]
+    RangeNotSupported,
     /// A lower-level error occurred while communicating with Soup.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -112,6 +125,157 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for ViewError {
     }
 }
 
+/// A set of keys in a view, identified by their position in the view's key order.
+///
+/// Keys are ordered lexicographically by the values of the view's key columns, so a range over a
+/// compound key compares the first key column first, then the second, and so on.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyRange {
+    /// All keys that fall between the two bounds.
+    Range(Bound<Vec<DataType>>, Bound<Vec<DataType>>),
+    /// All keys whose leading columns are equal to the given values.
+    Prefix(Vec<DataType>),
+}
+
+impl KeyRange {
+    /// Construct a `KeyRange` from any range expression over keys (e.g., `start..end`).
+    pub fn from_bounds<R: RangeBounds<Vec<DataType>>>(range: R) -> Self {
+        fn owned(b: Bound<&Vec<DataType>>) -> Bound<Vec<DataType>> {
+            match b {
+                Bound::Included(k) => Bound::Included(k.clone()),
+                Bound::Excluded(k) => Bound::Excluded(k.clone()),
+                Bound::Unbounded => Bound::Unbounded,
+            }
+        }
+        KeyRange::Range(owned(range.start_bound()), owned(range.end_bound()))
+    }
+
+    /// Returns true if the given key falls within this range.
+    pub fn contains(&self, key: &[DataType]) -> bool {
+        match *self {
+            KeyRange::Range(ref start, ref end) => {
+                let after_start = match *start {
+                    Bound::Included(ref s) => key >= &s[..],
+                    Bound::Excluded(ref s) => key > &s[..],
+                    Bound::Unbounded => true,
+                };
+                let before_end = match *end {
+                    Bound::Included(ref e) => key <= &e[..],
+                    Bound::Excluded(ref e) => key < &e[..],
+                    Bound::Unbounded => true,
+                };
+                after_start && before_end
+            }
+            KeyRange::Prefix(ref prefix) => key.starts_with(&prefix[..]),
+        }
+    }
+
+    /// Returns true if every key in `other` is also in `self`.
+    ///
+    /// This is conservative: it may return false for some ranges that are in fact covered.
+    pub fn covers(&self, other: &KeyRange) -> bool {
+        match (self, other) {
+            (KeyRange::Prefix(ref p), KeyRange::Prefix(ref q)) => q.starts_with(&p[..]),
+            (KeyRange::Prefix(ref p), KeyRange::Range(ref start, ref end)) => {
+                // all keys between two keys that share a prefix also share that prefix
+                match (start, end) {
+                    (Bound::Included(s), Bound::Included(e))
+                    | (Bound::Included(s), Bound::Excluded(e))
+                    | (Bound::Excluded(s), Bound::Included(e))
+                    | (Bound::Excluded(s), Bound::Excluded(e)) => {
+                        s.starts_with(&p[..]) && e.starts_with(&p[..])
+                    }
+                    _ => false,
+                }
+            }
+            (KeyRange::Range(ref s1, ref e1), KeyRange::Range(ref s2, ref e2)) => {
+                let starts_before = match (s1, s2) {
+                    (Bound::Unbounded, _) => true,
+                    (_, Bound::Unbounded) => false,
+                    (Bound::Excluded(a), Bound::Included(b)) => a < b,
+                    (Bound::Included(a), Bound::Included(b))
+                    | (Bound::Included(a), Bound::Excluded(b))
+                    | (Bound::Excluded(a), Bound::Excluded(b)) => a <= b,
+                };
+                let ends_after = match (e1, e2) {
+                    (Bound::Unbounded, _) => true,
+                    (_, Bound::Unbounded) => false,
+                    (Bound::Excluded(a), Bound::Included(b)) => b < a,
+                    (Bound::Included(a), Bound::Included(b))
+                    | (Bound::Included(a), Bound::Excluded(b))
+                    | (Bound::Excluded(a), Bound::Excluded(b)) => b <= a,
+                };
+                starts_before && ends_after
+            }
+            (KeyRange::Range(..), KeyRange::Prefix(..)) => false,
+        }
+    }
+
+    /// Returns true if this range cannot contain any keys.
+    pub fn is_empty(&self) -> bool {
+        match *self {
+            KeyRange::Range(Bound::Included(ref s), Bound::Included(ref e)) => s > e,
+            KeyRange::Range(Bound::Included(ref s), Bound::Excluded(ref e))
+            | KeyRange::Range(Bound::Excluded(ref s), Bound::Included(ref e))
+            | KeyRange::Range(Bound::Excluded(ref s), Bound::Excluded(ref e)) => s >= e,
+            _ => false,
+        }
+    }
+}
+
+/// A position in the rows for a single key of a view, from which a paginated read continues.
+///
+/// Cursors are returned by [`View::lookup_page`], and are only meaningful for the view (and key)
+/// that produced them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PageCursor(Vec<DataType>);
+
+/// The page of rows to return for a key.
+#[doc(hidden)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PageRequest {
+    /// The order in which to return rows.
+    pub order: Vec<(usize, OrderType)>,
+    /// Only return rows that come after this one.
+    pub after: Option<Vec<DataType>>,
+    /// The maximum number of rows to return.
+    pub limit: usize,
+}
+
+impl PageRequest {
+    /// Compare two rows in the order of this page.
+    ///
+    /// Rows that are equal in all the order columns are ordered by their full contents, so that
+    /// every row has a stable position to continue from.
+    pub fn cmp(&self, a: &[DataType], b: &[DataType]) -> Ordering {
+        self.order
+            .iter()
+            .map(|&(c, ref order_type)| match *order_type {
+                OrderType::OrderAscending => a[c].cmp(&b[c]),
+                OrderType::OrderDescending => b[c].cmp(&a[c]),
+            })
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or_else(|| a.cmp(b))
+    }
+
+    /// Select the rows in this page from all the rows for a key.
+    pub fn select<'a, I>(&self, rows: I) -> Vec<&'a Vec<DataType>>
+    where
+        I: IntoIterator<Item = &'a Vec<DataType>>,
+    {
+        let mut rows: Vec<_> = rows
+            .into_iter()
+            .filter(|r| match self.after {
+                Some(ref after) => self.cmp(r, after) == Ordering::Greater,
+                None => true,
+            })
+            .collect();
+        rows.sort_by(|a, b| self.cmp(a, b));
+        rows.truncate(self.limit);
+        rows
+    }
+}
+
 #[doc(hidden)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ReadQuery {
@@ -124,11 +288,52 @@ pub enum ReadQuery {
         /// Whether to block if a partial replay is triggered
         block: bool,
     },
+    /// Read all keys in the given ranges from a leaf view
+    Range {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Ranges to read
+        ranges: Vec<KeyRange>,
+        /// Whether to block if a partial replay is triggered
+        block: bool,
+    },
+    /// Read one page of the rows for a single key from a leaf view
+    Page {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Key to read with
+        key: Vec<DataType>,
+        /// Which rows to read
+        page: PageRequest,
+        /// Whether to block if a partial replay is triggered
+        block: bool,
+    },
     /// Read the size of a leaf view
     Size {
         /// Where to read from
         target: (NodeIndex, usize),
     },
+    /// Subscribe to changes to the given keys of a leaf view
+    Subscribe {
+        /// Where to subscribe
+        target: (NodeIndex, usize),
+        /// Keys to subscribe to
+        keys: Vec<Vec<DataType>>,
+    },
+    /// Wait for changes to the keys of a subscription
+    Poll {
+        /// Where the subscription was made
+        target: (NodeIndex, usize),
+        /// The subscription to poll
+        subscription: u64,
+    },
+    /// Cancel a subscription
+    Unsubscribe {
+        /// Where the subscription was made
+        target: (NodeIndex, usize),
+        /// The subscription to cancel
+        subscription: u64,
+    },
 }
 
 #[doc(hidden)]
@@ -136,8 +341,16 @@ pub enum ReadQuery {
 pub enum ReadReply<D = ReadReplyBatch> {
     /// Errors if view isn't ready yet.
     Normal(Result<Vec<D>, ()>),
+    /// Ranges can't be replayed into the view, so a range lookup could not be answered.
+    RangeNotSupported,
     /// Read size of view
     Size(usize),
+    /// The identifier of a new subscription, or an error if the view isn't ready yet.
+    Subscribed(Result<u64, ()>),
+    /// Changes for a subscription, or an error if the subscription no longer exists.
+    Deltas(Result<Vec<Delta>, ()>),
+    /// The subscription fell too far behind, and was dropped.
+    Lagged,
 }
 
 #[doc(hidden)]
@@ -146,6 +359,7 @@ pub struct ViewBuilder {
     pub node: NodeIndex,
     pub columns: Vec<String>,
     pub schema: Option<Vec<ColumnSpecification>>,
+    pub order: Vec<(usize, OrderType)>,
     pub shards: Vec<SocketAddr>,
 }
 
@@ -160,6 +374,7 @@ impl ViewBuilder {
         let columns = self.columns.clone();
         let shards = self.shards.clone();
         let schema = self.schema.clone();
+        let order = self.order.clone();
 
         let mut addrs = Vec::with_capacity(shards.len());
         let mut conns = Vec::with_capacity(shards.len());
@@ -201,6 +416,7 @@ impl ViewBuilder {
             node,
             schema,
             columns,
+            order,
             shard_addrs: addrs,
             shards: conns,
             tracer,
@@ -217,6 +433,7 @@ pub struct View {
     node: NodeIndex,
     columns: Vec<String>,
     schema: Option<Vec<ColumnSpecification>>,
+    order: Vec<(usize, OrderType)>,
 
     shards: Vec<ViewRpc>,
     shard_addrs: Vec<SocketAddr>,
@@ -237,6 +454,9 @@ impl fmt::Debug for View {
 pub(crate) mod results;
 use self::results::{Results, Row};
 
+mod subscription;
+pub use self::subscription::{Delta, Subscription};
+
 impl Service<(Vec<Vec<DataType>>, bool)> for View {
     type Response = Vec<Results>;
     type Error = ViewError;
@@ -435,6 +655,215 @@ impl View {
         let rs = self.multi_lookup(vec![Vec::from(key)], block).await?;
         Ok(rs.into_iter().next().unwrap().into_iter().next())
     }
+
+    /// Retrieve a page of the query results for the given parameter value.
+    ///
+    /// Returns at most `limit` rows, starting after `cursor` (or from the first row if `cursor` is
+    /// `None`), along with a cursor to pass in to retrieve the next page. The cursor is `None` once
+    /// there are no more rows. Rows are returned in the order given by the query's `ORDER BY`,
+    /// and ties (and views without an `ORDER BY`) are ordered by the rows' contents.
+    ///
+    /// Note that a query with a `LIMIT` only ever has that many rows for each key to page through.
+    ///
+    /// The method will block if the results are not yet available only when `block` is `true`.
+    pub async fn lookup_page(
+        &mut self,
+        key: &[DataType],
+        cursor: Option<&PageCursor>,
+        limit: usize,
+        block: bool,
+    ) -> Result<(Results, Option<PageCursor>), ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let shardi = if self.shards.len() == 1 {
+            0
+        } else {
+            assert_eq!(key.len(), 1);
+            crate::shard_by(&key[0], self.shards.len())
+        };
+
+        let request = Tagged::from(ReadQuery::Page {
+            target: (self.node, shardi),
+            key: Vec::from(key),
+            page: PageRequest {
+                order: self.order.clone(),
+                after: cursor.map(|c| c.0.clone()),
+                limit,
+            },
+            block,
+        });
+
+        // poll_ready reserves a sender slot on every shard which we have to release
+        // https://github.com/tokio-rs/tokio/issues/898
+        for (i, shard) in self.shards.iter_mut().enumerate() {
+            if i != shardi {
+                *shard = shard.clone();
+            }
+        }
+
+        let reply = self.shards[shardi]
+            .call(request)
+            .map_err(ViewError::from)
+            .await?;
+        let rows: Vec<Vec<DataType>> = match reply.v {
+            ReadReply::Normal(Ok(rows)) => rows.into_iter().next().unwrap().into(),
+            ReadReply::Normal(Err(())) => return Err(ViewError::NotYetAvailable),
+            _ => unreachable!(),
+        };
+
+        let next = if rows.len() == limit {
+            rows.last().cloned().map(PageCursor)
+        } else {
+            None
+        };
+        Ok((Results::new(rows, Arc::from(&self.columns[..])), next))
+    }
+
+    /// Subscribe to changes to the query results for the given parameter values.
+    ///
+    /// The returned stream first yields the current results for the keys, as positive deltas, and
+    /// then yields a batch of deltas whenever the results change. Keys whose results are not yet
+    /// materialized are backfilled, and their results arrive as positive deltas once they are.
+    pub async fn subscribe(&mut self, keys: Vec<Vec<DataType>>) -> Result<Subscription, ViewError> {
+        let mut shard_keys = vec![Vec::new(); self.shards.len()];
+        if self.shards.len() == 1 {
+            shard_keys[0] = keys;
+        } else {
+            for key in keys {
+                assert_eq!(key.len(), 1);
+                let shard = crate::shard_by(&key[0], self.shards.len());
+                shard_keys[shard].push(key);
+            }
+        }
+
+        let mut shards = Vec::new();
+        let mut error = None;
+        for (shardi, keys) in shard_keys.into_iter().enumerate() {
+            if keys.is_empty() {
+                continue;
+            }
+
+            let mut rpc = self.shards[shardi].clone();
+            let target = (self.node, shardi);
+            match subscription::request(&mut rpc, ReadQuery::Subscribe { target, keys }).await {
+                Ok(ReadReply::Subscribed(Ok(id))) => shards.push((rpc, target, id)),
+                Ok(ReadReply::Subscribed(Err(()))) => error = Some(ViewError::NotYetAvailable),
+                Ok(_) => unreachable!(),
+                Err(e) => error = Some(e),
+            }
+            if error.is_some() {
+                break;
+            }
+        }
+
+        // if we fail part-way, dropping the subscription cancels it on the shards we did reach
+        let subscription = Subscription::new(shards);
+        match error {
+            Some(e) => Err(e),
+            None => Ok(subscription),
+        }
+    }
+
+    /// Retrieve the query results for all keys that fall within each of the given ranges.
+    ///
+    /// Rows for each range are returned in key order. Note that if the view is sharded, each
+    /// shard's rows are returned in key order, but rows from different shards are not interleaved.
+    ///
+    /// The method will block if the results are not yet available only when `block` is `true`.
+    /// If `block` is false, ranges that are not yet materialized will be returned as empty results
+    /// and backfilled asynchronously.
+    pub async fn multi_lookup_range(
+        &mut self,
+        ranges: Vec<KeyRange>,
+        block: bool,
+    ) -> Result<Vec<Results>, ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let span = if crate::trace_next_op() {
+            Some(tracing::trace_span!(
+                "view-range-request",
+                ?ranges,
+                node = self.node.index()
+            ))
+        } else {
+            None
+        };
+        let _guard = span.as_ref().map(tracing::Span::enter);
+        tracing::trace!("submit range request");
+
+        // we don't know which shards hold the keys in a range, so we have to ask all of them
+        let node = self.node;
+        let nranges = ranges.len();
+        let mut rsps = self
+            .shards
+            .iter_mut()
+            .enumerate()
+            .map(|(shardi, shard)| {
+                shard
+                    .call(Tagged::from(ReadQuery::Range {
+                        target: (node, shardi),
+                        ranges: ranges.clone(),
+                        block,
+                    }))
+                    .map_err(ViewError::from)
+                    .and_then(|reply| async move {
+                        match reply.v {
+                            ReadReply::Normal(Ok(rows)) => Ok(rows),
+                            ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
+                            ReadReply::RangeNotSupported => Err(ViewError::RangeNotSupported),
+                            _ => unreachable!(),
+                        }
+                    })
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut per_range = vec![Vec::new(); nranges];
+        while let Some(shard_rows) = rsps.next().await.transpose()? {
+            assert_eq!(shard_rows.len(), nranges);
+            for (rows, into) in shard_rows.into_iter().zip(per_range.iter_mut()) {
+                into.extend(rows);
+            }
+        }
+
+        let columns = Arc::from(&self.columns[..]);
+        Ok(per_range
+            .into_iter()
+            .map(|rows| Results::new(rows, Arc::clone(&columns)))
+            .collect())
+    }
+
+    /// Retrieve the query results for all keys in the given range.
+    ///
+    /// For example, `view.lookup_range(vec![1.into()]..vec![10.into()], true)` returns the rows
+    /// for all keys `k` where `1 <= k < 10`.
+    ///
+    /// The method will block if the results are not yet available only when `block` is `true`.
+    pub async fn lookup_range<R>(&mut self, range: R, block: bool) -> Result<Results, ViewError>
+    where
+        R: RangeBounds<Vec<DataType>>,
+    {
+        let rs = self
+            .multi_lookup_range(vec![KeyRange::from_bounds(range)], block)
+            .await?;
+        Ok(rs.into_iter().next().unwrap())
+    }
+
+    /// Retrieve the query results for all keys whose leading columns match `prefix`.
+    ///
+    /// This is useful for views with compound keys, where one wants all the rows that share the
+    /// first few key values.
+    ///
+    /// The method will block if the results are not yet available only when `block` is `true`.
+    pub async fn lookup_prefix(
+        &mut self,
+        prefix: &[DataType],
+        block: bool,
+    ) -> Result<Results, ViewError> {
+        let rs = self
+            .multi_lookup_range(vec![KeyRange::Prefix(Vec::from(prefix))], block)
+            .await?;
+        Ok(rs.into_iter().next().unwrap())
+    }
 }
 
 #[derive(Debug, Default)]
@@ -0,0 +1,141 @@
+use super::{ReadQuery, ReadReply, ViewError, ViewRpc};
+use crate::data::DataType;
+use crate::Tagged;
+use futures_util::{
+    future,
+    stream::{self, Stream, StreamExt},
+};
+use petgraph::graph::NodeIndex;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// A change to the rows of a view.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Delta {
+    /// The row was added.
+    Positive(Vec<DataType>),
+    /// The row was removed.
+    Negative(Vec<DataType>),
+}
+
+impl Delta {
+    /// The row that was added or removed.
+    pub fn row(&self) -> &[DataType] {
+        match *self {
+            Delta::Positive(ref r) | Delta::Negative(ref r) => &r[..],
+        }
+    }
+
+    /// Returns true if the row was added.
+    pub fn is_positive(&self) -> bool {
+        if let Delta::Positive(..) = *self {
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Send a single request to one shard of a view.
+pub(super) async fn request(rpc: &mut ViewRpc, query: ReadQuery) -> Result<ReadReply, ViewError> {
+    future::poll_fn(|cx| rpc.poll_ready(cx)).await?;
+    Ok(rpc.call(Tagged::from(query)).await?.v)
+}
+
+type Shard = (ViewRpc, (NodeIndex, usize), u64);
+
+/// A stream of changes to the query results for a set of keys in a [`View`](crate::View).
+///
+/// Created by [`View::subscribe`](crate::View::subscribe). Each item is a batch of changes that
+/// became visible in the view at the same time. Applying all the changes in the order they are
+/// received to an empty set of rows yields the current results for the subscribed keys.
+///
+/// The stream ends with an error if the view goes away. It also ends with
+/// [`ViewError::SubscriptionLagged`](crate::error::ViewError::SubscriptionLagged) if changes are
+/// not picked up as fast as they are made, and too many pile up on the server. Dropping the
+/// stream cancels the subscription.
+pub struct Subscription {
+    deltas: Pin<Box<dyn Stream<Item = Result<Vec<Delta>, ViewError>> + Send>>,
+    shards: Vec<Shard>,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field(
+                "shards",
+                &self
+                    .shards
+                    .iter()
+                    .map(|&(_, target, id)| (target, id))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Subscription {
+    pub(super) fn new(shards: Vec<Shard>) -> Self {
+        let polls = shards.iter().map(|&(ref rpc, target, id)| {
+            let rpc = rpc.clone();
+            Box::pin(stream::unfold(Some(rpc), move |rpc| async move {
+                let mut rpc = rpc?;
+                loop {
+                    let poll = ReadQuery::Poll {
+                        target,
+                        subscription: id,
+                    };
+                    match request(&mut rpc, poll).await {
+                        Ok(ReadReply::Deltas(Ok(deltas))) => {
+                            if deltas.is_empty() {
+                                // the server gave up on the poll without anything to report
+                                continue;
+                            }
+                            return Some((Ok(deltas), Some(rpc)));
+                        }
+                        Ok(ReadReply::Deltas(Err(()))) => {
+                            return Some((Err(ViewError::SubscriptionClosed), None));
+                        }
+                        Ok(ReadReply::Lagged) => {
+                            return Some((Err(ViewError::SubscriptionLagged), None));
+                        }
+                        Ok(_) => unreachable!(),
+                        Err(e) => return Some((Err(e), None)),
+                    }
+                }
+            }))
+        });
+
+        Subscription {
+            deltas: stream::select_all(polls).boxed(),
+            shards,
+        }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Result<Vec<Delta>, ViewError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.deltas.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // tell the server that it can stop collecting changes for us
+        if let Ok(rt) = tokio::runtime::Handle::try_current() {
+            for (mut rpc, target, id) in self.shards.drain(..) {
+                rt.spawn(async move {
+                    let unsubscribe = ReadQuery::Unsubscribe {
+                        target,
+                        subscription: id,
+                    };
+                    let _ = request(&mut rpc, unsubscribe).await;
+                });
+            }
+        }
+    }
+}
@@ -66,6 +66,15 @@ impl From<(Vec<DataType>, bool)> for Record {
     }
 }
 
+impl From<Record> for noria::Delta {
+    fn from(other: Record) -> Self {
+        match other {
+            Record::Positive(r) => noria::Delta::Positive(r),
+            Record::Negative(r) => noria::Delta::Negative(r),
+        }
+    }
+}
+
 impl Into<Vec<Record>> for Records {
     fn into(self) -> Vec<Record> {
         self.0
@@ -1,33 +1,49 @@
+use crate::eviction::{AccessTracker, EvictionPolicy};
 use crate::prelude::*;
 use ahash::RandomState;
 use common::SizeOf;
 use rand::prelude::*;
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+type Trigger = Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>;
+type RangeTrigger = Arc<dyn Fn(&KeyRange) -> bool + Send + Sync>;
 
 /// Allocate a new end-user facing result table.
 pub(crate) fn new(cols: usize, key: &[usize]) -> (SingleReadHandle, WriteHandle) {
-    new_inner(cols, key, None)
+    new_inner(cols, key, None, None)
 }
 
 /// Allocate a new partially materialized end-user facing result table.
 ///
 /// Misses in this table will call `trigger` to populate the entry, and retry until successful.
-pub(crate) fn new_partial<F>(
+/// Range lookups that cover keys that have not yet been filled will call `trigger_range`.
+pub(crate) fn new_partial<F, R>(
     cols: usize,
     key: &[usize],
     trigger: F,
+    trigger_range: R,
 ) -> (SingleReadHandle, WriteHandle)
 where
     F: Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + 'static + Send + Sync,
+    R: Fn(&KeyRange) -> bool + 'static + Send + Sync,
 {
-    new_inner(cols, key, Some(Arc::new(trigger)))
+    new_inner(
+        cols,
+        key,
+        Some(Arc::new(trigger)),
+        Some(Arc::new(trigger_range)),
+    )
 }
 
 fn new_inner(
     cols: usize,
     key: &[usize],
-    trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
+    trigger: Option<Trigger>,
+    trigger_range: Option<RangeTrigger>,
 ) -> (SingleReadHandle, WriteHandle) {
     let contiguous = {
         let mut contiguous = true;
@@ -63,6 +79,12 @@ fn new_inner(
         _ => make!(Many),
     };
 
+    let range_index = Arc::new(RwLock::new(RangeIndex::default()));
+    // only partial readers evict, so only they need to know which keys are being read
+    let accesses = trigger.as_ref().map(|_| Arc::new(Accesses::default()));
+    let subscriptions = Subscriptions::default();
+    let subscribed = subscriptions.count();
+    let subscriptions = Arc::new(Mutex::new(subscriptions));
     let w = WriteHandle {
         partial: trigger.is_some(),
         handle: w,
@@ -70,18 +92,97 @@ fn new_inner(
         cols,
         contiguous,
         mem_size: 0,
+        range_index: Arc::clone(&range_index),
+        range_tracking: false,
+        touched: HashSet::new(),
+        filled_ranges: Vec::new(),
+        filled_ranges_changed: false,
+        accesses: accesses.clone(),
+        subscriptions: Arc::clone(&subscriptions),
+        subscribed,
+        changes: Vec::new(),
+        refill: Vec::new(),
+        trigger: trigger.clone(),
     };
     let r = SingleReadHandle {
         handle: r,
         trigger,
+        trigger_range,
         key: Vec::from(key),
+        range_index,
+        accesses,
+        subscriptions,
     };
 
     (r, w)
 }
 
+/// Only one in this many read hits is recorded in a partial reader's access history.
+const READ_SAMPLE: u64 = 8;
+
+/// The read history of a partial reader, shared by its writer and its read handles.
+///
+/// Readers are on the hot path and run concurrently, so they only record a sample of their hits,
+/// and skip recording a hit entirely rather than wait for the tracker to become available. The
+/// eviction policies only need a rough idea of which keys are hot.
+#[derive(Debug, Default)]
+struct Accesses {
+    reads: AtomicU64,
+    tracker: Mutex<AccessTracker>,
+}
+
+impl Accesses {
+    fn read(&self, key: &[DataType]) {
+        if self.reads.fetch_add(1, Ordering::Relaxed) % READ_SAMPLE == 0 {
+            if let Ok(mut tracker) = self.tracker.try_lock() {
+                tracker.touch(key);
+            }
+        }
+    }
+
+    fn tracker(&self) -> std::sync::MutexGuard<'_, AccessTracker> {
+        self.tracker.lock().unwrap()
+    }
+}
+
+/// An ordered index over the keys of a reader, which allows it to answer range lookups.
+///
+/// The evmap that holds the reader's records is a hash map, so we keep the key order on the side.
+/// The index is updated by the writer whenever it swaps, so it may briefly list keys that have
+/// since been removed from the map (but never omits keys that are visible in the map).
+///
+/// Most readers never see a range lookup, so the index is only built once the first one arrives.
+#[derive(Debug, Default)]
+struct RangeIndex {
+    /// Set by the first range lookup. Until then, `keys` is empty and the writer does not
+    /// maintain it.
+    enabled: bool,
+    keys: BTreeSet<Vec<DataType>>,
+    /// Ranges that have been replayed into a partially materialized reader in their entirety.
+    filled: Vec<KeyRange>,
+    /// Set if ranges can't be replayed into the reader, so range lookups that miss must fail.
+    unsupported: bool,
+}
+
+impl RangeIndex {
+    fn keys_in<'a>(
+        &'a self,
+        range: &'a KeyRange,
+    ) -> Box<dyn Iterator<Item = &'a Vec<DataType>> + 'a> {
+        crate::state::keys_in(&self.keys, range)
+    }
+
+    fn is_filled(&self, range: &KeyRange) -> bool {
+        self.filled.iter().any(|f| f.covers(range))
+    }
+}
+
 mod multir;
 mod multiw;
+mod subscriptions;
+
+pub use self::subscriptions::SubscriptionPoll;
+use self::subscriptions::Subscriptions;
 
 fn key_to_single(k: Key) -> Cow<DataType> {
     assert_eq!(k.len(), 1);
@@ -111,6 +212,27 @@ pub(crate) struct WriteHandle {
     key: Vec<usize>,
     contiguous: bool,
     mem_size: usize,
+
+    range_index: Arc<RwLock<RangeIndex>>,
+    /// Set once the range index has been enabled and the writer has taken over maintaining it.
+    range_tracking: bool,
+    /// Keys whose presence in the map may have changed since the last swap (only used if
+    /// `range_tracking`).
+    touched: HashSet<Vec<DataType>>,
+    /// Ranges that have been filled in their entirety (only used for partial readers).
+    filled_ranges: Vec<KeyRange>,
+    filled_ranges_changed: bool,
+    /// Per-key read history, shared with the read handles (only used for partial readers).
+    accesses: Option<Arc<Accesses>>,
+
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    /// The number of subscriptions, so that writes don't need the lock when there are none.
+    subscribed: Arc<AtomicUsize>,
+    /// Changes to subscribed keys since the last swap.
+    changes: Vec<(Vec<DataType>, Record)>,
+    /// Subscribed keys that have been evicted since the last swap, and must be replayed again.
+    refill: Vec<Vec<DataType>>,
+    trigger: Option<Trigger>,
 }
 
 type Key<'a> = Cow<'a, [DataType]>;
@@ -130,6 +252,13 @@ impl<'a> MutWriteHandleEntry<'a> {
             .handle
             .meta_get_and(Cow::Borrowed(&*self.key), |rs| rs.is_empty())
         {
+            // keys are filled because someone tried to read them
+            if let Some(ref accesses) = self.handle.accesses {
+                accesses.tracker().touch(&self.key);
+            }
+            if self.handle.range_tracking {
+                self.handle.touched.insert(self.key.to_vec());
+            }
             self.handle.handle.clear(self.key)
         } else {
             unreachable!("attempted to fill already-filled key");
@@ -146,6 +275,14 @@ impl<'a> MutWriteHandleEntry<'a> {
             .map(|r| r.0.unwrap_or(0))
             .unwrap_or(0);
         self.handle.mem_size = self.handle.mem_size.checked_sub(size as usize).unwrap();
+        self.handle.note_eviction(&self.key);
+        self.handle.unfill_ranges_containing(&self.key);
+        if let Some(ref accesses) = self.handle.accesses {
+            accesses.tracker().forget(&self.key);
+        }
+        if self.handle.range_tracking {
+            self.handle.touched.insert(self.key.to_vec());
+        }
         self.handle.handle.empty(self.key)
     }
 }
@@ -229,7 +366,137 @@ impl WriteHandle {
     }
 
     pub(crate) fn swap(&mut self) {
+        // subscribers must not see the new state until they are also sent the changes
+        let subscriptions = Arc::clone(&self.subscriptions);
+        let mut subscriptions = subscriptions.lock().unwrap();
         self.handle.refresh();
+        if !subscriptions.is_empty() {
+            let handle = &self.handle;
+            subscriptions.deliver(&self.changes, |key| {
+                handle
+                    .meta_get_and(Cow::Borrowed(key), |rs| {
+                        rs.iter().cloned().collect::<Vec<_>>()
+                    })
+                    .and_then(|r| r.0)
+                    .unwrap_or_default()
+            });
+        }
+        drop(subscriptions);
+        self.changes.clear();
+
+        if !self.refill.is_empty() {
+            // subscribers expect to keep hearing about these keys
+            let refill = mem::take(&mut self.refill);
+            if let Some(ref trigger) = self.trigger {
+                trigger(&mut refill.iter().map(Vec::as_slice));
+            }
+        }
+
+        if !self.range_tracking {
+            if self.filled_ranges_changed || self.range_index.read().unwrap().enabled {
+                let mut index = self.range_index.write().unwrap();
+                if index.enabled {
+                    // a reader has started doing range lookups, so take over the index from it
+                    index.keys = self.handle.keys().unwrap_or_default();
+                    self.range_tracking = true;
+                }
+                index.filled = self.filled_ranges.clone();
+                self.filled_ranges_changed = false;
+            }
+            return;
+        }
+
+        if self.touched.is_empty() && !self.filled_ranges_changed {
+            return;
+        }
+
+        // now that the map reflects all our writes, bring the key order up to date with it
+        let mut index = self.range_index.write().unwrap();
+        for key in self.touched.drain() {
+            let present = self
+                .handle
+                .meta_get_and(Cow::Borrowed(&key[..]), |_| ())
+                .map(|(v, _)| v.is_some())
+                .unwrap_or(false);
+            if present {
+                index.keys.insert(key);
+            } else {
+                index.keys.remove(&key);
+            }
+        }
+        if self.filled_ranges_changed {
+            index.filled = self.filled_ranges.clone();
+            self.filled_ranges_changed = false;
+        }
+    }
+
+    /// Mark every key in `range` as filled.
+    ///
+    /// Records for keys in the range that are not yet present will be added as they arrive, rather
+    /// than discarded as misses. This will be made visible to readers after the next call to
+    /// `swap()`.
+    pub(crate) fn mark_range_filled(&mut self, range: KeyRange) {
+        debug_assert!(self.partial);
+        if !self.is_range_filled(&range) {
+            self.filled_ranges.push(range);
+            self.filled_ranges_changed = true;
+        }
+    }
+
+    /// Note that ranges can't be replayed into this (partial) reader.
+    ///
+    /// Read handles will then report range lookups that miss as unsupported, rather than wait for
+    /// them to be filled.
+    pub(crate) fn mark_ranges_unsupported(&mut self) {
+        self.range_index.write().unwrap().unsupported = true;
+    }
+
+    /// Returns true if all keys in the given range are known to be filled.
+    pub(crate) fn is_range_filled(&self, range: &KeyRange) -> bool {
+        self.filled_ranges.iter().any(|f| f.covers(range))
+    }
+
+    /// Returns true if the given key falls within a range that has been filled in its entirety.
+    pub(crate) fn in_filled_range(&self, key: &[DataType]) -> bool {
+        self.filled_ranges.iter().any(|f| f.contains(key))
+    }
+
+    fn unfill_ranges_containing(&mut self, key: &[DataType]) {
+        let had = self.filled_ranges.len();
+        self.filled_ranges.retain(|f| !f.contains(key));
+        if self.filled_ranges.len() != had {
+            self.filled_ranges_changed = true;
+        }
+    }
+
+    /// Whether there may be subscriptions that want to hear about changes.
+    ///
+    /// A subscription made after this returns false still sees every change, since it compares the
+    /// rows it started out with to the reader's contents at the next swap, and the swap takes the
+    /// lock, after which this returns true.
+    fn has_subscriptions(&self) -> bool {
+        self.subscribed.load(Ordering::Acquire) != 0
+    }
+
+    /// Called before `key` is evicted, so that its subscribers (if any) see its rows go away.
+    ///
+    /// The key is replayed again after the next swap, at which point its rows come back.
+    fn note_eviction(&mut self, key: &[DataType]) {
+        if !self.has_subscriptions() || !self.subscriptions.lock().unwrap().is_subscribed(key) {
+            return;
+        }
+
+        let rows: Vec<Vec<DataType>> = self
+            .handle
+            .meta_get_and(Cow::Borrowed(key), |rs| {
+                rs.iter().cloned().collect::<Vec<_>>()
+            })
+            .and_then(|r| r.0)
+            .unwrap_or_default();
+        for row in rows {
+            self.changes.push((key.to_vec(), Record::Negative(row)));
+        }
+        self.refill.push(key.to_vec());
     }
 
     /// Add a new set of records to the backlog.
@@ -239,6 +506,23 @@ impl WriteHandle {
     where
         I: IntoIterator<Item = Record>,
     {
+        let rs: Vec<_> = rs.into_iter().collect();
+        if self.has_subscriptions() {
+            let subscriptions = self.subscriptions.lock().unwrap();
+            for r in &rs {
+                let key: Vec<_> = self.key.iter().map(|&k| r[k].clone()).collect();
+                if subscriptions.is_subscribed(&key) {
+                    self.changes.push((key, r.clone()));
+                }
+            }
+        }
+        if self.range_tracking {
+            for r in &rs {
+                self.touched
+                    .insert(self.key.iter().map(|&k| r[k].clone()).collect());
+            }
+        }
+
         let mem_delta = self.handle.add(&self.key[..], self.cols, rs);
         if mem_delta > 0 {
             self.mem_size += mem_delta as usize;
@@ -254,27 +538,103 @@ impl WriteHandle {
         self.partial
     }
 
-    /// Evict `count` randomly selected keys from state and return them along with the number of
-    /// bytes that will be freed once the underlying `evmap` applies the operation.
-    pub(crate) fn evict_random_keys(&mut self, rng: &mut ThreadRng, mut n: usize) -> u64 {
+    /// The columns of the records in this backlog that it is keyed by.
+    pub(crate) fn key(&self) -> &[usize] {
+        &self.key[..]
+    }
+
+    /// Evict `n` keys chosen by `policy`, and return the number of bytes that will be freed once
+    /// the underlying `evmap` applies the operation.
+    ///
+    /// Keys are picked at random if the policy does not use access history.
+    pub(crate) fn evict_cold_keys(
+        &mut self,
+        policy: &dyn EvictionPolicy,
+        rng: &mut ThreadRng,
+        mut n: usize,
+    ) -> u64 {
         let mut bytes_to_be_freed = 0;
+        let mut tracker_bytes_freed = 0;
         if self.mem_size > 0 {
             if self.handle.is_empty() {
                 unreachable!("mem size is {}, but map is empty", self.mem_size);
             }
 
-            self.handle.empty_random_for_each(rng, n, |vs| {
-                let size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
-                bytes_to_be_freed += size;
-                n -= 1;
-            });
+            let coldest = self
+                .accesses
+                .as_ref()
+                .and_then(|accesses| accesses.tracker().coldest(policy, n, rng));
+            let mut evicted = Vec::new();
+            if let Some(mut keys) = coldest {
+                // subscribed keys would just be replayed again right away
+                if self.has_subscriptions() {
+                    let subscriptions = self.subscriptions.lock().unwrap();
+                    keys.retain(|key| !subscriptions.is_subscribed(key));
+                }
+                for key in keys {
+                    let size: u64 = self
+                        .handle
+                        .meta_get_and(Cow::Borrowed(&key[..]), |rs| {
+                            rs.iter().map(|r| r.deep_size_of() as u64).sum()
+                        })
+                        .and_then(|r| r.0)
+                        .unwrap_or(0);
+                    bytes_to_be_freed += size;
+                    self.handle.empty(Cow::Borrowed(&key[..]));
+                    evicted.push(key);
+                }
+            } else {
+                let subscriptions = if self.has_subscriptions() {
+                    Some(self.subscriptions.lock().unwrap())
+                } else {
+                    None
+                };
+                let changes = &mut self.changes;
+                let refill = &mut self.refill;
+                self.handle.empty_random_for_each(rng, n, |k, vs| {
+                    let size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
+                    bytes_to_be_freed += size;
+                    if subscriptions
+                        .as_ref()
+                        .map_or(false, |s| s.is_subscribed(&k))
+                    {
+                        for r in vs.iter() {
+                            changes.push((k.clone(), Record::Negative(r.clone())));
+                        }
+                        refill.push(k.clone());
+                    }
+                    evicted.push(k);
+                    n -= 1;
+                });
+            }
+
+            if let Some(ref accesses) = self.accesses {
+                let mut tracker = accesses.tracker();
+                let before = tracker.deep_size_of();
+                for key in &evicted {
+                    tracker.forget(key);
+                }
+                tracker_bytes_freed = before - tracker.deep_size_of();
+            }
+            for key in evicted {
+                self.unfill_ranges_containing(&key);
+                if self.range_tracking {
+                    self.touched.insert(key);
+                }
+            }
         }
 
         self.mem_size = self
             .mem_size
             .checked_sub(bytes_to_be_freed as usize)
             .unwrap();
-        bytes_to_be_freed
+        bytes_to_be_freed + tracker_bytes_freed
+    }
+}
+
+impl Drop for WriteHandle {
+    fn drop(&mut self) {
+        self.subscriptions.lock().unwrap().close();
     }
 }
 
@@ -286,7 +646,12 @@ impl SizeOf for WriteHandle {
     }
 
     fn deep_size_of(&self) -> u64 {
-        self.mem_size as u64
+        let tracked = self
+            .accesses
+            .as_ref()
+            .map(|accesses| accesses.tracker().deep_size_of())
+            .unwrap_or(0);
+        self.mem_size as u64 + tracked
     }
 
     fn is_empty(&self) -> bool {
@@ -298,8 +663,12 @@ impl SizeOf for WriteHandle {
 #[derive(Clone)]
 pub struct SingleReadHandle {
     handle: multir::Handle,
-    trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
+    trigger: Option<Trigger>,
+    trigger_range: Option<RangeTrigger>,
     key: Vec<usize>,
+    range_index: Arc<RwLock<RangeIndex>>,
+    accesses: Option<Arc<Accesses>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
 }
 
 impl std::fmt::Debug for SingleReadHandle {
@@ -307,6 +676,7 @@ impl std::fmt::Debug for SingleReadHandle {
         f.debug_struct("SingleReadHandle")
             .field("handle", &self.handle)
             .field("has_trigger", &self.trigger.is_some())
+            .field("has_range_trigger", &self.trigger_range.is_some())
             .field("key", &self.key)
             .finish()
     }
@@ -329,6 +699,16 @@ impl SingleReadHandle {
         (*self.trigger.as_ref().unwrap())(&mut it)
     }
 
+    /// Trigger a replay of all keys in a range of a partially materialized view.
+    pub fn trigger_range(&self, range: &KeyRange) -> bool {
+        assert!(
+            self.trigger_range.is_some(),
+            "tried to trigger a replay for a fully materialized view"
+        );
+
+        (*self.trigger_range.as_ref().unwrap())(range)
+    }
+
     /// Find all entries that matched the given conditions.
     ///
     /// Returned records are passed to `then` before being returned.
@@ -345,13 +725,115 @@ impl SingleReadHandle {
             .meta_get_and(key, &mut then)
             .ok_or(())
             .map(|(mut records, meta)| {
-                if records.is_none() && self.trigger.is_none() {
+                if records.is_some() {
+                    if let Some(ref accesses) = self.accesses {
+                        accesses.read(key);
+                    }
+                } else if self.trigger.is_none() || self.in_filled_range(key) {
                     records = Some(then(&evmap::Values::default()));
                 }
                 (records, meta)
             })
     }
 
+    fn in_filled_range(&self, key: &[DataType]) -> bool {
+        let index = self.range_index.read().unwrap();
+        index.filled.iter().any(|f| f.contains(key))
+    }
+
+    /// Find all entries whose keys fall in the given range, in key order.
+    ///
+    /// The records for each key are passed to `then`, and the results are returned in a `Vec`.
+    ///
+    /// If the view is partially materialized, and the range has not been filled in its entirety,
+    /// `Ok(None)` is returned.
+    pub fn try_find_range_and<F, T>(
+        &self,
+        range: &KeyRange,
+        mut then: F,
+    ) -> Result<Option<Vec<T>>, ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
+    {
+        if !self.handle.is_ready() {
+            return Err(());
+        }
+
+        if !self.range_index.read().unwrap().enabled {
+            let mut index = self.range_index.write().unwrap();
+            if !index.enabled {
+                // the writer keeps the index up to date from its next swap onwards
+                index.keys = self.handle.keys().ok_or(())?;
+                index.enabled = true;
+            }
+        }
+
+        let index = self.range_index.read().unwrap();
+        if self.trigger.is_some() && !index.is_filled(range) {
+            return Ok(None);
+        }
+
+        let mut results = Vec::new();
+        for key in index.keys_in(range) {
+            match self.handle.meta_get_and(&key[..], &mut then) {
+                Some((Some(rs), _)) => results.push(rs),
+                Some((None, _)) => {
+                    // key was removed from the map, but the index hasn't caught up yet
+                }
+                None => return Err(()),
+            }
+        }
+        Ok(Some(results))
+    }
+
+    /// Returns true if ranges can't be replayed into this view, so range lookups that miss will
+    /// never be filled.
+    pub fn ranges_unsupported(&self) -> bool {
+        self.range_index.read().unwrap().unsupported
+    }
+
+    /// Subscribe to changes to the given keys.
+    ///
+    /// Returns the subscription's identifier, along with the keys that are missing from a
+    /// partially materialized view. The caller should trigger replays for those. The current rows
+    /// for the keys that are present are the first changes that the subscription sees.
+    pub fn subscribe(&self, keys: Vec<Vec<DataType>>) -> Result<(u64, Vec<Vec<DataType>>), ()> {
+        // hold the lock so that the writer can't swap in changes we don't hear about
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+
+        let mut snapshot = HashMap::with_capacity(keys.len());
+        let mut misses = Vec::new();
+        for key in keys {
+            let rows = self
+                .handle
+                .meta_get_and(&key[..], |rs| rs.iter().cloned().collect::<Vec<_>>())
+                .ok_or(())?
+                .0;
+            let rows = match rows {
+                Some(rows) => rows,
+                None => {
+                    if self.trigger.is_some() && !self.in_filled_range(&key) {
+                        misses.push(key.clone());
+                    }
+                    Vec::new()
+                }
+            };
+            snapshot.insert(key, rows);
+        }
+
+        Ok((subscriptions.subscribe(snapshot), misses))
+    }
+
+    /// Check for changes to the keys of a subscription.
+    pub fn poll_subscription(&self, id: u64) -> SubscriptionPoll {
+        self.subscriptions.lock().unwrap().poll(id)
+    }
+
+    /// Stop collecting changes for a subscription.
+    pub fn unsubscribe(&self, id: u64) {
+        self.subscriptions.lock().unwrap().unsubscribe(id)
+    }
+
     pub fn len(&self) -> usize {
         self.handle.len()
     }
@@ -564,4 +1046,165 @@ mod tests {
             .0
             .unwrap());
     }
+
+    #[test]
+    fn range_lookups() {
+        let (r, mut w) = new(2, &[0]);
+        w.add((0..10).map(|i| Record::Positive(vec![i.into(), "x".into()])));
+        w.swap();
+        // no range lookups yet, so the writer does not maintain the index
+        assert!(!w.range_tracking);
+        assert!(w.touched.is_empty());
+
+        let keys = |range: KeyRange| -> Vec<i32> {
+            r.try_find_range_and(&range, |rs| i32::from(&rs.iter().next().unwrap()[0]))
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(
+            keys(KeyRange::from_bounds(vec![3.into()]..vec![6.into()])),
+            vec![3, 4, 5]
+        );
+        assert_eq!(keys(KeyRange::from_bounds(vec![7.into()]..)), vec![7, 8, 9]);
+        assert_eq!(keys(KeyRange::Prefix(vec![4.into()])), vec![4]);
+        assert!(keys(KeyRange::from_bounds(vec![6.into()]..vec![3.into()])).is_empty());
+
+        w.add(vec![Record::Negative(vec![4.into(), "x".into()])]);
+        w.swap();
+        assert_eq!(
+            keys(KeyRange::from_bounds(vec![3.into()]..vec![6.into()])),
+            vec![3, 5]
+        );
+
+        // from here on, the writer keeps the index up to date as it goes
+        assert!(w.range_tracking);
+        w.add(vec![Record::Positive(vec![10.into(), "x".into()])]);
+        w.swap();
+        assert_eq!(keys(KeyRange::from_bounds(vec![9.into()]..)), vec![9, 10]);
+    }
+
+    #[test]
+    fn partial_range_lookups() {
+        let (r, mut w) = new_partial(2, &[0], |_| true, |_| true);
+        w.swap();
+
+        let range = KeyRange::from_bounds(vec![3.into()]..vec![6.into()]);
+        assert_eq!(r.try_find_range_and(&range, |rs| rs.len()), Ok(None));
+
+        let key: Vec<DataType> = vec![4.into()];
+        w.mut_with_key(&key[..]).mark_filled();
+        w.add(vec![Record::Positive(vec![4.into(), "x".into()])]);
+        w.mark_range_filled(range.clone());
+        w.swap();
+        assert_eq!(
+            r.try_find_range_and(&range, |rs| rs.len()),
+            Ok(Some(vec![1]))
+        );
+
+        // a sub-range of a filled range is also filled
+        let sub = KeyRange::from_bounds(vec![4.into()]..=vec![5.into()]);
+        assert_eq!(r.try_find_range_and(&sub, |rs| rs.len()), Ok(Some(vec![1])));
+
+        // evicting a key in the range means the range is no longer filled
+        w.mut_with_key(&key[..]).mark_hole();
+        w.swap();
+        assert_eq!(r.try_find_range_and(&range, |rs| rs.len()), Ok(None));
+    }
+
+    #[test]
+    fn evicts_least_recently_read() {
+        use crate::eviction::Lru;
+
+        let (r, mut w) = new_partial(2, &[0], |_| true, |_| true);
+        w.swap();
+        for i in 0..3 {
+            let key: Vec<DataType> = vec![i.into()];
+            w.mut_with_key(&key[..]).mark_filled();
+            w.add(vec![Record::Positive(vec![i.into(), "x".into()])]);
+        }
+        w.swap();
+
+        // key 1 is the only one that hasn't been read since it was filled
+        // (only a sample of reads are recorded, so read the others often enough to be noticed)
+        for &k in &[0, 2] {
+            for _ in 0..READ_SAMPLE {
+                assert_eq!(
+                    r.try_find_and(&[k.into()], |rs| rs.len()).unwrap().0,
+                    Some(1)
+                );
+            }
+        }
+
+        assert!(w.evict_cold_keys(&Lru, &mut rand::thread_rng(), 1) > 0);
+        w.swap();
+        assert_eq!(r.try_find_and(&[1.into()], |rs| rs.len()).unwrap().0, None);
+        assert_eq!(
+            r.try_find_and(&[0.into()], |rs| rs.len()).unwrap().0,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn subscriptions_see_changes() {
+        let a = vec![1.into(), "a".into()];
+        let b = vec![1.into(), "b".into()];
+        let c = vec![2.into(), "c".into()];
+
+        let (r, mut w) = new(2, &[0]);
+        w.add(vec![Record::Positive(a.clone())]);
+        w.swap();
+
+        let (id, misses) = r.subscribe(vec![vec![1.into()]]).unwrap();
+        assert!(misses.is_empty());
+
+        // subscribers start out with the current rows, and also see rows that were added before
+        // they subscribed, but swapped in after.
+        w.add(vec![
+            Record::Positive(b.clone()),
+            Record::Positive(c.clone()),
+        ]);
+        w.swap();
+        match r.poll_subscription(id) {
+            SubscriptionPoll::Ready(mut changes) => {
+                changes.sort();
+                assert_eq!(
+                    changes,
+                    vec![Record::Positive(a.clone()), Record::Positive(b.clone())]
+                );
+            }
+            p => panic!("{:?}", p),
+        }
+
+        let mut rx = match r.poll_subscription(id) {
+            SubscriptionPoll::Pending(rx) => rx,
+            p => panic!("{:?}", p),
+        };
+        w.add(vec![
+            Record::Negative(a.clone()),
+            Record::Negative(c.clone()),
+        ]);
+        w.swap();
+        assert_eq!(rx.try_recv().unwrap(), vec![Record::Negative(a.clone())]);
+
+        r.unsubscribe(id);
+        assert!(matches!(r.poll_subscription(id), SubscriptionPoll::Closed));
+        assert!(!w.has_subscriptions());
+    }
+
+    #[test]
+    fn subscriptions_lag_when_falling_behind() {
+        let (r, mut w) = new(2, &[0]);
+        assert!(!w.has_subscriptions());
+        let (id, _) = r.subscribe(vec![vec![1.into()]]).unwrap();
+        assert!(w.has_subscriptions());
+
+        // changes pile up as long as the subscriber doesn't pick them up
+        let n = subscriptions::MAX_QUEUED as i32 + 1;
+        w.add((0..n).map(|i| Record::Positive(vec![1.into(), i.into()])));
+        w.swap();
+
+        assert!(matches!(r.poll_subscription(id), SubscriptionPoll::Lagged));
+        assert!(matches!(r.poll_subscription(id), SubscriptionPoll::Closed));
+        assert!(!w.has_subscriptions());
+    }
 }
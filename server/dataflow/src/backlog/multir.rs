@@ -1,6 +1,7 @@
 use ahash::RandomState;
 use common::DataType;
 use evmap;
+use std::collections::BTreeSet;
 
 #[derive(Clone, Debug)]
 pub(super) enum Handle {
@@ -18,6 +19,30 @@ impl Handle {
         }
     }
 
+    /// Returns true once the writer has published the map for the first time.
+    pub(super) fn is_ready(&self) -> bool {
+        match *self {
+            Handle::Single(ref h) => h.read().is_some(),
+            Handle::Double(ref h) => h.read().is_some(),
+            Handle::Many(ref h) => h.read().is_some(),
+        }
+    }
+
+    /// Returns all keys in the map, or `None` if the writer has not published it yet.
+    pub(super) fn keys(&self) -> Option<BTreeSet<Vec<DataType>>> {
+        match *self {
+            Handle::Single(ref h) => h
+                .read()
+                .map(|m| m.iter().map(|(k, _)| vec![k.clone()]).collect()),
+            Handle::Double(ref h) => h.read().map(|m| {
+                m.iter()
+                    .map(|(k, _)| vec![k.0.clone(), k.1.clone()])
+                    .collect()
+            }),
+            Handle::Many(ref h) => h.read().map(|m| m.iter().map(|(k, _)| k.clone()).collect()),
+        }
+    }
+
     pub(super) fn meta_get_and<F, T>(&self, key: &[DataType], then: F) -> Option<(Option<T>, i64)>
     where
         F: FnOnce(&evmap::Values<Vec<DataType>, RandomState>) -> T,
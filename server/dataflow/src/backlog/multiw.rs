@@ -2,6 +2,7 @@ use super::{key_to_double, key_to_single, Key};
 use crate::prelude::*;
 use ahash::RandomState;
 use evmap;
+use std::collections::BTreeSet;
 
 pub(super) enum Handle {
     Single(evmap::WriteHandle<DataType, Vec<DataType>, i64, RandomState>),
@@ -46,18 +47,37 @@ impl Handle {
         }
     }
 
-    /// Evict `count` randomly selected keys from state and return them along with the number of
-    /// bytes freed.
+    /// Evict `count` randomly selected keys from state, and call `f` with each evicted key and
+    /// its records.
     pub fn empty_random_for_each(
         &mut self,
         rng: &mut impl rand::Rng,
         n: usize,
-        mut f: impl FnMut(&evmap::Values<Vec<DataType>, RandomState>),
+        mut f: impl FnMut(Vec<DataType>, &evmap::Values<Vec<DataType>, RandomState>),
     ) {
         match *self {
-            Handle::Single(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
-            Handle::Double(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
-            Handle::Many(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
+            Handle::Single(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|r| f(vec![r.0.clone()], r.1)),
+            Handle::Double(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|r| f(vec![(r.0).0.clone(), (r.0).1.clone()], r.1)),
+            Handle::Many(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.0.clone(), r.1)),
+        }
+    }
+
+    /// Returns all keys in the map, or `None` if the writer has not published it yet.
+    pub fn keys(&self) -> Option<BTreeSet<Vec<DataType>>> {
+        match *self {
+            Handle::Single(ref h) => h
+                .read()
+                .map(|m| m.iter().map(|(k, _)| vec![k.clone()]).collect()),
+            Handle::Double(ref h) => h.read().map(|m| {
+                m.iter()
+                    .map(|(k, _)| vec![k.0.clone(), k.1.clone()])
+                    .collect()
+            }),
+            Handle::Many(ref h) => h.read().map(|m| m.iter().map(|(k, _)| k.clone()).collect()),
         }
     }
 
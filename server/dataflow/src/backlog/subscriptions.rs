@@ -0,0 +1,233 @@
+use crate::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time;
+use tokio::sync::oneshot;
+
+/// Subscriptions that have gone this long without being polled are assumed to be abandoned.
+const IDLE_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+/// Subscriptions with more than this many changes waiting to be picked up have fallen too far
+/// behind, and are dropped.
+pub(super) const MAX_QUEUED: usize = 1 << 16;
+
+/// The result of polling a subscription for changes.
+#[derive(Debug)]
+pub enum SubscriptionPoll {
+    /// There are changes waiting to be picked up.
+    Ready(Vec<Record>),
+    /// There are no changes yet. The receiver resolves once there are.
+    ///
+    /// If the subscription is polled again before then, the receiver resolves to no changes.
+    Pending(oneshot::Receiver<Vec<Record>>),
+    /// The subscription fell too far behind the reader, and was dropped. The subscriber has to
+    /// subscribe again to pick up the current rows.
+    Lagged,
+    /// The subscription (or the reader it was made on) no longer exists.
+    Closed,
+}
+
+#[derive(Debug)]
+struct Subscription {
+    keys: HashSet<Vec<DataType>>,
+    /// The rows for each key as of when the subscription was made.
+    ///
+    /// Changes made between the last swap and the subscription are not (all) captured as they are
+    /// added to the reader, so the first changes after subscribing are found by comparing this
+    /// with the reader's contents at the next swap instead.
+    snapshot: Option<HashMap<Vec<DataType>, Vec<Vec<DataType>>>>,
+    queued: Vec<Record>,
+    waiter: Option<oneshot::Sender<Vec<Record>>>,
+    last_polled: time::Instant,
+}
+
+/// Change subscriptions on the keys of a single reader.
+///
+/// Shared by the reader's read and write handles. The writer holds the lock while it swaps, so
+/// any state a subscriber observes while holding the lock is state the writer has already
+/// delivered changes for.
+#[derive(Debug, Default)]
+pub(super) struct Subscriptions {
+    next_id: u64,
+    subs: HashMap<u64, Subscription>,
+    /// The number of subscriptions to each key.
+    keys: HashMap<Vec<DataType>, usize>,
+    /// Subscriptions that were dropped for falling behind, and have not been told yet.
+    lagged: HashSet<u64>,
+    /// The number of subscriptions, so the writer can tell when there are none without locking.
+    count: Arc<AtomicUsize>,
+    closed: bool,
+}
+
+/// The changes that turn `before` into `after`, where both are multisets of rows.
+fn diff(before: Vec<Vec<DataType>>, after: Vec<Vec<DataType>>, into: &mut Vec<Record>) {
+    let mut counts: HashMap<Vec<DataType>, isize> = HashMap::new();
+    for row in before {
+        *counts.entry(row).or_insert(0) -= 1;
+    }
+    for row in after {
+        *counts.entry(row).or_insert(0) += 1;
+    }
+    for (row, n) in counts {
+        for _ in 0..n.abs() {
+            into.push((row.clone(), n > 0).into());
+        }
+    }
+}
+
+impl Subscriptions {
+    pub(super) fn is_empty(&self) -> bool {
+        self.subs.is_empty()
+    }
+
+    /// The number of subscriptions, kept up to date as subscriptions come and go.
+    pub(super) fn count(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.count)
+    }
+
+    fn update_count(&self) {
+        self.count.store(self.subs.len(), Ordering::Release);
+    }
+
+    pub(super) fn is_subscribed(&self, key: &[DataType]) -> bool {
+        !self.keys.is_empty() && self.keys.contains_key(key)
+    }
+
+    /// Register a subscription to the keys in `snapshot`, given the rows each key currently has.
+    ///
+    /// The current rows are the first changes the subscriber sees.
+    pub(super) fn subscribe(
+        &mut self,
+        snapshot: HashMap<Vec<DataType>, Vec<Vec<DataType>>>,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut keys = HashSet::with_capacity(snapshot.len());
+        let mut queued = Vec::new();
+        for (key, rows) in &snapshot {
+            *self.keys.entry(key.clone()).or_insert(0) += 1;
+            keys.insert(key.clone());
+            queued.extend(rows.iter().cloned().map(Record::Positive));
+        }
+
+        self.subs.insert(
+            id,
+            Subscription {
+                keys,
+                snapshot: Some(snapshot),
+                queued,
+                waiter: None,
+                last_polled: time::Instant::now(),
+            },
+        );
+        self.update_count();
+        id
+    }
+
+    pub(super) fn poll(&mut self, id: u64) -> SubscriptionPoll {
+        if self.closed {
+            return SubscriptionPoll::Closed;
+        }
+        if self.lagged.remove(&id) {
+            return SubscriptionPoll::Lagged;
+        }
+        let sub = match self.subs.get_mut(&id) {
+            Some(sub) => sub,
+            None => return SubscriptionPoll::Closed,
+        };
+
+        sub.last_polled = time::Instant::now();
+        if !sub.queued.is_empty() {
+            return SubscriptionPoll::Ready(mem::take(&mut sub.queued));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        if let Some(previous) = sub.waiter.replace(tx) {
+            // only one poll can be waiting at a time
+            let _ = previous.send(Vec::new());
+        }
+        SubscriptionPoll::Pending(rx)
+    }
+
+    pub(super) fn unsubscribe(&mut self, id: u64) {
+        self.lagged.remove(&id);
+        if let Some(sub) = self.subs.remove(&id) {
+            self.forget_keys(sub.keys);
+            self.update_count();
+        }
+    }
+
+    fn forget_keys(&mut self, keys: HashSet<Vec<DataType>>) {
+        for key in keys {
+            if let Some(n) = self.keys.get_mut(&key) {
+                *n -= 1;
+                if *n == 0 {
+                    self.keys.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Hand the changes made to the reader in one swap to the subscriptions they concern.
+    ///
+    /// `changes` holds every change to a subscribed key since the last swap, and `current` gives
+    /// the rows a key has after the swap.
+    pub(super) fn deliver<F>(&mut self, changes: &[(Vec<DataType>, Record)], mut current: F)
+    where
+        F: FnMut(&[DataType]) -> Vec<Vec<DataType>>,
+    {
+        let now = time::Instant::now();
+        let mut abandoned = Vec::new();
+        let mut lagged = Vec::new();
+        for (&id, sub) in &mut self.subs {
+            if let Some(snapshot) = sub.snapshot.take() {
+                for (key, before) in snapshot {
+                    diff(before, current(&key[..]), &mut sub.queued);
+                }
+            } else {
+                for (key, change) in changes {
+                    if sub.keys.contains(key) {
+                        sub.queued.push(change.clone());
+                    }
+                }
+            }
+
+            if !sub.queued.is_empty() {
+                if let Some(waiter) = sub.waiter.take() {
+                    if let Err(queued) = waiter.send(mem::take(&mut sub.queued)) {
+                        // the poll went away before we got to it
+                        sub.queued = queued;
+                    }
+                }
+            }
+
+            if sub.waiter.is_none() && now.duration_since(sub.last_polled) > IDLE_TIMEOUT {
+                abandoned.push(id);
+            } else if sub.queued.len() > MAX_QUEUED {
+                // holding on to changes for a subscriber that can't keep up would let it use up
+                // unbounded memory. it is cheaper for it to start over from the current rows.
+                lagged.push(id);
+            }
+        }
+
+        for id in abandoned {
+            self.unsubscribe(id);
+        }
+        for id in lagged {
+            self.unsubscribe(id);
+            self.lagged.insert(id);
+        }
+    }
+
+    /// Close all subscriptions, as the reader is going away.
+    pub(super) fn close(&mut self) {
+        self.closed = true;
+        self.subs.clear();
+        self.keys.clear();
+        self.lagged.clear();
+        self.update_count();
+    }
+}
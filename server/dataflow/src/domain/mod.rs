@@ -1,7 +1,6 @@
 use petgraph::graph::NodeIndex;
 use std::borrow::Cow;
 use std::cell;
-use std::cmp;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem;
 use std::net::SocketAddr;
@@ -9,6 +8,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time;
 
+use crate::eviction::{EvictionKind, EvictionPolicy, MemoryBudget};
 use crate::group_commit::GroupCommitQueueSet;
 use crate::payload::{ControlReplyPacket, ReplayPieceContext, SourceSelection};
 use crate::prelude::*;
@@ -16,6 +16,7 @@ use ahash::RandomState;
 use futures_util::{future::FutureExt, stream::StreamExt};
 use noria::channel::{self, TcpSender};
 pub use noria::internal::DomainIndex as Index;
+use noria::internal::LocalOrNot;
 use slog::Logger;
 use stream_cancel::Valve;
 
@@ -41,10 +42,25 @@ pub enum ProcessResult {
 pub struct Config {
     pub concurrent_replays: usize,
     pub replay_batch_timeout: time::Duration,
+    /// How long readers hold back writes for a transaction before giving up on the rest of its
+    /// writes ever arriving.
+    pub transaction_timeout: time::Duration,
+    /// How to pick the nodes and keys to evict from when asked to free memory.
+    pub eviction_policy: EvictionKind,
 }
 
 const BATCH_SIZE: usize = 256;
 
+/// A transaction whose writes have yet to all make it to a domain.
+struct PendingTransaction {
+    /// Where the writes have yet to arrive from.
+    awaiting: HashSet<Option<ReplicaAddr>>,
+    /// The domain's readers that the writes reach, which hold back writes until they arrive.
+    readers: HashSet<LocalNodeIndex>,
+    /// When the domain stops waiting for the writes.
+    deadline: time::Instant,
+}
+
 #[derive(Debug)]
 enum DomainMode {
     Forwarding,
@@ -184,14 +200,19 @@ impl DomainBuilder {
 
             buffered_replay_requests: Default::default(),
             replay_batch_timeout: self.config.replay_batch_timeout,
+            transaction_timeout: self.config.transaction_timeout,
             timed_purges: Default::default(),
 
             concurrent_replays: 0,
             max_concurrent_replays: self.config.concurrent_replays,
+            eviction_policy: self.config.eviction_policy.policy(),
             replay_request_queue: Default::default(),
             delayed_for_self: Default::default(),
 
             group_commit_queues,
+            paused_inputs: None,
+            copied_base_rows: Default::default(),
+            pending_transactions: Default::default(),
 
             state_size,
             total_time: Timer::new(),
@@ -239,6 +260,7 @@ pub struct Domain {
 
     concurrent_replays: usize,
     max_concurrent_replays: usize,
+    eviction_policy: &'static dyn EvictionPolicy,
     replay_request_queue: VecDeque<(Tag, Vec<Vec<DataType>>)>,
 
     shutdown_valve: Valve,
@@ -251,6 +273,16 @@ pub struct Domain {
     delayed_for_self: VecDeque<Box<Packet>>,
 
     group_commit_queues: GroupCommitQueueSet,
+    /// Inputs that arrived while inputs were paused, if they are.
+    #[allow(clippy::vec_box)]
+    paused_inputs: Option<Vec<Box<Packet>>>,
+    /// Copies of the rows of base nodes that are being read out a chunk at a time.
+    copied_base_rows: HashMap<LocalNodeIndex, std::vec::IntoIter<Vec<DataType>>>,
+    /// Transactions whose writes have yet to make it here.
+    ///
+    /// Readers don't expose new writes while any of them reach the reader.
+    pending_transactions: HashMap<u64, PendingTransaction>,
+    transaction_timeout: time::Duration,
 
     state_size: Arc<AtomicUsize>,
     total_time: Timer<SimpleTracker, RealTime>,
@@ -266,6 +298,16 @@ pub struct Domain {
 }
 
 impl Domain {
+    /// The name that the durable state of base node `node` in this domain is stored under.
+    fn base_name(&self, node: LocalNodeIndex) -> String {
+        format!(
+            "{}-{}-{}",
+            self.persistence_parameters.log_prefix,
+            self.nodes[node].borrow().name(),
+            self.shard.unwrap_or(0),
+        )
+    }
+
     fn find_tags_and_replay(
         &mut self,
         miss_keys: Vec<Vec<DataType>>,
@@ -329,6 +371,71 @@ impl Domain {
         }
     }
 
+    /// Request a replay of all keys in `range` into `miss_in`.
+    ///
+    /// Unlike keyed replays, range replays do not count towards `max_concurrent_replays`, and
+    /// the domain does not remember to retry them. Whoever asked for the range (ultimately, a
+    /// reader) will ask again if the range is not filled in time.
+    ///
+    /// Returns false if the range cannot be replayed, because the replay path goes through a
+    /// union or a sharded path.
+    fn find_tags_and_replay_range(
+        &mut self,
+        range: KeyRange,
+        miss_columns: &[usize],
+        miss_in: LocalNodeIndex,
+    ) -> bool {
+        let tags = self
+            .replay_paths_by_dst
+            .get(miss_in)
+            .and_then(|candidates| candidates.get(miss_columns))
+            .cloned()
+            .unwrap_or_default();
+
+        if tags.is_empty() {
+            unreachable!(format!(
+                "no tag found to fill missing range {:?} in {}.{:?}",
+                range, miss_in, miss_columns
+            ));
+        }
+
+        // unions (including shard mergers) buffer replay pieces until they have seen a piece for
+        // each key from each of their ancestors. the pieces for a range will generally contain
+        // different keys from each ancestor, so that buffering would never complete.
+        let sharded = match self.replay_paths[&tags[0]].trigger {
+            TriggerEndpoint::End { ref options, .. } => options.len() != 1,
+            _ => false,
+        };
+        if tags.len() != 1 || sharded {
+            warn!(self.log, "range replays through unions or sharded paths are not supported";
+                  "node" => ?miss_in,
+                  "range" => ?range);
+            return false;
+        }
+
+        let tag = tags[0];
+        let request = Box::new(Packet::RequestRangeReplay {
+            tag,
+            range,
+            requesting_shard: self.shard.unwrap_or(0),
+        });
+        match self.replay_paths.get_mut(&tag).unwrap().trigger {
+            TriggerEndpoint::Local(..) => {
+                // see find_tags_and_replay for why we don't just seed the replay directly
+                self.delayed_for_self.push_back(request);
+            }
+            TriggerEndpoint::End {
+                ref mut options, ..
+            } => {
+                if options[0].send(request).is_err() {
+                    // we're shutting down -- it's fine.
+                }
+            }
+            _ => unreachable!("asked to replay along non-existing path"),
+        }
+        true
+    }
+
     fn on_replay_miss(
         &mut self,
         miss_in: LocalNodeIndex,
@@ -579,18 +686,23 @@ impl Domain {
             return;
         }
 
+        // readers must not expose part of a transaction
+        let swap = !self
+            .pending_transactions
+            .values()
+            .any(|t| t.readers.contains(&me));
         let (mut m, evictions) = {
             let mut n = self.nodes[me].borrow_mut();
             self.process_times.start(me);
             self.process_ptimes.start(me);
             let mut m = Some(m);
-            let (misses, _, captured) = n.process(
+            let (mut misses, _, captured) = n.process(
                 &mut m,
                 None,
                 &mut self.state,
                 &self.nodes,
                 self.shard,
-                true,
+                swap,
                 None,
                 executor,
                 &self.log,
@@ -605,8 +717,11 @@ impl Domain {
             }
 
             // normally, we ignore misses during regular forwarding.
-            // however, we have to be a little careful in the case of joins.
-            let evictions = if n.is_internal() && n.is_join() && !misses.is_empty() {
+            // however, we have to be a little careful in the case of joins (and other operators
+            // that must evict on a miss). misses in the node's own state are fine, since anything
+            // downstream of a hole has a hole too.
+            misses.retain(|miss| miss.on != me);
+            let evictions = if n.is_internal() && n.must_evict_on_miss() && !misses.is_empty() {
                 // there are two possible cases here:
                 //
                 //  - this is a write that will hit a hole in every downstream materialization.
@@ -750,6 +865,100 @@ impl Domain {
         }
     }
 
+    /// Note that the writes of transaction `id` have all made it here from `from`.
+    ///
+    /// Once they have made it here from everywhere they are expected from, the domain tells its
+    /// children, and lets its readers expose the writes.
+    fn transaction_arrived(
+        &mut self,
+        id: u64,
+        from: Option<ReplicaAddr>,
+        executor: &mut dyn Executor,
+    ) {
+        match self.pending_transactions.get_mut(&id) {
+            Some(t) => {
+                t.awaiting.remove(&from);
+                if !t.awaiting.is_empty() {
+                    return;
+                }
+            }
+            // none of the transaction's writes reach us through `from`
+            None => return,
+        }
+        self.end_transaction(id);
+
+        let me = (self.index, self.shard.unwrap_or(0));
+        let children: HashSet<_> = self
+            .nodes
+            .values()
+            .flat_map(|n| n.borrow().child_domains())
+            .collect();
+        for child in children {
+            executor.send(child, Box::new(Packet::TransactionArrived { id, from: me }));
+        }
+    }
+
+    /// Apply the writes of transaction `id` to this domain's base nodes.
+    fn commit_transaction(&mut self, id: u64, inputs: Vec<Input>, executor: &mut dyn Executor) {
+        for input in inputs {
+            self.dispatch(
+                Box::new(Packet::Input {
+                    inner: LocalOrNot::new(input),
+                    src: None,
+                    senders: Vec::new(),
+                }),
+                executor,
+            );
+        }
+        self.transaction_arrived(id, None, executor);
+    }
+
+    /// Forget about transaction `id`, and have the readers it reaches expose the writes they held
+    /// back for it, unless another pending transaction also reaches them.
+    fn end_transaction(&mut self, id: u64) {
+        let t = match self.pending_transactions.remove(&id) {
+            Some(t) => t,
+            None => return,
+        };
+        for ni in t.readers {
+            if self
+                .pending_transactions
+                .values()
+                .any(|t| t.readers.contains(&ni))
+            {
+                continue;
+            }
+            let mut n = self.nodes[ni].borrow_mut();
+            if n.is_dropped() {
+                continue;
+            }
+            n.with_reader_mut(|r| {
+                if let Some(w) = r.writer_mut() {
+                    w.swap();
+                }
+            })
+            .unwrap();
+        }
+    }
+
+    /// Stop waiting for the writes of transactions that have been pending for too long.
+    ///
+    /// If the controller fails part-way through a transaction, the rest of its writes never
+    /// arrive, and the readers would otherwise never expose another write.
+    fn expire_transactions(&mut self) {
+        let now = time::Instant::now();
+        let expired: Vec<_> = self
+            .pending_transactions
+            .iter()
+            .filter(|&(_, t)| t.deadline <= now)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            warn!(self.log, "giving up on the writes of pending transaction"; "id" => id);
+            self.end_transaction(id);
+        }
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn handle(&mut self, m: Box<Packet>, executor: &mut dyn Executor, top: bool) {
         if self.wait_time.is_running() {
@@ -757,6 +966,16 @@ impl Domain {
         }
 
         match *m {
+            Packet::Input { .. } if self.paused_inputs.is_some() => {
+                self.paused_inputs.as_mut().unwrap().push(m);
+            }
+            Packet::CommitTransaction { .. } if self.paused_inputs.is_some() => {
+                // the controller can't wait for inputs to resume, so ack now and commit then
+                self.paused_inputs.as_mut().unwrap().push(m);
+                self.control_reply_tx
+                    .send(ControlReplyPacket::ack())
+                    .unwrap();
+            }
             Packet::Message { .. } | Packet::Input { .. } => {
                 // WO for https://github.com/rust-lang/rfcs/issues/1403
                 self.total_forward_time.start();
@@ -790,8 +1009,18 @@ impl Domain {
                     }
                     Packet::RemoveNodes { nodes } => {
                         for &node in &nodes {
-                            self.nodes[node].borrow_mut().remove();
+                            let mut n = self.nodes[node].borrow_mut();
+                            if n.is_reader() {
+                                // stop handing out the reader's state so that it can be freed
+                                let shard = *self.shard.as_ref().unwrap_or(&0);
+                                self.readers
+                                    .lock()
+                                    .unwrap()
+                                    .remove(&(n.global_addr(), shard));
+                            }
+                            n.remove();
                             self.state.remove(node);
+                            self.ingress_inject.remove(node);
                             trace!(self.log, "node removed"; "local" => node.id());
                         }
 
@@ -849,12 +1078,146 @@ impl Domain {
                             }
                         });
                     }
+                    Packet::RemoveEgressTx { node, dst } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.with_egress_mut(move |e| e.remove_tx(dst));
+                    }
                     Packet::UpdateSharder { node, new_txs } => {
                         let mut n = self.nodes[node].borrow_mut();
                         n.with_sharder_mut(move |s| {
                             s.add_sharded_child(new_txs.0, new_txs.1);
                         });
                     }
+                    Packet::UpdateMemoryBudget { node, budget } => {
+                        self.nodes[node].borrow_mut().memory_budget = budget;
+                    }
+                    Packet::EnableChanges { node } => {
+                        let name = self.base_name(node);
+                        let mut n = self.nodes[node].borrow_mut();
+                        let base = n
+                            .get_base_mut()
+                            .expect("asked to keep changes of non-base node");
+                        base.keep_changes();
+                        base.open_changes(&name, &self.persistence_parameters);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::ReadChanges { node, from, limit } => {
+                        let shard = self.shard.unwrap_or(0);
+                        let changes = self.nodes[node]
+                            .borrow()
+                            .get_base()
+                            .expect("asked for changes from non-base node")
+                            .read_changes(from, limit)
+                            .map(|(changes, next)| {
+                                let changes = changes
+                                    .into_iter()
+                                    .map(|(seq, rs)| noria::Change {
+                                        shard,
+                                        seq,
+                                        records: rs.into_iter().map(Into::into).collect(),
+                                    })
+                                    .collect();
+                                (changes, next)
+                            });
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Changes(shard, changes))
+                            .unwrap();
+                    }
+                    Packet::PauseInputs => {
+                        if self.paused_inputs.is_none() {
+                            self.paused_inputs = Some(Vec::new());
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::ResumeInputs => {
+                        for m in self.paused_inputs.take().unwrap_or_default() {
+                            match *m {
+                                // already acked when it was queued
+                                Packet::CommitTransaction { id, inputs } => {
+                                    self.commit_transaction(id, inputs, executor)
+                                }
+                                _ => self.handle(m, executor, false),
+                            }
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::BeginTransaction {
+                        id,
+                        awaiting,
+                        readers,
+                    } => {
+                        let deadline = time::Instant::now() + self.transaction_timeout;
+                        self.pending_transactions.insert(
+                            id,
+                            PendingTransaction {
+                                awaiting,
+                                readers,
+                                deadline,
+                            },
+                        );
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::CommitTransaction { id, inputs } => {
+                        self.commit_transaction(id, inputs, executor);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::TransactionArrived { id, from } => {
+                        self.transaction_arrived(id, Some(from), executor);
+                    }
+                    Packet::AbortTransaction { id } => {
+                        self.end_transaction(id);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::CopyBaseRows { node } => {
+                        let n = self.nodes[node].borrow();
+                        let base = n.get_base().expect("asked for rows of non-base node");
+                        let copied = match self.state.get(node) {
+                            Some(s) => {
+                                let rows: Vec<_> = s
+                                    .cloned_records()
+                                    .into_iter()
+                                    .map(|mut r| {
+                                        // rows added before columns were added lack those columns
+                                        base.fix(&mut r);
+                                        r
+                                    })
+                                    .collect();
+                                self.copied_base_rows.insert(node, rows.into_iter());
+                                Ok(Vec::new())
+                            }
+                            None => Err(format!("base node {:?} is not materialized", node)),
+                        };
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::BaseRows(copied))
+                            .unwrap();
+                    }
+                    Packet::ReadBaseRows { node, limit } => {
+                        let rows = match self.copied_base_rows.get_mut(&node) {
+                            Some(copy) => {
+                                let rows: Vec<_> = copy.take(limit).collect();
+                                if rows.is_empty() {
+                                    self.copied_base_rows.remove(&node);
+                                }
+                                Ok(rows)
+                            }
+                            None => Err(format!("no copy of the rows of base node {:?}", node)),
+                        };
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::BaseRows(rows))
+                            .unwrap();
+                    }
                     Packet::StateSizeProbe { node } => {
                         let row_count = self.state.get(node).map(|r| r.rows()).unwrap_or(0);
                         let mem_size = self.state.get(node).map(|s| s.deep_size_of()).unwrap_or(0);
@@ -895,10 +1258,8 @@ impl Domain {
                                 trigger_domain: (trigger_domain, shards),
                             } => {
                                 use crate::backlog;
-                                let k = key.clone(); // ugh
                                 let txs = (0..shards)
                                     .map(|shard| {
-                                        let key = key.clone();
                                         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
                                         let sender = self
                                             .channel_coordinator
@@ -910,13 +1271,6 @@ impl Domain {
                                         tokio::spawn(
                                             self.shutdown_valve
                                                 .wrap(rx)
-                                                .map(move |misses| {
-                                                    Box::new(Packet::RequestReaderReplay {
-                                                        keys: misses,
-                                                        cols: key.clone(),
-                                                        node,
-                                                    })
-                                                })
                                                 .map(Ok)
                                                 .forward(sender)
                                                 .map(|r| {
@@ -932,18 +1286,31 @@ impl Domain {
                                         tx
                                     })
                                     .collect::<Vec<_>>();
+
+                                // range lookups go to every shard of the reader, and each shard
+                                // fills the range from its own replay path.
+                                let range_tx = txs[self.shard.unwrap_or(0)].clone();
+                                let range_key = key.clone();
+                                let k = key.clone(); // ugh
                                 let (r_part, w_part) = backlog::new_partial(
                                     cols,
                                     &k[..],
                                     move |misses: &mut dyn Iterator<Item = &[DataType]>| {
                                         let n = txs.len();
+                                        let request = |keys: Vec<Vec<DataType>>| {
+                                            Box::new(Packet::RequestReaderReplay {
+                                                keys,
+                                                cols: key.clone(),
+                                                node,
+                                            })
+                                        };
                                         if n == 1 {
                                             use std::iter::FromIterator;
                                             let misses = Vec::from_iter(misses.map(Vec::from));
                                             if misses.is_empty() {
                                                 return true;
                                             }
-                                            txs[0].send(misses).is_ok()
+                                            txs[0].send(request(misses)).is_ok()
                                         } else {
                                             // TODO: compound reader
                                             let mut per_shard = HashMap::new();
@@ -958,11 +1325,20 @@ impl Domain {
                                             if per_shard.is_empty() {
                                                 return true;
                                             }
-                                            per_shard
-                                                .into_iter()
-                                                .all(|(shard, keys)| txs[shard].send(keys).is_ok())
+                                            per_shard.into_iter().all(|(shard, keys)| {
+                                                txs[shard].send(request(keys)).is_ok()
+                                            })
                                         }
                                     },
+                                    move |range: &KeyRange| {
+                                        range_tx
+                                            .send(Box::new(Packet::RequestReaderRangeReplay {
+                                                range: range.clone(),
+                                                cols: range_key.clone(),
+                                                node,
+                                            }))
+                                            .is_ok()
+                                    },
                                 );
 
                                 let mut n = self.nodes[node].borrow_mut();
@@ -1164,6 +1540,48 @@ impl Domain {
                         }
                         self.total_replay_time.stop();
                     }
+                    Packet::RequestReaderRangeReplay { range, cols, node } => {
+                        self.total_replay_time.start();
+                        // the range may have been filled since the request was sent
+                        let filled = self.nodes[node]
+                            .borrow_mut()
+                            .with_reader_mut(|r| {
+                                let w = r
+                                    .writer_mut()
+                                    .expect("reader replay requested for non-materialized reader");
+                                // ensure that all writes have been applied
+                                w.swap();
+                                w.is_range_filled(&range)
+                            })
+                            .expect("reader replay requested for non-reader node");
+
+                        if !filled && !self.find_tags_and_replay_range(range, &cols[..], node) {
+                            // tell the reader, so that it fails range lookups rather than waiting
+                            // for a replay that will never come.
+                            self.nodes[node]
+                                .borrow_mut()
+                                .with_reader_mut(|r| {
+                                    r.writer_mut().unwrap().mark_ranges_unsupported();
+                                })
+                                .unwrap();
+                        }
+                        self.total_replay_time.stop();
+                    }
+                    Packet::RequestRangeReplay {
+                        tag,
+                        range,
+                        requesting_shard,
+                    } => {
+                        trace!(
+                            self.log,
+                           "got range replay request";
+                           "tag" => tag,
+                           "range" => ?range
+                        );
+                        self.total_replay_time.start();
+                        self.seed_range(tag, range, requesting_shard, executor);
+                        self.total_replay_time.stop();
+                    }
                     Packet::StartReplay { tag, from } => {
                         use std::thread;
                         assert_eq!(self.replay_paths[&tag].source, Some(from));
@@ -1308,24 +1726,17 @@ impl Domain {
                         self.nodes[node].borrow_mut().purge = purge;
 
                         if !index.is_empty() {
+                            let base_name = || self.base_name(node);
                             let mut s: Box<dyn State> = {
                                 let n = self.nodes[node].borrow();
                                 let params = &self.persistence_parameters;
                                 match (n.get_base(), &params.mode) {
                                     (Some(base), &DurabilityMode::DeleteOnExit)
-                                    | (Some(base), &DurabilityMode::Permanent) => {
-                                        let base_name = format!(
-                                            "{}-{}-{}",
-                                            params.log_prefix,
-                                            n.name(),
-                                            self.shard.unwrap_or(0),
-                                        );
-
-                                        Box::new(PersistentState::new(
-                                            base_name,
-                                            base.key(),
-                                            &params,
-                                        ))
+                                    | (Some(base), &DurabilityMode::Permanent) => Box::new(
+                                        PersistentState::new(base_name(), base.key(), &params),
+                                    ),
+                                    (Some(_), &DurabilityMode::MemoryWithLog) => {
+                                        Box::new(LoggedState::new(base_name(), &params))
                                     }
                                     _ => Box::new(MemoryState::default()),
                                 }
@@ -1334,6 +1745,14 @@ impl Domain {
                                 s.add_key(&idx[..], None);
                             }
                             assert!(self.state.insert(node, s).is_none());
+
+                            // pick up the base's change feed where it left off, if it keeps one
+                            if self.nodes[node].borrow().is_base() {
+                                let name = self.base_name(node);
+                                let mut n = self.nodes[node].borrow_mut();
+                                let base = n.get_base_mut().unwrap();
+                                base.open_changes(&name, &self.persistence_parameters);
+                            }
                         } else {
                             // NOTE: just because index_on is None does *not* mean we're not
                             // materialized
@@ -1392,6 +1811,14 @@ impl Domain {
                                         .map(|s| s.deep_size_of())
                                         .unwrap_or(0)
                                 };
+                                let rows = if n.is_reader() {
+                                    0
+                                } else {
+                                    self.state
+                                        .get(local_index)
+                                        .map(|s| s.rows() as u64)
+                                        .unwrap_or(0)
+                                };
 
                                 let mat_state = if !n.is_reader() {
                                     match self.state.get(local_index) {
@@ -1433,6 +1860,7 @@ impl Domain {
                                             process_time: time.unwrap(),
                                             process_ptime: ptime.unwrap(),
                                             mem_size,
+                                            rows,
                                             materialized: mat_state,
                                             probe_result,
                                         },
@@ -1607,6 +2035,7 @@ impl Domain {
                         tag,
                         context: ReplayPieceContext::Partial {
                             for_keys: keys,
+                            for_range: None,
                             unishard: single_shard, // if we are the only source, only one path
                             ignore: false,
                             requesting_shard,
@@ -1668,6 +2097,107 @@ impl Domain {
         }
     }
 
+    /// Returns true if `key` is a hole in the index on `cols` of the (partial) state of `node`.
+    fn is_hole(&self, node: LocalNodeIndex, cols: &[usize], key: &[DataType]) -> bool {
+        if let Some(state) = self.state.get(node) {
+            match state.lookup(cols, &KeyType::from(key)) {
+                LookupResult::Missing => true,
+                LookupResult::Some(_) => false,
+            }
+        } else {
+            self.nodes[node]
+                .borrow()
+                .with_reader(|r| {
+                    r.writer()
+                        .map(|w| {
+                            w.with_key(key)
+                                .try_find_and(|_| ())
+                                .map(|(rs, _)| rs.is_none())
+                                .unwrap_or(true)
+                        })
+                        .unwrap_or(false)
+                })
+                .unwrap_or(false)
+        }
+    }
+
+    fn seed_range(
+        &mut self,
+        tag: Tag,
+        range: KeyRange,
+        requesting_shard: usize,
+        ex: &mut dyn Executor,
+    ) {
+        let (source, cols, first) = match self.replay_paths[&tag] {
+            ReplayPath {
+                source: Some(source),
+                trigger: TriggerEndpoint::Start(ref cols),
+                ref path,
+                ..
+            }
+            | ReplayPath {
+                source: Some(source),
+                trigger: TriggerEndpoint::Local(ref cols),
+                ref path,
+                ..
+            } => (source, cols.clone(), path[0].node),
+            _ => unreachable!(),
+        };
+
+        let found = self
+            .state
+            .get(source)
+            .expect("migration replay path started with non-materialized node")
+            .lookup_range(&cols[..], &range);
+
+        let found = match found {
+            Some(found) => found,
+            None => {
+                // the range isn't all there in our own (partial) state, so we have to fill it in
+                // before we can respond. we don't hold on to this request; it will be retried.
+                trace!(self.log,
+                       "missed during range replay request";
+                       "tag" => tag,
+                       "range" => ?range);
+                //
+                // if the range can't be replayed into our state either, there is no way to tell
+                // the reader that is waiting for it, so it keeps asking (and we keep warning).
+                self.find_tags_and_replay_range(range, &cols[..], source);
+                return;
+            }
+        };
+
+        let mut for_keys = HashSet::with_capacity(found.len());
+        let mut rs = Vec::new();
+        for (key, rows) in found {
+            for_keys.insert(key);
+            rs.extend(
+                rows.into_iter()
+                    .map(|r| self.seed_row(source, Cow::Owned(r))),
+            );
+        }
+
+        trace!(self.log,
+               "satisfied range replay request";
+               "tag" => tag,
+               "range" => ?range,
+               "keys" => for_keys.len());
+
+        let m = Box::new(Packet::ReplayPiece {
+            link: Link::new(source, first),
+            tag,
+            context: ReplayPieceContext::Partial {
+                for_keys,
+                for_range: Some(range),
+                unishard: true, // range replays only happen along unsharded paths
+                ignore: false,
+                requesting_shard,
+            },
+            data: rs.into(),
+        });
+        self.handle_replay(m, ex);
+    }
+
     fn seed_replay(
         &mut self,
         tag: Tag,
@@ -1735,6 +2265,7 @@ impl Domain {
                         tag,
                         context: ReplayPieceContext::Partial {
                             for_keys: k,
+                            for_range: None,
                             unishard: single_shard, // if we are the only source, only one path
                             ignore: false,
                             requesting_shard,
@@ -1857,15 +2388,48 @@ impl Domain {
                         .unwrap_or(false);
                     let dst_is_target = !self.nodes[dst].borrow().is_sender();
 
+                    // the range this replay fills at its target, if any
+                    let is_range_replay = match context {
+                        ReplayPieceContext::Partial { ref for_range, .. } => for_range.is_some(),
+                        ReplayPieceContext::Regular { .. } => false,
+                    };
+                    let mut fill_range = None;
+
                     if dst_is_target {
                         // prune keys and data for keys we're not waiting for
                         if let ReplayPieceContext::Partial {
-                            ref mut for_keys, ..
+                            ref mut for_keys,
+                            ref for_range,
+                            ..
                         } = context
                         {
                             let had = for_keys.len();
                             let partial_keys = path.last().unwrap().partial_key.as_ref().unwrap();
-                            if let Some(w) = self.waiting.get(dst) {
+                            if let Some(range) = for_range {
+                                // a range replay fills every key in the range that is still a
+                                // hole. keys that are waiting for a keyed replay are left to that
+                                // replay, and since it may then fill them *after* we have
+                                // declared the range filled, we only do so if there are no such
+                                // keys in the range.
+                                let pending: HashSet<Vec<DataType>> =
+                                    if let Some(w) = self.waiting.get(dst) {
+                                        w.redos
+                                            .keys()
+                                            .filter(|(cols, _)| cols == partial_keys)
+                                            .map(|(_, k)| k.clone())
+                                            .collect()
+                                    } else if let Some(ref prev) = self.reader_triggered.get(dst) {
+                                        prev.iter().cloned().collect()
+                                    } else {
+                                        HashSet::new()
+                                    };
+                                if !pending.iter().any(|k| range.contains(k)) {
+                                    fill_range = Some(range.clone());
+                                }
+                                for_keys.retain(|k| {
+                                    !pending.contains(k) && self.is_hole(dst, partial_keys, k)
+                                });
+                            } else if let Some(w) = self.waiting.get(dst) {
                                 // discard all the keys that we aren't waiting for
                                 for_keys.retain(|k| {
                                     w.redos.contains_key(&(partial_keys.clone(), k.clone()))
@@ -1879,7 +2443,7 @@ impl Domain {
                                 return;
                             }
 
-                            if for_keys.is_empty() {
+                            if for_keys.is_empty() && for_range.is_none() {
                                 return;
                             } else if for_keys.len() != had {
                                 // discard records in data associated with the keys we weren't
@@ -2040,7 +2604,20 @@ impl Domain {
                                     })
                                     .unwrap();
                                 }
-                            } else if is_reader {
+                            } else if let Some(range) = fill_range.take() {
+                                if let Some(state) = self.state.get_mut(segment.node) {
+                                    state.mark_range_filled(range, tag);
+                                } else {
+                                    n.with_reader_mut(|r| {
+                                        if let Some(wh) = r.writer_mut() {
+                                            wh.mark_range_filled(range);
+                                        }
+                                    })
+                                    .unwrap();
+                                }
+                            }
+
+                            if misses.is_empty() && is_reader {
                                 // we filled a hole! swap the reader.
                                 n.with_reader_mut(|r| {
                                     if let Some(wh) = r.writer_mut() {
@@ -2104,9 +2681,11 @@ impl Domain {
                         //     replay count! note that it's *not* sufficient to check if the
                         //     *current* node is a target/reader, because we could miss during a
                         //     join along the path.
+                        //  4. range replays don't count towards the concurrent replay limit.
                         if backfill_keys.is_some()
                             && finished_partial == 0
                             && (dst_is_reader || dst_is_target)
+                            && !is_range_replay
                         {
                             finished_partial = backfill_keys.as_ref().unwrap().len();
                         }
@@ -2160,6 +2739,9 @@ impl Domain {
                                 ));
                             }
 
+                            // a range with keys that missed along the way isn't filled
+                            fill_range = None;
+
                             // we should only finish the replays for keys that *didn't* miss
                             backfill_keys
                                 .as_mut()
@@ -2186,10 +2768,12 @@ impl Domain {
                         }
 
                         // no more keys to replay, so we might as well terminate early
-                        if backfill_keys
-                            .as_ref()
-                            .map(|b| b.is_empty())
-                            .unwrap_or(false)
+                        // (unless this is a range replay, which may fill a range with no keys)
+                        if !is_range_replay
+                            && backfill_keys
+                                .as_ref()
+                                .map(|b| b.is_empty())
+                                .unwrap_or(false)
                         {
                             break 'outer;
                         }
@@ -2343,12 +2927,22 @@ impl Domain {
                                 ..
                             } = m.as_deref().unwrap()
                             {
-                                trace!(self.log, "dropping empty non-terminal full replay packet");
-                                // don't continue processing empty updates, *except* if this is the
-                                // last replay batch. in that case we need to send it so that the
-                                // next domain knows that we're done
-                                // TODO: we *could* skip ahead to path.last() here
-                                break;
+                                // shard mergers below a sharded shuffle need to see every piece,
+                                // empty or not, so that they know the sending shard has started
+                                // its replay.
+                                let to_merger = i + 1 < path.len()
+                                    && self.nodes[path[i + 1].node].borrow().is_shard_merger();
+                                if !to_merger {
+                                    trace!(
+                                        self.log,
+                                        "dropping empty non-terminal full replay packet"
+                                    );
+                                    // don't continue processing empty updates, *except* if this is
+                                    // the last replay batch. in that case we need to send it so
+                                    // that the next domain knows that we're done
+                                    // TODO: we *could* skip ahead to path.last() here
+                                    break;
+                                }
                             }
                         }
 
@@ -2398,12 +2992,26 @@ impl Domain {
                         }
                         ReplayPieceContext::Partial {
                             for_keys,
+                            for_range,
                             ignore,
                             unishard: _,
                             requesting_shard: _,
                         } => {
                             assert!(!ignore);
-                            if dst_is_reader {
+                            if for_range.is_some() {
+                                // range replays are not tracked as pending, so there's nothing
+                                // to finish. if the range was filled, any later request for it
+                                // will find it there.
+                                if dst_is_reader && self.nodes[dst].borrow().beyond_mat_frontier() {
+                                    self.timed_purges.push_back(TimedPurge {
+                                        time: time::Instant::now()
+                                            + time::Duration::from_millis(50),
+                                        keys: for_keys,
+                                        view: dst,
+                                        tag,
+                                    });
+                                }
+                            } else if dst_is_reader {
                                 if self.nodes[dst].borrow().beyond_mat_frontier() {
                                     // make sure we eventually evict these from here
                                     self.timed_purges.push_back(TimedPurge {
@@ -2726,21 +3334,24 @@ impl Domain {
         }
 
         match (*m,) {
-            (Packet::Evict {
-                node,
-                mut num_bytes,
-            },) => {
+            (Packet::Evict { node, num_bytes },) => {
                 let nodes = if let Some(node) = node {
+                    if self.nodes[node].borrow().memory_budget == MemoryBudget::Pinned {
+                        debug!(self.log, "not evicting from pinned node {:?}", node);
+                        return;
+                    }
                     vec![(node, num_bytes)]
                 } else {
-                    let mut candidates: Vec<_> = self
+                    let candidates: Vec<_> = self
                         .nodes
                         .values()
                         .filter_map(|nd| {
                             let n = &*nd.borrow();
                             let local_index = n.local_addr();
 
-                            if n.is_reader() {
+                            if n.memory_budget == MemoryBudget::Pinned {
+                                None
+                            } else if n.is_reader() {
                                 let mut size = None;
                                 n.with_reader(|r| {
                                     if r.is_partial() {
@@ -2762,41 +3373,23 @@ impl Domain {
 
                     // we want to spread the eviction across the nodes,
                     // rather than emptying out one node completely.
-                    // -1* so we sort in descending order
-                    // TODO: be smarter than 3 here
-                    candidates.sort_unstable_by_key(|&(_, s)| -1 * (s as i64));
-                    candidates.truncate(3);
-
-                    // don't evict from tiny things (< 10% of max)
-                    if let Some(too_small_i) = candidates
-                        .iter()
-                        .position(|&(_, s)| s < candidates[0].1 / 10)
-                    {
-                        // everything beyond this is smaller, so also too small
-                        candidates.truncate(too_small_i);
-                    }
-
-                    let mut n = candidates.len();
-                    // rev to start with the smallest of the n domains
-                    for (_, size) in candidates.iter_mut().rev() {
-                        // TODO: should this be evenly divided, or weighted by the size of the domains?
-                        let share = (num_bytes + n - 1) / n;
-                        // we're only willing to evict at most half the state in each node
-                        // unless this is the only node left to evict from
-                        *size = if n > 1 {
-                            cmp::min(*size / 2, share)
-                        } else {
-                            assert_eq!(share, num_bytes);
-                            share
-                        };
-                        num_bytes -= *size;
-                        trace!(self.log, "chose to evict {}b from node {:?}", *size, n);
-                        n -= 1;
-                    }
-
-                    candidates
+                    let sizes: Vec<_> = candidates.iter().map(|&(_, s)| s).collect();
+                    self.eviction_policy
+                        .distribute(&sizes, num_bytes)
+                        .into_iter()
+                        .map(|(i, size)| {
+                            trace!(
+                                self.log,
+                                "chose to evict {}b from node {:?}",
+                                size,
+                                candidates[i].0
+                            );
+                            (candidates[i].0, size)
+                        })
+                        .collect()
                 };
 
+                let policy = self.eviction_policy;
                 for (node, num_bytes) in nodes {
                     let mut freed = 0u64;
                     let mut n = self.nodes[node].borrow_mut();
@@ -2805,7 +3398,9 @@ impl Domain {
                         if n.is_dropped() {
                             break; // Node was dropped. Give up.
                         } else if n.is_reader() {
-                            let freed_now = n.with_reader_mut(|r| r.evict_random_keys(16)).unwrap();
+                            let freed_now = n
+                                .with_reader_mut(|r| r.evict_cold_keys(policy, 16))
+                                .unwrap();
 
                             freed += freed_now;
                             if n.with_reader(|r| r.is_empty()).unwrap() {
@@ -2818,7 +3413,7 @@ impl Domain {
                             }
                         } else {
                             let (key_columns, keys, bytes) = {
-                                let k = self.state[node].evict_random_keys(16);
+                                let k = self.state[node].evict_cold_keys(policy, 16);
                                 (k.0.to_vec(), k.1, k.2)
                             };
                             freed += bytes;
@@ -2914,6 +3509,7 @@ impl Domain {
     }
 
     pub fn update_state_sizes(&mut self) {
+        let mut over_budget = Vec::new();
         let total: u64 = self
             .nodes
             .values()
@@ -2921,7 +3517,7 @@ impl Domain {
                 let n = &*nd.borrow();
                 let local_index = n.local_addr();
 
-                if n.is_reader() {
+                let size = if n.is_reader() {
                     // We are a reader, which has its own kind of state
                     let mut size = 0;
                     n.with_reader(|r| {
@@ -2938,12 +3534,29 @@ impl Domain {
                         .filter(|state| state.is_partial())
                         .map(|s| s.deep_size_of())
                         .unwrap_or(0)
+                };
+
+                if let MemoryBudget::Bytes(budget) = n.memory_budget {
+                    if size as usize > budget {
+                        over_budget.push((local_index, size as usize - budget));
+                    }
                 }
+                size
             })
             .sum();
 
         self.state_size.store(total as usize, Ordering::Release);
         // no response sent, as worker will read the atomic
+
+        // nodes that have outgrown their budget evict the excess the next time we get to it
+        for (node, num_bytes) in over_budget {
+            trace!(self.log, "node is over its memory budget";
+                   "node" => node.id(), "bytes" => num_bytes);
+            self.delayed_for_self.push_back(Box::new(Packet::Evict {
+                node: Some(node),
+                num_bytes,
+            }));
+        }
     }
 
     pub fn on_event(&mut self, executor: &mut dyn Executor, event: PollEvent) -> ProcessResult {
@@ -2975,13 +3588,35 @@ impl Domain {
                     }
                 });
 
-                let mut timeout = opt1.or(opt2).or(opt3);
+                // we want to get to things we've queued up for ourselves right away
+                let opt4 = if self.delayed_for_self.is_empty() {
+                    None
+                } else {
+                    Some(time::Duration::from_millis(0))
+                };
+                let opt5 = self
+                    .pending_transactions
+                    .values()
+                    .map(|t| {
+                        t.deadline
+                            .checked_duration_since(now)
+                            .unwrap_or(time::Duration::from_millis(0))
+                    })
+                    .min();
+
+                let mut timeout = opt1.or(opt2).or(opt3).or(opt4).or(opt5);
                 if let Some(opt2) = opt2 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt2));
                 }
                 if let Some(opt3) = opt3 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt3));
                 }
+                if let Some(opt4) = opt4 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt4));
+                }
+                if let Some(opt5) = opt5 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt5));
+                }
                 ProcessResult::KeepPolling(timeout)
             }
             PollEvent::Process(packet) => {
@@ -3010,7 +3645,12 @@ impl Domain {
                     self.handle(m, executor, true);
                 }
 
-                if !self.buffered_replay_requests.is_empty() || !self.timed_purges.is_empty() {
+                self.expire_transactions();
+
+                if !self.buffered_replay_requests.is_empty()
+                    || !self.timed_purges.is_empty()
+                    || !self.delayed_for_self.is_empty()
+                {
                     self.handle(Box::new(Packet::Spin), executor, true);
                 }
 
@@ -0,0 +1,425 @@
+//! Policies for choosing what to evict when a worker exceeds its memory limit.
+//!
+//! Eviction happens in two steps. The worker first splits the number of bytes it is over the limit
+//! across its domains, and each domain then splits its share across its partially materialized
+//! nodes. Both use `EvictionPolicy::distribute`. Each node then evicts keys in batches, and
+//! `EvictionPolicy::priority` decides which of its keys go first.
+
+use crate::prelude::*;
+use common::SizeOf;
+use indexmap::IndexMap;
+use rand::Rng;
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::str::FromStr;
+
+/// How recently and how often a key in partially materialized state has been read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyAccess {
+    /// The value of the state's access clock when the key was last read.
+    pub last: u64,
+    /// The number of times the key has been read since it was filled.
+    pub count: u64,
+}
+
+/// Decides where to evict from, and which keys to evict, under memory pressure.
+pub trait EvictionPolicy: fmt::Debug + Send + Sync {
+    /// Split an eviction of `bytes` across candidates with the given sizes.
+    ///
+    /// Returns the number of bytes to evict from each chosen candidate, by its index in `sizes`.
+    fn distribute(&self, sizes: &[usize], bytes: usize) -> Vec<(usize, usize)> {
+        largest_three(sizes, bytes)
+    }
+
+    /// The eviction priority of a key with the given access history.
+    ///
+    /// Keys with a lower priority are evicted first. Policies that return `None` do not make use
+    /// of access history, and keys are then evicted at random.
+    fn priority(&self, access: &KeyAccess) -> Option<u64>;
+}
+
+/// Spread the eviction across the (up to) three largest candidates.
+///
+/// We don't want to _empty_ anything if we can avoid it, so each candidate gives up at most half
+/// its state, unless it is the only one left to evict from.
+fn largest_three(sizes: &[usize], mut bytes: usize) -> Vec<(usize, usize)> {
+    let mut candidates: Vec<_> = sizes
+        .iter()
+        .cloned()
+        .enumerate()
+        .filter(|&(_, s)| s > 0)
+        .collect();
+    // -1* so we sort in descending order
+    candidates.sort_unstable_by_key(|&(_, s)| -1 * (s as i64));
+    candidates.truncate(3);
+
+    // don't evict from tiny things (< 10% of max)
+    if let Some(too_small_i) = candidates
+        .iter()
+        .position(|&(_, s)| s < candidates[0].1 / 10)
+    {
+        // everything beyond this is smaller, so also too small
+        candidates.truncate(too_small_i);
+    }
+
+    // starting with the smallest of the n candidates
+    let mut n = candidates.len();
+    for (_, size) in candidates.iter_mut().rev() {
+        let share = (bytes + n - 1) / n;
+        *size = if n > 1 {
+            cmp::min(*size / 2, share)
+        } else {
+            assert_eq!(share, bytes);
+            share
+        };
+        bytes -= *size;
+        n -= 1;
+    }
+    candidates
+}
+
+/// Evict randomly chosen keys from the three largest candidates.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Random;
+
+impl EvictionPolicy for Random {
+    fn priority(&self, _: &KeyAccess) -> Option<u64> {
+        None
+    }
+}
+
+/// Evict the least recently read keys from the three largest candidates.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lru;
+
+impl EvictionPolicy for Lru {
+    fn priority(&self, access: &KeyAccess) -> Option<u64> {
+        Some(access.last)
+    }
+}
+
+/// Evict the least frequently read keys from the three largest candidates.
+///
+/// Ties are broken by evicting the least recently read key first.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lfu;
+
+impl EvictionPolicy for Lfu {
+    fn priority(&self, access: &KeyAccess) -> Option<u64> {
+        Some(access.count)
+    }
+}
+
+/// Evict the least recently read keys from every candidate, in proportion to its size.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeWeighted;
+
+impl EvictionPolicy for SizeWeighted {
+    fn distribute(&self, sizes: &[usize], bytes: usize) -> Vec<(usize, usize)> {
+        let total: usize = sizes.iter().sum();
+        if total == 0 {
+            return Vec::new();
+        }
+
+        let mut left = bytes;
+        let mut shares: Vec<_> = sizes
+            .iter()
+            .enumerate()
+            .filter(|&(_, &s)| s > 0)
+            .map(|(i, &s)| {
+                let share = cmp::min(left, (bytes as u128 * s as u128 / total as u128) as usize);
+                left -= share;
+                (i, share)
+            })
+            .collect();
+        // hand out what was lost to rounding, starting with the largest candidate
+        if left != 0 {
+            if let Some(largest) = shares.iter_mut().max_by_key(|share| sizes[share.0]) {
+                largest.1 += left;
+            }
+        }
+        shares.retain(|&(_, share)| share != 0);
+        shares
+    }
+
+    fn priority(&self, access: &KeyAccess) -> Option<u64> {
+        Some(access.last)
+    }
+}
+
+/// The eviction policies a worker can be configured with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvictionKind {
+    /// See [`Random`].
+    Random,
+    /// See [`Lru`].
+    Lru,
+    /// See [`Lfu`].
+    Lfu,
+    /// See [`SizeWeighted`].
+    SizeWeighted,
+}
+
+impl EvictionKind {
+    /// The policy this kind refers to.
+    pub fn policy(self) -> &'static dyn EvictionPolicy {
+        match self {
+            EvictionKind::Random => &Random,
+            EvictionKind::Lru => &Lru,
+            EvictionKind::Lfu => &Lfu,
+            EvictionKind::SizeWeighted => &SizeWeighted,
+        }
+    }
+}
+
+impl Default for EvictionKind {
+    fn default() -> Self {
+        EvictionKind::Random
+    }
+}
+
+impl FromStr for EvictionKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(EvictionKind::Random),
+            "lru" => Ok(EvictionKind::Lru),
+            "lfu" => Ok(EvictionKind::Lfu),
+            "size-weighted" => Ok(EvictionKind::SizeWeighted),
+            _ => Err(format!("unknown eviction policy '{}'", s)),
+        }
+    }
+}
+
+/// How much memory a node's partially materialized state may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryBudget {
+    /// The state is only bounded by the memory limit of the worker it lives on.
+    Shared,
+    /// Keys are evicted from the state whenever it grows beyond this many bytes.
+    Bytes(usize),
+    /// The state is never chosen for eviction.
+    ///
+    /// Keys may still be evicted if they are evicted from upstream state that is not pinned.
+    Pinned,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        MemoryBudget::Shared
+    }
+}
+
+/// How many keys `AccessTracker::coldest` looks at for every key it is asked to pick.
+///
+/// Like Redis' approximated LRU, we only consider a random sample of the tracked keys, so that
+/// picking keys to evict does not get slower as the state grows.
+const SAMPLES_PER_KEY: usize = 8;
+
+/// Per-key access history for a partially materialized index.
+#[derive(Debug, Default)]
+pub(crate) struct AccessTracker {
+    clock: u64,
+    keys: IndexMap<Vec<DataType>, KeyAccess>,
+    /// The memory used by the tracked keys and their history.
+    bytes: u64,
+}
+
+fn entry_size(key: &[DataType]) -> u64 {
+    use std::mem::size_of;
+
+    key.iter().map(SizeOf::deep_size_of).sum::<u64>()
+        + (size_of::<Vec<DataType>>() + size_of::<KeyAccess>()) as u64
+}
+
+impl AccessTracker {
+    /// Record a read of `key`.
+    pub(crate) fn touch(&mut self, key: &[DataType]) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(access) = self.keys.get_mut(key) {
+            access.last = clock;
+            access.count += 1;
+        } else {
+            self.bytes += entry_size(key);
+            self.keys.insert(
+                key.to_vec(),
+                KeyAccess {
+                    last: clock,
+                    count: 1,
+                },
+            );
+        }
+    }
+
+    /// Forget about a key that is no longer present.
+    pub(crate) fn forget(&mut self, key: &[DataType]) {
+        if self.keys.swap_remove(key).is_some() {
+            self.bytes -= entry_size(key);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.keys.clear();
+        self.bytes = 0;
+    }
+
+    /// Pick (up to) `n` keys that `policy` would evict first.
+    ///
+    /// Once there are more than `SAMPLES_PER_KEY * n` keys, the keys are picked from a random
+    /// sample of that size rather than from all of them.
+    ///
+    /// Returns `None` if the policy does not use access history, or there is none to go by.
+    pub(crate) fn coldest<R: Rng>(
+        &self,
+        policy: &dyn EvictionPolicy,
+        n: usize,
+        rng: &mut R,
+    ) -> Option<Vec<Vec<DataType>>> {
+        if self.keys.is_empty() {
+            return None;
+        }
+
+        let samples = n.saturating_mul(SAMPLES_PER_KEY);
+        let candidates: Vec<usize> = if self.keys.len() <= samples {
+            (0..self.keys.len()).collect()
+        } else {
+            let mut sample: Vec<_> = (0..samples)
+                .map(|_| rng.gen_range(0, self.keys.len()))
+                .collect();
+            sample.sort_unstable();
+            sample.dedup();
+            sample
+        };
+
+        // a max-heap of the n coldest keys seen so far, so the warmest of them is on top
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        for i in candidates {
+            let (key, access) = self.keys.get_index(i).unwrap();
+            heap.push(((policy.priority(access)?, access.last), key));
+            if heap.len() > n {
+                heap.pop();
+            }
+        }
+        Some(heap.into_iter().map(|(_, key)| key.clone()).collect())
+    }
+}
+
+impl SizeOf for AccessTracker {
+    fn size_of(&self) -> u64 {
+        use std::mem::size_of;
+
+        size_of::<Self>() as u64
+    }
+
+    fn deep_size_of(&self) -> u64 {
+        self.bytes
+    }
+
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn key(k: i32) -> Vec<DataType> {
+        vec![k.into()]
+    }
+
+    #[test]
+    fn random_spreads_across_largest_three() {
+        let mut chosen = Random.distribute(&[10, 1000, 0, 800, 900], 300);
+        chosen.sort();
+        assert_eq!(chosen, vec![(1, 100), (3, 100), (4, 100)]);
+        assert_eq!(Random.distribute(&[1000, 50], 300), vec![(0, 300)]);
+    }
+
+    #[test]
+    fn size_weighted_spreads_across_all() {
+        let mut chosen = SizeWeighted.distribute(&[100, 300, 0, 600], 100);
+        chosen.sort();
+        assert_eq!(chosen, vec![(0, 10), (1, 30), (3, 60)]);
+
+        let chosen = SizeWeighted.distribute(&[1, 1, 1], 10);
+        assert_eq!(chosen.iter().map(|&(_, b)| b).sum::<usize>(), 10);
+    }
+
+    #[test]
+    fn lru_picks_least_recently_read() {
+        let mut t = AccessTracker::default();
+        for k in 0..4 {
+            t.touch(&key(k));
+        }
+        t.touch(&key(0));
+        t.touch(&key(1));
+
+        let rng = &mut rand::thread_rng();
+        let mut coldest = t.coldest(&Lru, 2, rng).unwrap();
+        coldest.sort();
+        assert_eq!(coldest, vec![key(2), key(3)]);
+        assert_eq!(t.coldest(&Random, 2, rng), None);
+    }
+
+    #[test]
+    fn lfu_picks_least_frequently_read() {
+        let mut t = AccessTracker::default();
+        for k in 0..3 {
+            t.touch(&key(k));
+            t.touch(&key(k));
+        }
+        t.touch(&key(3));
+        t.touch(&key(4));
+        t.touch(&key(4));
+        t.touch(&key(4));
+
+        let rng = &mut rand::thread_rng();
+        assert_eq!(t.coldest(&Lfu, 1, rng).unwrap(), vec![key(3)]);
+        t.forget(&key(3));
+        // ties go to the least recently read key
+        assert_eq!(t.coldest(&Lfu, 1, rng).unwrap(), vec![key(0)]);
+    }
+
+    #[test]
+    fn coldest_samples_large_states() {
+        let mut t = AccessTracker::default();
+        for k in 0..1000 {
+            t.touch(&key(k));
+        }
+
+        let rng = &mut StdRng::seed_from_u64(42);
+        for _ in 0..10 {
+            let coldest = t.coldest(&Lru, 4, rng).unwrap();
+            assert_eq!(coldest.len(), 4);
+            let mut unique = coldest.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(unique.len(), 4);
+            // the sample is small, but it is very unlikely to not contain any of the colder half
+            assert!(coldest.iter().any(|k| k < &key(500)));
+        }
+    }
+
+    #[test]
+    fn it_counts_tracked_bytes() {
+        let mut t = AccessTracker::default();
+        assert_eq!(t.deep_size_of(), 0);
+        t.touch(&key(1));
+        let one = t.deep_size_of();
+        assert!(one > 0);
+        t.touch(&key(1));
+        assert_eq!(t.deep_size_of(), one);
+        t.touch(&key(2));
+        assert_eq!(t.deep_size_of(), 2 * one);
+        t.forget(&key(1));
+        t.forget(&key(1));
+        assert_eq!(t.deep_size_of(), one);
+        t.clear();
+        assert_eq!(t.deep_size_of(), 0);
+    }
+}
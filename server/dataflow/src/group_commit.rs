@@ -20,9 +20,10 @@ impl GroupCommitQueueSet {
 
     /// Returns whether the given packet should be persisted.
     pub fn should_append(&self, p: &Packet, nodes: &DomainNodes) -> bool {
-        if let Packet::Input { .. } = *p {
+        if let Packet::Input { ref inner, .. } = *p {
             assert!(nodes[p.dst()].borrow().is_base());
-            true
+            // bulk loads are batched by the client, and make themselves durable when they finish
+            !unsafe { inner.deref() }.bulk
         } else {
             false
         }
@@ -100,9 +101,10 @@ impl GroupCommitQueueSet {
                     src,
                     senders,
                 } => {
-                    let Input { dst, data } = unsafe { inner.take() };
+                    let Input { dst, data, bulk } = unsafe { inner.take() };
 
                     assert_eq!(senders.len(), 0);
+                    assert!(!bulk);
                     assert_eq!(merged_dst, dst);
                     acc.extend(data);
 
@@ -119,6 +121,7 @@ impl GroupCommitQueueSet {
             inner: LocalOrNot::new(Input {
                 dst: merged_dst,
                 data: merged_data,
+                bulk: false,
             }),
             src: None,
             senders: all_senders,
@@ -14,6 +14,7 @@ extern crate serde_derive;
 extern crate slog;
 
 pub(crate) mod backlog;
+pub mod eviction;
 pub mod node;
 pub mod ops;
 pub mod payload; // it makes me _really_ sad that this has to be pub
@@ -29,12 +30,13 @@ use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time;
 
-pub use crate::backlog::SingleReadHandle;
+pub use crate::backlog::{SingleReadHandle, SubscriptionPoll};
 pub type Readers =
     Arc<Mutex<HashMap<(petgraph::graph::NodeIndex, usize), backlog::SingleReadHandle>>>;
 pub type DomainConfig = domain::Config;
 
 pub use crate::domain::{Domain, DomainBuilder, Index, PollEvent, ProcessResult};
+pub use crate::eviction::{EvictionKind, EvictionPolicy, MemoryBudget};
 pub use crate::payload::Packet;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -66,6 +68,8 @@ impl Sharding {
 pub enum DurabilityMode {
     /// Don't do any durability
     MemoryOnly,
+    /// Keep base tables in memory, but log every update to disk so they can be recovered.
+    MemoryWithLog,
     /// Delete any log files on exit. Useful mainly for tests.
     DeleteOnExit,
     /// Persist updates to disk, and don't delete them later.
@@ -102,13 +106,15 @@ impl Default for PersistenceParameters {
 impl PersistenceParameters {
     /// Parameters to control the persistence mode, and parameters related to persistence.
     ///
-    /// Three modes are available:
+    /// Four modes are available:
     ///
     ///  1. `DurabilityMode::Permanent`: all writes to base nodes should be written to disk.
     ///  2. `DurabilityMode::DeleteOnExit`: all writes to base nodes are written to disk, but the
     ///     persistent files are deleted once the `ControllerHandle` is dropped. Useful for tests.
     ///  3. `DurabilityMode::MemoryOnly`: no writes to disk, store all writes in memory.
     ///     Useful for baseline numbers.
+    ///  4. `DurabilityMode::MemoryWithLog`: store all writes in memory, but also append them to a
+    ///     log on disk that is replayed on restart.
     pub fn new(
         mode: DurabilityMode,
         flush_timeout: time::Duration,
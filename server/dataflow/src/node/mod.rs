@@ -1,4 +1,5 @@
 use crate::domain;
+use crate::eviction::MemoryBudget;
 use crate::ops;
 use crate::prelude::*;
 use petgraph;
@@ -32,6 +33,7 @@ pub struct Node {
     taken: bool,
 
     pub purge: bool,
+    pub memory_budget: MemoryBudget,
 
     sharded_by: Sharding,
 }
@@ -57,6 +59,7 @@ impl Node {
             taken: false,
 
             purge: false,
+            memory_budget: MemoryBudget::Shared,
 
             sharded_by: Sharding::None,
         }
@@ -140,10 +143,25 @@ impl Node {
         Ingredient::can_query_through(&**self)
     }
 
+    /// Indexes this node needs in addition to its suggested ones if it is fully materialized.
+    pub fn suggest_indexes_if_full(&self, you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        Ingredient::suggest_indexes_if_full(&**self, you)
+    }
+
+    /// Returns true if `parent` must be materialized itself, rather than looked up through.
+    pub fn must_materialize_parent(&self, parent: NodeIndex) -> bool {
+        Ingredient::must_materialize_parent(&**self, parent)
+    }
+
     pub fn is_join(&self) -> bool {
         Ingredient::is_join(&**self)
     }
 
+    /// Returns true if downstream state must be evicted when a write misses in this node.
+    pub fn must_evict_on_miss(&self) -> bool {
+        Ingredient::must_evict_on_miss(&**self)
+    }
+
     pub fn ancestors(&self) -> Vec<NodeIndex> {
         Ingredient::ancestors(&**self)
     }
@@ -202,6 +220,7 @@ impl Node {
         n.index = self.index;
         n.domain = self.domain;
         n.purge = self.purge;
+        n.memory_budget = self.memory_budget;
         self.taken = true;
 
         DanglingDomainNode(n)
@@ -313,6 +332,15 @@ impl Node {
     pub(crate) fn parents(&self) -> &[LocalNodeIndex] {
         &self.parents
     }
+
+    /// The domain shards this node sends updates to, if it is an egress or a sharder.
+    pub(crate) fn child_domains(&self) -> Vec<ReplicaAddr> {
+        match self.inner {
+            NodeType::Egress(Some(ref e)) => e.destinations().collect(),
+            NodeType::Sharder(ref s) => s.destinations().collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 // attributes
@@ -340,6 +368,10 @@ impl Node {
         self.fields.len() - 1
     }
 
+    pub fn rename_column(&mut self, column: usize, field: &str) {
+        self.fields[column] = field.to_string();
+    }
+
     pub fn has_domain(&self) -> bool {
         self.domain.is_some()
     }
@@ -35,8 +35,9 @@ impl Node {
                     Some(Packet::Input {
                         inner, mut senders, ..
                     }) => {
-                        let Input { dst, data } = unsafe { inner.take() };
-                        let mut rs = b.process(addr, data, &*state);
+                        let Input { dst, data, bulk } = unsafe { inner.take() };
+                        let finish_bulk = bulk && data.is_empty();
+                        let mut rs = b.process(addr, data, state, log);
 
                         // When a replay originates at a base node, we replay the data *through* that
                         // same base node because its column set may have changed. However, this replay
@@ -46,7 +47,17 @@ impl Node {
                         //
                         // So: only materialize if the message we're processing is not a replay!
                         if keyed_by.is_none() {
-                            materialize(&mut rs, None, state.get_mut(addr));
+                            if bulk {
+                                if let Some(s) = state.get_mut(addr) {
+                                    s.process_bulk_records(&mut rs);
+                                    if finish_bulk {
+                                        s.finish_bulk_load();
+                                    }
+                                }
+                            } else {
+                                materialize(&mut rs, None, state.get_mut(addr));
+                            }
+                            b.log_changes(&rs);
                         }
 
                         // Send write-ACKs to all the clients with updates that made
@@ -76,7 +87,7 @@ impl Node {
                 s.process(
                     m,
                     addr,
-                    on_shard.is_some(),
+                    on_shard,
                     replay_path.and_then(|rp| rp.partial_unicast_sharder.map(|ni| ni == gaddr)),
                     ex,
                 );
@@ -98,6 +109,7 @@ impl Node {
                             context:
                                 payload::ReplayPieceContext::Partial {
                                     ref for_keys,
+                                    for_range: _,
                                     requesting_shard,
                                     unishard,
                                     ignore,
@@ -201,8 +213,9 @@ impl Node {
                         // shouldn't wait for replays from other shards, since none will
                         // arrive. the same is not true for any _subsequent_ shard mergers
                         // though, since sharders along a replay path send to _all_ shards
-                        // (modulo the last one if the destination is sharded, but then there
-                        // is no shard merger after it).
+                        // (modulo the last one if the destination is sharded, but then any
+                        // shard merger after it belongs to a sharded shuffle, and the requesting
+                        // shard still hears from every shard that was sent the upquery).
                         //
                         // to ensure that this is in fact what happens, we need to _unset_
                         // unishard once we've passed the first shard merger, so that it is not
@@ -283,7 +296,7 @@ impl Node {
                 );
             }
             NodeType::Sharder(ref mut s) => {
-                s.process_eviction(key_columns, tag, keys, addr, on_shard.is_some(), ex);
+                s.process_eviction(key_columns, tag, keys, addr, ex);
             }
             NodeType::Internal(ref mut i) => {
                 i.on_eviction(from, tag, keys);
@@ -1,10 +1,203 @@
 use crate::prelude::*;
+use crate::state::{read_entry, sync_dir, write_entry, LogEntry};
 use noria::{Modification, Operation, TableOperation};
+use slog::Logger;
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use tempfile::{tempdir, TempDir};
 use vec_map::VecMap;
 
+/// The number of records a base node keeps around for its change feed.
+const CHANGE_LOG_RECORDS: usize = 100_000;
+
+/// The batches of records a base node has emitted, numbered in the order they were emitted.
+///
+/// Each batch is appended to a file along with its sequence number as the base emits it, so when
+/// the base's domain is recreated, the numbering picks up where it left off. Only the batches that
+/// hold the most recent `CHANGE_LOG_RECORDS` records can be read. The file holds the batches
+/// before them too, until they take up half of it, at which point it is rewritten without them.
+#[derive(Debug)]
+struct ChangeLog {
+    path: PathBuf,
+    log: File,
+    /// The length of the file.
+    len: u64,
+    /// The sequence number of the next batch.
+    next: u64,
+    /// The sequence number, offset in the file and number of records of each batch that can
+    /// still be read, oldest first.
+    kept: VecDeque<(u64, u64, usize)>,
+    kept_records: usize,
+    // With DurabilityMode::DeleteOnExit, the file is stored in a temporary directory.
+    _directory: Option<TempDir>,
+}
+
+impl ChangeLog {
+    /// Open the change log of the base called `name`, and recover the batches it holds.
+    fn open(name: &str, params: &PersistenceParameters) -> io::Result<Self> {
+        let (directory, dir) = match params.mode {
+            DurabilityMode::DeleteOnExit => {
+                let directory = tempdir()?;
+                let dir = directory.path().to_path_buf();
+                (Some(directory), dir)
+            }
+            _ => (
+                None,
+                params.log_dir.clone().unwrap_or_else(|| PathBuf::from(".")),
+            ),
+        };
+        let path = dir.join(format!("{}.changes", name));
+
+        let mut kept = VecDeque::new();
+        let mut kept_records = 0;
+        let mut len = 0;
+        match File::open(&path) {
+            Ok(f) => {
+                let mut f = BufReader::new(f);
+                loop {
+                    match read_entry::<_, (u64, Vec<Record>)>(&mut f)? {
+                        LogEntry::Records((seq, rs), n) => {
+                            kept.push_back((seq, len, rs.len()));
+                            kept_records += rs.len();
+                            len += n;
+                        }
+                        LogEntry::End | LogEntry::Torn => break,
+                        LogEntry::Corrupt(why) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("change log entry at offset {} is corrupt: {}", len, why),
+                            ));
+                        }
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        fs::create_dir_all(&dir)?;
+        let log = OpenOptions::new().create(true).append(true).open(&path)?;
+        // throw away the torn entry at the end, if any
+        log.set_len(len)?;
+
+        let mut changes = ChangeLog {
+            path,
+            log,
+            len,
+            next: kept.back().map(|&(seq, _, _)| seq + 1).unwrap_or(0),
+            kept,
+            kept_records,
+            _directory: directory,
+        };
+        changes.trim()?;
+        Ok(changes)
+    }
+
+    fn push(&mut self, rs: &[Record]) -> io::Result<()> {
+        if rs.is_empty() {
+            return Ok(());
+        }
+
+        let seq = self.next;
+        let mut entry = Vec::new();
+        write_entry(&mut entry, &(seq, rs))?;
+        self.log.write_all(&entry)?;
+        self.log.sync_data()?;
+
+        self.kept.push_back((seq, self.len, rs.len()));
+        self.kept_records += rs.len();
+        self.len += entry.len() as u64;
+        self.next += 1;
+        self.trim()
+    }
+
+    /// Stop keeping the oldest batches once there are too many records, and drop them from the
+    /// file once they take up half of it.
+    fn trim(&mut self) -> io::Result<()> {
+        while self.kept_records > CHANGE_LOG_RECORDS && self.kept.len() > 1 {
+            let (_, _, n) = self.kept.pop_front().unwrap();
+            self.kept_records -= n;
+        }
+
+        let start = match self.kept.front() {
+            Some(&(_, offset, _)) => offset,
+            None => return Ok(()),
+        };
+        if start == 0 || start < self.len - start {
+            return Ok(());
+        }
+
+        let tmp = self.path.with_extension("changes.trim");
+        let mut from = File::open(&self.path)?;
+        from.seek(SeekFrom::Start(start))?;
+        let mut to = File::create(&tmp)?;
+        io::copy(&mut from, &mut to)?;
+        to.sync_data()?;
+        fs::rename(&tmp, &self.path)?;
+        sync_dir(&self.path)?;
+
+        self.log = OpenOptions::new().append(true).open(&self.path)?;
+        self.len -= start;
+        for batch in &mut self.kept {
+            batch.1 -= start;
+        }
+        Ok(())
+    }
+
+    /// Read up to `limit` batches, starting with the one numbered `from` (or the oldest one).
+    ///
+    /// Returns the batches along with the sequence number to continue reading from.
+    fn read(
+        &self,
+        from: Option<u64>,
+        limit: usize,
+    ) -> Result<(Vec<(u64, Vec<Record>)>, u64), String> {
+        let oldest = self
+            .kept
+            .front()
+            .map(|&(seq, _, _)| seq)
+            .unwrap_or(self.next);
+        let from = from.unwrap_or(oldest);
+        if from < oldest {
+            return Err(format!(
+                "changes before {} have been trimmed from the feed (asked for {})",
+                oldest, from
+            ));
+        }
+        if from > self.next {
+            return Err(format!(
+                "no changes have been made past {} (asked for {})",
+                self.next, from
+            ));
+        }
+
+        let skip = (from - oldest) as usize;
+        let offset = match self.kept.get(skip) {
+            Some(&(_, offset, _)) => offset,
+            None => return Ok((Vec::new(), from)),
+        };
+        let read_failed = |e: io::Error| format!("failed to read the change log: {}", e);
+        let mut f = File::open(&self.path).map_err(read_failed)?;
+        f.seek(SeekFrom::Start(offset)).map_err(read_failed)?;
+        let mut f = BufReader::new(f);
+
+        let mut changes = Vec::new();
+        for &(seq, _, _) in self.kept.iter().skip(skip).take(limit) {
+            match read_entry::<_, (u64, Vec<Record>)>(&mut f).map_err(read_failed)? {
+                LogEntry::Records((s, rs), _) if s == seq => changes.push((seq, rs)),
+                _ => return Err(format!("change log entry {} is missing or corrupt", seq)),
+            }
+        }
+        let next = changes.last().map(|&(seq, _)| seq + 1).unwrap_or(from);
+        Ok((changes, next))
+    }
+}
+
 /// Base is used to represent the root nodes of the Noria data flow graph.
 ///
 /// These nodes perform no computation, and their job is merely to persist all received updates and
@@ -17,6 +210,11 @@ pub struct Base {
     defaults: Vec<DataType>,
     dropped: Vec<usize>,
     unmodified: bool,
+
+    /// Whether this base keeps a change feed.
+    keep_changes: bool,
+    #[serde(skip)]
+    changes: Option<ChangeLog>,
 }
 
 impl Base {
@@ -72,6 +270,52 @@ impl Base {
             .collect()
     }
 
+    /// Make this base keep a change feed of the records it emits from now on.
+    pub fn keep_changes(&mut self) {
+        self.keep_changes = true;
+    }
+
+    /// Whether this base keeps a change feed.
+    pub fn keeps_changes(&self) -> bool {
+        self.keep_changes
+    }
+
+    /// Open the change log of this base, called `name`, if it keeps a change feed.
+    pub(crate) fn open_changes(&mut self, name: &str, params: &PersistenceParameters) {
+        if !self.keep_changes || self.changes.is_some() {
+            return;
+        }
+        match tokio::task::block_in_place(|| ChangeLog::open(name, params)) {
+            Ok(changes) => self.changes = Some(changes),
+            Err(e) => panic!("failed to open change log of {}: {}", name, e),
+        }
+    }
+
+    /// Add a batch of records that this base has emitted to its change feed, if it keeps one.
+    pub(crate) fn log_changes(&mut self, rs: &[Record]) {
+        if let Some(ref mut changes) = self.changes {
+            if let Err(e) = tokio::task::block_in_place(|| changes.push(rs)) {
+                panic!("failed to log changes: {}", e);
+            }
+        }
+    }
+
+    /// Read up to `limit` batches of records from this base's change feed, starting with the
+    /// batch numbered `from`, or with the oldest batch still available if `from` is `None`.
+    ///
+    /// Returns the batches along with the sequence number to continue reading from. See
+    /// `ChangeLog` for which batches are available.
+    pub(crate) fn read_changes(
+        &self,
+        from: Option<u64>,
+        limit: usize,
+    ) -> Result<(Vec<(u64, Vec<Record>)>, u64), String> {
+        match self.changes {
+            Some(ref changes) => tokio::task::block_in_place(|| changes.read(from, limit)),
+            None => Err("changes are not enabled for this table".to_owned()),
+        }
+    }
+
     pub(crate) fn fix(&self, row: &mut Vec<DataType>) {
         if self.unmodified {
             return;
@@ -95,6 +339,9 @@ impl Clone for Base {
             defaults: self.defaults.clone(),
             dropped: self.dropped.clone(),
             unmodified: self.unmodified,
+
+            keep_changes: self.keep_changes,
+            changes: None,
         }
     }
 }
@@ -107,6 +354,9 @@ impl Default for Base {
             defaults: Vec::new(),
             dropped: Vec::new(),
             unmodified: true,
+
+            keep_changes: false,
+            changes: None,
         }
     }
 }
@@ -117,6 +367,9 @@ fn key_val(i: usize, col: usize, r: &TableOperation) -> &DataType {
         TableOperation::Delete { ref key } => &key[i],
         TableOperation::Update { ref key, .. } => &key[i],
         TableOperation::InsertOrUpdate { ref row, .. } => &row[col],
+        TableOperation::DeleteWhere { .. } | TableOperation::UpdateWhere { .. } => {
+            unreachable!("operations on matching rows are resolved before keying")
+        }
     }
 }
 
@@ -127,16 +380,191 @@ fn key_of<'a>(key_cols: &'a [usize], r: &'a TableOperation) -> impl Iterator<Ite
         .map(move |(i, col)| key_val(i, *col, r))
 }
 
+/// Returns the row in `db` whose primary key (over `key_cols`) is `key`, if any.
+fn lookup_row<'a>(
+    db: &'a dyn State,
+    key_cols: &[usize],
+    key: &[DataType],
+) -> Option<Cow<'a, [DataType]>> {
+    match db.lookup(key_cols, &KeyType::from(key)) {
+        LookupResult::Some(rows) => {
+            match rows.len() {
+                0 => None,
+                1 => rows.into_iter().next(),
+                n => {
+                    // primary key, so better be unique!
+                    assert_eq!(n, 1, "key {:?} not unique (n = {})!", key, n);
+                    unreachable!();
+                }
+            }
+        }
+        LookupResult::Missing => unreachable!(),
+    }
+}
+
+/// Returns the rows in `db` whose column `col` holds `value`.
+///
+/// `db` must have an index on `col`.
+fn matching_rows<'a>(db: &'a dyn State, col: usize, value: &DataType) -> Vec<Cow<'a, [DataType]>> {
+    match db.lookup(&[col], &KeyType::Single(value)) {
+        LookupResult::Some(rows) => rows.into_iter().collect(),
+        LookupResult::Missing => unreachable!("base nodes are always fully materialized"),
+    }
+}
+
+/// Applies `op` to `current`, the row with the operation's key as of the operations before it in
+/// the batch. `was` is the row that had the key before the batch.
+fn apply<'a>(
+    key_cols: &[usize],
+    was: Option<&Cow<'a, [DataType]>>,
+    current: Option<Cow<'a, [DataType]>>,
+    op: TableOperation,
+    log: &Logger,
+) -> Option<Cow<'a, [DataType]>> {
+    let update = match op {
+        TableOperation::Insert(row) => {
+            if let Some(was) = was {
+                warn!(log, "base ignoring insert of existing key";
+                      "row" => ?row, "existing" => ?was);
+                return current;
+            }
+            return Some(Cow::Owned(row));
+        }
+        TableOperation::Delete { .. } => {
+            // deleting a non-existing row is a no-op
+            return None;
+        }
+        TableOperation::Update { set, .. } => set,
+        TableOperation::InsertOrUpdate { row, update } => {
+            if current.is_none() {
+                return Some(Cow::Owned(row));
+            }
+            update
+        }
+        TableOperation::DeleteWhere { .. } | TableOperation::UpdateWhere { .. } => {
+            unreachable!("operations on matching rows are resolved before they are applied")
+        }
+    };
+
+    // updating a non-existing row is a no-op
+    let mut future = current?.into_owned();
+    for (col, op) in update.into_iter().enumerate() {
+        if op != Modification::None && key_cols.contains(&col) {
+            // the primary key identifies the row, so it must not change. Table rejects such
+            // updates, so this only happens for hand-crafted operations.
+            warn!(log, "base ignoring modification of primary key column";
+                  "column" => col, "modification" => ?op);
+            continue;
+        }
+
+        match op {
+            Modification::Set(v) => future[col] = v,
+            Modification::Apply(op, v) => {
+                let old: i128 = future[col].clone().into();
+                let delta: i128 = v.into();
+                future[col] = match op {
+                    Operation::Add => (old + delta).into(),
+                    Operation::Sub => (old - delta).into(),
+                };
+            }
+            Modification::None => {}
+        }
+    }
+    Some(Cow::Owned(future))
+}
+
+/// The rows that the operations so far in a batch have left behind, by primary key, along with the
+/// rows that had those keys before the batch.
+type BatchRows<'a> =
+    HashMap<Vec<DataType>, (Option<Cow<'a, [DataType]>>, Option<Cow<'a, [DataType]>>)>;
+
+/// Applies `op` to the row its key has in `batch`, looking up the row the key had before the batch
+/// in `db` if the batch hasn't touched the key yet.
+fn track<'a>(
+    batch: &mut BatchRows<'a>,
+    db: &'a dyn State,
+    key_cols: &[usize],
+    op: &TableOperation,
+    log: &Logger,
+) {
+    let key: Vec<_> = key_of(key_cols, op).cloned().collect();
+    let entry = match batch.entry(key) {
+        Entry::Occupied(e) => e.into_mut(),
+        Entry::Vacant(e) => {
+            let was = lookup_row(db, key_cols, e.key());
+            e.insert((was.clone(), was))
+        }
+    };
+    let current = entry.1.take();
+    entry.1 = apply(key_cols, entry.0.as_ref(), current, op.clone(), log);
+}
+
 impl Base {
     pub(in crate::node) fn take(&mut self) -> Self {
         Clone::clone(self)
     }
 
+    /// Expands operations that apply to all rows holding some value in a column into deletes and
+    /// updates of the individual rows, identified by their primary keys.
+    ///
+    /// Rows are matched as they are after the operations before them in the batch, so they see
+    /// rows inserted, updated and deleted earlier in the same batch. `db` must have an index on
+    /// every column that is matched on.
+    fn resolve_matching(&self, ops: Vec<TableOperation>, db: &dyn State) -> Vec<TableOperation> {
+        let key_cols = &self.primary_key.as_ref().unwrap()[..];
+        // anything worth warning about is warned about when the operations are applied for real
+        let quiet = Logger::root(slog::Discard, o!());
+        let mut batch = BatchRows::new();
+        let mut resolved = Vec::with_capacity(ops.len());
+        for op in ops {
+            let (column, value, set) = match op {
+                TableOperation::DeleteWhere { column, value } => (column, value, None),
+                TableOperation::UpdateWhere { column, value, set } => (column, value, Some(set)),
+                op => {
+                    track(&mut batch, db, key_cols, &op, &quiet);
+                    resolved.push(op);
+                    continue;
+                }
+            };
+
+            // rows touched earlier in the batch are matched as they are now, not as they were
+            let mut keys: Vec<Vec<DataType>> = matching_rows(db, column, &value)
+                .into_iter()
+                .map(|row| key_cols.iter().map(|&c| row[c].clone()).collect())
+                .filter(|key| !batch.contains_key(key))
+                .collect();
+            keys.extend(
+                batch
+                    .iter()
+                    .filter(|(_, (_, current))| match *current {
+                        Some(ref row) => row[column] == value,
+                        None => false,
+                    })
+                    .map(|(key, _)| key.clone()),
+            );
+            keys.sort();
+
+            for key in keys {
+                let op = match set {
+                    None => TableOperation::Delete { key },
+                    Some(ref set) => TableOperation::Update {
+                        key,
+                        set: set.clone(),
+                    },
+                };
+                track(&mut batch, db, key_cols, &op, &quiet);
+                resolved.push(op);
+            }
+        }
+        resolved
+    }
+
     pub(in crate::node) fn process(
         &mut self,
         us: LocalNodeIndex,
         mut ops: Vec<TableOperation>,
-        state: &StateMap,
+        state: &mut StateMap,
+        log: &Logger,
     ) -> Records {
         if self.primary_key.is_none() || ops.is_empty() {
             return ops
@@ -152,6 +580,41 @@ impl Base {
                 .collect();
         }
 
+        let matching = ops.iter().any(|op| match *op {
+            TableOperation::DeleteWhere { .. } | TableOperation::UpdateWhere { .. } => true,
+            _ => false,
+        });
+        if matching {
+            let db = state
+                .get(us)
+                .expect("base with primary key must be materialized");
+
+            // building an index here would stall the domain while it scans the whole base, so
+            // the index must have been added when the table was set up
+            let keys = db.keys();
+            ops.retain(|op| match *op {
+                TableOperation::DeleteWhere { column, .. }
+                | TableOperation::UpdateWhere { column, .. } => {
+                    let indexed = keys.iter().any(|k| k[..] == [column]);
+                    if !indexed {
+                        warn!(log, "ignoring operation matching on unindexed column";
+                              "column" => column);
+                    }
+                    indexed
+                }
+                _ => true,
+            });
+
+            ops = self.resolve_matching(ops, &**db);
+            if ops.is_empty() {
+                return Records::default();
+            }
+        }
+
+        let db = state
+            .get(us)
+            .expect("base with primary key must be materialized");
+
         let key_cols = &self.primary_key.as_ref().unwrap()[..];
         ops.sort_by(|a, b| key_of(key_cols, a).cmp(key_of(key_cols, b)));
 
@@ -159,27 +622,7 @@ impl Base {
         let mut this_key: Vec<_> = key_of(key_cols, &ops[0]).cloned().collect();
 
         // starting record state
-        let db = state
-            .get(us)
-            .expect("base with primary key must be materialized");
-
-        let get_current = |current_key: &'_ _| {
-            match db.lookup(key_cols, &KeyType::from(current_key)) {
-                LookupResult::Some(rows) => {
-                    match rows.len() {
-                        0 => None,
-                        1 => rows.into_iter().next(),
-                        n => {
-                            // primary key, so better be unique!
-                            assert_eq!(n, 1, "key {:?} not unique (n = {})!", current_key, n);
-                            unreachable!();
-                        }
-                    }
-                }
-                LookupResult::Missing => unreachable!(),
-            }
-        };
-        let mut current = get_current(&this_key);
+        let mut current = lookup_row(&**db, key_cols, &this_key);
         let mut was = current.clone();
 
         let mut results = Vec::with_capacity(ops.len());
@@ -195,62 +638,11 @@ impl Base {
                 }
 
                 this_key = key_of(key_cols, &op).cloned().collect();
-                current = get_current(&this_key);
+                current = lookup_row(&**db, key_cols, &this_key);
                 was = current.clone();
             }
 
-            let update = match op {
-                TableOperation::Insert(row) => {
-                    if let Some(ref was) = was {
-                        eprintln!("base ignoring {:?} since it already has {:?}", row, was);
-                    } else {
-                        //assert!(was.is_none());
-                        current = Some(Cow::Owned(row));
-                    }
-                    continue;
-                }
-                TableOperation::Delete { .. } => {
-                    if current.is_some() {
-                        current = None;
-                    } else {
-                        // supposed to delete a non-existing row?
-                        // TODO: warn?
-                    }
-                    continue;
-                }
-                TableOperation::Update { set, .. } => set,
-                TableOperation::InsertOrUpdate { row, update } => {
-                    if current.is_none() {
-                        current = Some(Cow::Owned(row));
-                        continue;
-                    }
-                    update
-                }
-            };
-
-            if current.is_none() {
-                // supposed to update a non-existing row?
-                // TODO: also warn here?
-                continue;
-            }
-
-            let mut future = current.unwrap().into_owned();
-            for (col, op) in update.into_iter().enumerate() {
-                // XXX: make sure user doesn't update primary key?
-                match op {
-                    Modification::Set(v) => future[col] = v,
-                    Modification::Apply(op, v) => {
-                        let old: i128 = future[col].clone().into();
-                        let delta: i128 = v.into();
-                        future[col] = match op {
-                            Operation::Add => (old + delta).into(),
-                            Operation::Sub => (old - delta).into(),
-                        };
-                    }
-                    Modification::None => {}
-                }
-            }
-            current = Some(Cow::Owned(future));
+            current = apply(key_cols, was.as_ref(), current, op, log);
         }
 
         // we may have changed things in the last iteration of the loop above
@@ -296,6 +688,87 @@ mod tests {
         assert_eq!(b.unmodified, true);
     }
 
+    fn change_log_params(dir: &tempfile::TempDir) -> PersistenceParameters {
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::Permanent;
+        params.log_dir = Some(dir.path().to_path_buf());
+        params
+    }
+
+    #[test]
+    fn it_keeps_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = change_log_params(&dir);
+        let mut log = ChangeLog::open("base", &params).unwrap();
+        assert_eq!(log.read(None, 10), Ok((vec![], 0)));
+
+        // empty batches don't count
+        log.push(&[Record::Positive(vec![1.into()])]).unwrap();
+        log.push(&[]).unwrap();
+        log.push(&[Record::Negative(vec![1.into()])]).unwrap();
+        assert_eq!(
+            log.read(None, 10),
+            Ok((
+                vec![
+                    (0, vec![Record::Positive(vec![1.into()])]),
+                    (1, vec![Record::Negative(vec![1.into()])])
+                ],
+                2
+            ))
+        );
+        assert_eq!(
+            log.read(Some(1), 10),
+            Ok((vec![(1, vec![Record::Negative(vec![1.into()])])], 2))
+        );
+        assert_eq!(log.read(Some(2), 10), Ok((vec![], 2)));
+        assert!(log.read(Some(3), 10).is_err());
+
+        // the log picks up where it left off when it is reopened
+        drop(log);
+        let mut log = ChangeLog::open("base", &params).unwrap();
+        log.push(&[Record::Positive(vec![2.into()])]).unwrap();
+        assert_eq!(
+            log.read(Some(1), 10),
+            Ok((
+                vec![
+                    (1, vec![Record::Negative(vec![1.into()])]),
+                    (2, vec![Record::Positive(vec![2.into()])])
+                ],
+                3
+            ))
+        );
+    }
+
+    #[test]
+    fn it_trims_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = change_log_params(&dir);
+        let mut log = ChangeLog::open("base", &params).unwrap();
+        let batch: Vec<_> = (0..CHANGE_LOG_RECORDS / 4)
+            .map(|i| Record::Positive(vec![(i as i64).into()]))
+            .collect();
+        for _ in 0..10 {
+            log.push(&batch).unwrap();
+        }
+
+        // only the most recent batches are kept, and reading from before them is reported
+        let (changes, next) = log.read(None, 10).unwrap();
+        assert_eq!(
+            changes.iter().map(|&(seq, _)| seq).collect::<Vec<_>>(),
+            vec![6, 7, 8, 9]
+        );
+        assert_eq!(changes[3].1, batch);
+        assert_eq!(next, 10);
+        let err = log.read(Some(5), 10).unwrap_err();
+        assert!(err.contains("trimmed"), "{}", err);
+
+        // and the trimmed batches don't come back with the file
+        drop(log);
+        let log = ChangeLog::open("base", &params).unwrap();
+        assert_eq!(log.read(None, 1).unwrap().0[0].0, 6);
+        assert!(log.read(Some(5), 10).is_err());
+    }
+
     #[test]
     fn it_works_new() {
         let b = Base::new(vec![]);
@@ -307,7 +780,12 @@ mod tests {
         assert_eq!(b.unmodified, true);
     }
 
-    fn test_lots_of_changes_in_same_batch(mut state: Box<dyn State>) {
+    /// Sets up a base node with columns `x`, `y` and `z` and the given key over `state`, and
+    /// returns a function that processes a batch of operations and materializes the result.
+    fn setup(
+        mut state: Box<dyn State>,
+        key: Vec<usize>,
+    ) -> impl FnMut(Vec<TableOperation>) -> Records {
         use crate::node;
         use crate::prelude::*;
 
@@ -319,7 +797,7 @@ mod tests {
             node::NodeType::Source,
         ));
 
-        let b = Base::new(vec![]).with_key(vec![0, 2]);
+        let b = Base::new(vec![]).with_key(key);
         let global = graph.add_node(Node::new("b", &["x", "y", "z"], b));
         graph.add_edge(source, global, ());
         let local = unsafe { LocalNodeIndex::make(0 as u32) };
@@ -343,12 +821,20 @@ mod tests {
         states.insert(local, state);
         let n = graph[global].take();
         let mut n = n.finalize(&graph);
+        let log = Logger::root(slog::Discard, o!());
 
-        let mut one = move |u: Vec<TableOperation>| {
-            let mut m = n.get_base_mut().unwrap().process(local, u, &states);
+        move |u: Vec<TableOperation>| {
+            let mut m = n
+                .get_base_mut()
+                .unwrap()
+                .process(local, u, &mut states, &log);
             node::materialize(&mut m, None, states.get_mut(local));
             m
-        };
+        }
+    }
+
+    fn test_lots_of_changes_in_same_batch(state: Box<dyn State>) {
+        let mut one = setup(state, vec![0, 2]);
 
         assert_eq!(
             one(vec![
@@ -417,4 +903,173 @@ mod tests {
 
         test_lots_of_changes_in_same_batch(Box::new(state));
     }
+
+    fn indexed_on(column: usize) -> Box<dyn State> {
+        let mut state = MemoryState::default();
+        state.add_key(&[column], None);
+        Box::new(state)
+    }
+
+    #[test]
+    fn it_resolves_operations_on_matching_rows() {
+        let mut one = setup(indexed_on(1), vec![0]);
+        let row = |x: i32, y: &str, z: i32| -> Vec<DataType> { vec![x.into(), y.into(), z.into()] };
+
+        one(vec![
+            TableOperation::Insert(row(1, "a", 10)),
+            TableOperation::Insert(row(2, "a", 20)),
+            TableOperation::Insert(row(3, "b", 30)),
+        ]);
+
+        // matches rows in the state as well as rows inserted earlier in the batch
+        let rs = one(vec![
+            TableOperation::Insert(row(4, "a", 40)),
+            TableOperation::UpdateWhere {
+                column: 1,
+                value: "a".into(),
+                set: vec![
+                    Modification::None,
+                    Modification::None,
+                    Modification::Apply(Operation::Add, 1.into()),
+                ],
+            },
+        ]);
+        assert_eq!(
+            rs,
+            vec![
+                Record::Negative(row(1, "a", 10)),
+                Record::Positive(row(1, "a", 11)),
+                Record::Negative(row(2, "a", 20)),
+                Record::Positive(row(2, "a", 21)),
+                Record::Positive(row(4, "a", 41)),
+            ]
+            .into()
+        );
+
+        let rs = one(vec![TableOperation::DeleteWhere {
+            column: 1,
+            value: "a".into(),
+        }]);
+        assert_eq!(
+            rs,
+            vec![
+                Record::Negative(row(1, "a", 11)),
+                Record::Negative(row(2, "a", 21)),
+                Record::Negative(row(4, "a", 41)),
+            ]
+            .into()
+        );
+
+        // nothing left to match
+        let rs = one(vec![TableOperation::DeleteWhere {
+            column: 1,
+            value: "a".into(),
+        }]);
+        assert_eq!(rs, Records::default());
+    }
+
+    #[test]
+    fn it_matches_rows_as_of_earlier_operations_in_batch() {
+        let mut one = setup(indexed_on(1), vec![0]);
+        let row = |x: i32, y: &str, z: i32| -> Vec<DataType> { vec![x.into(), y.into(), z.into()] };
+        let set_y = |y: &str| {
+            vec![
+                Modification::None,
+                Modification::Set(y.into()),
+                Modification::None,
+            ]
+        };
+
+        one(vec![
+            TableOperation::Insert(row(1, "a", 10)),
+            TableOperation::Insert(row(2, "b", 20)),
+            TableOperation::Insert(row(3, "a", 30)),
+        ]);
+
+        // 1 no longer matches, 2 now does, 3 is already gone, and 4 came and went
+        let rs = one(vec![
+            TableOperation::Update {
+                key: vec![1.into()],
+                set: set_y("b"),
+            },
+            TableOperation::Update {
+                key: vec![2.into()],
+                set: set_y("a"),
+            },
+            TableOperation::Delete {
+                key: vec![3.into()],
+            },
+            TableOperation::Insert(row(4, "a", 40)),
+            TableOperation::Update {
+                key: vec![4.into()],
+                set: set_y("c"),
+            },
+            TableOperation::DeleteWhere {
+                column: 1,
+                value: "a".into(),
+            },
+        ]);
+        assert_eq!(
+            rs,
+            vec![
+                Record::Negative(row(1, "a", 10)),
+                Record::Positive(row(1, "b", 10)),
+                Record::Negative(row(2, "b", 20)),
+                Record::Negative(row(3, "a", 30)),
+                Record::Positive(row(4, "c", 40)),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_ignores_matching_on_unindexed_columns() {
+        let mut one = setup(indexed_on(1), vec![0]);
+        let row = |x: i32, y: &str, z: i32| -> Vec<DataType> { vec![x.into(), y.into(), z.into()] };
+
+        one(vec![
+            TableOperation::Insert(row(1, "a", 10)),
+            TableOperation::Insert(row(2, "b", 10)),
+        ]);
+
+        // the other operations in the batch still apply
+        let rs = one(vec![
+            TableOperation::DeleteWhere {
+                column: 2,
+                value: 10.into(),
+            },
+            TableOperation::DeleteWhere {
+                column: 1,
+                value: "b".into(),
+            },
+        ]);
+        assert_eq!(rs, vec![Record::Negative(row(2, "b", 10))].into());
+    }
+
+    #[test]
+    fn it_ignores_primary_key_modifications() {
+        let mut one = setup(Box::new(MemoryState::default()), vec![0]);
+
+        one(vec![TableOperation::Insert(vec![
+            1.into(),
+            "a".into(),
+            10.into(),
+        ])]);
+        let rs = one(vec![TableOperation::Update {
+            key: vec![1.into()],
+            set: vec![
+                Modification::Set(2.into()),
+                Modification::Set("b".into()),
+                Modification::None,
+            ],
+        }]);
+        assert_eq!(
+            rs,
+            vec![
+                Record::Negative(vec![1.into(), "a".into(), 10.into()]),
+                Record::Positive(vec![1.into(), "b".into(), 10.into()]),
+            ]
+            .into()
+        );
+    }
 }
@@ -47,6 +47,15 @@ impl Egress {
         self.tags.insert(tag, dst);
     }
 
+    pub fn remove_tx(&mut self, dst: NodeIndex) {
+        self.txs.retain(|tx| tx.node != dst);
+        self.tags.retain(|_, n| *n != dst);
+    }
+
+    pub(crate) fn destinations<'a>(&'a self) -> impl Iterator<Item = ReplicaAddr> + 'a {
+        self.txs.iter().map(|tx| tx.dest)
+    }
+
     pub fn process(
         &mut self,
         m: &mut Option<Box<Packet>>,
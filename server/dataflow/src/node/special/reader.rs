@@ -1,5 +1,7 @@
 use crate::backlog;
+use crate::eviction::EvictionPolicy;
 use crate::prelude::*;
+use nom_sql::OrderType;
 
 #[derive(Serialize, Deserialize)]
 pub struct Reader {
@@ -8,6 +10,7 @@ pub struct Reader {
 
     for_node: NodeIndex,
     state: Option<Vec<usize>>,
+    order: Vec<(usize, OrderType)>,
 }
 
 impl Clone for Reader {
@@ -16,6 +19,7 @@ impl Clone for Reader {
         Reader {
             writer: None,
             state: self.state.clone(),
+            order: self.order.clone(),
             for_node: self.for_node,
         }
     }
@@ -26,6 +30,7 @@ impl Reader {
         Reader {
             writer: None,
             state: None,
+            order: Vec::new(),
             for_node,
         }
     }
@@ -36,8 +41,7 @@ impl Reader {
         self.for_node
     }
 
-    #[allow(dead_code)]
-    fn writer(&self) -> Option<&backlog::WriteHandle> {
+    pub(crate) fn writer(&self) -> Option<&backlog::WriteHandle> {
         self.writer.as_ref()
     }
 
@@ -49,6 +53,7 @@ impl Reader {
         Self {
             writer: self.writer.take(),
             state: self.state.clone(),
+            order: self.order.clone(),
             for_node: self.for_node,
         }
     }
@@ -81,6 +86,15 @@ impl Reader {
         }
     }
 
+    /// The order in which rows for a given key should be returned when paginating.
+    pub fn order(&self) -> &[(usize, OrderType)] {
+        &self.order[..]
+    }
+
+    pub fn set_order(&mut self, order: Vec<(usize, OrderType)>) {
+        self.order = order;
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         self.writer.as_ref().map(|w| w.is_empty()).unwrap_or(true)
     }
@@ -89,14 +103,12 @@ impl Reader {
         self.writer.as_ref().map(SizeOf::deep_size_of)
     }
 
-    /// Evict a randomly selected key, returning the number of bytes evicted.
-    /// Note that due to how `evmap` applies the evictions asynchronously, we can only evict a
-    /// single key at a time here.
-    pub(crate) fn evict_random_keys(&mut self, n: usize) -> u64 {
+    /// Evict `n` keys chosen by `policy`, returning the number of bytes evicted.
+    pub(crate) fn evict_cold_keys(&mut self, policy: &dyn EvictionPolicy, n: usize) -> u64 {
         let mut bytes_freed = 0;
         if let Some(ref mut handle) = self.writer {
             let mut rng = rand::thread_rng();
-            bytes_freed = handle.evict_random_keys(&mut rng, n);
+            bytes_freed = handle.evict_cold_keys(policy, &mut rng, n);
             handle.swap();
         }
         bytes_freed
@@ -118,13 +130,25 @@ impl Reader {
             // make sure we don't fill a partial materialization
             // hole with incomplete (i.e., non-replay) state.
             if m.is_regular() && state.is_partial() {
+                let mut fill = Vec::new();
                 m.map_data(|data| {
                     data.retain(|row| {
                         match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
                             Ok((None, _)) => {
-                                // row would miss in partial state.
-                                // leave it blank so later lookup triggers replay.
-                                false
+                                let key: Vec<_> =
+                                    state.key().iter().map(|&c| row[c].clone()).collect();
+                                if state.in_filled_range(&key) {
+                                    // the key falls in a range that has been replayed in its
+                                    // entirety, so it is not a hole -- it just had no rows yet.
+                                    if !fill.contains(&key) {
+                                        fill.push(key);
+                                    }
+                                    true
+                                } else {
+                                    // row would miss in partial state.
+                                    // leave it blank so later lookup triggers replay.
+                                    false
+                                }
                             }
                             Err(_) => unreachable!(),
                             _ => {
@@ -135,6 +159,9 @@ impl Reader {
                         }
                     });
                 });
+                for key in fill {
+                    state.mut_with_key(key).mark_filled();
+                }
             }
 
             // it *can* happen that multiple readers miss (and thus request replay for) the
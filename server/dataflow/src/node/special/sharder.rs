@@ -52,6 +52,10 @@ impl Sharder {
         self.shard_by
     }
 
+    pub(crate) fn destinations<'a>(&'a self) -> impl Iterator<Item = ReplicaAddr> + 'a {
+        self.txs.iter().map(|&(_, dest)| dest)
+    }
+
     #[inline]
     fn to_shard(&self, r: &Record) -> usize {
         self.shard(&r[self.shard_by])
@@ -66,7 +70,7 @@ impl Sharder {
         &mut self,
         m: &mut Option<Box<Packet>>,
         index: LocalNodeIndex,
-        is_sharded: bool,
+        on_shard: Option<usize>,
         is_last_sharder_for_tag: Option<bool>,
         output: &mut dyn Executor,
     ) {
@@ -96,6 +100,19 @@ impl Sharder {
             // this is the last replay piece for a full replay
             // we need to make sure it gets to every shard so they know to ready the node
             dest = Destination::All;
+        } else if let Packet::ReplayPiece {
+            context: payload::ReplayPieceContext::Regular { last: false },
+            ..
+        } = *m
+        {
+            if on_shard.is_some() {
+                // the shard merger below us in each destination shard holds back updates from any
+                // source shard whose full replay it hasn't yet seen start. if we only forwarded
+                // to the shards we have data for, a destination shard could miss the start of our
+                // replay, and would then let through (and lose) updates we send it in the
+                // meantime. so, make sure every shard hears about every piece.
+                dest = Destination::All;
+            }
         } else if let Packet::ReplayPiece {
             context:
                 payload::ReplayPieceContext::Partial {
@@ -141,19 +158,18 @@ impl Sharder {
             Destination::Any => {}
         }
 
-        if is_sharded {
-            // FIXME: we don't know how many shards in the destination domain our sibling Sharders
-            // sent to, so we don't know what to put here. we *could* put self.txs.len() and send
-            // empty messages to all other shards, which is probably pretty sensible, but that only
-            // solves half the problem. the destination shard domains will then recieve *multiple*
-            // replay pieces for each incoming replay piece, and needs to combine them somehow.
-            // it's unclear how we do that.
-            unimplemented!();
-        }
+        // if we are sharded ourselves, every destination shard hears from all of our sibling
+        // sharders, and has a shard merger right below its ingress to combine the pieces. that
+        // merger needs to know which of our shards each piece came from, just like with sharded
+        // egress nodes.
+        let src = match on_shard {
+            Some(shard) => unsafe { LocalNodeIndex::make(shard as u32) },
+            None => index,
+        };
 
         for (i, &mut (dst, addr)) in self.txs.iter_mut().enumerate() {
             if let Some(mut shard) = self.sharded.remove(i) {
-                shard.link_mut().src = index;
+                shard.link_mut().src = src;
                 shard.link_mut().dst = dst;
                 output.send(addr, shard);
             }
@@ -166,11 +182,8 @@ impl Sharder {
         tag: Tag,
         keys: &[Vec<DataType>],
         src: LocalNodeIndex,
-        is_sharded: bool,
         output: &mut dyn Executor,
     ) {
-        assert!(!is_sharded);
-
         if key_columns.len() == 1 && key_columns[0] == self.shard_by {
             // Send only to the shards that must evict something.
             for key in keys {
@@ -4,6 +4,13 @@ use std::collections::HashMap;
 use crate::prelude::*;
 
 /// This will get distinct records from a set of records compared over a given set of columns
+///
+/// Each distinct record is emitted with the number of records it stands for in a column after
+/// the record's own columns, so that the record is only revoked once the last of them is. Columns
+/// that records are not compared over hold the values of the first record that was seen.
+///
+/// Distinct records are looked up by `key`, which should be the columns that views below look
+/// them up by, so that a partial replay fills in every record that a later write may change.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Distinct {
     // Parent Node
@@ -12,16 +19,25 @@ pub struct Distinct {
     us: Option<IndexPair>,
 
     group_by: Vec<usize>,
+    key: Vec<usize>,
+    // the column holding the count, after the parent's columns
+    count: usize,
 }
 
 impl Distinct {
-    pub fn new(src: NodeIndex, group_by: Vec<usize>) -> Self {
+    pub fn new(src: NodeIndex, group_by: Vec<usize>, key: Vec<usize>) -> Self {
         let mut group_by = group_by;
         group_by.sort();
+        assert!(
+            key.iter().all(|c| group_by.contains(c)),
+            "distinct records must be looked up by columns they are compared over"
+        );
         Distinct {
             src: src.into(),
             us: None,
             group_by,
+            key,
+            count: 0,
         }
     }
 }
@@ -41,7 +57,7 @@ impl Ingredient for Distinct {
         _: &mut dyn Executor,
         from: LocalNodeIndex,
         rs: Records,
-        _: Option<&[usize]>,
+        replay_key_cols: Option<&[usize]>,
         _: &DomainNodes,
         state: &StateMap,
     ) -> ProcessingResult {
@@ -60,14 +76,8 @@ impl Ingredient for Distinct {
             .get(*us)
             .expect("Distinct must have its own state initialized");
 
-        let pos_comp = |a: &Record, b: &Record| a.is_positive().cmp(&b.is_positive());
-
-        let mut rs: Vec<_> = rs.into();
-        // Sort by positive or negative
-        rs.sort_by(&pos_comp);
-
-        let group_by = &self.group_by;
-
+        let group_by = &self.group_by[..];
+        let key_cols = &self.key[..];
         let group_cmp = |a: &Record, b: &Record| {
             group_by
                 .iter()
@@ -75,61 +85,90 @@ impl Ingredient for Distinct {
                 .cmp(group_by.iter().map(|&col| &b[col]))
         };
 
-        // First, we want to be smart about multiple added/removed rows with same group.
-        // For example, if we get a -, then a +, for the same group, we don't want to
-        // execute two queries. We'll do this by sorting the batch by our group by.
+        // Sort the batch by our group by, so that all the changes to a record are handled at
+        // once, with a single lookup.
+        let mut rs: Vec<_> = rs.into();
         rs.sort_by(&group_cmp);
 
         let mut output = Vec::new();
-        let mut prev_grp = Vec::new();
-        let mut prev_pos = false;
-
-        for rec in rs {
-            let group_by = &self.group_by[..];
-            let group = rec
+        let mut misses = Vec::new();
+        let mut lookups = Vec::new();
+
+        let mut rs = rs.into_iter().peekable();
+        while let Some(first) = rs.next() {
+            let mut group_rs = vec![first];
+            while let Some(r) = rs.next_if(|r| group_cmp(&group_rs[0], r) == Ordering::Equal) {
+                group_rs.push(r);
+            }
+            let key: Vec<_> = key_cols
                 .iter()
-                .enumerate()
-                .filter_map(|(i, v)| {
-                    if self.group_by.iter().any(|col| col == &i) {
-                        Some(v)
-                    } else {
-                        None
+                .map(|&col| group_rs[0][col].clone())
+                .collect();
+
+            let old = match db.lookup(key_cols, &KeyType::from(&key[..])) {
+                LookupResult::Some(rr) => {
+                    if replay_key_cols.is_some() {
+                        lookups.push(Lookup {
+                            on: *us,
+                            cols: key_cols.to_vec(),
+                            key: key.clone(),
+                        });
                     }
-                })
-                .cloned()
-                .collect::<Vec<_>>();
-
-            // Do not take overlapping elements
-            if prev_grp.iter().cmp(group_by.iter().map(|&col| &rec[col])) == Ordering::Equal
-                && prev_pos == rec.is_positive()
-            {
+
+                    let mut rr = rr
+                        .into_iter()
+                        .filter(|r| group_by.iter().all(|&col| r[col] == group_rs[0][col]));
+                    let old = rr.next();
+                    debug_assert!(rr.next().is_none(), "a distinct record appeared twice");
+                    old
+                }
+                LookupResult::Missing => {
+                    misses.extend(group_rs.into_iter().map(|r| Miss {
+                        on: *us,
+                        lookup_idx: key_cols.to_vec(),
+                        lookup_cols: key_cols.to_vec(),
+                        replay_cols: replay_key_cols.map(Vec::from),
+                        record: r.extract().0,
+                    }));
+                    continue;
+                }
+            };
+
+            let delta: i64 = group_rs
+                .iter()
+                .map(|r| if r.is_positive() { 1 } else { -1 })
+                .sum();
+            if delta == 0 {
                 continue;
             }
 
-            // make ready for the new one
-            prev_grp.clear();
-            prev_grp.extend(group_by.iter().map(|&col| &rec[col]).cloned());
-            prev_pos = rec.is_positive();
-
-            let positive = rec.is_positive();
-            match db.lookup(group_by, &KeyType::from(&group[..])) {
-                LookupResult::Some(rr) => {
-                    if positive {
-                        //println!("record {:?}", rr);
-                        if rr.is_empty() {
-                            output.push(rec.clone());
-                        }
-                    } else if !rr.is_empty() {
-                        output.push(rec.clone());
-                    }
+            let count = old.as_ref().map(|r| i64::from(&r[self.count])).unwrap_or(0);
+            let mut row = match old {
+                Some(old) => {
+                    let old = old.into_owned();
+                    output.push(Record::Negative(old.clone()));
+                    old
                 }
-                LookupResult::Missing => unimplemented!("Distinct does not yet support partial"),
+                None => match group_rs.into_iter().find(|r| r.is_positive()) {
+                    Some(r) => {
+                        let mut row = r.extract().0;
+                        row.push(DataType::None);
+                        row
+                    }
+                    // the records we were told to revoke were never here
+                    None => continue,
+                },
+            };
+            if count + delta > 0 {
+                row[self.count] = (count + delta).into();
+                output.push(Record::Positive(row));
             }
         }
 
         ProcessingResult {
             results: output.into(),
-            ..Default::default()
+            lookups,
+            misses,
         }
     }
 
@@ -137,7 +176,9 @@ impl Ingredient for Distinct {
         "Distinct".into()
     }
 
-    fn on_connected(&mut self, _: &Graph) {}
+    fn on_connected(&mut self, g: &Graph) {
+        self.count = g[self.src.as_global()].fields().len();
+    }
 
     fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
         self.src.remap(remap);
@@ -145,19 +186,21 @@ impl Ingredient for Distinct {
     }
 
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if column == self.count {
+            return vec![(self.src.as_global(), None)];
+        }
         vec![(self.src.as_global(), Some(column))]
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col == self.count {
+            return None;
+        }
         Some(vec![(self.src.as_global(), col)])
     }
 
-    fn requires_full_materialization(&self) -> bool {
-        true
-    }
-
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
-        vec![(this, self.group_by.clone())].into_iter().collect()
+        vec![(this, self.key.clone())].into_iter().collect()
     }
 }
 
@@ -169,18 +212,28 @@ mod tests {
     use crate::ops;
 
     fn setup(materialized: bool) -> ops::test::MockGraph {
+        setup_keyed(materialized, vec![1, 2])
+    }
+
+    fn setup_keyed(materialized: bool, key: Vec<usize>) -> ops::test::MockGraph {
         let mut g = ops::test::MockGraph::new();
 
         let s = g.add_base("source", &["x", "y", "z"]);
         g.set_op(
             "distinct",
-            &["x", "y", "z"],
-            Distinct::new(s.as_global(), vec![1, 2]),
+            &["x", "y", "z", "count"],
+            Distinct::new(s.as_global(), vec![1, 2], key),
             materialized,
         );
         g
     }
 
+    fn counted(r: &[DataType], count: i64) -> Vec<DataType> {
+        let mut r = r.to_vec();
+        r.push(count.into());
+        r
+    }
+
     #[test]
     fn simple_distinct() {
         let mut g = setup(true);
@@ -190,13 +243,58 @@ mod tests {
         let r3: Vec<DataType> = vec![1.into(), "c".into(), 2.into()];
 
         let a = g.narrow_one_row(r1.clone(), true);
-        assert_eq!(a, vec![r1.clone()].into());
+        assert_eq!(a, vec![counted(&r1, 1)].into());
 
-        let a = g.narrow_one_row(r2.clone(), false);
-        assert_eq!(a.len(), 0);
+        let a = g.narrow_one_row(r2.clone(), true);
+        assert_eq!(
+            a,
+            vec![(counted(&r1, 1), false), (counted(&r1, 2), true)].into()
+        );
 
         let a = g.narrow_one_row(r3.clone(), true);
-        assert_eq!(a, vec![r3.clone()].into());
+        assert_eq!(a, vec![counted(&r3, 1)].into());
+    }
+
+    #[test]
+    fn it_misses_with_partial_state() {
+        let mut g = setup(true);
+        g.set_partial(&[1, 2]);
+        let src = g.narrow_base_id();
+
+        let r1: Vec<DataType> = vec![1.into(), "z".into(), 1.into()];
+        let (m, _) = g.input(src, vec![r1.clone()], None);
+        assert!(m.results.is_empty());
+        assert_eq!(
+            m.misses,
+            vec![Miss {
+                on: g.node().local_addr(),
+                lookup_idx: vec![1, 2],
+                lookup_cols: vec![1, 2],
+                replay_cols: None,
+                record: r1.clone(),
+            }]
+        );
+
+        // once the group has been filled, records only change its count
+        g.fill(vec!["z".into(), 1.into()], vec![counted(&r1, 1)]);
+        let r2: Vec<DataType> = vec![2.into(), "z".into(), 1.into()];
+        let (m, _) = g.input(src, vec![r2], None);
+        assert_eq!(
+            m.results,
+            vec![(counted(&r1, 1), false), (counted(&r1, 2), true)].into()
+        );
+        assert!(m.misses.is_empty());
+
+        // and lookups made while replaying are reported
+        let (m, _) = g.input(src, vec![r1.clone()], Some(&[1, 2]));
+        assert_eq!(
+            m.lookups,
+            vec![Lookup {
+                on: g.node().local_addr(),
+                cols: vec![1, 2],
+                key: vec!["z".into(), 1.into()],
+            }]
+        );
     }
 
     #[test]
@@ -208,22 +306,85 @@ mod tests {
         let r3: Vec<DataType> = vec![3.into(), "c".into(), 2.into()];
 
         let a = g.narrow_one_row(r1.clone(), true);
-        println!("{:?}", a);
-        assert_eq!(a, vec![r1.clone()].into());
+        assert_eq!(a, vec![counted(&r1, 1)].into());
 
         let a = g.narrow_one_row(r2.clone(), true);
-        println!("{:?}", a);
-        assert_eq!(a, vec![r2.clone()].into());
+        assert_eq!(a, vec![counted(&r2, 1)].into());
 
         let a = g.narrow_one_row(r3.clone(), true);
-        assert_eq!(a, vec![r3.clone()].into());
+        assert_eq!(a, vec![counted(&r3, 1)].into());
 
         let a = g.narrow_one_row((r1.clone(), false), true);
-        println!("{:?}", a);
+        assert_eq!(a, vec![(counted(&r1, 1), false)].into());
 
         let a = g.narrow_one_row((r1.clone(), true), true);
-        println!("{:?}", a);
-        assert_eq!(a, vec![r1.clone()].into());
+        assert_eq!(a, vec![counted(&r1, 1)].into());
+    }
+
+    #[test]
+    fn it_keeps_records_until_the_last_duplicate_is_removed() {
+        let mut g = setup(true);
+
+        let r1: Vec<DataType> = vec![1.into(), "z".into(), 1.into()];
+        let r2: Vec<DataType> = vec![2.into(), "z".into(), 1.into()];
+
+        let a = g.narrow_one(vec![(r1.clone(), true), (r2.clone(), true)], true);
+        assert_eq!(a, vec![counted(&r1, 2)].into());
+
+        // removing one of the duplicates keeps the record around
+        let a = g.narrow_one_row((r2.clone(), false), true);
+        assert_eq!(
+            a,
+            vec![(counted(&r1, 2), false), (counted(&r1, 1), true)].into()
+        );
+
+        // removing the last one revokes it
+        let a = g.narrow_one_row((r1.clone(), false), true);
+        assert_eq!(a, vec![(counted(&r1, 1), false)].into());
+
+        // and a removal and an addition in the same batch cancel out
+        let a = g.narrow_one_row(r1.clone(), true);
+        assert_eq!(a, vec![counted(&r1, 1)].into());
+        let a = g.narrow_one(vec![(r1.clone(), false), (r2.clone(), true)], true);
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn it_looks_up_records_by_key() {
+        let mut g = setup_keyed(true, vec![1]);
+        g.set_partial(&[1]);
+        let src = g.narrow_base_id();
+
+        let r1: Vec<DataType> = vec![1.into(), "z".into(), 1.into()];
+        let r2: Vec<DataType> = vec![2.into(), "z".into(), 2.into()];
+        let (m, _) = g.input(src, vec![r1.clone()], None);
+        assert_eq!(
+            m.misses,
+            vec![Miss {
+                on: g.node().local_addr(),
+                lookup_idx: vec![1],
+                lookup_cols: vec![1],
+                replay_cols: None,
+                record: r1.clone(),
+            }]
+        );
+
+        // records that share a key are still told apart by all the columns they are compared over
+        g.fill(vec!["z".into()], vec![counted(&r1, 1)]);
+        let (m, _) = g.input(src, vec![r1.clone(), r2.clone()], None);
+        assert_eq!(m.results.len(), 3);
+        assert!(m
+            .results
+            .iter()
+            .any(|r| r == &(counted(&r1, 1), false).into()));
+        assert!(m
+            .results
+            .iter()
+            .any(|r| r == &(counted(&r1, 2), true).into()));
+        assert!(m
+            .results
+            .iter()
+            .any(|r| r == &(counted(&r2, 1), true).into()));
     }
 
     #[test]
@@ -243,12 +404,25 @@ mod tests {
             ],
             true,
         );
-        assert!(a.iter().any(|r| r == &(r1.clone(), true).into()));
-        assert!(a.iter().any(|r| r == &(r2.clone(), true).into()));
-        assert!(a.iter().any(|r| r == &(r3.clone(), true).into()));
+        assert_eq!(a.len(), 3);
+        assert!(a.iter().any(|r| r == &(counted(&r1, 2), true).into()));
+        assert!(a.iter().any(|r| r == &(counted(&r2, 1), true).into()));
+        assert!(a.iter().any(|r| r == &(counted(&r3, 1), true).into()));
 
         let a = g.narrow_one(vec![(r1.clone(), false), (r3.clone(), true)], true);
-        assert!(a.iter().any(|r| r == &(r1.clone(), false).into()));
-        assert!(!a.iter().any(|r| r == &(r3.clone(), true).into()));
+        assert!(a.iter().any(|r| r == &(counted(&r1, 2), false).into()));
+        assert!(a.iter().any(|r| r == &(counted(&r1, 1), true).into()));
+        assert!(a.iter().any(|r| r == &(counted(&r3, 1), false).into()));
+        assert!(a.iter().any(|r| r == &(counted(&r3, 2), true).into()));
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup(false);
+        assert_eq!(
+            g.node().resolve(0),
+            Some(vec![(g.narrow_base_id().as_global(), 0)])
+        );
+        assert_eq!(g.node().resolve(3), None);
     }
 }
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::sync;
 
+use crate::ops::project::ProjectExpression;
 use crate::prelude::*;
 pub use nom_sql::Operator;
 
@@ -17,6 +18,17 @@ pub struct Filter {
 pub enum Value {
     Constant(DataType),
     Column(usize),
+    Expression(ProjectExpression),
+}
+
+impl Value {
+    fn eval<'a>(&'a self, r: &'a [DataType]) -> Cow<'a, DataType> {
+        match *self {
+            Value::Constant(ref dt) => Cow::Borrowed(dt),
+            Value::Column(c) => Cow::Borrowed(&r[c]),
+            Value::Expression(ref e) => Cow::Owned(e.eval(r)),
+        }
+    }
 }
 
 impl From<DataType> for Value {
@@ -30,6 +42,7 @@ impl Display for Value {
         match *self {
             Value::Constant(ref c) => write!(f, "{}", c),
             Value::Column(ref ci) => write!(f, "col: {}", ci),
+            Value::Expression(ref e) => write!(f, "({})", e),
         }
     }
 }
@@ -38,6 +51,112 @@ impl Display for Value {
 pub enum FilterCondition {
     Comparison(Operator, Value),
     In(Vec<DataType>),
+    /// Compares the result of an expression over the record, rather than the value of the
+    /// condition's column, with a value.
+    Expression(ProjectExpression, Operator, Value),
+}
+
+impl FilterCondition {
+    /// Returns true if the record `r` satisfies this condition on column `col`.
+    pub fn matches(&self, col: usize, r: &[DataType]) -> bool {
+        let compare = |op: &Operator, d: &DataType, v: &DataType| match *op {
+            Operator::Equal | Operator::Is => d == v,
+            Operator::NotEqual => d != v,
+            Operator::Greater => d > v,
+            Operator::GreaterOrEqual => d >= v,
+            Operator::Less => d < v,
+            Operator::LessOrEqual => d <= v,
+            Operator::Like => like(d, v).unwrap_or(false),
+            Operator::NotLike => like(d, v).map(|m| !m).unwrap_or(false),
+            // lists of more than one value are given as `FilterCondition::In`
+            Operator::In => d == v,
+            Operator::And | Operator::Or | Operator::Not => {
+                unreachable!("{} is not a comparison", op)
+            }
+        };
+
+        // an expression over a NULL or a value that isn't a number, or one that divides by zero,
+        // is NULL, and matches nothing
+        let unknown = |op: &Operator, d: &DataType| d.is_none() && *op != Operator::Is;
+
+        match *self {
+            FilterCondition::Comparison(ref op, Value::Expression(ref e)) => {
+                let v = e.eval(r);
+                !unknown(op, &v) && compare(op, &r[col], &v)
+            }
+            FilterCondition::Comparison(ref op, ref v) => compare(op, &r[col], &v.eval(r)),
+            FilterCondition::In(ref fs) => fs.contains(&r[col]),
+            FilterCondition::Expression(ref e, ref op, ref v) => {
+                let d = e.eval(r);
+                !unknown(op, &d) && compare(op, &d, &v.eval(r))
+            }
+        }
+    }
+}
+
+/// Whether `d` matches the SQL `LIKE` pattern `pattern`, in which `%` matches any sequence of
+/// characters, `_` matches any single character, and `\` escapes the character after it.
+///
+/// Returns `None` if either of them is not text.
+pub(crate) fn like(d: &DataType, pattern: &DataType) -> Option<bool> {
+    enum Token {
+        Any,
+        One,
+        Char(char),
+    }
+
+    if !d.is_string() || !pattern.is_string() {
+        return None;
+    }
+    let s: Vec<char> = <&str>::from(d).chars().collect();
+    let mut p = Vec::new();
+    let mut chars = <&str>::from(pattern).chars();
+    while let Some(c) = chars.next() {
+        p.push(match c {
+            '%' => Token::Any,
+            '_' => Token::One,
+            // a trailing `\` has nothing to escape, and matches itself
+            '\\' => Token::Char(chars.next().unwrap_or('\\')),
+            c => Token::Char(c),
+        });
+    }
+
+    // Match greedily, remembering only the last `%` seen. When the rest of the pattern fails to
+    // match, that `%` takes one more character and we retry from there. Earlier `%`s never need to
+    // be revisited, since the last one can absorb anything they would, so this takes O(s * p) time
+    // rather than the exponential time of trying every split at every `%`.
+    let (mut si, mut pi) = (0, 0);
+    let mut backtrack = None;
+    while si < s.len() {
+        match p.get(pi) {
+            Some(Token::Any) => {
+                backtrack = Some((pi, si));
+                pi += 1;
+            }
+            Some(Token::One) => {
+                si += 1;
+                pi += 1;
+            }
+            Some(Token::Char(c)) if *c == s[si] => {
+                si += 1;
+                pi += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bs)) => {
+                    backtrack = Some((bp, bs + 1));
+                    pi = bp + 1;
+                    si = bs + 1;
+                }
+                None => return Some(false),
+            },
+        }
+    }
+
+    // whatever is left of the pattern must match the empty string
+    Some(p[pi..].iter().all(|t| match *t {
+        Token::Any => true,
+        _ => false,
+    }))
 }
 
 impl Filter {
@@ -81,31 +200,7 @@ impl Ingredient for Filter {
         _: &DomainNodes,
         _: &StateMap,
     ) -> ProcessingResult {
-        rs.retain(|r| {
-            self.filter.iter().all(|(i, cond)| {
-                // check if this filter matches
-                let d = &r[*i];
-                match cond {
-                    FilterCondition::Comparison(ref op, ref f) => {
-                        let v = match *f {
-                            Value::Constant(ref dt) => dt,
-                            Value::Column(c) => &r[c],
-                        };
-                        match *op {
-                            Operator::Equal => d == v,
-                            Operator::NotEqual => d != v,
-                            Operator::Greater => d > v,
-                            Operator::GreaterOrEqual => d >= v,
-                            Operator::Less => d < v,
-                            Operator::LessOrEqual => d <= v,
-                            Operator::In => unreachable!(),
-                            _ => unimplemented!(),
-                        }
-                    }
-                    FilterCondition::In(ref fs) => fs.contains(d),
-                }
-            })
-        });
+        rs.retain(|r| self.filter.iter().all(|(i, cond)| cond.matches(*i, r)));
 
         ProcessingResult {
             results: rs,
@@ -150,6 +245,8 @@ impl Ingredient for Filter {
                             .collect::<Vec<_>>()
                             .join(", ")
                     )),
+                    FilterCondition::Expression(ref e, ref op, ref x) =>
+                        Some(format!("({}) {} {}", e, escape(&format!("{}", op)), x)),
                 })
                 .collect::<Vec<_>>()
                 .as_slice()
@@ -172,30 +269,7 @@ impl Ingredient for Filter {
         self.lookup(*self.src, columns, key, nodes, states)
             .and_then(|result| {
                 let f = self.filter.clone();
-                let filter = move |r: &[DataType]| {
-                    f.iter().all(|(i, ref cond)| {
-                        // check if this filter matches
-                        let d = &r[*i];
-                        match *cond {
-                            FilterCondition::Comparison(ref op, ref f) => {
-                                let v = match *f {
-                                    Value::Constant(ref dt) => dt,
-                                    Value::Column(c) => &r[c],
-                                };
-                                match *op {
-                                    Operator::Equal => d == v,
-                                    Operator::NotEqual => d != v,
-                                    Operator::Greater => d > v,
-                                    Operator::GreaterOrEqual => d >= v,
-                                    Operator::Less => d < v,
-                                    Operator::LessOrEqual => d <= v,
-                                    _ => unimplemented!(),
-                                }
-                            }
-                            FilterCondition::In(ref fs) => fs.contains(d),
-                        }
-                    })
-                };
+                let filter = move |r: &[DataType]| f.iter().all(|(i, cond)| cond.matches(*i, r));
 
                 match result {
                     Some(rs) => {
@@ -392,6 +466,62 @@ mod tests {
         assert_eq!(g.narrow_one_row(left.clone(), false), Records::default());
     }
 
+    #[test]
+    fn it_works_with_expressions() {
+        use crate::ops::project::ProjectExpression;
+        use nom_sql::ArithmeticOperator;
+
+        let times = |left, right| {
+            ProjectExpression::arithmetic(
+                ArithmeticOperator::Multiply,
+                ProjectExpression::Column(left),
+                right,
+            )
+        };
+        let mut g = setup(
+            false,
+            Some(&[
+                // x * y > 10
+                (
+                    0,
+                    FilterCondition::Expression(
+                        times(0, ProjectExpression::Column(1)),
+                        Operator::Greater,
+                        Value::Constant(10.into()),
+                    ),
+                ),
+                // y < x * 2
+                (
+                    1,
+                    FilterCondition::Comparison(
+                        Operator::Less,
+                        Value::Expression(times(0, ProjectExpression::Literal(2.into()))),
+                    ),
+                ),
+            ]),
+        );
+
+        let mut left: Vec<DataType>;
+
+        // both conditions match (4 * 3 > 10, 3 < 4 * 2)
+        left = vec![4.into(), 3.into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // first condition fails (2 * 3 > 10)
+        left = vec![2.into(), 3.into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // second condition fails (11 < 1 * 2)
+        left = vec![1.into(), 11.into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // arithmetic over values that aren't numbers is NULL, and matches nothing
+        left = vec!["a".into(), 3.into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+        left = vec![DataType::from(nom_sql::Literal::CurrentTimestamp), 3.into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
     #[test]
     fn it_works_with_in_list() {
         let mut g = setup(
@@ -420,4 +550,69 @@ mod tests {
         left = vec![42.into(), "b".into()];
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
+
+    #[test]
+    fn it_matches_like_patterns() {
+        let matches = |s: &str, p: &str| like(&s.into(), &p.into()).unwrap();
+
+        assert!(matches("", ""));
+        assert!(matches("", "%%"));
+        assert!(!matches("", "_"));
+        assert!(matches("abc", "abc"));
+        assert!(!matches("abc", "ab"));
+        assert!(matches("abc", "a%"));
+        assert!(matches("abc", "%c"));
+        assert!(matches("abc", "%b%"));
+        assert!(matches("abc", "_b_"));
+        assert!(!matches("abc", "_c%"));
+        assert!(matches("abcbd", "a%b_"));
+        assert!(matches("mississippi", "m%iss%ppi"));
+        assert!(!matches("mississippi", "m%iss%ppx"));
+        assert!(matches("50%", "50\\%"));
+        assert!(!matches("500", "50\\%"));
+        assert!(matches("a\\", "a\\"));
+
+        // many `%`s that almost match don't take exponential time
+        let s = "a".repeat(1000);
+        assert!(!matches(&s, "%a%a%a%a%a%a%a%a%a%a%a%a%b"));
+        assert!(matches(&s, "%a%a%a%a%a%a%a%a%a%a%a%a%"));
+    }
+
+    #[test]
+    fn it_works_with_like() {
+        let mut g = setup(
+            false,
+            Some(&[
+                (
+                    0,
+                    FilterCondition::Comparison(Operator::Like, Value::Constant("a%c_".into())),
+                ),
+                (
+                    1,
+                    FilterCondition::Comparison(
+                        Operator::NotLike,
+                        Value::Constant("100\\%".into()),
+                    ),
+                ),
+            ]),
+        );
+
+        let mut left: Vec<DataType>;
+
+        // both conditions match ("abcd" LIKE "a%c_", "100" NOT LIKE "100\%")
+        left = vec!["abcd".into(), "100".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // first condition fails ("abc" NOT LIKE "a%c_")
+        left = vec!["abc".into(), "100".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // second condition fails ("100%" LIKE "100\%")
+        left = vec!["acd".into(), "100%".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // neither condition matches values that aren't text
+        left = vec![1.into(), 2.into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
 }
@@ -5,11 +5,19 @@ use crate::prelude::*;
 
 /// Supported aggregation operators.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
 pub enum Aggregation {
     /// Count the number of records for each group. The value for the `over` column is ignored.
     COUNT,
     /// Sum the value of the `over` column for all records of each group.
     SUM,
+    /// Average the non-NULL values of the `over` column for all records of each group.
+    AVG,
+    /// Count the number of distinct non-NULL values of the `over` column for each group.
+    ///
+    /// The source must be a `COUNT` grouped by the group columns and the `over` column, so that
+    /// each of its records holds the number of times a value occurs in a group.
+    COUNT_DISTINCT,
 }
 
 impl Aggregation {
@@ -17,7 +25,8 @@ impl Aggregation {
     ///
     /// The aggregation will aggregate the value in column number `over` from its inputs (i.e.,
     /// from the `src` node in the graph), and use the columns in the `group_by` array as a group
-    /// identifier. The `over` column should not be in the `group_by` array.
+    /// identifier. The `over` column should not be in the `group_by` array, unless this is a
+    /// `COUNT`, which ignores it.
     pub fn over(
         self,
         src: NodeIndex,
@@ -25,7 +34,7 @@ impl Aggregation {
         group_by: &[usize],
     ) -> GroupedOperator<Aggregator> {
         assert!(
-            !group_by.iter().any(|&i| i == over),
+            self == Aggregation::COUNT || !group_by.iter().any(|&i| i == over),
             "cannot group by aggregation column"
         );
         GroupedOperator::new(
@@ -34,11 +43,19 @@ impl Aggregation {
                 op: self,
                 over,
                 group: group_by.into(),
+                count: 0,
             },
         )
     }
 }
 
+impl GroupedOperator<Aggregator> {
+    /// The aggregation performed by this operator.
+    pub fn kind(&self) -> &Aggregation {
+        &self.inner.op
+    }
+}
+
 /// Aggregator implementas a Soup node that performans common aggregation operations such as counts
 /// and sums.
 ///
@@ -52,21 +69,48 @@ impl Aggregation {
 /// identifying the group, and appending the aggregated value. For example, for a sum with
 /// `self.over == 1`, a previous sum of `3`, and an incoming record with `[a, 1, x]`, the output
 /// would be `[a, x, 4]`.
+///
+/// An average cannot be computed from the previous average alone, so `AVG` keeps the sum and the
+/// number of the group's non-NULL values in two state columns after the average. `COUNT_DISTINCT`
+/// adds or subtracts 1 whenever a value's count in its source becomes or stops being non-zero.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Aggregator {
     op: Aggregation,
     over: usize,
     group: Vec<usize>,
+    /// The source column holding each value's count, for `COUNT_DISTINCT`.
+    count: usize,
+}
+
+/// A single change to an aggregated group.
+pub enum DiffType {
+    /// A change to a running count or sum.
+    Delta(i128),
+    /// A change to the sum and the number of the values that are averaged.
+    Average(f64, i64),
+}
+
+fn to_f64(v: &DataType) -> f64 {
+    match *v {
+        DataType::Int(n) => f64::from(n),
+        DataType::UnsignedInt(n) => f64::from(n),
+        DataType::BigInt(n) => n as f64,
+        DataType::UnsignedBigInt(n) => n as f64,
+        DataType::Real(..) => v.into(),
+        ref x => unreachable!("tried to aggregate over {:?}", x),
+    }
 }
 
 impl GroupedOperation for Aggregator {
-    type Diff = i128;
+    type Diff = DiffType;
 
     fn setup(&mut self, parent: &Node) {
         assert!(
             self.over < parent.fields().len(),
             "cannot aggregate over non-existing column"
         );
+        // the counts are computed last by the source `COUNT`
+        self.count = parent.fields().len() - 1;
     }
 
     fn group_by(&self) -> &[usize] {
@@ -74,9 +118,9 @@ impl GroupedOperation for Aggregator {
     }
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        let sign: i128 = if pos { 1 } else { -1 };
         match self.op {
-            Aggregation::COUNT if pos => 1,
-            Aggregation::COUNT => -1,
+            Aggregation::COUNT => DiffType::Delta(sign),
             Aggregation::SUM => {
                 let v = match r[self.over] {
                     DataType::Int(n) => i128::from(n),
@@ -86,11 +130,16 @@ impl GroupedOperation for Aggregator {
                     DataType::None => 0,
                     ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
                 };
-                if pos {
-                    v
-                } else {
-                    0i128 - v
-                }
+                DiffType::Delta(sign * v)
+            }
+            // aggregates ignore NULLs
+            Aggregation::AVG if r[self.over].is_none() => DiffType::Average(0.0, 0),
+            Aggregation::AVG => DiffType::Average(sign as f64 * to_f64(&r[self.over]), sign as i64),
+            Aggregation::COUNT_DISTINCT if r[self.over].is_none() => DiffType::Delta(0),
+            Aggregation::COUNT_DISTINCT => {
+                // a value is in the group while it occurs at least once
+                let n: i128 = (&r[self.count]).into();
+                DiffType::Delta(if n > 0 { sign } else { 0 })
             }
         }
     }
@@ -98,17 +147,53 @@ impl GroupedOperation for Aggregator {
     fn apply(
         &self,
         current: Option<&DataType>,
+        state: &mut Vec<DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
-    ) -> DataType {
-        let n = match current {
-            Some(&DataType::Int(n)) => i128::from(n),
-            Some(&DataType::UnsignedInt(n)) => i128::from(n),
-            Some(&DataType::BigInt(n)) => i128::from(n),
-            Some(&DataType::UnsignedBigInt(n)) => i128::from(n),
-            None => 0,
-            _ => unreachable!(),
-        };
-        diffs.fold(n, |n, d| n + d).into()
+    ) -> Option<DataType> {
+        match self.op {
+            Aggregation::COUNT | Aggregation::SUM | Aggregation::COUNT_DISTINCT => {
+                let n = match current {
+                    Some(&DataType::Int(n)) => i128::from(n),
+                    Some(&DataType::UnsignedInt(n)) => i128::from(n),
+                    Some(&DataType::BigInt(n)) => i128::from(n),
+                    Some(&DataType::UnsignedBigInt(n)) => i128::from(n),
+                    None => 0,
+                    _ => unreachable!(),
+                };
+                Some(
+                    diffs
+                        .fold(n, |n, d| match d {
+                            DiffType::Delta(d) => n + d,
+                            DiffType::Average(..) => unreachable!(),
+                        })
+                        .into(),
+                )
+            }
+            Aggregation::AVG => {
+                let (sum, count) = match state[..] {
+                    [ref sum, ref count] => (f64::from(sum), i64::from(count)),
+                    _ => (0.0, 0),
+                };
+                let (sum, count) = diffs.fold((sum, count), |(sum, count), d| match d {
+                    DiffType::Average(s, n) => (sum + s, count + n),
+                    DiffType::Delta(_) => unreachable!(),
+                });
+
+                *state = vec![sum.into(), count.into()];
+                if count == 0 {
+                    Some(DataType::None)
+                } else {
+                    Some((sum / count as f64).into())
+                }
+            }
+        }
+    }
+
+    fn state_columns(&self) -> usize {
+        match self.op {
+            Aggregation::AVG => 2,
+            _ => 0,
+        }
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -116,12 +201,16 @@ impl GroupedOperation for Aggregator {
             return String::from(match self.op {
                 Aggregation::COUNT => "+",
                 Aggregation::SUM => "𝛴",
+                Aggregation::AVG => "μ",
+                Aggregation::COUNT_DISTINCT => "+≠",
             });
         }
 
         let op_string = match self.op {
             Aggregation::COUNT => "|*|".into(),
             Aggregation::SUM => format!("𝛴({})", self.over),
+            Aggregation::AVG => format!("μ({})", self.over),
+            Aggregation::COUNT_DISTINCT => format!("|≠{}|", self.over),
         };
         let group_cols = self
             .group
@@ -389,6 +478,131 @@ mod tests {
 
     // TODO: also test SUM
 
+    fn setup_op(op: Aggregation, base: &[&str], fields: &[&str]) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", base);
+        g.set_op("identity", fields, op.over(s.as_global(), 1, &[0]), true);
+        g
+    }
+
+    #[test]
+    fn it_averages() {
+        let mut c = setup_op(Aggregation::AVG, &["x", "y"], &["x", "ys", "sum", "count"]);
+
+        // the sum and the count of the averaged values follow the average
+        let rs = c.narrow_through_row(vec![1.into(), 2.into()], true);
+        assert_eq!(
+            rs,
+            vec![(vec![1.into(), 2.0.into(), 2.0.into(), 1.into()], true)].into()
+        );
+
+        let rs = c.narrow_through_row(vec![1.into(), 4.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 2.0.into(), 2.0.into(), 1.into()], false),
+                (vec![1.into(), 3.0.into(), 6.0.into(), 2.into()], true),
+            ]
+            .into()
+        );
+
+        // NULLs don't count towards the average
+        let rs = c.narrow_through_row(vec![1.into(), DataType::None], true);
+        assert!(rs.is_empty());
+
+        let rs = c.narrow_through_row((vec![1.into(), 2.into()], false), true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 3.0.into(), 6.0.into(), 2.into()], false),
+                (vec![1.into(), 4.0.into(), 4.0.into(), 1.into()], true),
+            ]
+            .into()
+        );
+
+        // an empty group has no average
+        let rs = c.narrow_through_row((vec![1.into(), 4.into()], false), true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 4.0.into(), 4.0.into(), 1.into()], false),
+                (vec![1.into(), DataType::None, 0.0.into(), 0.into()], true),
+            ]
+            .into()
+        );
+
+        // and the average is picked up again from the state
+        let rs = c.narrow_through_row(vec![1.into(), 5.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), DataType::None, 0.0.into(), 0.into()], false),
+                (vec![1.into(), 5.0.into(), 5.0.into(), 1.into()], true),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_counts_distinct() {
+        // the source counts how often each value occurs in a group
+        let mut c = setup_op(Aggregation::COUNT_DISTINCT, &["x", "y", "n"], &["x", "ys"]);
+
+        let rs = c.narrow_through_row(vec![1.into(), "a".into(), 1.into()], true);
+        assert_eq!(rs, vec![(vec![1.into(), 1.into()], true)].into());
+
+        // a duplicate value does not change the count
+        let rs = c.narrow_through(
+            vec![
+                (vec![1.into(), "a".into(), 1.into()], false),
+                (vec![1.into(), "a".into(), 2.into()], true),
+            ],
+            true,
+        );
+        assert!(rs.is_empty());
+
+        let rs = c.narrow_through_row(vec![1.into(), "b".into(), 1.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 1.into()], false),
+                (vec![1.into(), 2.into()], true),
+            ]
+            .into()
+        );
+
+        // removing one of the duplicates does not either
+        let rs = c.narrow_through(
+            vec![
+                (vec![1.into(), "a".into(), 2.into()], false),
+                (vec![1.into(), "a".into(), 1.into()], true),
+            ],
+            true,
+        );
+        assert!(rs.is_empty());
+
+        // but removing the last one does, even though its count stays behind
+        let rs = c.narrow_through(
+            vec![
+                (vec![1.into(), "a".into(), 1.into()], false),
+                (vec![1.into(), "a".into(), 0.into()], true),
+            ],
+            true,
+        );
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 2.into()], false),
+                (vec![1.into(), 1.into()], true),
+            ]
+            .into()
+        );
+
+        // NULLs are not counted
+        let rs = c.narrow_through_row(vec![1.into(), DataType::None, 1.into()], true);
+        assert!(rs.is_empty());
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = 1.into();
@@ -401,6 +615,13 @@ mod tests {
 
         // should only index on the group-by column
         assert_eq!(idx[&me], vec![0]);
+
+        // averages keep their own state, and don't need the parent's records
+        let c = setup_op(Aggregation::AVG, &["x", "y"], &["x", "ys", "sum", "count"]);
+        let me = c.node().global_addr();
+        let idx = c.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx[&me], vec![0]);
     }
 
     #[test]
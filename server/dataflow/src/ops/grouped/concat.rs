@@ -153,8 +153,9 @@ impl GroupedOperation for GroupConcat {
     fn apply(
         &self,
         current: Option<&DataType>,
+        _: &mut Vec<DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
-    ) -> DataType {
+    ) -> Option<DataType> {
         use std::collections::BTreeSet;
         use std::iter::FromIterator;
 
@@ -200,7 +201,7 @@ impl GroupedOperation for GroupConcat {
         // we pushed one separator too many above
         let real_len = new.len() - self.separator.len();
         new.truncate(real_len);
-        new.into()
+        Some(new.into())
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -51,6 +51,10 @@ impl Extremum {
 /// incoming record. The output record is constructed by concatenating the columns identifying the
 /// group, and appending the aggregated value. For example, for a sum with `self.over == 1`, a
 /// previous sum of `3`, and an incoming record with `[a, 1, x]`, the output would be `[a, x, 4]`.
+///
+/// If the current extreme value is removed, and no value at least as extreme arrives with it, the
+/// next extreme value is found by looking at all of the group's records in the parent. If a group
+/// becomes empty, its extreme value is NULL.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExtremumOperator {
     op: Extremum,
@@ -58,6 +62,16 @@ pub struct ExtremumOperator {
     group: Vec<usize>,
 }
 
+fn to_i128(v: &DataType) -> Option<i128> {
+    match *v {
+        DataType::Int(n) => Some(i128::from(n)),
+        DataType::UnsignedInt(n) => Some(i128::from(n)),
+        DataType::BigInt(n) => Some(i128::from(n)),
+        DataType::UnsignedBigInt(n) => Some(i128::from(n)),
+        _ => None,
+    }
+}
+
 pub enum DiffType {
     Insert(i128),
     Remove(i128),
@@ -78,12 +92,9 @@ impl GroupedOperation for ExtremumOperator {
     }
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
-        let v = match r[self.over] {
-            DataType::Int(n) => i128::from(n),
-            DataType::UnsignedInt(n) => i128::from(n),
-            DataType::BigInt(n) => i128::from(n),
-            DataType::UnsignedBigInt(n) => i128::from(n),
-            _ => {
+        let v = match to_i128(&r[self.over]) {
+            Some(v) => v,
+            None => {
                 // the column we're aggregating over is non-numerical (or rather, this value is).
                 // if you've removed a column, chances are the  default value has the wrong type.
                 unreachable!();
@@ -107,30 +118,19 @@ impl GroupedOperation for ExtremumOperator {
     fn apply(
         &self,
         current: Option<&DataType>,
+        _: &mut Vec<DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
-    ) -> DataType {
-        // Extreme values are those that are at least as extreme as the current min/max (if any).
-        // let mut is_extreme_value : Box<dyn Fn(i64) -> bool> = Box::new(|_|true);
-        let mut extreme_values: Vec<i128> = vec![];
-        if let Some(data) = current {
-            match *data {
-                DataType::Int(n) => extreme_values.push(i128::from(n)),
-                DataType::UnsignedInt(n) => extreme_values.push(i128::from(n)),
-                DataType::BigInt(n) => extreme_values.push(i128::from(n)),
-                DataType::UnsignedBigInt(n) => extreme_values.push(i128::from(n)),
-                _ => unreachable!(),
-            }
-        };
+    ) -> Option<DataType> {
+        // a NULL extreme means that the group was empty
+        let current = current.and_then(|data| match *data {
+            DataType::None => None,
+            ref data => Some(to_i128(data).unwrap()),
+        });
 
+        // Extreme values are those that are at least as extreme as the current min/max (if any).
+        let mut extreme_values: Vec<i128> = current.into_iter().collect();
         let is_extreme_value = |x: i128| {
-            if let Some(data) = current {
-                let n = match *data {
-                    DataType::Int(n) => i128::from(n),
-                    DataType::UnsignedInt(n) => i128::from(n),
-                    DataType::BigInt(n) => i128::from(n),
-                    DataType::UnsignedBigInt(n) => i128::from(n),
-                    _ => unreachable!(),
-                };
+            if let Some(n) = current {
                 match self.op {
                     Extremum::MAX => x >= n,
                     Extremum::MIN => x <= n,
@@ -157,12 +157,25 @@ impl GroupedOperation for ExtremumOperator {
             Extremum::MAX => extreme_values.into_iter().max(),
         };
 
-        if let Some(extreme) = extreme {
-            return extreme.into();
-        }
+        // if there is no candidate left, the group may still hold other copies of the old extreme
+        // or other, less extreme values. only our parent can tell.
+        extreme.map(DataType::from)
+    }
+
+    fn requires_recompute(&self) -> bool {
+        true
+    }
 
-        // TODO: handle this case by querying into the parent.
-        unimplemented!();
+    fn recompute(&self, records: &mut dyn Iterator<Item = &[DataType]>) -> DataType {
+        let values = records.filter_map(|r| to_i128(&r[self.over]));
+        let extreme = match self.op {
+            Extremum::MIN => values.min(),
+            Extremum::MAX => values.max(),
+        };
+        match extreme {
+            Some(extreme) => extreme.into(),
+            None => DataType::None,
+        }
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -243,27 +256,27 @@ mod tests {
         let key = 1;
 
         // First insertion should trigger an update.
-        let out = c.narrow_one_row(vec![key.into(), 4.into()], true);
+        let out = c.narrow_through_row(vec![key.into(), 4.into()], true);
         assert_positive_record(key, 4, out);
 
         // Larger value should also trigger an update.
-        let out = c.narrow_one_row(vec![key.into(), 7.into()], true);
+        let out = c.narrow_through_row(vec![key.into(), 7.into()], true);
         assert_record_change(key, 4, 7, out);
 
         // No change if new value isn't the max.
-        let rs = c.narrow_one_row(vec![key.into(), 2.into()], true);
+        let rs = c.narrow_through_row(vec![key.into(), 2.into()], true);
         assert!(rs.is_empty());
 
         // Insertion into a different group should be independent.
-        let out = c.narrow_one_row(vec![2.into(), 3.into()], true);
+        let out = c.narrow_through_row(vec![2.into(), 3.into()], true);
         assert_positive_record(2, 3, out);
 
         // Larger than last value, but not largest in group.
-        let rs = c.narrow_one_row(vec![key.into(), 5.into()], true);
+        let rs = c.narrow_through_row(vec![key.into(), 5.into()], true);
         assert!(rs.is_empty());
 
         // One more new max.
-        let out = c.narrow_one_row(vec![key.into(), 22.into()], true);
+        let out = c.narrow_through_row(vec![key.into(), 22.into()], true);
         assert_record_change(key, 7, 22, out);
 
         // Negative for old max should be fine if there is a positive for a larger value.
@@ -271,7 +284,7 @@ mod tests {
             (vec![key.into(), 22.into()], false),
             (vec![key.into(), 23.into()], true),
         ];
-        let out = c.narrow_one(u, true);
+        let out = c.narrow_through(u, true);
         assert_record_change(key, 22, 23, out);
     }
 
@@ -281,23 +294,23 @@ mod tests {
         let key = 1;
 
         // First insertion should trigger an update.
-        let out = c.narrow_one_row(vec![key.into(), 10.into()], true);
+        let out = c.narrow_through_row(vec![key.into(), 10.into()], true);
         assert_positive_record(key, 10, out);
 
         // Smaller value should also trigger an update.
-        let out = c.narrow_one_row(vec![key.into(), 7.into()], true);
+        let out = c.narrow_through_row(vec![key.into(), 7.into()], true);
         assert_record_change(key, 10, 7, out);
 
         // No change if new value isn't the min.
-        let rs = c.narrow_one_row(vec![key.into(), 9.into()], true);
+        let rs = c.narrow_through_row(vec![key.into(), 9.into()], true);
         assert!(rs.is_empty());
 
         // Insertion into a different group should be independent.
-        let out = c.narrow_one_row(vec![2.into(), 15.into()], true);
+        let out = c.narrow_through_row(vec![2.into(), 15.into()], true);
         assert_positive_record(2, 15, out);
 
         // Smaller than last value, but not smallest in group.
-        let rs = c.narrow_one_row(vec![key.into(), 8.into()], true);
+        let rs = c.narrow_through_row(vec![key.into(), 8.into()], true);
         assert!(rs.is_empty());
 
         // Negative for old min should be fine if there is a positive for a smaller value.
@@ -305,36 +318,80 @@ mod tests {
             (vec![key.into(), 7.into()], false),
             (vec![key.into(), 5.into()], true),
         ];
-        let out = c.narrow_one(u, true);
+        let out = c.narrow_through(u, true);
         assert_record_change(key, 7, 5, out);
     }
 
     #[test]
     fn it_cancels_out_opposite_records() {
         let mut c = setup(Extremum::MAX, true);
-        c.narrow_one_row(vec![1.into(), 5.into()], true);
+        c.narrow_through_row(vec![1.into(), 5.into()], true);
         // Competing positive and negative should cancel out.
         let u = vec![
             (vec![1.into(), 10.into()], true),
             (vec![1.into(), 10.into()], false),
         ];
 
-        let out = c.narrow_one(u, true);
+        let out = c.narrow_through(u, true);
         assert!(out.is_empty());
     }
 
+    #[test]
+    fn it_recovers_from_removing_the_extreme() {
+        let mut c = setup(Extremum::MAX, true);
+        let key = 1;
+
+        c.narrow_through_row(vec![key.into(), 4.into()], true);
+        c.narrow_through_row(vec![key.into(), 7.into()], true);
+        c.narrow_through_row(vec![key.into(), 7.into()], true);
+        c.narrow_through_row(vec![key.into(), 2.into()], true);
+
+        // removing one of two copies of the max leaves the max in place
+        let rs = c.narrow_through_row((vec![key.into(), 7.into()], false), true);
+        assert!(rs.is_empty());
+
+        // removing the last copy falls back to the next largest value
+        let out = c.narrow_through_row((vec![key.into(), 7.into()], false), true);
+        assert_record_change(key, 7, 4, out);
+
+        let out = c.narrow_through_row((vec![key.into(), 4.into()], false), true);
+        assert_record_change(key, 4, 2, out);
+
+        // an empty group has no maximum
+        let out = c.narrow_through_row((vec![key.into(), 2.into()], false), true);
+        assert_eq!(
+            out,
+            vec![
+                (vec![key.into(), 2.into()], false),
+                (vec![key.into(), DataType::None], true),
+            ]
+            .into()
+        );
+
+        // and picks up again from scratch
+        let out = c.narrow_through_row(vec![key.into(), 1.into()], true);
+        assert_eq!(
+            out,
+            vec![
+                (vec![key.into(), DataType::None], false),
+                (vec![key.into(), 1.into()], true),
+            ]
+            .into()
+        );
+    }
+
     #[test]
     fn it_suggests_indices() {
-        let me = 1.into();
         let c = setup(Extremum::MAX, false);
+        let me = c.node().global_addr();
         let idx = c.node().suggest_indexes(me);
 
-        // should only add index on own columns
-        assert_eq!(idx.len(), 1);
-        assert!(idx.contains_key(&me));
+        // should add index on own columns, and on the parent's to find the next extreme value
+        assert_eq!(idx.len(), 2);
 
         // should only index on the group-by column
         assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&c.narrow_base_id().as_global()], vec![0]);
     }
 
     #[test]
@@ -53,11 +53,38 @@ pub trait GroupedOperation: fmt::Debug + Clone {
 
     /// Given the given `current` value, and a number of changes for a group (`diffs`), compute the
     /// updated group value.
+    ///
+    /// `state` holds the group's running state, as described for `state_columns`, and `apply`
+    /// updates it along with the value. It is empty if the group has no value yet.
+    ///
+    /// Returns `None` if the updated value can't be derived from `current` and `diffs` at all. The
+    /// group's records are then looked up in the parent, and the value is computed from them with
+    /// `recompute` instead.
     fn apply(
         &self,
         current: Option<&DataType>,
+        state: &mut Vec<DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
-    ) -> DataType;
+    ) -> Option<DataType>;
+
+    /// The number of columns of running state that the operation keeps for each group, after the
+    /// group's value in the operator's output.
+    ///
+    /// The state is materialized along with the value, so it is evicted and replayed with it.
+    fn state_columns(&self) -> usize {
+        0
+    }
+
+    /// Returns true if `apply` may return `None`, in which case the parent must be materialized
+    /// on the group by columns.
+    fn requires_recompute(&self) -> bool {
+        false
+    }
+
+    /// Compute the value of a group from all of its records in the parent.
+    fn recompute(&self, _records: &mut dyn Iterator<Item = &[DataType]>) -> DataType {
+        unreachable!("{:?} never has to recompute a group", self)
+    }
 
     fn description(&self, detailed: bool) -> String;
     fn over_columns(&self) -> Vec<usize>;
@@ -95,6 +122,12 @@ impl<T: GroupedOperation> GroupedOperator<T> {
     pub fn over_columns(&self) -> Vec<usize> {
         self.inner.over_columns()
     }
+
+    /// The number of columns of running state that follow the computed column in this operator's
+    /// output.
+    pub fn state_columns(&self) -> usize {
+        self.inner.state_columns()
+    }
 }
 
 /// Extract a copy of all values in the record being targeted by the group
@@ -113,6 +146,32 @@ fn get_group_values(group_by: &[usize], row: &Record) -> Vec<DataType> {
     group
 }
 
+/// Replace the output row `old` of a group (if there is one) with the `group` values plus `new`
+/// and the group's running `state`.
+///
+/// Nothing is emitted if neither the group's value nor its state changed.
+fn emit_group(
+    out: &mut Vec<Record>,
+    old: Option<Cow<[DataType]>>,
+    mut group: Vec<DataType>,
+    new: DataType,
+    state: Vec<DataType>,
+) {
+    if let Some(old) = old {
+        // current value follows the group columns, and the state follows the value
+        if old[group.len()] == new && old[group.len() + 1..] == state[..] {
+            return;
+        }
+        // revoke old value
+        out.push(Record::Negative(old.into_owned()));
+    }
+
+    // emit positive, which is group + new + state.
+    group.push(new);
+    group.extend(state);
+    out.push(Record::Positive(group));
+}
+
 impl<T: GroupedOperation + Send + 'static> Ingredient for GroupedOperator<T>
 where
     Self: Into<NodeOperator>,
@@ -164,7 +223,7 @@ where
         from: LocalNodeIndex,
         rs: Records,
         replay_key_cols: Option<&[usize]>,
-        _: &DomainNodes,
+        nodes: &DomainNodes,
         state: &StateMap,
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
@@ -199,6 +258,8 @@ where
         let mut misses = Vec::new();
         let mut lookups = Vec::new();
         let mut out = Vec::new();
+        // groups whose value has to be computed from their records in our parent
+        let mut recompute = Vec::new();
         {
             let out_key = &self.out_key;
             let mut handle_group =
@@ -237,30 +298,19 @@ where
                     };
 
                     let old = rs.into_iter().next();
-                    // current value is in the last output column
-                    // or "" if there is no current group
-                    let current = old.as_ref().map(|rows| match rows {
-                        Cow::Borrowed(rs) => Cow::Borrowed(&rs[rs.len() - 1]),
-                        Cow::Owned(rs) => Cow::Owned(rs[rs.len() - 1].clone()),
-                    });
+                    // current value follows the group columns, and the state follows the value
+                    let current = old.as_ref().map(|old| &old[out_key.len()]);
+                    let mut state = old
+                        .as_ref()
+                        .map(|old| old[out_key.len() + 1..].to_vec())
+                        .unwrap_or_default();
 
                     // new is the result of applying all diffs for the group to the current value
-                    let new = inner.apply(current.as_ref().map(|v| &**v), &mut diffs as &mut _);
-                    match current {
-                        Some(ref current) if new == **current => {
-                            // no change
-                        }
-                        _ => {
-                            if let Some(old) = old {
-                                // revoke old value
-                                debug_assert!(current.is_some());
-                                out.push(Record::Negative(old.into_owned()));
-                            }
-
-                            // emit positive, which is group + new.
-                            let mut rec = group;
-                            rec.push(new);
-                            out.push(Record::Positive(rec));
+                    match inner.apply(current, &mut state, &mut diffs as &mut _) {
+                        Some(new) => emit_group(&mut out, old, group, new, state),
+                        None => {
+                            let r = group_rs.next().unwrap().extract().0;
+                            recompute.push((group, old, r));
                         }
                     }
                 };
@@ -279,6 +329,39 @@ where
             handle_group(&mut self.inner, group_rs.drain(..), diffs.drain(..));
         }
 
+        for (group, old, r) in recompute {
+            // our parent has already applied the records we were given, so it holds the group's
+            // records as they are now.
+            match self.lookup(
+                *self.src,
+                group_by,
+                &KeyType::from(&group[..]),
+                nodes,
+                state,
+            ) {
+                Some(Some(rs)) => {
+                    if replay_key_cols.is_some() {
+                        lookups.push(Lookup {
+                            on: *self.src,
+                            cols: group_by.clone(),
+                            key: group.clone(),
+                        });
+                    }
+                    let rs: Vec<_> = rs.collect();
+                    let new = self.inner.recompute(&mut rs.iter().map(|r| &r[..]));
+                    emit_group(&mut out, old, group, new, Vec::new());
+                }
+                Some(None) => misses.push(Miss {
+                    on: *self.src,
+                    lookup_idx: group_by.clone(),
+                    lookup_cols: group_by.clone(),
+                    replay_cols: replay_key_cols.map(Vec::from),
+                    record: r,
+                }),
+                None => unreachable!("parent of {:?} is not materialized", self.inner),
+            }
+        }
+
         ProcessingResult {
             results: out.into(),
             lookups,
@@ -288,11 +371,17 @@ where
 
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         // index by our primary key
-        Some((this, self.out_key.clone())).into_iter().collect()
+        let mut idx: HashMap<_, _> = Some((this, self.out_key.clone())).into_iter().collect();
+        if self.inner.requires_recompute() {
+            // and have our parent hold on to the records of each group for us
+            idx.insert(self.src.as_global(), self.group_by.clone());
+        }
+        idx
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
-        if col == self.colfix.len() {
+        // the computed column and any state after it are generated here
+        if col >= self.colfix.len() {
             return None;
         }
         Some(vec![(self.src.as_global(), self.colfix[col])])
@@ -303,7 +392,7 @@ where
     }
 
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
-        if column == self.colfix.len() {
+        if column >= self.colfix.len() {
             return vec![(self.src.as_global(), None)];
         }
         vec![(self.src.as_global(), Some(self.colfix[column]))]
@@ -3,16 +3,33 @@ use std::collections::HashSet;
 use std::mem;
 
 use crate::prelude::*;
+use slog::Logger;
 
 /// Kind of join
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum JoinType {
     /// Left join between two views
     Left,
+    /// Right join between two views
+    Right,
+    /// Full outer join between two views
+    Full,
     /// Inner join between two views
     Inner,
 }
 
+impl JoinType {
+    /// Whether rows from the left (or right) parent are kept even if they have no match.
+    fn preserves(&self, left: bool) -> bool {
+        match *self {
+            JoinType::Inner => false,
+            JoinType::Left => left,
+            JoinType::Right => !left,
+            JoinType::Full => true,
+        }
+    }
+}
+
 /// Where to source a join column
 #[derive(Debug, Clone)]
 pub enum JoinSource {
@@ -24,7 +41,7 @@ pub enum JoinSource {
     B(usize, usize),
 }
 
-/// Join provides a left outer join between two views.
+/// Join provides an inner or (left, right, or full) outer join between two views.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Join {
     left: IndexPair,
@@ -179,13 +196,137 @@ impl Join {
         reuse
     }
 
+    /// Whether output column `col` is the join column.
+    fn is_join_column(&self, col: usize) -> bool {
+        let (from_left, c) = self.emit[col];
+        if from_left {
+            c == self.on.0
+        } else {
+            c == self.on.1
+        }
+    }
+
+    /// Finds the keys of a partial replay through the left parent of a full join that may have
+    /// rows which exist only on the right, along with where the join column is in each key.
+    ///
+    /// Rows that exist only on the right are NULL in every column from the left but the join
+    /// column, so we only look for them for keys that cover the join column, and are NULL in any
+    /// other column from the left. A key without the join column could only match right-only rows
+    /// if it were NULL throughout, and those are not filled in.
+    fn right_only_keys(
+        &self,
+        key_cols: &[usize],
+        keys: &HashSet<Vec<DataType>>,
+        rs: &Records,
+    ) -> Vec<(Vec<DataType>, usize)> {
+        let join_at = match key_cols.iter().position(|&c| self.is_join_column(c)) {
+            Some(join_at) => join_at,
+            None => return Vec::new(),
+        };
+        keys.iter()
+            .filter(|key| {
+                // the other key columns from the left are NULL in right-only rows
+                key_cols.iter().zip(key.iter()).all(|(&c, v)| {
+                    self.is_join_column(c) || !self.emit[c].0 || *v == DataType::None
+                })
+            })
+            // if the left replayed a row with the key's join value, the key has no right-only
+            // rows. the left may have rows with that join value that the key doesn't cover, so
+            // for multi-column keys we still have to check the left in `fill_right_only`.
+            .filter(|key| !rs.iter().any(|r| r[self.on.0] == key[join_at]))
+            .map(|key| (key.clone(), join_at))
+            .collect()
+    }
+
+    /// Adds the rows that exist only on the right and match `key` (whose join value is at
+    /// `join_at`) to the result of a partial replay through the left parent of a full join.
+    fn fill_right_only(
+        &self,
+        key: Vec<DataType>,
+        join_at: usize,
+        key_cols: &[usize],
+        nodes: &DomainNodes,
+        state: &StateMap,
+        result: &mut ProcessingResult,
+    ) {
+        // if we miss in either parent, pretend we were processing a record that consists of just
+        // the key, so that the replay is retried once the parent has been filled.
+        let miss = |on, lookup_idx| Miss {
+            on,
+            lookup_idx: vec![lookup_idx],
+            lookup_cols: vec![join_at],
+            replay_cols: Some((0..key.len()).collect()),
+            record: key.clone(),
+        };
+
+        if key_cols.len() != 1 {
+            // the replayed rows only tell us about the left's rows for the whole key
+            let left = self
+                .lookup(
+                    *self.left,
+                    &[self.on.0],
+                    &KeyType::Single(&key[join_at]),
+                    nodes,
+                    state,
+                )
+                .unwrap();
+            match left {
+                Some(mut rows) => {
+                    let matched = rows.next().is_some();
+                    result.lookups.push(Lookup {
+                        on: *self.left,
+                        cols: vec![self.on.0],
+                        key: vec![key[join_at].clone()],
+                    });
+                    if matched {
+                        return;
+                    }
+                }
+                None => {
+                    result.misses.push(miss(*self.left, self.on.0));
+                    return;
+                }
+            }
+        }
+
+        let right = self
+            .lookup(
+                *self.right,
+                &[self.on.1],
+                &KeyType::Single(&key[join_at]),
+                nodes,
+                state,
+            )
+            .unwrap();
+        match right {
+            Some(rows) => {
+                for row in rows {
+                    let row = self.generate_null(&row, false);
+                    if key_cols.iter().zip(key.iter()).all(|(&c, v)| row[c] == *v) {
+                        result.results.push((row, true).into());
+                    }
+                }
+                result.lookups.push(Lookup {
+                    on: *self.right,
+                    cols: vec![self.on.1],
+                    key: vec![key[join_at].clone()],
+                });
+            }
+            None => result.misses.push(miss(*self.right, self.on.1)),
+        }
+    }
+
     // TODO: make non-allocating
-    fn generate_null(&self, left: &[DataType]) -> Vec<DataType> {
+    fn generate_null(&self, row: &[DataType], row_is_left: bool) -> Vec<DataType> {
         self.emit
             .iter()
             .map(|&(from_left, col)| {
-                if from_left {
-                    left[col].clone()
+                if from_left == row_is_left {
+                    row[col].clone()
+                } else if !row_is_left && col == self.on.0 {
+                    // the shared join column is emitted from the left, but a right row padded
+                    // with NULLs still knows its value.
+                    row[self.on.1].clone()
                 } else {
                     DataType::None
                 }
@@ -209,7 +350,12 @@ impl Ingredient for Join {
 
     fn must_replay_among(&self) -> Option<HashSet<NodeIndex>> {
         match self.kind {
-            JoinType::Left => Some(Some(self.left.as_global()).into_iter().collect()),
+            // a full join replays through the left, and fills in right-only rows itself (see
+            // `on_input_raw`).
+            JoinType::Left | JoinType::Full => {
+                Some(Some(self.left.as_global()).into_iter().collect())
+            }
+            JoinType::Right => Some(Some(self.right.as_global()).into_iter().collect()),
             JoinType::Inner => Some(
                 vec![self.left.as_global(), self.right.as_global()]
                     .into_iter()
@@ -218,6 +364,11 @@ impl Ingredient for Join {
         }
     }
 
+    fn must_materialize_parent(&self, parent: NodeIndex) -> bool {
+        // a full replay of a full join reads all of the right's rows (see `on_input_raw`)
+        self.kind == JoinType::Full && parent == self.right.as_global()
+    }
+
     fn on_connected(&mut self, _g: &Graph) {}
 
     fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
@@ -245,7 +396,8 @@ impl Ingredient for Join {
             };
         }
 
-        let (other, from_key, other_key) = if from == *self.left {
+        let from_left = from == *self.left;
+        let (other, from_key, other_key) = if from_left {
             (*self.right, self.on.0, self.on.1)
         } else {
             (*self.left, self.on.1, self.on.0)
@@ -288,15 +440,18 @@ impl Ingredient for Join {
         let mut ret: Vec<Record> = Vec::with_capacity(rs.len());
         let mut at = 0;
         while at != rs.len() {
-            let mut old_right_count = None;
-            let mut new_right_count = None;
+            let mut old_from_count = None;
+            let mut new_from_count = None;
             let prev_join_key = rs[at][from_key].clone();
 
-            if from == *self.right && self.kind == JoinType::Left {
+            if self.kind.preserves(!from_left) {
+                // the other side is preserved, so its rows for this key may have to switch
+                // between being NULL-padded and being joined. to know, we need the number of rows
+                // on our side for this key.
                 let rc = self
                     .lookup(
-                        *self.right,
-                        &[self.on.1],
+                        from,
+                        &[from_key],
                         &KeyType::Single(&prev_join_key),
                         nodes,
                         state,
@@ -304,7 +459,7 @@ impl Ingredient for Join {
                     .unwrap();
 
                 if rc.is_none() {
-                    // we got something from right, but that row's key is not in right??
+                    // we got something from a parent, but that row's key is not in the parent??
                     //
                     // this *can* happen! imagine if you have two partial indices on right,
                     // one on column a and one on column b. imagine that a is the join key.
@@ -322,15 +477,15 @@ impl Ingredient for Join {
                 } else {
                     if replay_key_cols.is_some() {
                         lookups.push(Lookup {
-                            on: *self.right,
-                            cols: vec![self.on.1],
+                            on: from,
+                            cols: vec![from_key],
                             key: vec![prev_join_key.clone()],
                         });
                     }
 
                     let rc = rc.unwrap().count();
-                    old_right_count = Some(rc);
-                    new_right_count = Some(rc);
+                    old_from_count = Some(rc);
+                    new_from_count = Some(rc);
                 }
             }
 
@@ -374,11 +529,11 @@ impl Ingredient for Join {
 
             let start = at;
             let mut make_null = None;
-            if self.kind == JoinType::Left && from == *self.right {
-                // If records are being received from the right, we need to find the number of
-                // records that existed *before* this batch of records was processed so we know
-                // whether or not to generate +/- NULL rows.
-                if let Some(mut old_rc) = old_right_count {
+            if self.kind.preserves(!from_left) {
+                // If records are being received from the side opposite a preserved one, we need to
+                // find the number of records that existed *before* this batch of records was
+                // processed so we know whether or not to generate +/- NULL rows.
+                if let Some(mut old_rc) = old_from_count {
                     while at != rs.len() && rs[at][from_key] == prev_join_key {
                         if rs[at].is_positive() {
                             old_rc -= 1
@@ -388,17 +543,17 @@ impl Ingredient for Join {
                         at += 1;
                     }
 
-                    // emit null rows if necessary for left join
-                    let new_rc = new_right_count.unwrap();
+                    // emit null rows if necessary for the preserved side
+                    let new_rc = new_from_count.unwrap();
                     if new_rc == 0 && old_rc != 0 {
-                        // all lefts for this key must emit + NULLs
+                        // all others for this key must emit + NULLs
                         make_null = Some(true);
                     } else if new_rc != 0 && old_rc == 0 {
-                        // all lefts for this key must emit - NULLs
+                        // all others for this key must emit - NULLs
                         make_null = Some(false);
                     }
                 } else {
-                    // we got a right, but missed in right; clearly, a replay is needed
+                    // we got a record, but missed in its own parent; clearly, a replay is needed
                     let start = at;
                     at = rs[at..]
                         .iter()
@@ -407,7 +562,7 @@ impl Ingredient for Join {
                         .unwrap_or_else(|| rs.len());
                     misses.extend((start..at).map(|i| Miss {
                         on: from,
-                        lookup_idx: vec![self.on.1],
+                        lookup_idx: vec![from_key],
                         lookup_cols: vec![from_key],
                         replay_cols: replay_key_cols.clone(),
                         // NOTE: we're stealing data here!
@@ -436,9 +591,9 @@ impl Ingredient for Join {
                     // we have yet to iterate through other_rows
                     let mut other_rows = other_rows.peekable();
                     if other_rows.peek().is_none() {
-                        if self.kind == JoinType::Left && from == *self.left {
-                            // left join, got a thing from left, no rows in right == NULL
-                            ret.push((self.generate_null(&row), positive).into());
+                        if self.kind.preserves(from_left) {
+                            // outer join, got a preserved row, no rows on the other side == NULL
+                            ret.push((self.generate_null(&row, from_left), positive).into());
                         }
                         continue;
                     }
@@ -450,10 +605,10 @@ impl Ingredient for Join {
                     let mut other = other_rows.next().unwrap();
                     while other_rows.peek().is_some() {
                         if let Some(false) = make_null {
-                            // we need to generate a -NULL for all these lefts
-                            ret.push((self.generate_null(&other), false).into());
+                            // we need to generate a -NULL for all these others
+                            ret.push((self.generate_null(&other, !from_left), false).into());
                         }
-                        if from == *self.left {
+                        if from_left {
                             ret.push(
                                 (
                                     self.generate_row(&row, &other, Preprocessed::Neither),
@@ -471,41 +626,42 @@ impl Ingredient for Join {
                             );
                         }
                         if let Some(true) = make_null {
-                            // we need to generate a +NULL for all these lefts
-                            ret.push((self.generate_null(&other), true).into());
+                            // we need to generate a +NULL for all these others
+                            ret.push((self.generate_null(&other, !from_left), true).into());
                         }
                         other = other_rows.next().unwrap();
                         other_rows_count += 1;
                     }
 
                     if let Some(false) = make_null {
-                        // we need to generate a -NULL for the last left too
-                        ret.push((self.generate_null(&other), false).into());
+                        // we need to generate a -NULL for the last other too
+                        ret.push((self.generate_null(&other, !from_left), false).into());
                     }
-                    ret.push(
-                        (
-                            self.regenerate_row(row, &other, from == *self.left, false),
-                            positive,
-                        )
-                            .into(),
-                    );
+                    ret.push((self.regenerate_row(row, &other, from_left, false), positive).into());
                     if let Some(true) = make_null {
-                        // we need to generate a +NULL for the last left too
-                        ret.push((self.generate_null(&other), true).into());
+                        // we need to generate a +NULL for the last other too
+                        ret.push((self.generate_null(&other, !from_left), true).into());
                     }
                 } else if other_rows_count == 0 {
-                    if self.kind == JoinType::Left && from == *self.left {
-                        // left join, got a thing from left, no rows in right == NULL
-                        ret.push((self.generate_null(&row), positive).into());
+                    if self.kind.preserves(from_left) {
+                        // outer join, got a preserved row, no rows on the other side == NULL
+                        ret.push((self.generate_null(&row, from_left), positive).into());
                     }
                 } else {
                     // we no longer have access to `other_rows`
                     // *but* the values are all in ret[-other_rows_count:]!
-                    let start = ret.len() - other_rows_count;
-                    let end = ret.len();
+                    // (or every other row of ret[-2 * other_rows_count:] if the first pass also
+                    // emitted NULL rows; the joined row follows the -NULL, and precedes the +NULL)
+                    let (stride, offset) = match make_null.take() {
+                        None => (1, 0),
+                        Some(true) => (2, 0),
+                        Some(false) => (2, 1),
+                    };
+                    let start = ret.len() - stride * other_rows_count + offset;
+                    let last = start + stride * (other_rows_count - 1);
                     // we again use the trick above where the last row we produce reuses `row`
-                    for i in start..(end - 1) {
-                        if from == *self.left {
+                    for i in (start..last).step_by(stride) {
+                        if from_left {
                             let r = (
                                 self.generate_row(&row, &ret[i], Preprocessed::Right),
                                 positive,
@@ -522,7 +678,7 @@ impl Ingredient for Join {
                         }
                     }
                     let r = (
-                        self.regenerate_row(row, &ret[end - 1], from == *self.left, true),
+                        self.regenerate_row(row, &ret[last], from_left, true),
                         positive,
                     )
                         .into();
@@ -538,6 +694,88 @@ impl Ingredient for Join {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
+    fn on_input_raw(
+        &mut self,
+        ex: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay: ReplayContext,
+        nodes: &DomainNodes,
+        state: &StateMap,
+        _: &Logger,
+    ) -> RawProcessingResult {
+        let replay_key_cols = match replay {
+            ReplayContext::Partial { key_cols, .. } => Some(key_cols),
+            _ => None,
+        };
+
+        if self.kind != JoinType::Full || from != *self.left {
+            return RawProcessingResult::Regular(self.on_input(
+                ex,
+                from,
+                rs,
+                replay_key_cols,
+                nodes,
+                state,
+            ));
+        }
+
+        // a full join only ever replays through its left parent, so rows that exist only on the
+        // right would never be produced by a replay. we fill them in here: for a partial replay,
+        // any replayed key whose join value has no rows on the left, and for a full replay, any
+        // right row that has no match on the left once the last piece comes through.
+        let right_only_rows = match replay {
+            ReplayContext::Full { last: true } => {
+                // `must_materialize_parent` makes sure that we can see all of the right's rows
+                let right = state
+                    .get(*self.right)
+                    .expect("full join's right parent is not materialized");
+                right
+                    .cloned_records()
+                    .into_iter()
+                    .filter(|r| {
+                        self.lookup(
+                            *self.left,
+                            &[self.on.0],
+                            &KeyType::Single(&r[self.on.1]),
+                            nodes,
+                            state,
+                        )
+                        .unwrap()
+                        .map(|mut rs| rs.next().is_none())
+                        .unwrap_or(false)
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let right_only_keys = match replay {
+            ReplayContext::Partial { key_cols, keys, .. } => {
+                self.right_only_keys(key_cols, keys, &rs)
+            }
+            _ => Vec::new(),
+        };
+
+        let mut result = self.on_input(ex, from, rs, replay_key_cols, nodes, state);
+        for row in right_only_rows {
+            result
+                .results
+                .push((self.generate_null(&row, false), true).into());
+        }
+        for (key, join_at) in right_only_keys {
+            self.fill_right_only(
+                key,
+                join_at,
+                replay_key_cols.unwrap(),
+                nodes,
+                state,
+                &mut result,
+            );
+        }
+        RawProcessingResult::Regular(result)
+    }
+
     fn suggest_indexes(&self, _this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         vec![
             (self.left.as_global(), vec![self.on.0]),
@@ -560,6 +798,8 @@ impl Ingredient for Join {
         if !detailed {
             return String::from(match self.kind {
                 JoinType::Left => "⋉",
+                JoinType::Right => "⋊",
+                JoinType::Full => "⟗",
                 JoinType::Inner => "⋈",
             });
         }
@@ -576,6 +816,8 @@ impl Ingredient for Join {
 
         let op = match self.kind {
             JoinType::Left => "⋉",
+            JoinType::Right => "⋊",
+            JoinType::Full => "⟗",
             JoinType::Inner => "⋈",
         };
 
@@ -614,6 +856,10 @@ mod tests {
     use crate::ops;
 
     fn setup() -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        setup_kind(JoinType::Left)
+    }
+
+    fn setup_kind(kind: JoinType) -> (ops::test::MockGraph, IndexPair, IndexPair) {
         let mut g = ops::test::MockGraph::new();
         let l = g.add_base("left", &["l0", "l1"]);
         let r = g.add_base("right", &["r0", "r1"]);
@@ -622,7 +868,7 @@ mod tests {
         let j = Join::new(
             l.as_global(),
             r.as_global(),
-            JoinType::Left,
+            kind,
             vec![B(0, 0), L(1), R(1)],
         );
 
@@ -737,6 +983,173 @@ mod tests {
         assert_eq!(rs.len(), 0);
     }
 
+    #[test]
+    fn it_works_right() {
+        let (mut j, l, r) = setup_kind(JoinType::Right);
+        let l_a1 = vec![1.into(), "a".into()];
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_y2 = vec![2.into(), "y".into()];
+
+        // unmatched forward from left should have no effect
+        j.seed(l, l_a1.clone());
+        let rs = j.one_row(l, l_a1.clone(), false);
+        assert_eq!(rs.len(), 0);
+
+        // unmatched right produces a NULL-padded row that still carries the join column
+        j.seed(r, r_y2.clone());
+        let rs = j.one_row(r, r_y2.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![2.into(), DataType::None, "y".into()], true)].into()
+        );
+
+        // matched right produces a full row
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![1.into(), "a".into(), "x".into()], true)].into()
+        );
+
+        // removing the left revokes the full row and replaces it with a NULL-padded one
+        j.unseed(l);
+        let rs = j.one_row(l, (l_a1.clone(), false), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), "a".into(), "x".into()], false),
+                (vec![1.into(), DataType::None, "x".into()], true),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_works_full() {
+        let (mut j, l, r) = setup_kind(JoinType::Full);
+        let l_a1 = vec![1.into(), "a".into()];
+        let l_b2 = vec![2.into(), "b".into()];
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_y2 = vec![2.into(), "y".into()];
+        let r_z2 = vec![2.into(), "z".into()];
+
+        // unmatched rows from either side are NULL-padded
+        j.seed(l, l_a1.clone());
+        let rs = j.one_row(l, l_a1.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![1.into(), "a".into(), DataType::None], true)].into()
+        );
+
+        j.seed(r, r_y2.clone());
+        j.seed(r, r_z2.clone());
+        let rs = j.one(r, vec![r_y2.clone(), r_z2.clone()], false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![2.into(), DataType::None, "y".into()], true),
+                (vec![2.into(), DataType::None, "z".into()], true),
+            ]
+            .into()
+        );
+
+        // a match from the right revokes the left's NULLs
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1.clone(), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), "a".into(), DataType::None], false),
+                (vec![1.into(), "a".into(), "x".into()], true),
+            ]
+            .into()
+        );
+
+        // and a match from the left revokes the right's NULLs
+        j.seed(l, l_b2.clone());
+        let rs = j.one_row(l, l_b2.clone(), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![2.into(), DataType::None, "y".into()], false),
+                (vec![2.into(), "b".into(), "y".into()], true),
+                (vec![2.into(), DataType::None, "z".into()], false),
+                (vec![2.into(), "b".into(), "z".into()], true),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_replays_right_only_rows_for_compound_keys() {
+        let (mut j, l, r) = setup_kind(JoinType::Full);
+        j.seed(l, vec![1.into(), "a".into()]);
+        j.seed(r, vec![1.into(), "x".into()]);
+        j.seed(r, vec![2.into(), "y".into()]);
+        j.seed(r, vec![2.into(), "z".into()]);
+
+        let mut replay = |key_cols: &[usize], keys: Vec<Vec<DataType>>| {
+            let keys: HashSet<_> = keys.into_iter().collect();
+            let context = ReplayContext::Partial {
+                key_cols,
+                keys: &keys,
+                requesting_shard: 0,
+                tag: Tag::new(0),
+                unishard: true,
+            };
+            let result = j.replay(l, Vec::<Record>::new(), context);
+            assert_eq!(result.misses, vec![]);
+            result.results
+        };
+
+        // a key with a column from the right only gets the right-only rows that match it
+        assert_eq!(
+            replay(&[0, 2], vec![vec![2.into(), "y".into()]]),
+            vec![(vec![2.into(), DataType::None, "y".into()], true)].into()
+        );
+
+        // a key with another column from the left only matches right-only rows if it is NULL
+        let rs = replay(&[0, 1], vec![vec![2.into(), DataType::None]]);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.iter().all(|r| r.is_positive() && r[1] == DataType::None));
+        assert!(replay(&[0, 1], vec![vec![2.into(), "b".into()]]).is_empty());
+
+        // the left has a row with this join value, even if it wasn't replayed for the key
+        assert!(replay(&[0, 1], vec![vec![1.into(), DataType::None]]).is_empty());
+    }
+
+    #[test]
+    fn it_replays_right_only_rows_in_full() {
+        let (mut j, l, r) = setup_kind(JoinType::Full);
+        let l_a1 = vec![1.into(), "a".into()];
+        j.seed(l, l_a1.clone());
+        j.seed(r, vec![1.into(), "x".into()]);
+        j.seed(r, vec![2.into(), "y".into()]);
+
+        // right-only rows are only filled in once the last piece of the replay comes through
+        let rs = j.replay(l, vec![l_a1.clone()], ReplayContext::Full { last: false });
+        assert_eq!(
+            rs.results,
+            vec![(vec![1.into(), "a".into(), "x".into()], true)].into()
+        );
+        let rs = j.replay(l, Vec::<Record>::new(), ReplayContext::Full { last: true });
+        assert_eq!(
+            rs.results,
+            vec![(vec![2.into(), DataType::None, "y".into()], true)].into()
+        );
+    }
+
+    #[test]
+    fn it_materializes_right_parent_of_full_join() {
+        let (j, l, r) = setup_kind(JoinType::Full);
+        let join = j.node();
+        assert!(join.must_materialize_parent(r.as_global()));
+        assert!(!join.must_materialize_parent(l.as_global()));
+
+        let (j, _, r) = setup();
+        assert!(!j.node().must_materialize_parent(r.as_global()));
+    }
+
     #[test]
     fn it_suggests_indices() {
         use std::collections::HashMap;
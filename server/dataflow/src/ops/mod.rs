@@ -127,6 +127,9 @@ impl Ingredient for NodeOperator {
     fn suggest_indexes(&self, you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         impl_ingredient_fn_ref!(self, suggest_indexes, you)
     }
+    fn suggest_indexes_if_full(&self, you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        impl_ingredient_fn_ref!(self, suggest_indexes_if_full, you)
+    }
     fn resolve(&self, i: usize) -> Option<Vec<(NodeIndex, usize)>> {
         impl_ingredient_fn_ref!(self, resolve, i)
     }
@@ -223,6 +226,12 @@ impl Ingredient for NodeOperator {
     fn requires_full_materialization(&self) -> bool {
         impl_ingredient_fn_ref!(self, requires_full_materialization,)
     }
+    fn must_materialize_parent(&self, parent: NodeIndex) -> bool {
+        impl_ingredient_fn_ref!(self, must_materialize_parent, parent)
+    }
+    fn must_evict_on_miss(&self) -> bool {
+        impl_ingredient_fn_ref!(self, must_evict_on_miss,)
+    }
 }
 
 #[cfg(test)]
@@ -327,7 +336,9 @@ pub mod test {
                 .on_commit(&self.remap);
 
             // we need to set the indices for all the base tables so they *actually* store things.
-            let idx = self.graph[global].suggest_indexes(global);
+            // the node under test starts out fully materialized.
+            let mut idx = self.graph[global].suggest_indexes(global);
+            idx.extend(self.graph[global].suggest_indexes_if_full(global));
             for (tbl, col) in idx {
                 if let Some(ref mut s) = self.states.get_mut(self.graph[tbl].local_addr()) {
                     s.add_key(&col[..], None);
@@ -440,6 +451,92 @@ pub mod test {
             u
         }
 
+        /// Like `one`, but hands over whatever the node under test produced, misses and lookups
+        /// included, along with the universes it asked to create. `replay_key_cols` is passed on
+        /// as is, and nothing is remembered.
+        pub fn input<U: Into<Records>>(
+            &mut self,
+            src: IndexPair,
+            u: U,
+            replay_key_cols: Option<&[usize]>,
+        ) -> (ProcessingResult, Vec<HashMap<String, DataType>>) {
+            assert!(self.nut.is_some());
+
+            struct Ex(Vec<HashMap<String, DataType>>);
+
+            impl Executor for Ex {
+                fn ack(&mut self, _: SourceChannelIdentifier) {}
+                fn create_universe(&mut self, req: HashMap<String, DataType>) {
+                    self.0.push(req);
+                }
+                fn send(&mut self, _: ReplicaAddr, _: Box<Packet>) {}
+            }
+
+            let mut ex = Ex(Vec::new());
+            let id = self.nut.unwrap();
+            let mut n = self.nodes[*id].borrow_mut();
+            let m = n.on_input(
+                &mut ex,
+                *src,
+                u.into(),
+                replay_key_cols,
+                &self.nodes,
+                &self.states,
+            );
+            (m, ex.0)
+        }
+
+        /// Like `input`, but hands `u` to the node under test as part of the given `replay`.
+        pub fn replay<U: Into<Records>>(
+            &mut self,
+            src: IndexPair,
+            u: U,
+            replay: ReplayContext,
+        ) -> ProcessingResult {
+            assert!(self.nut.is_some());
+
+            struct Ex;
+
+            impl Executor for Ex {
+                fn ack(&mut self, _: SourceChannelIdentifier) {}
+                fn create_universe(&mut self, _: HashMap<String, DataType>) {}
+                fn send(&mut self, _: ReplicaAddr, _: Box<Packet>) {}
+            }
+
+            let log = slog::Logger::root(slog::Discard, o!());
+            let id = self.nut.unwrap();
+            let mut n = self.nodes[*id].borrow_mut();
+            let m = n.on_input_raw(
+                &mut Ex,
+                *src,
+                u.into(),
+                replay,
+                &self.nodes,
+                &self.states,
+                &log,
+            );
+            match m {
+                RawProcessingResult::Regular(m) => m,
+                _ => unreachable!(),
+            }
+        }
+
+        /// Make the state of the node under test partial on the given columns. Every key starts
+        /// out as a hole until it is filled with `fill`.
+        pub fn set_partial(&mut self, columns: &[usize]) {
+            let mut state = MemoryState::default();
+            state.add_key(columns, Some(vec![Tag::new(0)]));
+            self.states.insert(*self.nut.unwrap(), Box::new(state));
+        }
+
+        /// Fill the hole for `key` in the partial state of the node under test with `rows`, as a
+        /// replay would.
+        pub fn fill(&mut self, key: Vec<DataType>, rows: Vec<Vec<DataType>>) {
+            let state = self.states.get_mut(*self.nut.unwrap()).unwrap();
+            state.mark_filled(key, Tag::new(0));
+            state.process_records(&mut rows.into(), Some(Tag::new(0)));
+        }
+
         pub fn one_row<R: Into<Record>>(
             &mut self,
             src: IndexPair,
@@ -458,6 +555,21 @@ pub mod test {
             self.narrow_one::<Record>(d.into(), remember)
         }
 
+        /// Like `narrow_one`, but also applies `u` to the base's state (if it has any) first, as
+        /// the domain would before handing `u` to the node under test.
+        pub fn narrow_through<U: Into<Records>>(&mut self, u: U, remember: bool) -> Records {
+            let src = self.narrow_base_id();
+            let mut u = u.into();
+            if let Some(state) = self.states.get_mut(*src) {
+                state.process_records(&mut u, None);
+            }
+            self.one::<Records>(src, u, remember)
+        }
+
+        pub fn narrow_through_row<R: Into<Record>>(&mut self, d: R, remember: bool) -> Records {
+            self.narrow_through::<Record>(d.into(), remember)
+        }
+
         pub fn node(&self) -> cell::Ref<Node> {
             self.nodes[*self.nut.unwrap()].borrow()
         }
@@ -1,53 +1,515 @@
-use nom_sql::ArithmeticOperator;
+use nom_sql::{ArithmeticOperator, Operator};
 
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::ops::filter::like;
 use crate::prelude::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ProjectExpressionBase {
-    Column(usize),
-    Literal(DataType),
+/// A scalar function that can be applied to values within a projection.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum BuiltinFunction {
+    /// `COALESCE(a, b, ...)`: the first argument that is not NULL.
+    Coalesce,
+    /// `IFNULL(a, b)`: `a`, or `b` if `a` is NULL.
+    IfNull,
+    /// `CONCAT(a, b, ...)`: the concatenation of the arguments' textual representations.
+    Concat,
+    /// `LOWER(s)`
+    Lower,
+    /// `UPPER(s)`
+    Upper,
+    /// `SUBSTRING(s, pos[, len])`, where `pos` is 1-based, or counts from the end if negative.
+    Substring,
+    /// `DATE_FORMAT(ts, fmt)`, using MySQL's format specifiers.
+    DateFormat,
+    /// `DATE(ts)`: the timestamp truncated to midnight.
+    Date,
+    /// `ROUND(x[, d])`: `x` rounded to `d` decimal places, or to an integer if `d` is omitted.
+    Round,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProjectExpression {
-    op: ArithmeticOperator,
-    left: ProjectExpressionBase,
-    right: ProjectExpressionBase,
+impl BuiltinFunction {
+    /// The function's SQL name.
+    pub fn name(self) -> &'static str {
+        match self {
+            BuiltinFunction::Coalesce => "coalesce",
+            BuiltinFunction::IfNull => "ifnull",
+            BuiltinFunction::Concat => "concat",
+            BuiltinFunction::Lower => "lower",
+            BuiltinFunction::Upper => "upper",
+            BuiltinFunction::Substring => "substring",
+            BuiltinFunction::DateFormat => "date_format",
+            BuiltinFunction::Date => "date",
+            BuiltinFunction::Round => "round",
+        }
+    }
+
+    /// Returns true if the function can be called with `n` arguments.
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            BuiltinFunction::Coalesce | BuiltinFunction::Concat => n >= 1,
+            BuiltinFunction::IfNull | BuiltinFunction::DateFormat => n == 2,
+            BuiltinFunction::Lower | BuiltinFunction::Upper | BuiltinFunction::Date => n == 1,
+            BuiltinFunction::Substring => n == 2 || n == 3,
+            BuiltinFunction::Round => n == 1 || n == 2,
+        }
+    }
+
+    /// Applies the function to `args`, giving NULL if they are of the wrong number or type.
+    fn apply(self, args: &[DataType]) -> DataType {
+        if !self.accepts(args.len()) {
+            return DataType::None;
+        }
+
+        match self {
+            BuiltinFunction::Coalesce => args
+                .iter()
+                .find(|a| !a.is_none())
+                .cloned()
+                .unwrap_or(DataType::None),
+            BuiltinFunction::IfNull if args[0].is_none() => args[1].clone(),
+            BuiltinFunction::IfNull => args[0].clone(),
+            // all remaining functions return NULL if any of their arguments is NULL
+            _ if args.iter().any(DataType::is_none) => DataType::None,
+            BuiltinFunction::Concat => args.iter().map(text).collect::<String>().into(),
+            BuiltinFunction::Lower => text(&args[0]).to_lowercase().into(),
+            BuiltinFunction::Upper => text(&args[0]).to_uppercase().into(),
+            BuiltinFunction::Substring => {
+                let s: Vec<char> = text(&args[0]).chars().collect();
+                let (pos, len) = match (integer(&args[1]), args.get(2).map(integer)) {
+                    (Some(pos), None) => (pos, i64::max_value()),
+                    (Some(pos), Some(Some(len))) => (pos, len),
+                    _ => return DataType::None,
+                };
+                let start = if pos < 0 {
+                    s.len() as i64 + pos
+                } else {
+                    pos - 1
+                };
+                if pos == 0 || start < 0 || start as usize >= s.len() || len <= 0 {
+                    return "".into();
+                }
+                s[start as usize..]
+                    .iter()
+                    .take(len as usize)
+                    .collect::<String>()
+                    .into()
+            }
+            BuiltinFunction::DateFormat => match args[0] {
+                DataType::Timestamp(ts) => {
+                    let fmt = strftime_format(&text(&args[1]));
+                    ts.format(&fmt).to_string().into()
+                }
+                _ => DataType::None,
+            },
+            BuiltinFunction::Date => match args[0] {
+                DataType::Timestamp(ts) => DataType::Timestamp(ts.date().and_hms(0, 0, 0)),
+                _ => DataType::None,
+            },
+            BuiltinFunction::Round => {
+                use std::convert::TryFrom;
+
+                let digits = match args.get(1).map(integer) {
+                    None => 0,
+                    Some(Some(digits)) => digits,
+                    Some(None) => return DataType::None,
+                };
+                match args[0] {
+                    DataType::Real(..) => {
+                        let x: f64 = (&args[0]).into();
+                        // an f64 has at most 17 significant digits, and is below 10^309
+                        if digits > 17 {
+                            x.into()
+                        } else if digits < -308 {
+                            0.0.into()
+                        } else {
+                            let scale = 10f64.powi(digits as i32);
+                            ((x * scale).round() / scale).into()
+                        }
+                    }
+                    DataType::Int(..)
+                    | DataType::UnsignedInt(..)
+                    | DataType::BigInt(..)
+                    | DataType::UnsignedBigInt(..) => {
+                        if digits >= 0 {
+                            return args[0].clone();
+                        }
+                        let x = i128::from(&args[0]);
+                        let scale = digits
+                            .checked_neg()
+                            .and_then(|d| u32::try_from(d).ok())
+                            .and_then(|d| 10i128.checked_pow(d));
+                        let rounded = match scale {
+                            Some(scale) => {
+                                let half = if x < 0 { -scale / 2 } else { scale / 2 };
+                                (x + half) / scale * scale
+                            }
+                            // rounding to more digits than any integer has leaves nothing
+                            None => 0,
+                        };
+                        i64::try_from(rounded)
+                            .map(DataType::from)
+                            .or_else(|_| u64::try_from(rounded).map(DataType::from))
+                            .unwrap_or(DataType::None)
+                    }
+                    _ => DataType::None,
+                }
+            }
+        }
+    }
 }
 
-impl ProjectExpression {
-    pub fn new(
+/// Returns a numeric value as an integer, rounding reals, or `None` if it isn't a number that fits
+/// in an `i64`.
+fn integer(d: &DataType) -> Option<i64> {
+    use std::convert::TryFrom;
+
+    match *d {
+        DataType::Int(n) => Some(i64::from(n)),
+        DataType::UnsignedInt(n) => Some(i64::from(n)),
+        DataType::BigInt(n) => Some(n),
+        DataType::UnsignedBigInt(n) => i64::try_from(n).ok(),
+        DataType::Real(..) => {
+            let x = f64::from(d).round();
+            if x >= i64::min_value() as f64 && x < i64::max_value() as f64 {
+                Some(x as i64)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the operands of an arithmetic operation in a form that `DataType` arithmetic accepts, or
+/// `None` if either operand is not a number.
+fn numeric_operands(left: DataType, right: DataType) -> Option<(DataType, DataType)> {
+    let numeric = |d: &DataType| match *d {
+        DataType::Int(..)
+        | DataType::UnsignedInt(..)
+        | DataType::BigInt(..)
+        | DataType::UnsignedBigInt(..)
+        | DataType::Real(..) => true,
+        _ => false,
+    };
+    if !numeric(&left) || !numeric(&right) {
+        return None;
+    }
+
+    match (&left, &right) {
+        (DataType::Real(..), _)
+        | (_, DataType::Real(..))
+        | (DataType::UnsignedInt(..), DataType::UnsignedInt(..))
+        | (DataType::UnsignedInt(..), DataType::UnsignedBigInt(..))
+        | (DataType::UnsignedBigInt(..), DataType::UnsignedInt(..)) => Some((left, right)),
+        _ => {
+            // unsigned 32-bit integers only mix with other unsigned integers, so widen them
+            let widen = |d: DataType| match d {
+                DataType::UnsignedInt(n) => DataType::BigInt(i64::from(n)),
+                d => d,
+            };
+            Some((widen(left), widen(right)))
+        }
+    }
+}
+
+/// Returns the textual representation of a value, as used by string functions.
+fn text(d: &DataType) -> String {
+    match *d {
+        DataType::Text(..) | DataType::TinyText(..) => <&str>::from(d).to_owned(),
+        DataType::Real(..) => f64::from(d).to_string(),
+        DataType::Timestamp(ts) => ts.format("%Y-%m-%d %H:%M:%S").to_string(),
+        ref d => d.to_string(),
+    }
+}
+
+/// Translates a MySQL `DATE_FORMAT` format string into the strftime syntax used by chrono.
+fn strftime_format(fmt: &str) -> String {
+    let mut out = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(c @ 'a') | Some(c @ 'b') | Some(c @ 'd') | Some(c @ 'H') | Some(c @ 'I')
+            | Some(c @ 'j') | Some(c @ 'm') | Some(c @ 'p') | Some(c @ 'y') | Some(c @ 'Y') => {
+                out.push('%');
+                out.push(c);
+            }
+            Some('c') => out.push_str("%-m"),
+            Some('e') => out.push_str("%-d"),
+            Some('f') => out.push_str("%6f"),
+            Some('h') => out.push_str("%I"),
+            Some('i') => out.push_str("%M"),
+            Some('k') => out.push_str("%-H"),
+            Some('l') => out.push_str("%-I"),
+            Some('M') => out.push_str("%B"),
+            Some('r') => out.push_str("%I:%M:%S %p"),
+            Some('s') | Some('S') => out.push_str("%S"),
+            Some('T') => out.push_str("%H:%M:%S"),
+            Some('W') => out.push_str("%A"),
+            // MySQL prints unknown specifiers (including `%%`) as the character itself
+            Some('%') => out.push_str("%%"),
+            Some(c) => out.push(c),
+            None => out.push_str("%%"),
+        }
+    }
+    out
+}
+
+/// Returns the truth value of `d` when used as a condition, or `None` if it is NULL.
+fn truth(d: &DataType) -> Option<bool> {
+    match *d {
+        DataType::None => None,
+        DataType::Real(i, f) => Some(i != 0 || f != 0),
+        DataType::Text(..) | DataType::TinyText(..) => Some(!<&str>::from(d).is_empty()),
+        DataType::Timestamp(_) => Some(true),
+        ref d => Some(i128::from(d) != 0),
+    }
+}
+
+/// Converts the (possibly unknown) outcome of a condition into a value.
+fn truth_value(b: Option<bool>) -> DataType {
+    match b {
+        None => DataType::None,
+        Some(b) => DataType::Int(b as i32),
+    }
+}
+
+/// An expression computed over the columns of a record.
+///
+/// Columns are identified by `C`: dataflow operators use column indices, while MIR refers to
+/// columns by name until they are resolved against the parent node.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ProjectExpression<C = usize> {
+    Column(C),
+    Literal(DataType),
+    Arithmetic(
+        ArithmeticOperator,
+        Box<ProjectExpression<C>>,
+        Box<ProjectExpression<C>>,
+    ),
+    /// A comparison or logical connective, which evaluates to 1, 0, or NULL.
+    Condition(
+        Operator,
+        Box<ProjectExpression<C>>,
+        Box<ProjectExpression<C>>,
+    ),
+    /// `CASE WHEN c1 THEN v1 [WHEN c2 THEN v2 ...] [ELSE e] END`
+    Case(
+        Vec<(ProjectExpression<C>, ProjectExpression<C>)>,
+        Option<Box<ProjectExpression<C>>>,
+    ),
+    Call(BuiltinFunction, Vec<ProjectExpression<C>>),
+}
+
+impl<C> ProjectExpression<C> {
+    pub fn arithmetic(
         op: ArithmeticOperator,
-        left: ProjectExpressionBase,
-        right: ProjectExpressionBase,
-    ) -> ProjectExpression {
-        ProjectExpression { op, left, right }
+        left: ProjectExpression<C>,
+        right: ProjectExpression<C>,
+    ) -> ProjectExpression<C> {
+        ProjectExpression::Arithmetic(op, Box::new(left), Box::new(right))
+    }
+
+    pub fn condition(
+        op: Operator,
+        left: ProjectExpression<C>,
+        right: ProjectExpression<C>,
+    ) -> ProjectExpression<C> {
+        ProjectExpression::Condition(op, Box::new(left), Box::new(right))
+    }
+
+    /// Returns the columns that the expression reads, in order of appearance.
+    pub fn columns(&self) -> Vec<&C> {
+        let mut cols = vec![];
+        self.collect_columns(&mut cols);
+        cols
+    }
+
+    fn collect_columns<'a>(&'a self, cols: &mut Vec<&'a C>) {
+        match *self {
+            ProjectExpression::Column(ref c) => cols.push(c),
+            ProjectExpression::Literal(_) => {}
+            ProjectExpression::Arithmetic(_, ref l, ref r)
+            | ProjectExpression::Condition(_, ref l, ref r) => {
+                l.collect_columns(cols);
+                r.collect_columns(cols);
+            }
+            ProjectExpression::Case(ref branches, ref otherwise) => {
+                for (c, v) in branches {
+                    c.collect_columns(cols);
+                    v.collect_columns(cols);
+                }
+                if let Some(ref e) = *otherwise {
+                    e.collect_columns(cols);
+                }
+            }
+            ProjectExpression::Call(_, ref args) => {
+                for a in args {
+                    a.collect_columns(cols);
+                }
+            }
+        }
+    }
+
+    /// Returns a copy of the expression with every column reference replaced by `f(column)`.
+    pub fn map_columns<D, F>(&self, f: &mut F) -> ProjectExpression<D>
+    where
+        F: FnMut(&C) -> D,
+    {
+        match *self {
+            ProjectExpression::Column(ref c) => ProjectExpression::Column(f(c)),
+            ProjectExpression::Literal(ref d) => ProjectExpression::Literal(d.clone()),
+            ProjectExpression::Arithmetic(ref op, ref l, ref r) => {
+                ProjectExpression::arithmetic(op.clone(), l.map_columns(f), r.map_columns(f))
+            }
+            ProjectExpression::Condition(ref op, ref l, ref r) => {
+                ProjectExpression::condition(op.clone(), l.map_columns(f), r.map_columns(f))
+            }
+            ProjectExpression::Case(ref branches, ref otherwise) => ProjectExpression::Case(
+                branches
+                    .iter()
+                    .map(|(c, v)| (c.map_columns(f), v.map_columns(f)))
+                    .collect(),
+                otherwise.as_ref().map(|e| Box::new(e.map_columns(f))),
+            ),
+            ProjectExpression::Call(func, ref args) => {
+                ProjectExpression::Call(func, args.iter().map(|a| a.map_columns(f)).collect())
+            }
+        }
     }
 }
 
-impl fmt::Display for ProjectExpressionBase {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl ProjectExpression {
+    /// Evaluates the expression over the given record.
+    pub fn eval(&self, record: &[DataType]) -> DataType {
         match *self {
-            ProjectExpressionBase::Column(u) => write!(f, "{}", u),
-            ProjectExpressionBase::Literal(ref l) => write!(f, "(lit: {})", l),
+            ProjectExpression::Column(i) => record[i].clone(),
+            ProjectExpression::Literal(ref data) => data.clone(),
+            ProjectExpression::Arithmetic(ref op, ref left, ref right) => {
+                // like NULL, an operand that isn't a number makes the result unknown
+                let (left, right) = match numeric_operands(left.eval(record), right.eval(record)) {
+                    Some(operands) => operands,
+                    None => return DataType::None,
+                };
+
+                // like MySQL, dividing by zero gives NULL rather than an error
+                let zero = match right {
+                    DataType::Int(0)
+                    | DataType::UnsignedInt(0)
+                    | DataType::BigInt(0)
+                    | DataType::UnsignedBigInt(0) => true,
+                    DataType::Real(..) => f64::from(&right) == 0.0,
+                    _ => false,
+                };
+                if zero && *op == ArithmeticOperator::Divide {
+                    return DataType::None;
+                }
+
+                match *op {
+                    ArithmeticOperator::Add => &left + &right,
+                    ArithmeticOperator::Subtract => &left - &right,
+                    ArithmeticOperator::Multiply => &left * &right,
+                    ArithmeticOperator::Divide => &left / &right,
+                }
+            }
+            ProjectExpression::Condition(ref op, ref left, ref right) => {
+                let left = left.eval(record);
+                let right = right.eval(record);
+                let result = match *op {
+                    Operator::And => match (truth(&left), truth(&right)) {
+                        (Some(false), _) | (_, Some(false)) => Some(false),
+                        (Some(true), Some(true)) => Some(true),
+                        _ => None,
+                    },
+                    Operator::Or => match (truth(&left), truth(&right)) {
+                        (Some(true), _) | (_, Some(true)) => Some(true),
+                        (Some(false), Some(false)) => Some(false),
+                        _ => None,
+                    },
+                    Operator::Is => Some(left == right),
+                    // negates the left-hand side; the right-hand side is ignored
+                    Operator::Not => truth(&left).map(|b| !b),
+                    _ if left.is_none() || right.is_none() => None,
+                    Operator::Equal => Some(left == right),
+                    Operator::NotEqual => Some(left != right),
+                    Operator::Greater => Some(left > right),
+                    Operator::GreaterOrEqual => Some(left >= right),
+                    Operator::Less => Some(left < right),
+                    Operator::LessOrEqual => Some(left <= right),
+                    Operator::Like => like(&left, &right),
+                    Operator::NotLike => like(&left, &right).map(|m| !m),
+                    // a list of one value
+                    Operator::In => Some(left == right),
+                };
+                truth_value(result)
+            }
+            ProjectExpression::Case(ref branches, ref otherwise) => branches
+                .iter()
+                .find(|(c, _)| truth(&c.eval(record)) == Some(true))
+                .map(|(_, v)| v)
+                .or_else(|| otherwise.as_ref().map(|e| &**e))
+                .map(|e| e.eval(record))
+                .unwrap_or(DataType::None),
+            ProjectExpression::Call(func, ref args) => {
+                let args: Vec<_> = args.iter().map(|a| a.eval(record)).collect();
+                func.apply(&args)
+            }
         }
     }
 }
 
-impl fmt::Display for ProjectExpression {
+impl<C: fmt::Display> fmt::Display for ProjectExpression<C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let op = match self.op {
-            ArithmeticOperator::Add => "+",
-            ArithmeticOperator::Subtract => "-",
-            ArithmeticOperator::Divide => "/",
-            ArithmeticOperator::Multiply => "*",
+        // operands that are themselves binary operations are parenthesized
+        let operand = |e: &ProjectExpression<C>| match *e {
+            ProjectExpression::Arithmetic(..) | ProjectExpression::Condition(..) => {
+                format!("({})", e)
+            }
+            ref e => format!("{}", e),
         };
 
-        write!(f, "{} {} {}", self.left, op, self.right)
+        match *self {
+            ProjectExpression::Column(ref c) => write!(f, "{}", c),
+            ProjectExpression::Literal(ref l) => write!(f, "(lit: {})", l),
+            ProjectExpression::Arithmetic(ref op, ref left, ref right) => {
+                let op = match *op {
+                    ArithmeticOperator::Add => "+",
+                    ArithmeticOperator::Subtract => "-",
+                    ArithmeticOperator::Divide => "/",
+                    ArithmeticOperator::Multiply => "*",
+                };
+                write!(f, "{} {} {}", operand(left), op, operand(right))
+            }
+            ProjectExpression::Condition(ref op, ref left, ref right) => {
+                write!(f, "{} {} {}", operand(left), op, operand(right))
+            }
+            ProjectExpression::Case(ref branches, ref otherwise) => {
+                write!(f, "CASE")?;
+                for (c, v) in branches {
+                    write!(f, " WHEN {} THEN {}", c, v)?;
+                }
+                if let Some(ref e) = *otherwise {
+                    write!(f, " ELSE {}", e)?;
+                }
+                write!(f, " END")
+            }
+            ProjectExpression::Call(func, ref args) => write!(
+                f,
+                "{}({})",
+                func.name(),
+                args.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
     }
 }
 
@@ -100,25 +562,6 @@ impl Project {
     }
 }
 
-fn eval_expression(expression: &ProjectExpression, record: &[DataType]) -> DataType {
-    let left = match expression.left {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
-    };
-
-    let right = match expression.right {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
-    };
-
-    match expression.op {
-        ArithmeticOperator::Add => left + right,
-        ArithmeticOperator::Subtract => left - right,
-        ArithmeticOperator::Multiply => left * right,
-        ArithmeticOperator::Divide => left / right,
-    }
-}
-
 impl Ingredient for Project {
     fn take(&mut self) -> NodeOperator {
         Clone::clone(self).into()
@@ -168,7 +611,7 @@ impl Ingredient for Project {
                         Some(emit) => Box::new(rs.map(move |r| {
                             let mut new_r = Vec::with_capacity(r.len());
                             let mut expr: Vec<DataType> = if let Some(ref e) = expressions {
-                                e.iter().map(|i| eval_expression(i, &r[..])).collect()
+                                e.iter().map(|i| i.eval(&r[..])).collect()
                             } else {
                                 vec![]
                             };
@@ -239,7 +682,7 @@ impl Ingredient for Project {
                 }
 
                 if let Some(ref e) = self.expressions {
-                    new_r.extend(e.iter().map(|i| eval_expression(i, &r[..])));
+                    new_r.extend(e.iter().map(|i| i.eval(&r[..])));
                 }
 
                 if let Some(ref a) = self.additional {
@@ -351,11 +794,11 @@ mod tests {
     }
 
     fn setup_column_arithmetic(op: ArithmeticOperator) -> ops::test::MockGraph {
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
+        let expression = ProjectExpression::arithmetic(
             op,
-        };
+            ProjectExpression::Column(0),
+            ProjectExpression::Column(1),
+        );
 
         setup_arithmetic(expression)
     }
@@ -470,14 +913,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_divides_by_zero_to_null() {
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Divide);
+        let rec = vec![10.into(), 0.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![10.into(), 0.into(), DataType::None]].into()
+        );
+        let rec = vec![10.into(), (0.0).into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![10.into(), (0.0).into(), DataType::None]].into()
+        );
+    }
+
+    #[test]
+    fn it_gives_null_for_non_numeric_arithmetic() {
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Multiply);
+        let rec = vec!["text".into(), 2.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec!["text".into(), 2.into(), DataType::None]].into()
+        );
+
+        let ts = DataType::from(nom_sql::Literal::CurrentTimestamp);
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Add);
+        let rec = vec![ts.clone(), 3600.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![ts, 3600.into(), DataType::None]].into()
+        );
+
+        // mixed signed and unsigned integers are still numbers
+        let rec = vec![DataType::UnsignedInt(3), DataType::Int(-1)];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![DataType::UnsignedInt(3), DataType::Int(-1), 2.into()]].into()
+        );
+    }
+
     #[test]
     fn it_forwards_arithmetic_w_literals() {
         let number: DataType = 40.into();
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Literal(number),
-            op: ArithmeticOperator::Multiply,
-        };
+        let expression = ProjectExpression::arithmetic(
+            ArithmeticOperator::Multiply,
+            ProjectExpression::Column(0),
+            ProjectExpression::Literal(number),
+        );
 
         let mut p = setup_arithmetic(expression);
         let rec = vec![10.into(), 0.into()];
@@ -491,11 +974,11 @@ mod tests {
     fn it_forwards_arithmetic_w_only_literals() {
         let a: DataType = 80.into();
         let b: DataType = 40.into();
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Literal(a),
-            right: ProjectExpressionBase::Literal(b),
-            op: ArithmeticOperator::Divide,
-        };
+        let expression = ProjectExpression::arithmetic(
+            ArithmeticOperator::Divide,
+            ProjectExpression::Literal(a),
+            ProjectExpression::Literal(b),
+        );
 
         let mut p = setup_arithmetic(expression);
         let rec = vec![0.into(), 0.into()];
@@ -505,6 +988,196 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_forwards_nested_arithmetic() {
+        // (x + y) * 2
+        let expression = ProjectExpression::arithmetic(
+            ArithmeticOperator::Multiply,
+            ProjectExpression::arithmetic(
+                ArithmeticOperator::Add,
+                ProjectExpression::Column(0),
+                ProjectExpression::Column(1),
+            ),
+            ProjectExpression::Literal(2.into()),
+        );
+        assert_eq!(format!("{}", expression), "(0 + 1) * (lit: 2)");
+
+        let mut p = setup_arithmetic(expression);
+        let rec = vec![10.into(), 20.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![10.into(), 20.into(), 60.into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_case() {
+        // CASE WHEN x > 10 THEN 'big' WHEN y IS NULL THEN 'unknown' ELSE 'small' END
+        let expression = ProjectExpression::Case(
+            vec![
+                (
+                    ProjectExpression::condition(
+                        Operator::Greater,
+                        ProjectExpression::Column(0),
+                        ProjectExpression::Literal(10.into()),
+                    ),
+                    ProjectExpression::Literal("big".into()),
+                ),
+                (
+                    ProjectExpression::condition(
+                        Operator::Is,
+                        ProjectExpression::Column(1),
+                        ProjectExpression::Literal(DataType::None),
+                    ),
+                    ProjectExpression::Literal("unknown".into()),
+                ),
+            ],
+            Some(Box::new(ProjectExpression::Literal("small".into()))),
+        );
+
+        let mut p = setup_arithmetic(expression);
+        let row = |x: DataType, y: DataType, out: &str| -> Records {
+            vec![vec![x, y, out.into()]].into()
+        };
+        assert_eq!(
+            p.narrow_one_row(vec![20.into(), 1.into()], false),
+            row(20.into(), 1.into(), "big")
+        );
+        assert_eq!(
+            p.narrow_one_row(vec![5.into(), DataType::None], false),
+            row(5.into(), DataType::None, "unknown")
+        );
+        assert_eq!(
+            p.narrow_one_row(vec![5.into(), 1.into()], false),
+            row(5.into(), 1.into(), "small")
+        );
+    }
+
+    #[test]
+    fn it_evaluates_builtin_functions() {
+        use super::BuiltinFunction::*;
+        use super::ProjectExpression::{Call, Column, Literal};
+        let call = |f: BuiltinFunction, args: Vec<ProjectExpression>| Call(f, args);
+        let eval = |e: ProjectExpression, r: &[DataType]| e.eval(r);
+
+        let r: Vec<DataType> = vec![DataType::None, "Hello".into(), 3.14159.into()];
+        assert_eq!(
+            eval(call(Coalesce, vec![Column(0), Column(1)]), &r),
+            "Hello".into()
+        );
+        assert_eq!(
+            eval(call(IfNull, vec![Column(0), Literal(7.into())]), &r),
+            7.into()
+        );
+        assert_eq!(
+            eval(
+                call(
+                    Concat,
+                    vec![Column(1), Literal(", ".into()), Literal(2.into())]
+                ),
+                &r
+            ),
+            "Hello, 2".into()
+        );
+        assert_eq!(
+            eval(call(Concat, vec![Column(1), Column(0)]), &r),
+            DataType::None
+        );
+        assert_eq!(eval(call(Lower, vec![Column(1)]), &r), "hello".into());
+        assert_eq!(eval(call(Upper, vec![Column(1)]), &r), "HELLO".into());
+        assert_eq!(
+            eval(call(Substring, vec![Column(1), Literal(2.into())]), &r),
+            "ello".into()
+        );
+        assert_eq!(
+            eval(
+                call(
+                    Substring,
+                    vec![Column(1), Literal((-4).into()), Literal(2.into())]
+                ),
+                &r
+            ),
+            "el".into()
+        );
+        assert_eq!(
+            eval(call(Round, vec![Column(2), Literal(2.into())]), &r),
+            3.14.into()
+        );
+        assert_eq!(eval(call(Round, vec![Column(2)]), &r), 3.0.into());
+        assert_eq!(
+            eval(
+                call(Round, vec![Literal(1250.into()), Literal((-2).into())]),
+                &r
+            ),
+            1300.into()
+        );
+
+        // arguments of the wrong number or type give NULL
+        assert_eq!(eval(call(Lower, vec![]), &r), DataType::None);
+        assert_eq!(
+            eval(call(Substring, vec![Column(1), Column(1)]), &r),
+            DataType::None
+        );
+        assert_eq!(
+            eval(call(Round, vec![Column(1), Literal((-2).into())]), &r),
+            DataType::None
+        );
+        assert_eq!(
+            eval(call(Round, vec![Column(2), Column(1)]), &r),
+            DataType::None
+        );
+
+        // rounding to more digits than there are leaves nothing, or the value as it is
+        assert_eq!(
+            eval(
+                call(Round, vec![Literal(1250.into()), Literal((-100).into())]),
+                &r
+            ),
+            0.into()
+        );
+        assert_eq!(
+            eval(
+                call(
+                    Round,
+                    vec![Literal(1250.into()), Literal(i64::min_value().into())]
+                ),
+                &r
+            ),
+            0.into()
+        );
+        assert_eq!(
+            eval(call(Round, vec![Column(2), Literal(1000.into())]), &r),
+            3.14159.into()
+        );
+    }
+
+    #[test]
+    fn it_evaluates_date_functions() {
+        let today = match DataType::from(nom_sql::Literal::CurrentTimestamp) {
+            DataType::Timestamp(ts) => ts.date(),
+            _ => unreachable!(),
+        };
+        let r = vec![DataType::Timestamp(today.and_hms(14, 5, 9))];
+        let call = |f: BuiltinFunction, args: Vec<ProjectExpression>| {
+            ProjectExpression::Call(f, args).eval(&r)
+        };
+
+        assert_eq!(
+            call(BuiltinFunction::Date, vec![ProjectExpression::Column(0)]),
+            DataType::Timestamp(today.and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            call(
+                BuiltinFunction::DateFormat,
+                vec![
+                    ProjectExpression::Column(0),
+                    ProjectExpression::Literal("%Y-%m-%d %H:%i:%s (%k) 100%%".into()),
+                ]
+            ),
+            format!("{} 14:05:09 (14) 100%", today.format("%Y-%m-%d")).into()
+        );
+    }
+
     fn setup_query_through(
         mut state: Box<dyn State>,
         permutation: &[usize],
@@ -617,11 +1290,11 @@ mod tests {
     #[test]
     fn it_queries_through_w_arithmetic_and_literals() {
         let additional = Some(vec![DataType::Int(42)]);
-        let expressions = Some(vec![ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
-            op: ArithmeticOperator::Add,
-        }]);
+        let expressions = Some(vec![ProjectExpression::arithmetic(
+            ArithmeticOperator::Add,
+            ProjectExpression::Column(0),
+            ProjectExpression::Column(1),
+        )]);
 
         let state = Box::new(MemoryState::default());
         let (p, states) = setup_query_through(state, &[1], additional, expressions);
@@ -632,11 +1305,11 @@ mod tests {
     #[test]
     fn it_queries_through_w_arithmetic_and_literals_persistent() {
         let additional = Some(vec![DataType::Int(42)]);
-        let expressions = Some(vec![ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
-            op: ArithmeticOperator::Add,
-        }]);
+        let expressions = Some(vec![ProjectExpression::arithmetic(
+            ArithmeticOperator::Add,
+            ProjectExpression::Column(0),
+            ProjectExpression::Column(1),
+        )]);
 
         let state = Box::new(PersistentState::new(
             String::from("it_queries_through_w_arithmetic_and_literals_persistent"),
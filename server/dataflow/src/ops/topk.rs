@@ -31,9 +31,15 @@ impl From<Vec<(usize, OrderType)>> for Order {
 
 /// TopK provides an operator that will produce the top k elements for each group.
 ///
+/// If an offset is given, the operator instead produces the k elements that follow the first
+/// `offset` elements of each group.
+///
 /// Positives are generally fast to process, while negative records can trigger expensive backwards
-/// queries. It is also worth noting that due the nature of Soup, the results of this operator are
-/// unordered.
+/// queries. Any change to a group with a non-zero offset also queries backwards, since the window
+/// of elements it produces shifts whenever an element ahead of it changes. A partially
+/// materialized TopK without an offset does not query backwards, and instead evicts a group that
+/// shrinks below k elements so that it is replayed in full. It is also worth noting that due the
+/// nature of Soup, the results of this operator are unordered.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TopK {
     src: IndexPair,
@@ -47,6 +53,7 @@ pub struct TopK {
 
     order: Order,
     k: usize,
+    offset: usize,
 }
 
 impl TopK {
@@ -54,12 +61,13 @@ impl TopK {
     ///
     /// `src` is this operator's ancestor, `over` is the column to compute the top K over,
     /// `group_by` indicates the columns that this operator is keyed on, and k is the maximum number
-    /// of results per group.
+    /// of results per group. The first `offset` results of each group are skipped.
     pub fn new(
         src: NodeIndex,
         order: Vec<(usize, OrderType)>,
         group_by: Vec<usize>,
         k: usize,
+        offset: usize,
     ) -> Self {
         let mut group_by = group_by;
         group_by.sort();
@@ -73,8 +81,38 @@ impl TopK {
             group_by,
             order: order.into(),
             k,
+            offset,
         }
     }
+
+    /// Compute the rows that should be in the output for a group with the given rows.
+    ///
+    /// Only the last `offset + k` rows of the sorted group can end up in the output, so we never
+    /// hold on to more than twice that many rows of the group at a time.
+    fn window<'a, I>(&self, rows: I) -> Vec<Cow<'a, [DataType]>>
+    where
+        I: IntoIterator<Item = Cow<'a, [DataType]>>,
+    {
+        let keep = self.offset + self.k;
+        let retain_last = |rows: &mut Vec<Cow<'a, [DataType]>>| {
+            // like in on_input, the rows we keep are at the end of the sorted group
+            rows.sort_unstable_by(|a, b| self.order.cmp(&*a, &*b));
+            let skip = rows.len().saturating_sub(keep);
+            rows.drain(..skip);
+        };
+
+        let mut kept = Vec::new();
+        for r in rows {
+            kept.push(r);
+            if kept.len() >= 2 * keep {
+                retain_last(&mut kept);
+            }
+        }
+        retain_last(&mut kept);
+        let end = kept.len().saturating_sub(self.offset);
+        kept.truncate(end);
+        kept
+    }
 }
 
 impl Ingredient for TopK {
@@ -90,6 +128,7 @@ impl Ingredient for TopK {
 
             order: self.order.clone(),
             k: self.k,
+            offset: self.offset,
         }
         .into()
     }
@@ -118,7 +157,7 @@ impl Ingredient for TopK {
         from: LocalNodeIndex,
         rs: Records,
         replay_key_cols: Option<&[usize]>,
-        _: &DomainNodes,
+        nodes: &DomainNodes,
         state: &StateMap,
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
@@ -149,8 +188,10 @@ impl Ingredient for TopK {
             .get(*us)
             .expect("topk operators must have their own state materialized");
 
+        let this = &*self;
         let mut out = Vec::new();
         let mut grp = Vec::new();
+        let mut grp_rec = Vec::new();
         let mut grpk = 0;
         let mut missed = false;
         // current holds (Cow<Row>, bool) where bool = is_new
@@ -159,19 +200,95 @@ impl Ingredient for TopK {
         let mut lookups = Vec::new();
 
         macro_rules! post_group {
-            ($out:ident, $current:ident, $grpk:expr, $k:expr, $order:expr) => {{
+            ($this:ident, $out:ident, $current:ident, $grpk:expr, $grp:ident, $grp_rec:ident) => {{
+                let k = $this.k;
+                let order = &$this.order;
+
+                // if there used to be k things in the group, and now there are fewer, or if we are
+                // not producing the top of the group, we can only tell what belongs in our output
+                // by looking at the whole group in our parent.
+                let requery = $this.offset != 0 || ($grpk == k && $current.len() < k);
+                if requery && $this.offset == 0 && db.is_partial() {
+                    // we don't keep an index on our parent just for groups that shrink (see
+                    // `suggest_indexes`). instead, we treat this like a miss in our parent, so
+                    // that the domain evicts the group from us and everything downstream, and the
+                    // next read of the group replays it in full.
+                    misses.push(Miss {
+                        on: *$this.src,
+                        lookup_idx: group_by.clone(),
+                        lookup_cols: group_by.clone(),
+                        replay_cols: replay_key_cols.map(Vec::from),
+                        record: $grp_rec.clone(),
+                    });
+                    $current.clear();
+                } else if requery {
+                    let group = match $this.lookup(
+                        *$this.src,
+                        &group_by[..],
+                        &KeyType::from(&$grp[..]),
+                        nodes,
+                        state,
+                    ) {
+                        Some(Some(rs)) => {
+                            if replay_key_cols.is_some() {
+                                lookups.push(Lookup {
+                                    on: *$this.src,
+                                    cols: group_by.clone(),
+                                    key: $grp.clone(),
+                                });
+                            }
+                            Some($this.window(rs))
+                        }
+                        Some(None) => {
+                            // the rows we have are no longer enough to go on, and guessing would
+                            // produce the wrong window. a replay will refill the group, and
+                            // during regular forwarding, the domain evicts what we would have
+                            // changed downstream instead.
+                            misses.push(Miss {
+                                on: *$this.src,
+                                lookup_idx: group_by.clone(),
+                                lookup_cols: group_by.clone(),
+                                replay_cols: replay_key_cols.map(Vec::from),
+                                record: $grp_rec.clone(),
+                            });
+                            None
+                        }
+                        None => unreachable!("topk parent must be indexed on the group"),
+                    };
+
+                    match group {
+                        Some(group) => {
+                            // emit the difference between what we used to output and the new
+                            // window. any old rows that were removed in this batch have already
+                            // been retracted.
+                            let mut old: Vec<_> = $current
+                                .drain(..)
+                                .filter(|&(_, is_new)| !is_new)
+                                .map(|(r, _)| r)
+                                .collect();
+                            let mut added = Vec::new();
+                            for r in group {
+                                if let Some(i) = old.iter().position(|o| *o == r) {
+                                    old.swap_remove(i);
+                                } else {
+                                    added.push(Record::Positive(r.into_owned()));
+                                }
+                            }
+                            $out.extend(old.into_iter().map(|r| Record::Negative(r.into_owned())));
+                            $out.extend(added);
+                        }
+                        None => $current.clear(),
+                    }
+                } else {
+                    post_group!(@incremental $out, $current, $grpk, k, order);
+                }
+            }};
+            (@incremental $out:ident, $current:ident, $grpk:expr, $k:expr, $order:expr) => {{
                 $current.sort_unstable_by(|a, b| $order.cmp(&*a.0, &*b.0));
 
                 let start = $current.len().saturating_sub($k);
 
                 if $grpk == $k {
-                    if $current.len() < $grpk {
-                        // there used to be k things in the group
-                        // now there are fewer than k
-                        // we don't know if querying would bring us back to k
-                        unimplemented!();
-                    }
-
                     // FIXME: if all the elements with the smallest value in the new topk are new,
                     // then it *could* be that there exists some value that is greater than all
                     // those values, and <= the smallest old value. we would only discover that by
@@ -228,13 +345,14 @@ impl Ingredient for TopK {
                 // new group!
 
                 // first, tidy up the old one
-                if !grp.is_empty() {
-                    post_group!(out, current, grpk, self.k, self.order);
+                if !grp.is_empty() && !missed {
+                    post_group!(this, out, current, grpk, grp, grp_rec);
                 }
 
                 // make ready for the new one
                 grp.clear();
                 grp.extend(group_by.iter().map(|&col| &r[col]).cloned());
+                grp_rec = r.rec().to_vec();
 
                 // check out current state
                 match db.lookup(&group_by[..], &KeyType::from(&grp[..])) {
@@ -279,8 +397,8 @@ impl Ingredient for TopK {
                 }
             }
         }
-        if !grp.is_empty() {
-            post_group!(out, current, grpk, self.k, self.order);
+        if !grp.is_empty() && !missed {
+            post_group!(this, out, current, grpk, grp, grp_rec);
         }
 
         ProcessingResult {
@@ -291,13 +409,31 @@ impl Ingredient for TopK {
     }
 
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
-        vec![(this, self.group_by.clone())].into_iter().collect()
+        let mut idx = HashMap::new();
+        idx.insert(this, self.group_by.clone());
+        if self.offset != 0 {
+            // any change to a group with an offset queries our parent for the whole group
+            idx.insert(self.src.as_global(), self.group_by.clone());
+        }
+        idx
+    }
+
+    fn suggest_indexes_if_full(&self, _: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // we can't evict a group that shrinks below k, so we have to refill it from our parent
+        vec![(self.src.as_global(), self.group_by.clone())]
+            .into_iter()
+            .collect()
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
         Some(vec![(self.src.as_global(), col)])
     }
 
+    fn must_evict_on_miss(&self) -> bool {
+        // a group that misses in our parent keeps the rows we last produced for it downstream
+        true
+    }
+
     fn description(&self, detailed: bool) -> String {
         if !detailed {
             return String::from("TopK");
@@ -309,7 +445,11 @@ impl Ingredient for TopK {
             .map(ToString::to_string)
             .collect::<Vec<_>>()
             .join(", ");
-        format!("TopK γ[{}]", group_cols)
+        if self.offset == 0 {
+            format!("TopK γ[{}]", group_cols)
+        } else {
+            format!("TopK γ[{}] +{}", group_cols, self.offset)
+        }
     }
 
     fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
@@ -335,7 +475,7 @@ mod tests {
         g.set_op(
             "topk",
             &["x", "y", "z"],
-            TopK::new(s.as_global(), cmp_rows, vec![1], 3),
+            TopK::new(s.as_global(), cmp_rows, vec![1], 3, 0),
             true,
         );
         (g, s)
@@ -395,7 +535,6 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn it_must_query() {
         let (mut g, s) = setup(false);
 
@@ -444,6 +583,110 @@ mod tests {
         assert!(a[1] == (r10b.clone(), true).into() || a[1] == (r10c.clone(), true).into());
     }
 
+    #[test]
+    fn it_skips_offset() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op(
+            "topk",
+            &["x", "y", "z"],
+            TopK::new(
+                s.as_global(),
+                vec![(2, OrderType::OrderAscending)],
+                vec![1],
+                2,
+                1,
+            ),
+            true,
+        );
+
+        let r10: Vec<DataType> = vec![1.into(), "z".into(), 10.into()];
+        let r12: Vec<DataType> = vec![2.into(), "z".into(), 12.into()];
+        let r11: Vec<DataType> = vec![3.into(), "z".into(), 11.into()];
+        let r15: Vec<DataType> = vec![4.into(), "z".into(), 15.into()];
+
+        // the first row is skipped by the offset
+        g.seed(s, r10.clone());
+        let a = g.narrow_one_row(r10.clone(), true);
+        assert_eq!(a.len(), 0);
+
+        g.seed(s, r12.clone());
+        let a = g.narrow_one_row(r12.clone(), true);
+        assert_eq!(a, vec![r10.clone()].into());
+
+        g.seed(s, r11.clone());
+        let a = g.narrow_one_row(r11.clone(), true);
+        assert_eq!(a, vec![r11.clone()].into());
+
+        // a new top row pushes the window along
+        g.seed(s, r15.clone());
+        let a = g.narrow_one_row(r15.clone(), true);
+        assert_eq!(a.len(), 2);
+        assert!(a.iter().any(|r| r == &(r10.clone(), false).into()));
+        assert!(a.iter().any(|r| r == &(r12.clone(), true).into()));
+
+        // and removing a row from the window pulls it back
+        g.unseed(s);
+        g.seed(s, r10.clone());
+        g.seed(s, r11.clone());
+        g.seed(s, r15.clone());
+        let a = g.narrow_one_row((r12.clone(), false), true);
+        assert_eq!(a.len(), 2);
+        assert!(a.iter().any(|r| r == &(r12.clone(), false).into()));
+        assert!(a.iter().any(|r| r == &(r10.clone(), true).into()));
+    }
+
+    #[test]
+    fn it_misses_instead_of_guessing_offset_window() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op(
+            "topk",
+            &["x", "y", "z"],
+            TopK::new(
+                s.as_global(),
+                vec![(2, OrderType::OrderAscending)],
+                vec![1],
+                2,
+                1,
+            ),
+            true,
+        );
+
+        let r10: Vec<DataType> = vec![1.into(), "z".into(), 10.into()];
+        let r11: Vec<DataType> = vec![2.into(), "z".into(), 11.into()];
+        let r12: Vec<DataType> = vec![3.into(), "z".into(), 12.into()];
+
+        // our window for the group is known, but the group is a hole in our parent
+        let mut parent = MemoryState::default();
+        parent.add_key(&[1], Some(vec![Tag::new(1)]));
+        g.states.insert(*s, Box::new(parent));
+        g.set_partial(&[1]);
+        g.fill(vec!["z".into()], vec![r10.clone()]);
+
+        // the new row may or may not push r10 out of the window, so we must not guess
+        let (m, _) = g.input(s, vec![r12.clone()], None);
+        assert!(m.results.is_empty());
+        assert_eq!(m.misses.len(), 1);
+        assert_eq!(m.misses[0].on, *s);
+        assert_eq!(m.misses[0].lookup_idx, vec![1]);
+
+        // the same goes for replays, which are retried once the group has been replayed
+        let (m, _) = g.input(
+            s,
+            vec![(r10.clone(), false), (r11.clone(), true)],
+            Some(&[1]),
+        );
+        assert!(m.results.iter().all(|r| !r.is_positive()));
+        assert_eq!(m.misses.len(), 1);
+    }
+
+    #[test]
+    fn it_evicts_on_miss() {
+        let (g, _) = setup(false);
+        assert!(g.node().must_evict_on_miss());
+    }
+
     #[test]
     fn it_forwards_reversed() {
         let (mut g, _) = setup(true);
@@ -472,13 +715,84 @@ mod tests {
         assert!(a.iter().any(|r| r == &(r15.clone(), true).into()));
     }
 
+    #[test]
+    fn it_evicts_shrinking_partial_groups() {
+        let (mut g, s) = setup(false);
+
+        let r10: Vec<DataType> = vec![1.into(), "z".into(), 10.into()];
+        let r11: Vec<DataType> = vec![2.into(), "z".into(), 11.into()];
+        let r12: Vec<DataType> = vec![3.into(), "z".into(), 12.into()];
+
+        g.set_partial(&[1]);
+        g.fill(
+            vec!["z".into()],
+            vec![r10.clone(), r11.clone(), r12.clone()],
+        );
+
+        // the group now has fewer than k rows, and we don't look at our parent to refill it
+        let (m, _) = g.input(s, vec![(r11.clone(), false)], None);
+        assert!(m.results.iter().all(|r| !r.is_positive()));
+        assert_eq!(m.misses.len(), 1);
+        assert_eq!(m.misses[0].on, *s);
+        assert_eq!(m.misses[0].lookup_idx, vec![1]);
+    }
+
     #[test]
     fn it_suggests_indices() {
-        let (g, _) = setup(false);
+        let (g, s) = setup(false);
         let me = 2.into();
         let idx = g.node().suggest_indexes(me);
         assert_eq!(idx.len(), 1);
-        assert_eq!(*idx.iter().next().unwrap().1, vec![1]);
+        assert_eq!(idx[&me], vec![1]);
+
+        // only a full topk needs to refill shrinking groups from its parent
+        let idx = g.node().suggest_indexes_if_full(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx[&s.as_global()], vec![1]);
+    }
+
+    #[test]
+    fn it_suggests_parent_index_with_offset() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op(
+            "topk",
+            &["x", "y", "z"],
+            TopK::new(
+                s.as_global(),
+                vec![(2, OrderType::OrderAscending)],
+                vec![1],
+                2,
+                1,
+            ),
+            true,
+        );
+        let me = 2.into();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 2);
+        assert!(idx.values().all(|cols| *cols == vec![1]));
+    }
+
+    #[test]
+    fn it_windows_large_groups() {
+        let topk = TopK::new(
+            0.into(),
+            vec![(2, OrderType::OrderAscending)],
+            vec![1],
+            3,
+            1,
+        );
+        let rows: Vec<Vec<DataType>> = (0..100)
+            .rev()
+            .map(|i| vec![i.into(), "z".into(), i.into()])
+            .collect();
+        let mut window: Vec<_> = topk
+            .window(rows.iter().map(|r| Cow::from(&r[..])))
+            .into_iter()
+            .map(|r| r[2].clone())
+            .collect();
+        window.sort();
+        assert_eq!(window, vec![96.into(), 97.into(), 98.into()]);
     }
 
     #[test]
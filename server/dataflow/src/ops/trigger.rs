@@ -85,8 +85,8 @@ impl Ingredient for Trigger {
         executor: &mut dyn Executor,
         from: LocalNodeIndex,
         rs: Records,
-        _: Option<&[usize]>,
-        _: &DomainNodes,
+        replay_key_cols: Option<&[usize]>,
+        nodes: &DomainNodes,
         state: &StateMap,
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
@@ -102,32 +102,95 @@ impl Ingredient for Trigger {
         trigger_keys.sort();
         trigger_keys.dedup();
 
-        let keys = trigger_keys
-            .iter()
-            .filter_map(|k| match db.lookup(&[self.key], &KeyType::Single(&k)) {
+        let mut lookups = Vec::new();
+        let mut missed = Vec::new();
+        let mut keys = Vec::new();
+        for k in trigger_keys {
+            match db.lookup(&[self.key], &KeyType::Single(&k)) {
                 LookupResult::Some(rs) => {
+                    if replay_key_cols.is_some() {
+                        lookups.push(Lookup {
+                            on: *us,
+                            cols: vec![self.key],
+                            key: vec![k.clone()],
+                        });
+                    }
                     if rs.is_empty() {
-                        Some(k)
-                    } else {
-                        None
+                        keys.push(k);
+                    }
+                }
+                LookupResult::Missing => {
+                    // our state doesn't know about the key, but our parent, which has already
+                    // seen this batch, does. the key is new if all its rows came in this batch.
+                    if replay_key_cols.is_none() {
+                        let parent = self
+                            .lookup(*self.src, &[self.key], &KeyType::Single(&k), nodes, state)
+                            .expect("trigger must have its parent materialized");
+                        // if our parent has a hole for the key, it dropped the records for it, and
+                        // they can't have reached us.
+                        if let Some(rows) = parent {
+                            let added = rs
+                                .iter()
+                                .filter(|r| r.is_positive() && r[self.key] == k)
+                                .count();
+                            if added > 0 && rows.count() == added {
+                                keys.push(k.clone());
+                            }
+                        }
                     }
+                    missed.push(k)
                 }
-                LookupResult::Missing => unimplemented!(),
+            }
+        }
+
+        // replayed records aren't new; their keys were triggered when they first came through.
+        if replay_key_cols.is_none() {
+            self.trigger(executor, keys);
+        }
+
+        if missed.is_empty() {
+            return ProcessingResult {
+                results: rs,
+                lookups,
+                ..Default::default()
+            };
+        }
+
+        // we can't tell whether the keys we missed on are new, so hold on to their records until
+        // our state has been filled for them.
+        let (results, misses): (Vec<_>, Vec<_>) = rs
+            .into_iter()
+            .partition(|r| missed.binary_search(&r[self.key]).is_err());
+        let misses = misses
+            .into_iter()
+            .map(|r| Miss {
+                on: *us,
+                lookup_idx: vec![self.key],
+                lookup_cols: vec![self.key],
+                replay_cols: replay_key_cols.map(Vec::from),
+                record: r.extract().0,
             })
-            .cloned()
             .collect();
 
-        self.trigger(executor, keys);
-
         ProcessingResult {
-            results: rs,
-            ..Default::default()
+            results: results.into(),
+            lookups,
+            misses,
         }
     }
 
+    // We want group universes to be long lived and to exist even if no user makes use of them.
+    // We do this for two reasons: 1) to make user universe creation faster and 2) so we don't
+    // have to order group and user universe migrations. Trigger nodes used to require full
+    // materialization for this. Instead, when we have a hole for a key, we find out from our
+    // parent whether the key is new, so that universes are created even for keys nobody has read.
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
-        // index all key columns
-        Some((this, vec![self.key])).into_iter().collect()
+        vec![
+            (this, vec![self.key]),
+            (self.src.as_global(), vec![self.key]),
+        ]
+        .into_iter()
+        .collect()
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
@@ -141,14 +204,6 @@ impl Ingredient for Trigger {
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
         vec![(self.src.as_global(), Some(column))]
     }
-
-    // Trigger nodes require full materialization because we want group universes
-    // to be long lived and to exist even if no user makes use of it.
-    // We do this for two reasons: 1) to make user universe creation faster and
-    // 2) so we don't have to order group and user universe migrations.
-    fn requires_full_materialization(&self) -> bool {
-        true
-    }
 }
 
 #[cfg(test)]
@@ -181,12 +236,62 @@ mod tests {
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
 
+    #[test]
+    fn it_triggers_with_partial_state() {
+        let mut g = setup(true);
+        g.set_partial(&[0]);
+        let src = g.narrow_base_id();
+
+        // we have a hole for the key, so our parent tells us that it's new
+        let r1: Vec<DataType> = vec![1.into(), "a".into(), 1.into()];
+        g.seed(src, r1.clone());
+        let (m, universes) = g.input(src, vec![r1.clone()], None);
+        assert_eq!(universes.len(), 1);
+        assert_eq!(universes[0]["id"], 1.into());
+        assert!(m.results.is_empty());
+        assert_eq!(m.misses.len(), 1);
+        assert_eq!(m.misses[0].record, r1);
+
+        // or that it isn't
+        let r2: Vec<DataType> = vec![1.into(), "b".into(), 2.into()];
+        g.seed(src, r2.clone());
+        let (_, universes) = g.input(src, vec![r2.clone()], None);
+        assert!(universes.is_empty());
+
+        // replayed records never trigger
+        let (m, universes) = g.input(src, vec![r1.clone(), r2.clone()], Some(&[0]));
+        assert!(universes.is_empty());
+        assert_eq!(m.misses.len(), 2);
+
+        // once the hole is filled, our own state tells us
+        g.fill(vec![1.into()], vec![r1.clone(), r2.clone()]);
+        let (m, universes) = g.input(src, vec![r1.clone(), r2.clone()], Some(&[0]));
+        assert!(universes.is_empty());
+        assert_eq!(m.results, vec![r1, r2].into());
+        assert_eq!(
+            m.lookups,
+            vec![Lookup {
+                on: g.node().local_addr(),
+                cols: vec![0],
+                key: vec![1.into()],
+            }]
+        );
+
+        let r3: Vec<DataType> = vec![1.into(), "c".into(), 3.into()];
+        g.seed(src, r3.clone());
+        let (m, universes) = g.input(src, vec![r3.clone()], None);
+        assert!(universes.is_empty());
+        assert_eq!(m.results, vec![r3].into());
+    }
+
     #[test]
     fn it_suggests_indices() {
         let g = setup(false);
         let me = 1.into();
         let idx = g.node().suggest_indexes(me);
-        assert_eq!(idx.len(), 1);
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&g.narrow_base_id().as_global()], vec![0]);
     }
 
     #[test]
@@ -2,6 +2,7 @@ use petgraph;
 use serde::{Deserialize, Serialize};
 
 use crate::domain;
+use crate::eviction::MemoryBudget;
 use crate::prelude::*;
 use noria;
 use noria::internal::LocalOrNot;
@@ -61,6 +62,9 @@ pub enum InitialState {
 pub enum ReplayPieceContext {
     Partial {
         for_keys: HashSet<Vec<DataType>>,
+        /// Set if this is a response to a range replay, in which case `for_keys` holds all the
+        /// keys in the range that the source had records for.
+        for_range: Option<KeyRange>,
         requesting_shard: usize,
         unishard: bool,
         ignore: bool,
@@ -154,6 +158,12 @@ pub enum Packet {
         new_tag: Option<(Tag, NodeIndex)>,
     },
 
+    /// Stop an Egress node from sending to an ingress node that has been removed.
+    RemoveEgressTx {
+        node: LocalNodeIndex,
+        dst: NodeIndex,
+    },
+
     /// Add a shard to a Sharder node.
     ///
     /// Note that this *must* be done *before* the sharder starts being used!
@@ -162,6 +172,88 @@ pub enum Packet {
         new_txs: (LocalNodeIndex, Vec<ReplicaAddr>),
     },
 
+    /// Change how much memory a node's partially materialized state may use.
+    UpdateMemoryBudget {
+        node: LocalNodeIndex,
+        budget: MemoryBudget,
+    },
+
+    /// Make a base node keep a change feed from now on.
+    ///
+    /// The domain acks once the feed has been opened.
+    EnableChanges {
+        node: LocalNodeIndex,
+    },
+
+    /// Read from the change feed of a base node.
+    ///
+    /// The domain replies with `ControlReplyPacket::Changes`.
+    ReadChanges {
+        node: LocalNodeIndex,
+        from: Option<u64>,
+        limit: usize,
+    },
+
+    /// Stop processing inputs to base nodes, and buffer them until `ResumeInputs` arrives.
+    ///
+    /// The domain acks once every input it processed before the pause has taken effect.
+    PauseInputs,
+
+    /// Process the inputs buffered since `PauseInputs`, and any that arrive after.
+    ResumeInputs,
+
+    /// Hold back updates to `readers` until the writes of transaction `id` have made it to this
+    /// domain, or until the domain's transaction timeout passes.
+    ///
+    /// `awaiting` lists where the domain will hear that they have: `None` stands for the
+    /// controller's `CommitTransaction`, and each `Some` for an upstream domain shard the writes
+    /// flow through. `readers` are the domain's readers that the writes reach. The domain acks
+    /// once it is holding them back.
+    BeginTransaction {
+        id: u64,
+        awaiting: HashSet<Option<ReplicaAddr>>,
+        readers: HashSet<LocalNodeIndex>,
+    },
+
+    /// Apply the writes of transaction `id` to this domain's base nodes.
+    ///
+    /// The domain acks once they have been processed, or, if inputs are paused, once they have
+    /// been buffered along with the other inputs.
+    CommitTransaction {
+        id: u64,
+        inputs: Vec<Input>,
+    },
+
+    /// Stop holding back reader updates for transaction `id`, whose writes will not all arrive.
+    ///
+    /// The domain acks once it has forgotten about the transaction.
+    AbortTransaction {
+        id: u64,
+    },
+
+    /// Tells a domain that all the writes of transaction `id` that flow through `from` have been
+    /// sent to it.
+    TransactionArrived {
+        id: u64,
+        from: ReplicaAddr,
+    },
+
+    /// Take a copy of all the rows of a base node, for `ReadBaseRows` to read.
+    ///
+    /// The domain replies with an empty `ControlReplyPacket::BaseRows` once it has the copy.
+    CopyBaseRows {
+        node: LocalNodeIndex,
+    },
+
+    /// Read up to `limit` more rows from the copy `CopyBaseRows` took of a base node's rows.
+    ///
+    /// The domain replies with `ControlReplyPacket::BaseRows`, and throws away the copy once it
+    /// replies with no rows.
+    ReadBaseRows {
+        node: LocalNodeIndex,
+        limit: usize,
+    },
+
     /// Set up a fresh, empty state for a node, indexed by a particular column.
     ///
     /// This is done in preparation of a subsequent state replay.
@@ -200,6 +292,20 @@ pub enum Packet {
         keys: Vec<Vec<DataType>>,
     },
 
+    /// Ask domain (nicely) to replay all keys in a particular range.
+    RequestRangeReplay {
+        tag: Tag,
+        range: KeyRange,
+        requesting_shard: usize,
+    },
+
+    /// Ask domain (nicely) to replay all keys in a particular range into a Reader.
+    RequestReaderRangeReplay {
+        node: LocalNodeIndex,
+        cols: Vec<usize>,
+        range: KeyRange,
+    },
+
     /// Instruct domain to replay the state of a particular node along an existing replay path.
     StartReplay {
         tag: Tag,
@@ -339,6 +445,12 @@ impl fmt::Debug for Packet {
             Packet::RequestPartialReplay { ref tag, .. } => {
                 write!(f, "Packet::RequestPartialReplay({:?})", tag)
             }
+            Packet::RequestReaderRangeReplay { ref range, .. } => {
+                write!(f, "Packet::RequestReaderRangeReplay({:?})", range)
+            }
+            Packet::RequestRangeReplay { ref tag, .. } => {
+                write!(f, "Packet::RequestRangeReplay({:?})", tag)
+            }
             Packet::ReplayPiece {
                 ref link,
                 ref tag,
@@ -369,6 +481,9 @@ pub enum ControlReplyPacket {
         HashMap<petgraph::graph::NodeIndex, noria::debug::stats::NodeStats>,
     ),
     Booted(usize, SocketAddr),
+    /// (shard, changes and the sequence number to continue from)
+    Changes(usize, Result<(Vec<noria::Change>, u64), String>),
+    BaseRows(Result<Vec<Vec<DataType>>, String>),
 }
 
 impl ControlReplyPacket {
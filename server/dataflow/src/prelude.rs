@@ -16,11 +16,11 @@ pub(crate) type Edge = ();
 
 // dataflow types
 pub(crate) use crate::payload::{ReplayPathSegment, SourceChannelIdentifier};
-pub(crate) use noria::Input;
+pub(crate) use noria::{Input, KeyRange};
 
 // domain local state
 pub(crate) use crate::state::{
-    LookupResult, MemoryState, PersistentState, RecordResult, Row, Rows, State,
+    LoggedState, LookupResult, MemoryState, PersistentState, RecordResult, Row, Rows, State,
 };
 pub(crate) type StateMap = Map<Box<dyn State>>;
 pub(crate) type DomainNodes = Map<cell::RefCell<Node>>;
@@ -126,6 +126,15 @@ where
     /// *compound* key, *not* that multiple columns should be independently indexed.
     fn suggest_indexes(&self, you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>>;
 
+    /// Suggest indexes that this view needs in addition to those from `suggest_indexes`, but only
+    /// if it ends up being fully materialized.
+    ///
+    /// Unlike suggested indexes, these are never looked up through, so they are placed on the
+    /// given nodes themselves.
+    fn suggest_indexes_if_full(&self, _you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        HashMap::new()
+    }
+
     /// Resolve where the given field originates from. If the view is materialized, or the value is
     /// otherwise created by this view, None should be returned.
     fn resolve(&self, i: usize) -> Option<Vec<(NodeIndex, usize)>>;
@@ -279,4 +288,20 @@ where
     fn requires_full_materialization(&self) -> bool {
         false
     }
+
+    /// Returns true if this operator reads all the rows of `parent` when its own state is
+    /// initialized, and so needs `parent` itself to be materialized, rather than looked up
+    /// through.
+    fn must_materialize_parent(&self, _parent: NodeIndex) -> bool {
+        false
+    }
+
+    /// Returns true if a write that misses while this operator processes it may still have to
+    /// change state further downstream, so that the domain must evict that state instead.
+    ///
+    /// Joins are like this, since they can't produce any output for a write that misses in the
+    /// other side of the join.
+    fn must_evict_on_miss(&self) -> bool {
+        self.is_join()
+    }
 }
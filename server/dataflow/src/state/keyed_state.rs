@@ -1,5 +1,6 @@
 use ahash::RandomState;
 use indexmap::IndexMap;
+use std::collections::BTreeSet;
 use std::rc::Rc;
 
 use super::mk_key::MakeKey;
@@ -31,6 +32,47 @@ impl KeyedState {
         }
     }
 
+    /// Return all keys, in key order.
+    pub(super) fn keys(&self) -> BTreeSet<Vec<DataType>> {
+        match *self {
+            KeyedState::Single(ref m) => m.keys().map(|k| vec![k.clone()]).collect(),
+            KeyedState::Double(ref m) => m.keys().map(|k| vec![k.0.clone(), k.1.clone()]).collect(),
+            KeyedState::Tri(ref m) => m
+                .keys()
+                .map(|k| vec![k.0.clone(), k.1.clone(), k.2.clone()])
+                .collect(),
+            KeyedState::Quad(ref m) => m
+                .keys()
+                .map(|k| vec![k.0.clone(), k.1.clone(), k.2.clone(), k.3.clone()])
+                .collect(),
+            KeyedState::Quin(ref m) => m
+                .keys()
+                .map(|k| {
+                    vec![
+                        k.0.clone(),
+                        k.1.clone(),
+                        k.2.clone(),
+                        k.3.clone(),
+                        k.4.clone(),
+                    ]
+                })
+                .collect(),
+            KeyedState::Sex(ref m) => m
+                .keys()
+                .map(|k| {
+                    vec![
+                        k.0.clone(),
+                        k.1.clone(),
+                        k.2.clone(),
+                        k.3.clone(),
+                        k.4.clone(),
+                        k.5.clone(),
+                    ]
+                })
+                .collect(),
+        }
+    }
+
     /// Remove all rows for a randomly chosen key seeded by `seed`, returning that key along with
     /// the number of bytes freed. Returns `None` if map is empty.
     pub(super) fn evict_with_seed(&mut self, seed: usize) -> Option<(u64, Vec<DataType>)> {
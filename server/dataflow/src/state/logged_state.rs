@@ -0,0 +1,574 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use crate::eviction::EvictionPolicy;
+use crate::prelude::*;
+use crate::state::{MemoryState, State};
+use common::SizeOf;
+
+// The log is compacted once it holds this many more records than there are rows in the state,
+// and at least twice as many.
+const COMPACT_AFTER: usize = 100_000;
+
+// Maximum records per log entry when compacting the log.
+const COMPACT_BATCH_SIZE: usize = 100_000;
+
+// Each log entry starts with the length of its data, the CRC of its data, and the CRC of the
+// preceding two fields.
+const HEADER_LEN: usize = 16;
+
+/// LoggedState keeps the rows of a base node in memory, but also appends every batch of records
+/// it processes to a log on disk before applying it.
+///
+/// The log is replayed into memory when the state is first indexed, so after a restart the base
+/// node comes back with the rows it had. Each log entry is a batch of records, prefixed by its
+/// length and checksums. An entry that was only partially written when the process went away is
+/// ignored, but corruption anywhere before the last entry of the log is an error.
+///
+/// To keep the log from growing without bound as rows are updated and deleted, it is now and then
+/// rewritten to hold only the current rows. The rewrite happens on a background thread, and the
+/// state switches over to the new log once it has been written.
+pub struct LoggedState {
+    inner: MemoryState,
+    path: PathBuf,
+    // Only opened once the log has been replayed.
+    log: Option<BufWriter<File>>,
+    // The number of records in the log.
+    logged: usize,
+    compaction: Option<Compaction>,
+}
+
+/// A rewrite of the log that is in progress on a background thread.
+struct Compaction {
+    /// Yields the new log, and the number of records in it, once it has been written.
+    done: mpsc::Receiver<io::Result<(BufWriter<File>, usize)>>,
+    /// Batches that were logged after the compaction took its copy of the rows, and so must be
+    /// appended to the new log before switching over to it.
+    since: Vec<Records>,
+}
+
+impl SizeOf for LoggedState {
+    fn size_of(&self) -> u64 {
+        use std::mem::size_of;
+
+        size_of::<Self>() as u64
+    }
+
+    fn deep_size_of(&self) -> u64 {
+        self.inner.deep_size_of()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+impl State for LoggedState {
+    fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>) {
+        assert!(partial.is_none(), "LoggedState can't be partial");
+        self.inner.add_key(columns, None);
+        if self.log.is_none() {
+            // the state can't hold any rows until it has an index
+            if let Err(e) = tokio::task::block_in_place(|| self.recover()) {
+                panic!("failed to recover {}: {}", self.path.display(), e);
+            }
+        }
+    }
+
+    fn is_useful(&self) -> bool {
+        self.inner.is_useful()
+    }
+
+    fn is_partial(&self) -> bool {
+        false
+    }
+
+    fn process_records(&mut self, records: &mut Records, partial_tag: Option<Tag>) {
+        assert!(partial_tag.is_none(), "LoggedState can't be partial");
+        if records.is_empty() {
+            return;
+        }
+
+        tokio::task::block_in_place(|| self.append(records, true)).unwrap();
+        self.inner.process_records(records, None);
+        self.compact_if_necessary();
+    }
+
+    fn process_bulk_records(&mut self, records: &mut Records) {
+        if records.is_empty() {
+            return;
+        }
+
+        tokio::task::block_in_place(|| self.append(records, false)).unwrap();
+        self.inner.process_records(records, None);
+    }
+
+    fn finish_bulk_load(&mut self) {
+        tokio::task::block_in_place(|| self.sync()).unwrap();
+        self.compact_if_necessary();
+    }
+
+    fn mark_hole(&mut self, _: &[DataType], _: Tag) {
+        unreachable!("LoggedState can't be partial")
+    }
+
+    fn mark_filled(&mut self, _: Vec<DataType>, _: Tag) {
+        unreachable!("LoggedState can't be partial")
+    }
+
+    fn lookup<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a> {
+        self.inner.lookup(columns, key)
+    }
+
+    fn lookup_range(
+        &self,
+        columns: &[usize],
+        range: &KeyRange,
+    ) -> Option<Vec<(Vec<DataType>, Vec<Vec<DataType>>)>> {
+        self.inner.lookup_range(columns, range)
+    }
+
+    fn mark_range_filled(&mut self, _: KeyRange, _: Tag) {
+        unreachable!("LoggedState can't be partial")
+    }
+
+    fn rows(&self) -> usize {
+        self.inner.rows()
+    }
+
+    fn keys(&self) -> Vec<Vec<usize>> {
+        self.inner.keys()
+    }
+
+    fn cloned_records(&self) -> Vec<Vec<DataType>> {
+        self.inner.cloned_records()
+    }
+
+    fn evict_cold_keys(
+        &mut self,
+        _: &dyn EvictionPolicy,
+        _: usize,
+    ) -> (&[usize], Vec<Vec<DataType>>, u64) {
+        unreachable!("can't evict keys from LoggedState")
+    }
+
+    fn evict_keys(&mut self, _: Tag, _: &[Vec<DataType>]) -> Option<(&[usize], u64)> {
+        unreachable!("can't evict keys from LoggedState")
+    }
+
+    fn clear(&mut self) {
+        unreachable!("can't clear LoggedState")
+    }
+}
+
+/// CRC-32 (IEEE) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, t) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 == 1 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *t = c;
+    }
+    !data.iter().fold(!0u32, |c, &b| {
+        table[((c ^ u32::from(b)) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+/// Read into `buf` until it is full or the reader runs out, and return how much was read.
+fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+pub(crate) enum LogEntry<T = Records> {
+    /// A complete entry, and its length in bytes.
+    Records(T, u64),
+    /// The log ends here.
+    End,
+    /// The rest of the log is an entry that was only partially written.
+    Torn,
+    /// The entry is corrupt, for the given reason.
+    Corrupt(&'static str),
+}
+
+/// Read the next entry of a log.
+pub(crate) fn read_entry<R: Read, T: serde::de::DeserializeOwned>(
+    r: &mut R,
+) -> io::Result<LogEntry<T>> {
+    let mut header = [0u8; HEADER_LEN];
+    match read_full(r, &mut header)? {
+        0 => return Ok(LogEntry::End),
+        n if n < HEADER_LEN => return Ok(LogEntry::Torn),
+        _ => {}
+    }
+
+    let mut field = [0u8; 4];
+    field.copy_from_slice(&header[12..16]);
+    if crc32(&header[..12]) != u32::from_le_bytes(field) {
+        // space for an entry may have been allocated without any of it being written
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest)?;
+        if header.iter().chain(&rest).all(|&b| b == 0) {
+            return Ok(LogEntry::Torn);
+        }
+        return Ok(LogEntry::Corrupt("bad header checksum"));
+    }
+
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[..8]);
+    let len = u64::from_le_bytes(len);
+    field.copy_from_slice(&header[8..12]);
+    let crc = u32::from_le_bytes(field);
+
+    let mut data = Vec::new();
+    r.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Ok(LogEntry::Torn);
+    }
+    if crc32(&data) != crc {
+        // only the last entry can have been torn
+        if read_full(r, &mut [0u8; 1])? == 0 {
+            return Ok(LogEntry::Torn);
+        }
+        return Ok(LogEntry::Corrupt("bad data checksum"));
+    }
+    match bincode::deserialize(&data) {
+        Ok(records) => Ok(LogEntry::Records(records, HEADER_LEN as u64 + len)),
+        Err(_) => Ok(LogEntry::Corrupt("undecodable records")),
+    }
+}
+
+pub(crate) fn write_entry<W: Write, T: serde::Serialize + ?Sized>(
+    w: &mut W,
+    records: &T,
+) -> io::Result<()> {
+    let data = bincode::serialize(records).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(&(data.len() as u64).to_le_bytes());
+    header[8..12].copy_from_slice(&crc32(&data).to_le_bytes());
+    let crc = crc32(&header[..12]);
+    header[12..].copy_from_slice(&crc.to_le_bytes());
+    w.write_all(&header)?;
+    w.write_all(&data)
+}
+
+/// Write a new log that holds just `rows` to `path`, and make sure it is on disk.
+fn write_rows(path: &Path, rows: Vec<Vec<DataType>>) -> io::Result<(BufWriter<File>, usize)> {
+    let mut w = BufWriter::new(File::create(path)?);
+    for chunk in rows.chunks(COMPACT_BATCH_SIZE) {
+        let records: Records = chunk
+            .iter()
+            .cloned()
+            .map(Record::Positive)
+            .collect::<Vec<_>>()
+            .into();
+        write_entry(&mut w, &records)?;
+    }
+    w.flush()?;
+    w.get_ref().sync_data()?;
+    Ok((w, rows.len()))
+}
+
+impl LoggedState {
+    pub fn new(name: String, params: &PersistenceParameters) -> Self {
+        let dir = params.log_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+        Self {
+            inner: MemoryState::default(),
+            path: dir.join(format!("{}.log", name)),
+            log: None,
+            logged: 0,
+            compaction: None,
+        }
+    }
+
+    /// Replay the log into memory, and open it for appending.
+    fn recover(&mut self) -> io::Result<()> {
+        let mut end = 0;
+        match File::open(&self.path) {
+            Ok(f) => {
+                let mut f = BufReader::new(f);
+                loop {
+                    match read_entry(&mut f)? {
+                        LogEntry::Records(mut records, len) => {
+                            self.logged += records.len();
+                            self.inner.process_records(&mut records, None);
+                            end += len;
+                        }
+                        LogEntry::End | LogEntry::Torn => break,
+                        LogEntry::Corrupt(why) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("log entry at offset {} is corrupt: {}", end, why),
+                            ));
+                        }
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut f = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&self.path)?;
+        // throw away the torn entry at the end, if any
+        f.set_len(end)?;
+        f.seek(SeekFrom::End(0))?;
+        self.log = Some(BufWriter::new(f));
+        Ok(())
+    }
+
+    fn append(&mut self, records: &Records, sync: bool) -> io::Result<()> {
+        let log = self
+            .log
+            .as_mut()
+            .expect("LoggedState must be indexed before use");
+        write_entry(log, records)?;
+        self.logged += records.len();
+        if let Some(ref mut compaction) = self.compaction {
+            compaction.since.push(records.clone());
+        }
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if let Some(ref mut log) = self.log {
+            log.flush()?;
+            log.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn compact_if_necessary(&mut self) {
+        if self.compaction.is_some() {
+            tokio::task::block_in_place(|| self.finish_compaction(false)).unwrap();
+            return;
+        }
+
+        // the inner state counts each row once per index
+        let rows = self.inner.rows() / std::cmp::max(self.inner.keys().len(), 1);
+        if self.logged > rows + COMPACT_AFTER && self.logged > 2 * rows {
+            self.compact();
+        }
+    }
+
+    /// Start replacing the log with one that holds just the current rows.
+    fn compact(&mut self) {
+        let rows = self.inner.cloned_records();
+        let tmp = self.path.with_extension("log.compact");
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("log-compaction".to_string())
+            .spawn(move || {
+                let _ = tx.send(write_rows(&tmp, rows));
+            })
+            .unwrap();
+        self.compaction = Some(Compaction {
+            done: rx,
+            since: Vec::new(),
+        });
+    }
+
+    /// Switch over to the new log if the compaction has finished writing it (or, if `wait`, once
+    /// it has).
+    fn finish_compaction(&mut self, wait: bool) -> io::Result<()> {
+        let written = match self.compaction {
+            Some(ref compaction) if wait => compaction.done.recv().ok(),
+            Some(ref compaction) => match compaction.done.try_recv() {
+                Ok(written) => Some(written),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => None,
+            },
+            None => return Ok(()),
+        };
+        let since = self.compaction.take().unwrap().since;
+        let (mut w, mut logged) = written.expect("log compaction thread panicked")?;
+
+        for records in &since {
+            write_entry(&mut w, records)?;
+            logged += records.len();
+        }
+        w.flush()?;
+        w.get_ref().sync_data()?;
+
+        fs::rename(self.path.with_extension("log.compact"), &self.path)?;
+        sync_dir(&self.path)?;
+        self.log = Some(w);
+        self.logged = logged;
+        Ok(())
+    }
+}
+
+impl Drop for LoggedState {
+    fn drop(&mut self) {
+        // a later compaction of the same log would otherwise write to the same file as this one
+        let _ = self.finish_compaction(true);
+    }
+}
+
+/// Make sure a rename of the file at `path` survives a crash.
+pub(crate) fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir, TempDir};
+
+    fn setup(dir: &TempDir) -> LoggedState {
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::MemoryWithLog;
+        params.log_dir = Some(dir.path().to_path_buf());
+        let mut state = LoggedState::new(String::from("soup-Base-0"), &params);
+        state.add_key(&[0], None);
+        state
+    }
+
+    fn lookup(state: &LoggedState, key: DataType) -> Vec<Vec<DataType>> {
+        match state.lookup(&[0], &KeyType::Single(&key)) {
+            LookupResult::Some(rows) => rows.into_iter().map(|r| r.into_owned()).collect(),
+            LookupResult::Missing => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn logged_state_recover() {
+        let dir = tempdir().unwrap();
+        {
+            let mut state = setup(&dir);
+            state.process_records(
+                &mut vec![
+                    (vec![1.into(), "A".into()], true),
+                    (vec![2.into(), "B".into()], true),
+                ]
+                .into(),
+                None,
+            );
+            state.process_records(&mut vec![(vec![1.into(), "A".into()], false)].into(), None);
+        }
+
+        let state = setup(&dir);
+        assert_eq!(state.rows(), 1);
+        assert!(lookup(&state, 1.into()).is_empty());
+        assert_eq!(lookup(&state, 2.into()), vec![vec![2.into(), "B".into()]]);
+    }
+
+    #[test]
+    fn logged_state_ignores_torn_write() {
+        let dir = tempdir().unwrap();
+        {
+            let mut state = setup(&dir);
+            state.process_records(&mut vec![(vec![1.into(), "A".into()], true)].into(), None);
+        }
+
+        // pretend we crashed part-way through writing an entry
+        let path = dir.path().join("soup-Base-0.log");
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&100u64.to_le_bytes()).unwrap();
+        f.write_all(b"garbage").unwrap();
+        drop(f);
+
+        {
+            let mut state = setup(&dir);
+            assert_eq!(state.rows(), 1);
+            state.process_records(&mut vec![(vec![2.into(), "B".into()], true)].into(), None);
+        }
+
+        // or after writing the header, but not all of the data
+        let mut entry = Vec::new();
+        let records: Records = vec![(vec![3.into(), "C".into()], true)].into();
+        write_entry(&mut entry, &records).unwrap();
+        let last = entry.len() - 1;
+        entry[last] ^= 0xff;
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&entry).unwrap();
+        drop(f);
+
+        let state = setup(&dir);
+        assert_eq!(state.rows(), 2);
+        assert_eq!(lookup(&state, 2.into()), vec![vec![2.into(), "B".into()]]);
+        assert!(lookup(&state, 3.into()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupt")]
+    fn logged_state_rejects_corruption() {
+        let dir = tempdir().unwrap();
+        {
+            let mut state = setup(&dir);
+            state.process_records(&mut vec![(vec![1.into(), "A".into()], true)].into(), None);
+            state.process_records(&mut vec![(vec![2.into(), "B".into()], true)].into(), None);
+        }
+
+        // flip a bit in the data of the first entry
+        let path = dir.path().join("soup-Base-0.log");
+        let mut log = fs::read(&path).unwrap();
+        log[HEADER_LEN + 1] ^= 1;
+        fs::write(&path, &log).unwrap();
+
+        setup(&dir);
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn logged_state_compacts() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("soup-Base-0.log");
+        {
+            let mut state = setup(&dir);
+            let row = |i: usize| vec![(i % 10).into(), i.into()];
+            for i in 0..120 {
+                let mut records: Records = (i * 1000..(i + 1) * 1000)
+                    .map(|j| Record::Positive(row(j)))
+                    .collect::<Vec<_>>()
+                    .into();
+                state.process_records(&mut records, None);
+                let mut records: Records = (i * 1000..(i + 1) * 1000)
+                    .map(|j| Record::Negative(row(j)))
+                    .collect::<Vec<_>>()
+                    .into();
+                state.process_records(&mut records, None);
+            }
+            state.process_records(&mut vec![(vec![1.into(), "A".into()], true)].into(), None);
+        }
+        // dropping the state waits for any compaction that is still running
+        assert!(fs::metadata(&path).unwrap().len() < 1_000_000);
+
+        let state = setup(&dir);
+        assert!(state.logged < COMPACT_AFTER);
+        assert_eq!(state.rows(), 1);
+        assert_eq!(lookup(&state, 1.into()), vec![vec![1.into(), "A".into()]]);
+    }
+}
@@ -3,6 +3,7 @@ use std::rc::Rc;
 
 use rand::{self, Rng};
 
+use crate::eviction::EvictionPolicy;
 use crate::prelude::*;
 use crate::state::single_state::SingleState;
 use common::SizeOf;
@@ -22,7 +23,7 @@ impl SizeOf for MemoryState {
     }
 
     fn deep_size_of(&self) -> u64 {
-        self.mem_size
+        self.mem_size + self.tracked_bytes()
     }
 
     fn is_empty(&self) -> bool {
@@ -139,6 +140,30 @@ impl State for MemoryState {
         self.state[index].lookup(key)
     }
 
+    fn lookup_range(
+        &self,
+        columns: &[usize],
+        range: &KeyRange,
+    ) -> Option<Vec<(Vec<DataType>, Vec<Vec<DataType>>)>> {
+        debug_assert!(!self.state.is_empty(), "lookup on uninitialized index");
+        let index = self
+            .state_for(columns)
+            .expect("lookup on non-indexed column set");
+        let found = self.state[index].lookup_range(range)?;
+        Some(
+            found
+                .into_iter()
+                .map(|(k, rs)| (k, rs.iter().map(|r| Vec::clone(&**r)).collect()))
+                .collect(),
+        )
+    }
+
+    fn mark_range_filled(&mut self, range: KeyRange, tag: Tag) {
+        debug_assert!(!self.state.is_empty(), "filling uninitialized index");
+        let index = self.by_tag[&tag];
+        self.state[index].mark_range_filled(range);
+    }
+
     fn keys(&self) -> Vec<Vec<usize>> {
         self.state.iter().map(|s| s.key().to_vec()).collect()
     }
@@ -153,12 +178,18 @@ impl State for MemoryState {
         self.state[0].values().flat_map(fix).collect()
     }
 
-    fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64) {
+    fn evict_cold_keys(
+        &mut self,
+        policy: &dyn EvictionPolicy,
+        count: usize,
+    ) -> (&[usize], Vec<Vec<DataType>>, u64) {
         let mut rng = rand::thread_rng();
         let index = rng.gen_range(0, self.state.len());
-        let (bytes_freed, keys) = self.state[index].evict_random_keys(count, &mut rng);
+        let tracked = self.state[index].tracked_bytes();
+        let (bytes_freed, keys) = self.state[index].evict_cold_keys(policy, count, &mut rng);
         self.mem_size = self.mem_size.saturating_sub(bytes_freed);
-        (self.state[index].key(), keys, bytes_freed)
+        let tracked_freed = tracked - self.state[index].tracked_bytes();
+        (self.state[index].key(), keys, bytes_freed + tracked_freed)
     }
 
     fn evict_keys(&mut self, tag: Tag, keys: &[Vec<DataType>]) -> Option<(&[usize], u64)> {
@@ -166,9 +197,11 @@ impl State for MemoryState {
         // this can happen if an upstream domain issues an eviction for a replay path that we have
         // been told about, but that has not yet been finalized.
         self.by_tag.get(&tag).cloned().map(move |index| {
+            let tracked = self.state[index].tracked_bytes();
             let bytes = self.state[index].evict_keys(keys);
             self.mem_size = self.mem_size.saturating_sub(bytes);
-            (self.state[index].key(), bytes)
+            let tracked_freed = tracked - self.state[index].tracked_bytes();
+            (self.state[index].key(), bytes + tracked_freed)
         })
     }
 
@@ -181,6 +214,11 @@ impl State for MemoryState {
 }
 
 impl MemoryState {
+    /// The memory used to track reads of the keys in partially materialized indices.
+    fn tracked_bytes(&self) -> u64 {
+        self.state.iter().map(SingleState::tracked_bytes).sum()
+    }
+
     /// Returns the index in `self.state` of the index keyed on `cols`, or None if no such index
     /// exists.
     fn state_for(&self, cols: &[usize]) -> Option<usize> {
@@ -284,4 +322,78 @@ mod tests {
             _ => unreachable!(),
         };
     }
+
+    #[test]
+    fn memory_state_lookup_range() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        for i in 0..10 {
+            insert(&mut state, vec![i.into(), "x".into()]);
+        }
+
+        let range = KeyRange::from_bounds(vec![3.into()]..vec![6.into()]);
+        let found = state.lookup_range(&[0], &range).unwrap();
+        let keys: Vec<_> = found.iter().map(|(k, _)| k[0].clone()).collect();
+        assert_eq!(keys, vec![3.into(), 4.into(), 5.into()]);
+        assert!(found.iter().all(|(_, rs)| rs.len() == 1));
+
+        // keys added after the first range lookup are found too
+        insert(&mut state, vec![10.into(), "x".into()]);
+        insert(&mut state, vec![4.into(), "y".into()]);
+        let range = KeyRange::from_bounds(vec![4.into()]..);
+        let found = state.lookup_range(&[0], &range).unwrap();
+        assert_eq!(found.len(), 7);
+        assert_eq!(found[0].1.len(), 2);
+        assert_eq!(found[6].0, vec![10.into()]);
+    }
+
+    #[test]
+    fn memory_state_partial_range_filled() {
+        let tag = Tag::new(1);
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![tag]));
+
+        let range = KeyRange::from_bounds(vec![3.into()]..vec![6.into()]);
+        assert!(state.lookup_range(&[0], &range).is_none());
+
+        state.mark_range_filled(range.clone(), tag);
+        assert_eq!(state.lookup_range(&[0], &range).unwrap().len(), 0);
+
+        // records for keys in the range are no longer dropped as misses
+        insert(&mut state, vec![4.into(), "x".into()]);
+        assert_eq!(state.lookup_range(&[0], &range).unwrap().len(), 1);
+        match state.lookup(&[0], &KeyType::Single(&5.into())) {
+            LookupResult::Some(rs) => assert!(rs.is_empty()),
+            LookupResult::Missing => unreachable!(),
+        }
+
+        // but evicting one of them makes the range partial again
+        state.mark_hole(&[4.into()], tag);
+        assert!(state.lookup_range(&[0], &range).is_none());
+    }
+
+    #[test]
+    fn memory_state_evicts_least_recently_read() {
+        use crate::eviction::Lru;
+
+        let tag = Tag::new(1);
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![tag]));
+        for i in 0..3 {
+            state.mark_filled(vec![i.into()], tag);
+            insert(&mut state, vec![i.into(), "x".into()]);
+        }
+
+        // key 1 is the only one that hasn't been read since it was filled
+        state.lookup(&[0], &KeyType::Single(&0.into()));
+        state.lookup(&[0], &KeyType::Single(&2.into()));
+
+        let (_, keys, bytes) = state.evict_cold_keys(&Lru, 1);
+        assert_eq!(keys, vec![vec![1.into()]]);
+        assert!(bytes > 0);
+        match state.lookup(&[0], &KeyType::Single(&1.into())) {
+            LookupResult::Missing => {}
+            LookupResult::Some(_) => unreachable!(),
+        }
+    }
 }
@@ -1,22 +1,55 @@
 mod keyed_state;
+mod logged_state;
 mod memory_state;
 mod mk_key;
 mod persistent_state;
 mod single_state;
 
 use std::borrow::Cow;
-use std::ops::Deref;
+use std::collections::BTreeSet;
+use std::ops::{Bound, Deref};
 use std::rc::Rc;
 use std::vec;
 
+use crate::eviction::EvictionPolicy;
 use crate::prelude::*;
 use ahash::RandomState;
 use common::SizeOf;
 use hashbag::HashBag;
 
+pub(crate) use self::logged_state::{read_entry, sync_dir, write_entry, LogEntry, LoggedState};
 pub(crate) use self::memory_state::MemoryState;
 pub(crate) use self::persistent_state::PersistentState;
 
+fn bound_as_slice(b: &Bound<Vec<DataType>>) -> Bound<&[DataType]> {
+    match *b {
+        Bound::Included(ref k) => Bound::Included(&k[..]),
+        Bound::Excluded(ref k) => Bound::Excluded(&k[..]),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Returns the keys in the ordered set `keys` that fall within `range`, in key order.
+pub(crate) fn keys_in<'a>(
+    keys: &'a BTreeSet<Vec<DataType>>,
+    range: &'a KeyRange,
+) -> Box<dyn Iterator<Item = &'a Vec<DataType>> + 'a> {
+    if range.is_empty() {
+        // BTreeSet::range panics if given a range whose end comes before its start
+        return Box::new(std::iter::empty());
+    }
+
+    match *range {
+        KeyRange::Range(ref start, ref end) => {
+            Box::new(keys.range::<[DataType], _>((bound_as_slice(start), bound_as_slice(end))))
+        }
+        KeyRange::Prefix(ref prefix) => Box::new(
+            keys.range::<[DataType], _>((Bound::Included(&prefix[..]), Bound::Unbounded))
+                .take_while(move |k| k.starts_with(&prefix[..])),
+        ),
+    }
+}
+
 pub(crate) trait State: SizeOf + Send {
     /// Add an index keyed by the given columns and replayed to by the given partial tags.
     fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>);
@@ -31,12 +64,35 @@ pub(crate) trait State: SizeOf + Send {
     // are removed from `records` (thus the mutable reference).
     fn process_records(&mut self, records: &mut Records, partial_tag: Option<Tag>);
 
+    /// Like `process_records`, but for records that are part of a bulk load into full state.
+    ///
+    /// The records need not be durable until `finish_bulk_load` is called.
+    fn process_bulk_records(&mut self, records: &mut Records) {
+        self.process_records(records, None)
+    }
+
+    /// Make all records added through `process_bulk_records` durable.
+    fn finish_bulk_load(&mut self) {}
+
     fn mark_hole(&mut self, key: &[DataType], tag: Tag);
 
     fn mark_filled(&mut self, key: Vec<DataType>, tag: Tag);
 
     fn lookup<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a>;
 
+    /// Look up all keys in the index on `columns` that fall within `range`, along with their
+    /// records, in key order.
+    ///
+    /// Returns `None` if the index is partial and the range has not been filled in its entirety.
+    fn lookup_range(
+        &self,
+        columns: &[usize],
+        range: &KeyRange,
+    ) -> Option<Vec<(Vec<DataType>, Vec<Vec<DataType>>)>>;
+
+    /// Mark every key in `range` as filled in the index replayed to by `tag`.
+    fn mark_range_filled(&mut self, range: KeyRange, tag: Tag);
+
     fn rows(&self) -> usize;
 
     fn keys(&self) -> Vec<Vec<usize>>;
@@ -44,9 +100,13 @@ pub(crate) trait State: SizeOf + Send {
     /// Return a copy of all records. Panics if the state is only partially materialized.
     fn cloned_records(&self) -> Vec<Vec<DataType>>;
 
-    /// Evict `count` randomly selected keys, returning key colunms of the index chosen to evict
+    /// Evict `count` keys chosen by `policy`, returning key colunms of the index chosen to evict
     /// from along with the keys evicted and the number of bytes evicted.
-    fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64);
+    fn evict_cold_keys(
+        &mut self,
+        policy: &dyn EvictionPolicy,
+        count: usize,
+    ) -> (&[usize], Vec<Vec<DataType>>, u64);
 
     /// Evict the listed keys from the materialization targeted by `tag`, returning the key columns
     /// of the index that was evicted from and the number of bytes evicted.
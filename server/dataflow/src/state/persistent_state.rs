@@ -2,8 +2,10 @@ use bincode;
 use itertools::Itertools;
 use rocksdb::{self, PlainTableFactoryOptions, SliceTransform, WriteBatch};
 use serde;
+use std::collections::BTreeMap;
 use tempfile::{tempdir, TempDir};
 
+use crate::eviction::EvictionPolicy;
 use crate::prelude::*;
 use crate::state::{RecordResult, State};
 use common::SizeOf;
@@ -61,26 +63,29 @@ pub struct PersistentState {
 impl State for PersistentState {
     fn process_records(&mut self, records: &mut Records, partial_tag: Option<Tag>) {
         assert!(partial_tag.is_none(), "PersistentState can't be partial");
-        if records.len() == 0 {
-            return;
-        }
-
-        let mut batch = WriteBatch::default();
-        for r in records.iter() {
-            match *r {
-                Record::Positive(ref r) => {
-                    self.insert(&mut batch, r);
-                }
-                Record::Negative(ref r) => {
-                    self.remove(&mut batch, r);
-                }
-            }
-        }
-
-        // Sync the writes to RocksDB's WAL:
         let mut opts = rocksdb::WriteOptions::default();
         opts.set_sync(true);
-        tokio::task::block_in_place(|| self.db.as_ref().unwrap().write_opt(batch, &opts)).unwrap();
+        self.write_records(records, opts);
+    }
+
+    fn process_bulk_records(&mut self, records: &mut Records) {
+        // Bulk loaded rows skip RocksDB's WAL altogether, and only become durable once the load
+        // finishes and the memtables they are in have been flushed to SST files.
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.disable_wal(true);
+        self.write_records(records, opts);
+    }
+
+    fn finish_bulk_load(&mut self) {
+        let db = self.db.as_ref().unwrap();
+        tokio::task::block_in_place(|| {
+            db.flush().unwrap();
+            let mut opts = rocksdb::WriteOptions::default();
+            opts.set_sync(true);
+            let mut batch = WriteBatch::default();
+            batch.put(META_KEY, &bincode::serialize(&self.meta()).unwrap());
+            db.write_opt(batch, &opts).unwrap();
+        });
     }
 
     fn lookup(&self, columns: &[usize], key: &KeyType) -> LookupResult {
@@ -114,6 +119,28 @@ impl State for PersistentState {
         })
     }
 
+    fn lookup_range(
+        &self,
+        columns: &[usize],
+        range: &KeyRange,
+    ) -> Option<Vec<(Vec<DataType>, Vec<Vec<DataType>>)>> {
+        // the serialized keys don't sort the same way as the values they encode, so we have no
+        // choice but to scan all the rows.
+        let mut found: BTreeMap<Vec<DataType>, Vec<Vec<DataType>>> = BTreeMap::new();
+        for (_, value) in self.all_rows() {
+            let row: Vec<DataType> = bincode::deserialize(&value).unwrap();
+            let key: Vec<_> = columns.iter().map(|&c| row[c].clone()).collect();
+            if range.contains(&key) {
+                found.entry(key).or_default().push(row);
+            }
+        }
+        Some(found.into_iter().collect())
+    }
+
+    fn mark_range_filled(&mut self, _: KeyRange, _: Tag) {
+        unreachable!("PersistentState can't be partial")
+    }
+
     fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>) {
         assert!(partial.is_none(), "Bases can't be partial");
         let existing = self
@@ -204,7 +231,11 @@ impl State for PersistentState {
         unreachable!("PersistentState can't be partial")
     }
 
-    fn evict_random_keys(&mut self, _: usize) -> (&[usize], Vec<Vec<DataType>>, u64) {
+    fn evict_cold_keys(
+        &mut self,
+        _: &dyn EvictionPolicy,
+        _: usize,
+    ) -> (&[usize], Vec<Vec<DataType>>, u64) {
         unreachable!("can't evict keys from PersistentState")
     }
 
@@ -389,19 +420,41 @@ impl PersistentState {
         meta
     }
 
-    fn persist_meta(&mut self) {
-        let db = self.db.as_ref().unwrap();
-        // Stores the columns of self.indices in RocksDB so that we don't rebuild indices on recovery.
+    fn meta(&self) -> PersistentMeta {
         let columns = self.indices.iter().map(|i| i.columns.clone()).collect();
-        let meta = PersistentMeta {
+        PersistentMeta {
             indices: columns,
             epoch: self.epoch,
-        };
+        }
+    }
 
-        let data = bincode::serialize(&meta).unwrap();
+    fn persist_meta(&mut self) {
+        let db = self.db.as_ref().unwrap();
+        // Stores the columns of self.indices in RocksDB so that we don't rebuild indices on recovery.
+        let data = bincode::serialize(&self.meta()).unwrap();
         db.put(META_KEY, &data).unwrap();
     }
 
+    fn write_records(&mut self, records: &Records, opts: rocksdb::WriteOptions) {
+        if records.len() == 0 {
+            return;
+        }
+
+        let mut batch = WriteBatch::default();
+        for r in records.iter() {
+            match *r {
+                Record::Positive(ref r) => {
+                    self.insert(&mut batch, r);
+                }
+                Record::Negative(ref r) => {
+                    self.remove(&mut batch, r);
+                }
+            }
+        }
+
+        tokio::task::block_in_place(|| self.db.as_ref().unwrap().write_opt(batch, &opts)).unwrap();
+    }
+
     // Our RocksDB keys come in three forms, and are encoded as follows:
     //
     // * Unique Primary Keys
@@ -880,6 +933,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn persistent_state_recover_bulk_load() {
+        let (_dir, name) = get_tmp_path();
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::Permanent;
+        {
+            let mut state = PersistentState::new(name.clone(), Some(&[0]), &params);
+            state.add_key(&[1], None);
+            for batch in 0..10 {
+                let mut records: Records = (0..100)
+                    .map(|i| vec![(batch * 100 + i).into(), (i % 10).into()])
+                    .map(Record::Positive)
+                    .collect::<Vec<_>>()
+                    .into();
+                state.process_bulk_records(&mut records);
+            }
+            state.finish_bulk_load();
+        }
+
+        let state = PersistentState::new(name, Some(&[0]), &params);
+        assert_eq!(state.rows(), 1000);
+        match state.lookup(&[0], &KeyType::Single(&999.into())) {
+            LookupResult::Some(RecordResult::Owned(rows)) => {
+                assert_eq!(rows, vec![vec![999.into(), 9.into()]]);
+            }
+            _ => unreachable!(),
+        }
+        match state.lookup(&[1], &KeyType::Single(&3.into())) {
+            LookupResult::Some(RecordResult::Owned(rows)) => assert_eq!(rows.len(), 100),
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn persistent_state_recover_unique_key() {
         let (_dir, name) = get_tmp_path();
@@ -1,8 +1,11 @@
 use super::mk_key::MakeKey;
+use crate::eviction::{AccessTracker, EvictionPolicy};
 use crate::prelude::*;
 use crate::state::keyed_state::KeyedState;
 use common::SizeOf;
 use rand::prelude::*;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
 use std::rc::Rc;
 
 pub(super) struct SingleState {
@@ -10,6 +13,49 @@ pub(super) struct SingleState {
     state: KeyedState,
     partial: bool,
     rows: usize,
+    /// Key ranges that have been replayed in their entirety (only used if `partial`).
+    filled_ranges: Vec<KeyRange>,
+    /// Per-key read history (only used if `partial`).
+    ///
+    /// Lookups only borrow the state immutably, so this has to be tracked on the side.
+    accesses: RefCell<AccessTracker>,
+    /// The keys of `state` in key order, for range lookups.
+    ///
+    /// Built on the first range lookup, and kept up to date from then on.
+    ordered: RefCell<Option<BTreeSet<Vec<DataType>>>>,
+}
+
+/// Returns true if the key of `r` falls within any of the given filled ranges.
+fn in_filled_range(ranges: &[KeyRange], key: &[usize], r: &[DataType]) -> bool {
+    if ranges.is_empty() {
+        return false;
+    }
+    let key: Vec<_> = key.iter().map(|&c| r[c].clone()).collect();
+    ranges.iter().any(|range| range.contains(&key))
+}
+
+fn key_to_vec(key: &KeyType) -> Vec<DataType> {
+    match *key {
+        KeyType::Single(k) => vec![k.clone()],
+        KeyType::Double(ref k) => vec![k.0.clone(), k.1.clone()],
+        KeyType::Tri(ref k) => vec![k.0.clone(), k.1.clone(), k.2.clone()],
+        KeyType::Quad(ref k) => vec![k.0.clone(), k.1.clone(), k.2.clone(), k.3.clone()],
+        KeyType::Quin(ref k) => vec![
+            k.0.clone(),
+            k.1.clone(),
+            k.2.clone(),
+            k.3.clone(),
+            k.4.clone(),
+        ],
+        KeyType::Sex(ref k) => vec![
+            k.0.clone(),
+            k.1.clone(),
+            k.2.clone(),
+            k.3.clone(),
+            k.4.clone(),
+            k.5.clone(),
+        ],
+    }
 }
 
 macro_rules! insert_row_match_impl {
@@ -19,7 +65,11 @@ macro_rules! insert_row_match_impl {
             Entry::Occupied(mut rs) => {
                 rs.get_mut().insert($r);
             }
-            Entry::Vacant(..) if $self.partial => return false,
+            Entry::Vacant(..)
+                if $self.partial && !in_filled_range(&$self.filled_ranges, &$self.key, &$r) =>
+            {
+                return false
+            }
             rs @ Entry::Vacant(..) => {
                 rs.or_default().insert($r);
             }
@@ -44,6 +94,9 @@ impl SingleState {
             state: columns.into(),
             partial,
             rows: 0,
+            filled_ranges: Vec::new(),
+            accesses: Default::default(),
+            ordered: Default::default(),
         }
     }
 
@@ -51,6 +104,11 @@ impl SingleState {
     /// not inserted).
     pub(super) fn insert_row(&mut self, r: Row) -> bool {
         use indexmap::map::Entry;
+        let ordered_key: Option<Vec<_>> = self
+            .ordered
+            .get_mut()
+            .as_ref()
+            .map(|_| self.key.iter().map(|&c| r[c].clone()).collect());
         match self.state {
             KeyedState::Single(ref mut map) => {
                 // treat this specially to avoid the extra Vec
@@ -61,7 +119,7 @@ impl SingleState {
                     self.rows += 1;
                     rs.insert(r);
                     return true;
-                } else if self.partial {
+                } else if self.partial && !in_filled_range(&self.filled_ranges, &self.key, &r) {
                     // trying to insert a record into partial materialization hole!
                     return false;
                 }
@@ -74,6 +132,9 @@ impl SingleState {
             KeyedState::Sex(ref mut map) => insert_row_match_impl!(self, r, map),
         }
 
+        if let (Some(key), Some(ordered)) = (ordered_key, self.ordered.get_mut()) {
+            ordered.insert(key);
+        }
         self.rows += 1;
         true
     }
@@ -132,6 +193,11 @@ impl SingleState {
     }
 
     pub(super) fn mark_filled(&mut self, key: Vec<DataType>) {
+        // keys are filled because someone tried to read them
+        self.accesses.get_mut().touch(&key);
+        if let Some(ref mut ordered) = *self.ordered.get_mut() {
+            ordered.insert(key.clone());
+        }
         let mut key = key.into_iter();
         let replaced = match self.state {
             KeyedState::Single(ref mut map) => map.insert(key.next().unwrap(), Rows::default()),
@@ -180,7 +246,27 @@ impl SingleState {
         assert!(replaced.is_none());
     }
 
+    /// Mark every key in `range` as filled, so that records for keys in the range that are not yet
+    /// present are inserted rather than treated as hitting a hole.
+    pub(super) fn mark_range_filled(&mut self, range: KeyRange) {
+        debug_assert!(self.partial);
+        if !self.filled_ranges.iter().any(|f| f.covers(&range)) {
+            self.filled_ranges.push(range);
+        }
+    }
+
+    fn unfill_ranges_containing(&mut self, key: &[DataType]) {
+        if !self.filled_ranges.is_empty() {
+            self.filled_ranges.retain(|f| !f.contains(key));
+        }
+    }
+
     pub(super) fn mark_hole(&mut self, key: &[DataType]) -> u64 {
+        self.unfill_ranges_containing(key);
+        self.accesses.get_mut().forget(key);
+        if let Some(ref mut ordered) = *self.ordered.get_mut() {
+            ordered.remove(key);
+        }
         let removed = match self.state {
             KeyedState::Single(ref mut m) => m.swap_remove(&(key[0])),
             KeyedState::Double(ref mut m) => {
@@ -210,6 +296,9 @@ impl SingleState {
 
     pub(super) fn clear(&mut self) {
         self.rows = 0;
+        self.filled_ranges.clear();
+        self.accesses.get_mut().clear();
+        *self.ordered.get_mut() = None;
         match self.state {
             KeyedState::Single(ref mut map) => map.clear(),
             KeyedState::Double(ref mut map) => map.clear(),
@@ -220,18 +309,31 @@ impl SingleState {
         };
     }
 
-    /// Evict `count` randomly selected keys from state and return them along with the number of
+    /// Evict `count` keys chosen by `policy` from state and return them along with the number of
     /// bytes freed.
-    pub(super) fn evict_random_keys(
+    ///
+    /// Keys are picked at random if the policy does not use access history.
+    pub(super) fn evict_cold_keys(
         &mut self,
+        policy: &dyn EvictionPolicy,
         count: usize,
         rng: &mut ThreadRng,
     ) -> (u64, Vec<Vec<DataType>>) {
+        if let Some(keys) = self.accesses.get_mut().coldest(policy, count, rng) {
+            let bytes_freed = self.evict_keys(&keys);
+            return (bytes_freed, keys);
+        }
+
         let mut bytes_freed = 0;
         let mut keys = Vec::with_capacity(count);
         for _ in 0..count {
             if let Some((n, key)) = self.state.evict_with_seed(rng.gen()) {
                 bytes_freed += n;
+                self.unfill_ranges_containing(&key);
+                self.accesses.get_mut().forget(&key);
+                if let Some(ref mut ordered) = *self.ordered.get_mut() {
+                    ordered.remove(&key);
+                }
                 keys.push(key);
             } else {
                 break;
@@ -242,6 +344,13 @@ impl SingleState {
 
     /// Evicts a specified key from this state, returning the number of bytes freed.
     pub(super) fn evict_keys(&mut self, keys: &[Vec<DataType>]) -> u64 {
+        for k in keys {
+            self.unfill_ranges_containing(k);
+            self.accesses.get_mut().forget(k);
+            if let Some(ref mut ordered) = *self.ordered.get_mut() {
+                ordered.remove(k);
+            }
+        }
         keys.iter().map(|k| self.state.evict(k)).sum()
     }
 
@@ -264,17 +373,56 @@ impl SingleState {
     pub(super) fn rows(&self) -> usize {
         self.rows
     }
+    /// The memory used by the read history of this index.
+    pub(super) fn tracked_bytes(&self) -> u64 {
+        self.accesses.borrow().deep_size_of()
+    }
     pub(super) fn is_empty(&self) -> bool {
         self.rows == 0
     }
     pub(super) fn lookup<'a>(&'a self, key: &KeyType) -> LookupResult<'a> {
         if let Some(rs) = self.state.lookup(key) {
+            if self.partial {
+                self.accesses.borrow_mut().touch(&key_to_vec(key));
+            }
             LookupResult::Some(RecordResult::Borrowed(rs))
-        } else if self.partial() {
+        } else if self.partial() && !self.key_in_filled_range(key) {
             // partially materialized, so this is a hole (empty results would be vec![])
             LookupResult::Missing
         } else {
             LookupResult::Some(RecordResult::Owned(vec![]))
         }
     }
+
+    fn key_in_filled_range(&self, key: &KeyType) -> bool {
+        if self.filled_ranges.is_empty() {
+            return false;
+        }
+        let key = key_to_vec(key);
+        self.filled_ranges.iter().any(|f| f.contains(&key))
+    }
+
+    /// Look up all keys that fall within `range`, in key order.
+    ///
+    /// Returns `None` if this state is partial, and the range has not been filled in its
+    /// entirety.
+    pub(super) fn lookup_range<'a>(
+        &'a self,
+        range: &KeyRange,
+    ) -> Option<Vec<(Vec<DataType>, &'a Rows)>> {
+        if self.partial() && !self.filled_ranges.iter().any(|f| f.covers(range)) {
+            return None;
+        }
+        let mut ordered = self.ordered.borrow_mut();
+        let ordered = ordered.get_or_insert_with(|| self.state.keys());
+        Some(
+            super::keys_in(ordered, range)
+                .filter_map(|k| {
+                    self.state
+                        .lookup(&KeyType::from(&k[..]))
+                        .map(|rs| (k.clone(), rs))
+                })
+                .collect(),
+        )
+    }
 }
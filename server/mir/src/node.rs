@@ -1,4 +1,4 @@
-use nom_sql::{ArithmeticExpression, ColumnSpecification, Literal, OrderType};
+use nom_sql::{ColumnSpecification, Literal, OrderType};
 use petgraph::graph::NodeIndex;
 use std::cell::RefCell;
 use std::fmt::{Debug, Display, Error, Formatter};
@@ -12,6 +12,7 @@ use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::grouped::aggregate::Aggregation as AggregationKind;
 use dataflow::ops::grouped::extremum::Extremum as ExtremumKind;
 use dataflow::ops::grouped::filteraggregate::FilterAggregation as FilterAggregationKind;
+use dataflow::ops::project::ProjectExpression;
 use std::collections::HashMap;
 
 /// Helper enum to avoid having separate `make_aggregation_node` and `make_extremum_node` functions
@@ -61,11 +62,13 @@ impl MirNode {
         rc_mn
     }
 
-    /// Adapts an existing `Base`-type MIR Node with the specified column additions and removals.
+    /// Adapts an existing `Base`-type MIR Node with the specified column additions, removals and
+    /// renames. Renamed columns are given as pairs of their old and new specifications.
     pub fn adapt_base(
         node: MirNodeRef,
         added_cols: Vec<&ColumnSpecification>,
         removed_cols: Vec<&ColumnSpecification>,
+        renamed_cols: Vec<(&ColumnSpecification, &ColumnSpecification)>,
     ) -> MirNodeRef {
         let over_node = node.borrow();
         match over_node.inner {
@@ -78,6 +81,13 @@ impl MirNode {
                     .iter()
                     .cloned()
                     .filter(|&(ref cs, _)| !removed_cols.contains(&cs))
+                    .map(|(cs, cid)| {
+                        // renamed columns keep their column ID
+                        match renamed_cols.iter().find(|&&(old, _)| *old == cs) {
+                            Some(&(_, new)) => (new.clone(), cid),
+                            None => (cs, cid),
+                        }
+                    })
                     .chain(
                         added_cols
                             .iter()
@@ -102,6 +112,10 @@ impl MirNode {
                         over: node.clone(),
                         columns_added: added_cols.into_iter().cloned().collect(),
                         columns_removed: removed_cols.into_iter().cloned().collect(),
+                        columns_renamed: renamed_cols
+                            .into_iter()
+                            .map(|(old, new)| (old.clone(), new.clone()))
+                            .collect(),
                     }),
                 };
                 MirNode::new(
@@ -388,14 +402,104 @@ impl MirNode {
     }
 }
 
-/// Specifies the adapatation of an existing base node by column addition/removal.
+/// Copies MIR nodes along with the rest of the MIR graph they are connected to, so that the
+/// copies can be changed without affecting the original nodes. Nodes that are reachable from
+/// several of the copied nodes are only copied once.
+#[derive(Default)]
+pub struct MirGraphCopy {
+    copies: HashMap<*const MirNode, MirNodeRef>,
+}
+
+impl MirGraphCopy {
+    /// Returns the copy of `node`.
+    pub fn node(&mut self, node: &MirNodeRef) -> MirNodeRef {
+        let mut queue = Vec::new();
+        let copy = self.copy_of(node, &mut queue);
+
+        // link up the copies, copying any nodes we have not seen before along the way
+        while let Some((original, copy)) = queue.pop() {
+            let original = original.borrow();
+            let ancestors = original
+                .ancestors
+                .iter()
+                .map(|n| self.copy_of(n, &mut queue))
+                .collect();
+            let children = original
+                .children
+                .iter()
+                .map(|n| self.copy_of(n, &mut queue))
+                .collect();
+            let inner = match original.inner.clone() {
+                MirNodeType::Reuse { node } => MirNodeType::Reuse {
+                    node: self.copy_of(&node, &mut queue),
+                },
+                MirNodeType::Leaf { node, keys } => MirNodeType::Leaf {
+                    node: self.copy_of(&node, &mut queue),
+                    keys,
+                },
+                MirNodeType::Base {
+                    column_specs,
+                    keys,
+                    adapted_over: Some(mut adaptation),
+                } => {
+                    adaptation.over = self.copy_of(&adaptation.over, &mut queue);
+                    MirNodeType::Base {
+                        column_specs,
+                        keys,
+                        adapted_over: Some(adaptation),
+                    }
+                }
+                inner => inner,
+            };
+
+            let mut copy = copy.borrow_mut();
+            copy.ancestors = ancestors;
+            copy.children = children;
+            copy.inner = inner;
+        }
+
+        copy
+    }
+
+    fn copy_of(
+        &mut self,
+        node: &MirNodeRef,
+        queue: &mut Vec<(MirNodeRef, MirNodeRef)>,
+    ) -> MirNodeRef {
+        if let Some(copy) = self.copies.get(&(node.as_ptr() as *const MirNode)) {
+            return copy.clone();
+        }
+
+        // the copy is linked up with the other copies once it comes off the queue
+        let n = node.borrow();
+        let copy = Rc::new(RefCell::new(MirNode {
+            name: n.name.clone(),
+            from_version: n.from_version,
+            columns: n.columns.clone(),
+            inner: n.inner.clone(),
+            ancestors: vec![],
+            children: vec![],
+            flow_node: n.flow_node.clone(),
+        }));
+        self.copies
+            .insert(node.as_ptr() as *const MirNode, copy.clone());
+        queue.push((node.clone(), copy.clone()));
+        copy
+    }
+}
+
+/// Specifies the adapatation of an existing base node by column addition/removal/renaming.
 /// `over` is a `MirNode` of type `Base`.
+#[derive(Clone)]
 pub struct BaseNodeAdaptation {
     pub over: MirNodeRef,
     pub columns_added: Vec<ColumnSpecification>,
     pub columns_removed: Vec<ColumnSpecification>,
+    /// Old and new specification of each renamed column.
+    pub columns_renamed: Vec<(ColumnSpecification, ColumnSpecification)>,
 }
 
+#[derive(Clone)]
 pub enum MirNodeType {
     /// over column, group_by columns
     Aggregation {
@@ -403,7 +507,7 @@ pub enum MirNodeType {
         group_by: Vec<Column>,
         kind: AggregationKind,
     },
-    /// column specifications, keys (non-compound), tx flag, adapted base
+    /// column specifications, keys (non-compound), adapted base
     Base {
         column_specs: Vec<(ColumnSpecification, Option<usize>)>,
         keys: Vec<Column>,
@@ -428,10 +532,7 @@ pub enum MirNodeType {
         conditions: Vec<(usize, FilterCondition)>,
     },
     /// over column, separator
-    GroupConcat {
-        on: Column,
-        separator: String,
-    },
+    GroupConcat { on: Column, separator: String },
     /// no extra info required
     Identity,
     /// left node, right node, on left columns, on right columns, emit columns
@@ -446,22 +547,30 @@ pub enum MirNodeType {
         on_right: Vec<Column>,
         project: Vec<Column>,
     },
+    /// on left column, on right column, emit columns
+    RightJoin {
+        on_left: Vec<Column>,
+        on_right: Vec<Column>,
+        project: Vec<Column>,
+    },
+    /// on left column, on right column, emit columns
+    FullJoin {
+        on_left: Vec<Column>,
+        on_right: Vec<Column>,
+        project: Vec<Column>,
+    },
     /// group columns
     // currently unused
     #[allow(dead_code)]
-    Latest {
-        group_by: Vec<Column>,
-    },
+    Latest { group_by: Vec<Column> },
     /// emit columns
     Project {
         emit: Vec<Column>,
-        arithmetic: Vec<(String, ArithmeticExpression)>,
+        expressions: Vec<(String, ProjectExpression<Column>)>,
         literals: Vec<(String, DataType)>,
     },
     /// emit columns
-    Union {
-        emit: Vec<Vec<Column>>,
-    },
+    Union { emit: Vec<Vec<Column>> },
     /// order function, group columns, k
     TopK {
         order: Option<Vec<(Column, OrderType)>>,
@@ -472,16 +581,12 @@ pub enum MirNodeType {
     // Get the distinct element sorted by a specific column
     Distinct {
         group_by: Vec<Column>,
+        key: Vec<Column>,
     },
     /// reuse another node
-    Reuse {
-        node: MirNodeRef,
-    },
+    Reuse { node: MirNodeRef },
     /// leaf (reader) node, keys
-    Leaf {
-        node: MirNodeRef,
-        keys: Vec<Column>,
-    },
+    Leaf { node: MirNodeRef, keys: Vec<Column> },
     /// Rewrite node
     Rewrite {
         value: String,
@@ -519,6 +624,12 @@ impl MirNodeType {
             }
             | MirNodeType::LeftJoin {
                 ref mut project, ..
+            }
+            | MirNodeType::RightJoin {
+                ref mut project, ..
+            }
+            | MirNodeType::FullJoin {
+                ref mut project, ..
             } => {
                 project.push(c);
             }
@@ -692,22 +803,62 @@ impl MirNodeType {
                     _ => false,
                 }
             }
+            MirNodeType::RightJoin {
+                on_left: ref our_on_left,
+                on_right: ref our_on_right,
+                project: ref our_project,
+            } => {
+                match *other {
+                    MirNodeType::RightJoin {
+                        ref on_left,
+                        ref on_right,
+                        ref project,
+                    } => {
+                        // TODO(malte): column order does not actually need to match, but this only
+                        // succeeds if it does.
+                        our_on_left == on_left && our_on_right == on_right && our_project == project
+                    }
+                    _ => false,
+                }
+            }
+            MirNodeType::FullJoin {
+                on_left: ref our_on_left,
+                on_right: ref our_on_right,
+                project: ref our_project,
+            } => {
+                match *other {
+                    MirNodeType::FullJoin {
+                        ref on_left,
+                        ref on_right,
+                        ref project,
+                    } => {
+                        // TODO(malte): column order does not actually need to match, but this only
+                        // succeeds if it does.
+                        our_on_left == on_left && our_on_right == on_right && our_project == project
+                    }
+                    _ => false,
+                }
+            }
             MirNodeType::Project {
                 emit: ref our_emit,
                 literals: ref our_literals,
-                arithmetic: ref our_arithmetic,
+                expressions: ref our_expressions,
             } => match *other {
                 MirNodeType::Project {
                     ref emit,
                     ref literals,
-                    ref arithmetic,
-                } => our_emit == emit && our_literals == literals && our_arithmetic == arithmetic,
+                    ref expressions,
+                } => our_emit == emit && our_literals == literals && our_expressions == expressions,
                 _ => false,
             },
             MirNodeType::Distinct {
                 group_by: ref our_group_by,
+                key: ref our_key,
             } => match *other {
-                MirNodeType::Distinct { ref group_by } => group_by == our_group_by,
+                MirNodeType::Distinct {
+                    ref group_by,
+                    ref key,
+                } => group_by == our_group_by && key == our_key,
                 _ => false,
             },
             MirNodeType::Reuse { node: ref us } => {
@@ -807,6 +958,8 @@ impl Debug for MirNodeType {
                 let op_string = match *kind {
                     AggregationKind::COUNT => format!("|*|({})", on.name.as_str()),
                     AggregationKind::SUM => format!("𝛴({})", on.name.as_str()),
+                    AggregationKind::AVG => format!("μ({})", on.name.as_str()),
+                    AggregationKind::COUNT_DISTINCT => format!("|≠{}|", on.name.as_str()),
                 };
                 let group_cols = group_by
                     .iter()
@@ -874,6 +1027,8 @@ impl Debug for MirNodeType {
                                     .collect::<Vec<_>>()
                                     .join(", ")
                             )),
+                            FilterCondition::Expression(ref e, ref op, ref x) =>
+                                Some(format!("({}) {} {:?}", e, escape(&format!("{}", op)), x)),
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
@@ -955,6 +1110,50 @@ impl Debug for MirNodeType {
                     jc
                 )
             }
+            MirNodeType::RightJoin {
+                ref on_left,
+                ref on_right,
+                ref project,
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", l.name, r.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "⋊ [{} on {}]",
+                    project
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    jc
+                )
+            }
+            MirNodeType::FullJoin {
+                ref on_left,
+                ref on_right,
+                ref project,
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", l.name, r.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "⟗ [{} on {}]",
+                    project
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    jc
+                )
+            }
             MirNodeType::Latest { ref group_by } => {
                 let key_cols = group_by
                     .iter()
@@ -966,7 +1165,7 @@ impl Debug for MirNodeType {
             MirNodeType::Project {
                 ref emit,
                 ref literals,
-                ref arithmetic,
+                ref expressions,
             } => write!(
                 f,
                 "π [{}{}{}]",
@@ -974,14 +1173,16 @@ impl Debug for MirNodeType {
                     .map(|c| c.name.as_str())
                     .collect::<Vec<_>>()
                     .join(", "),
-                if arithmetic.is_empty() {
+                if expressions.is_empty() {
                     "".into()
                 } else {
                     format!(
                         ", {}",
-                        arithmetic
+                        expressions
                             .iter()
-                            .map(|&(ref n, ref e)| format!("{}: {}", n, e))
+                            .map(|&(ref n, ref e)| {
+                                format!("{}: {}", n, e.map_columns(&mut |c| c.name.clone()))
+                            })
                             .collect::<Vec<_>>()
                             .join(", ")
                     )
@@ -1005,7 +1206,7 @@ impl Debug for MirNodeType {
                 node.borrow().versioned_name(),
                 node.borrow()
             ),
-            MirNodeType::Distinct { ref group_by } => {
+            MirNodeType::Distinct { ref group_by, .. } => {
                 let key_cols = group_by
                     .iter()
                     .map(|k| k.name.clone())
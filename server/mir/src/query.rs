@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::fmt::{Display, Error, Formatter};
 
+use crate::node::MirGraphCopy;
 use crate::MirNodeRef;
 use petgraph::graph::NodeIndex;
 
@@ -32,6 +33,15 @@ impl MirQuery {
         }
     }
 
+    /// Copies the query's MIR nodes using `copy`; see `MirGraphCopy`.
+    pub fn deep_copy(&self, copy: &mut MirGraphCopy) -> MirQuery {
+        MirQuery {
+            name: self.name.clone(),
+            roots: self.roots.iter().map(|n| copy.node(n)).collect(),
+            leaf: copy.node(&self.leaf),
+        }
+    }
+
     #[cfg(test)]
     pub fn topo_nodes(&self) -> Vec<MirNodeRef> {
         use std::collections::VecDeque;
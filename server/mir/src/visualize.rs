@@ -107,6 +107,8 @@ impl GraphViz for MirNodeType {
                 let op_string = match *kind {
                     AggregationKind::COUNT => format!("\\|*\\|({})", print_col(on)),
                     AggregationKind::SUM => format!("𝛴({})", print_col(on)),
+                    AggregationKind::AVG => format!("μ({})", print_col(on)),
+                    AggregationKind::COUNT_DISTINCT => format!("\\|≠{}\\|", print_col(on)),
                 };
                 let group_cols = group_by
                     .iter()
@@ -193,6 +195,8 @@ impl GraphViz for MirNodeType {
                                     .collect::<Vec<_>>()
                                     .join(", ")
                             )),
+                            FilterCondition::Expression(ref e, ref op, ref x) =>
+                                Some(format!("({}) {} {}", e, escape(&format!("{}", op)), x)),
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
@@ -242,6 +246,32 @@ impl GraphViz for MirNodeType {
                     .join(", ");
                 write!(out, "⋉  | on: {}", jc)?;
             }
+            MirNodeType::RightJoin {
+                ref on_left,
+                ref on_right,
+                ..
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", print_col(l), print_col(r)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(out, "⋊  | on: {}", jc)?;
+            }
+            MirNodeType::FullJoin {
+                ref on_left,
+                ref on_right,
+                ..
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", print_col(l), print_col(r)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(out, "⟗  | on: {}", jc)?;
+            }
             MirNodeType::Latest { ref group_by } => {
                 let key_cols = group_by
                     .iter()
@@ -253,7 +283,7 @@ impl GraphViz for MirNodeType {
             MirNodeType::Project {
                 ref emit,
                 ref literals,
-                ref arithmetic,
+                ref expressions,
             } => {
                 write!(
                     out,
@@ -262,14 +292,16 @@ impl GraphViz for MirNodeType {
                         .map(|c| print_col(c))
                         .collect::<Vec<_>>()
                         .join(", "),
-                    if arithmetic.is_empty() {
+                    if expressions.is_empty() {
                         "".into()
                     } else {
                         format!(
                             ", {}",
-                            arithmetic
+                            expressions
                                 .iter()
-                                .map(|&(ref n, ref e)| format!("{}: {}", n, e))
+                                .map(|&(ref n, ref e)| {
+                                    format!("{}: {}", n, e.map_columns(&mut |c| c.name.clone()))
+                                })
                                 .collect::<Vec<_>>()
                                 .join(", ")
                         )
@@ -291,7 +323,7 @@ impl GraphViz for MirNodeType {
             MirNodeType::Reuse { ref node } => {
                 write!(out, "Reuse | using: {}", node.borrow().versioned_name(),)?;
             }
-            MirNodeType::Distinct { ref group_by } => {
+            MirNodeType::Distinct { ref group_by, .. } => {
                 let key_cols = group_by
                     .iter()
                     .map(|k| print_col(k))
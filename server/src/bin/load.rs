@@ -0,0 +1,219 @@
+use nom_sql::SqlType;
+use noria::{ControllerHandle, DataType};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Tsv,
+}
+
+/// Split a line of a CSV file into its fields.
+///
+/// Fields may be quoted, with `""` standing for a quote inside a quoted field. Quoted fields may
+/// not span multiple lines.
+fn split_csv(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::replace(&mut field, String::new())),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Turn the text of a field into a value for a column of the given type.
+///
+/// `\N` is taken to mean NULL, as it is in MySQL dumps.
+fn parse_field(field: String, ty: Option<&SqlType>) -> Result<DataType, String> {
+    if field == "\\N" {
+        return Ok(DataType::None);
+    }
+
+    match ty {
+        Some(SqlType::Int(_))
+        | Some(SqlType::Bigint(_))
+        | Some(SqlType::Tinyint(_))
+        | Some(SqlType::UnsignedInt(_))
+        | Some(SqlType::UnsignedBigint(_)) => field
+            .parse::<i64>()
+            .map(DataType::from)
+            .map_err(|e| format!("'{}' is not an integer: {}", field, e)),
+        Some(SqlType::Double) | Some(SqlType::Float) | Some(SqlType::Real) => field
+            .parse::<f64>()
+            .map(DataType::from)
+            .map_err(|e| format!("'{}' is not a number: {}", field, e)),
+        Some(_) => Ok(field.into()),
+        None => Ok(field
+            .parse::<i64>()
+            .map(DataType::from)
+            .unwrap_or_else(|_| field.into())),
+    }
+}
+
+/// Turn a line of the input file into a row for a table with columns of the given types.
+fn parse_line(
+    line: io::Result<String>,
+    format: Format,
+    types: &[Option<SqlType>],
+) -> Result<Vec<DataType>, String> {
+    let line = line.map_err(|e| e.to_string())?;
+    let fields = match format {
+        Format::Csv => split_csv(&line)?,
+        Format::Tsv => line.split('\t').map(String::from).collect(),
+    };
+    if fields.len() != types.len() {
+        return Err(format!(
+            "expected {} fields, found {}",
+            types.len(),
+            fields.len()
+        ));
+    }
+
+    fields
+        .into_iter()
+        .zip(types)
+        .map(|(field, ty)| parse_field(field, ty.as_ref()))
+        .collect()
+}
+
+fn main() {
+    use clap::{App, Arg};
+    let matches = App::new("noria-load")
+        .version("0.0.1")
+        .about("Inserts rows from a CSV or TSV file into a Noria base table in large batches.")
+        .arg(
+            Arg::with_name("zookeeper")
+                .short("z")
+                .long("zookeeper")
+                .takes_value(true)
+                .default_value("127.0.0.1:2181")
+                .help("Zookeeper connection info."),
+        )
+        .arg(
+            Arg::with_name("deployment")
+                .long("deployment")
+                .short("d")
+                .required(true)
+                .takes_value(true)
+                .help("Noria deployment ID."),
+        )
+        .arg(
+            Arg::with_name("table")
+                .long("table")
+                .short("t")
+                .required(true)
+                .takes_value(true)
+                .help("Base table to load the rows into."),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .short("f")
+                .takes_value(true)
+                .possible_values(&["csv", "tsv"])
+                .help("Format of the input file [default: by file extension, or csv]."),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .help("Skip the first line of the input file."),
+        )
+        .arg(
+            Arg::with_name("FILE")
+                .required(true)
+                .help("File to load the rows from, or - for standard input."),
+        )
+        .get_matches();
+
+    let deployment = matches.value_of("deployment").unwrap();
+    let zookeeper_addr = format!("{}/{}", matches.value_of("zookeeper").unwrap(), deployment);
+    let table_name = matches.value_of("table").unwrap();
+    let path = matches.value_of("FILE").unwrap();
+    let format = match matches.value_of("format") {
+        Some("tsv") => Format::Tsv,
+        Some(_) => Format::Csv,
+        None if path.ends_with(".tsv") || path.ends_with(".tab") => Format::Tsv,
+        None => Format::Csv,
+    };
+    let header = matches.is_present("header");
+
+    let input: Box<dyn BufRead> = if path == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        match File::open(path) {
+            Ok(f) => Box::new(BufReader::with_capacity(4 * 1024 * 1024, f)),
+            Err(e) => {
+                eprintln!("failed to open {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    };
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let loaded = rt.block_on(async move {
+        let mut ch = ControllerHandle::from_zk(&zookeeper_addr).await?;
+        let mut table = ch.table(table_name).await?;
+        let types: Vec<_> = match table.schema() {
+            Some(schema) => schema
+                .fields
+                .iter()
+                .map(|f| Some(f.sql_type.clone()))
+                .collect(),
+            None => vec![None; table.columns().len()],
+        };
+
+        // rows are parsed as they are loaded, so the file never has to fit in memory.
+        // a bad row ends the load early. the rows before it are still loaded (and made durable)
+        // before the error is reported.
+        let mut loaded = 0;
+        let mut bad_row = None;
+        let rows = input
+            .lines()
+            .enumerate()
+            .skip(if header { 1 } else { 0 })
+            .map(|(i, line)| (i, parse_line(line, format, &types)))
+            .scan(&mut bad_row, |bad_row, (i, row)| match row {
+                Ok(row) => Some(row),
+                Err(e) => {
+                    **bad_row = Some(format!("line {}: {}", i + 1, e));
+                    None
+                }
+            })
+            .fuse()
+            .inspect(|_| loaded += 1);
+        table.bulk_load(rows).await?;
+
+        if let Some(e) = bad_row {
+            failure::bail!("{} (after loading {} rows)", e, loaded);
+        }
+        Ok::<_, failure::Error>(loaded)
+    });
+
+    match loaded {
+        Ok(n) => println!("loaded {} rows into {}", n, table_name),
+        Err(e) => {
+            eprintln!("failed to load {}: {}", table_name, e);
+            process::exit(1);
+        }
+    }
+}
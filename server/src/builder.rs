@@ -2,7 +2,7 @@ use crate::handle::Handle;
 use crate::Config;
 use crate::FrontierStrategy;
 use crate::ReuseConfigType;
-use dataflow::PersistenceParameters;
+use dataflow::{EvictionKind, PersistenceParameters};
 use noria::consensus::{Authority, LocalAuthority};
 use std::future::Future;
 use std::net::IpAddr;
@@ -43,6 +43,12 @@ impl Builder {
         self.config.domain_config.replay_batch_timeout = t;
     }
 
+    /// Set how long readers hold back writes for a transaction whose writes have yet to all
+    /// arrive, after which they give up on the rest of them.
+    pub fn set_transaction_timeout(&mut self, t: time::Duration) {
+        self.config.domain_config.transaction_timeout = t;
+    }
+
     /// Set the persistence parameters used by the system.
     pub fn set_persistence(&mut self, p: PersistenceParameters) {
         self.config.persistence = p;
@@ -70,12 +76,19 @@ impl Builder {
         self.config.quorum = quorum;
     }
 
-    /// Set the memory limit (target) and how often we check it (in millis).
-    pub fn set_memory_limit(&mut self, limit: usize, check_freq: time::Duration) {
+    /// Set the memory limit (target), how often we check it (in millis), and which policy is used
+    /// to pick what to evict when we exceed it.
+    pub fn set_memory_limit(
+        &mut self,
+        limit: usize,
+        check_freq: time::Duration,
+        policy: EvictionKind,
+    ) {
         assert_ne!(limit, 0);
         assert_ne!(check_freq, time::Duration::from_millis(0));
         self.memory_limit = Some(limit);
         self.memory_check_frequency = Some(check_freq);
+        self.config.domain_config.eviction_policy = policy;
     }
 
     /// Set the IP address that the worker should use for listening.
@@ -115,6 +128,7 @@ impl Builder {
             memory_check_frequency,
             ref log,
         } = *self;
+        let eviction_policy = config.domain_config.eviction_policy;
 
         let config = config.clone();
         let log = log.clone();
@@ -125,6 +139,7 @@ impl Builder {
             config,
             memory_limit,
             memory_check_frequency,
+            eviction_policy,
             log,
         )
     }
@@ -5,8 +5,11 @@ use crate::controller::schema;
 use crate::controller::{ControllerState, Migration, Recipe};
 use crate::controller::{Worker, WorkerIdentifier};
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
+use crate::snapshot;
 use dataflow::prelude::*;
-use dataflow::{node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig};
+use dataflow::{
+    node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig, MemoryBudget,
+};
 use futures_util::stream::StreamExt;
 use hyper::{self, Method, StatusCode};
 use nom_sql::ColumnSpecification;
@@ -14,12 +17,14 @@ use noria::builders::*;
 use noria::channel::tcp::{SendError, TcpSender};
 use noria::consensus::{Authority, Epoch, STATE_KEY};
 use noria::debug::stats::{DomainStats, GraphStats, NodeStats};
-use noria::ActivationResult;
-use petgraph::visit::Bfs;
+use noria::{ActivationResult, Change, ChangeOffset, Input, TableOperation};
+use noria::{PlannedMaterialization, PlannedNode, RecipeDiff, RecipePlan, RecipeVersion};
+use petgraph::visit::{Bfs, Reversed};
 use slog::Logger;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::mem;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{cell, io, time};
@@ -44,6 +49,10 @@ pub(super) struct ControllerInner {
 
     /// Current recipe
     recipe: Recipe,
+    /// The text of the current security configuration, if one has been set.
+    security_config: Option<String>,
+    /// The base tables that keep a change feed.
+    change_feeds: HashSet<String>,
 
     pub(super) domains: HashMap<DomainIndex, DomainHandle>,
     pub(in crate::controller) domain_nodes: HashMap<DomainIndex, Vec<NodeIndex>>,
@@ -52,6 +61,8 @@ pub(super) struct ControllerInner {
     /// Map from worker address to the address the worker is listening on for reads.
     read_addrs: HashMap<WorkerIdentifier, SocketAddr>,
     pub(super) workers: HashMap<WorkerIdentifier, Worker>,
+    /// Workers that have been drained and removed, but may still be sending heartbeats.
+    decommissioned: HashSet<WorkerIdentifier>,
 
     /// State between migrations
     pub(super) remap: HashMap<DomainIndex, HashMap<NodeIndex, IndexPair>>,
@@ -60,6 +71,14 @@ pub(super) struct ControllerInner {
 
     pending_recovery: Option<(Vec<String>, usize)>,
 
+    /// The identifier to give the next transaction.
+    next_transaction: u64,
+    /// Fail the send to a domain shard that comes after this many more have succeeded.
+    #[cfg(test)]
+    pub(super) fail_sends_after: Option<usize>,
+    #[cfg(test)]
+    pub(super) abandon_next_transaction: bool,
+
     quorum: usize,
     heartbeat_every: Duration,
     healthcheck_every: Duration,
@@ -89,7 +108,11 @@ impl DomainReplies {
     }
 
     pub(in crate::controller) async fn wait_for_acks(&mut self, d: &DomainHandle) {
-        for r in self.read_n_domain_replies(d.shards()).await {
+        self.wait_for_n_acks(d.shards()).await
+    }
+
+    async fn wait_for_n_acks(&mut self, n: usize) {
+        for r in self.read_n_domain_replies(n).await {
             match r {
                 ControlReplyPacket::Ack(_) => {}
                 r => unreachable!("got unexpected non-ack control reply: {:?}", r),
@@ -110,6 +133,27 @@ impl DomainReplies {
         }
         stats
     }
+
+    async fn wait_for_changes(
+        &mut self,
+        n: usize,
+    ) -> Vec<(usize, Result<(Vec<Change>, u64), String>)> {
+        let mut changes = Vec::with_capacity(n);
+        for r in self.read_n_domain_replies(n).await {
+            match r {
+                ControlReplyPacket::Changes(shard, c) => changes.push((shard, c)),
+                r => unreachable!("got unexpected non-changes control reply: {:?}", r),
+            }
+        }
+        changes
+    }
+
+    async fn wait_for_base_rows(&mut self) -> Result<Vec<Vec<DataType>>, String> {
+        match self.read_n_domain_replies(1).await.pop() {
+            Some(ControlReplyPacket::BaseRows(rows)) => rows,
+            r => unreachable!("got unexpected non-rows control reply: {:?}", r),
+        }
+    }
 }
 
 pub(super) fn graphviz(
@@ -167,6 +211,15 @@ pub(super) fn graphviz(
     s
 }
 
+/// Reads the controller state that `authority` holds.
+fn read_state<A: Authority + 'static>(authority: &Arc<A>) -> Result<ControllerState, String> {
+    authority
+        .try_read(STATE_KEY)
+        .map_err(|e| format!("failed to read controller state: {:?}", e))?
+        .and_then(|state| serde_json::from_slice(&state).ok())
+        .ok_or_else(|| "no controller state".to_owned())
+}
+
 impl ControllerInner {
     pub(in crate::controller) fn topo_order(&self, new: &HashSet<NodeIndex>) -> Vec<NodeIndex> {
         let mut topo_list = Vec::with_capacity(new.len());
@@ -277,10 +330,28 @@ impl ControllerInner {
                     self.install_recipe(authority, args)
                         .map(|r| json::to_string(&r).unwrap())
                 }),
+            (Method::POST, "/recipe_versions") => Ok(self
+                .recipe_versions(authority)
+                .map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/diff_recipes") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.diff_recipes(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/rollback_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.rollback_recipe(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/plan_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.plan_recipe(args).map(|r| json::to_string(&r).unwrap())),
             (Method::POST, "/set_security_config") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
-                    self.set_security_config(args)
+                    self.set_security_config(authority, args)
                         .map(|r| json::to_string(&r).unwrap())
                 }),
             (Method::POST, "/create_universe") => json::from_slice(&body)
@@ -289,6 +360,34 @@ impl ControllerInner {
                     self.create_universe(args)
                         .map(|r| json::to_string(&r).unwrap())
                 }),
+            (Method::POST, "/instances") => Ok(Ok(json::to_string(&self.get_instances()).unwrap())),
+            (Method::POST, "/decommission_worker") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.decommission_worker(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/enable_table_changes") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.enable_table_changes(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/table_changes") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.table_changes(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/transaction") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.transaction(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/snapshot") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.snapshot(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
             (Method::POST, "/remove_node") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
@@ -332,10 +431,27 @@ impl ControllerInner {
                     recipe_version + 1 - recipes.len(),
                     Some(self.log.clone()),
                 );
+                if let Some(ref config) = self.security_config {
+                    self.recipe.set_security_config(config);
+                }
                 for r in recipes {
                     self.apply_recipe(self.recipe.clone().extend(&r).unwrap())
                         .unwrap();
                 }
+
+                let inputs = self.inputs();
+                for table in self.change_feeds.clone() {
+                    let kept = match inputs.get(&table) {
+                        Some(&ni) => self.keep_changes(ni),
+                        None => Err(format!("no base table named {}", table)),
+                    };
+                    if let Err(e) = kept {
+                        error!(
+                            self.log,
+                            "failed to restore change feed of {}: {}", table, e
+                        );
+                    }
+                }
             }
         }
 
@@ -379,14 +495,23 @@ impl ControllerInner {
             affected_nodes.extend(self.get_failed_nodes(&wi));
         }
 
-        // then, figure out which queries are affected (and thus must be removed and added again in
-        // a migration)
+        self.rebuild_queries(affected_nodes)
+            .expect("failed to recover from worker failure");
+    }
+
+    /// Remove and re-add every query that includes any of the given nodes.
+    ///
+    /// The re-added queries are placed only on healthy workers that are not being drained, and
+    /// their state is rebuilt by replaying from upstream.
+    fn rebuild_queries(&mut self, affected_nodes: Vec<NodeIndex>) -> Result<(), String> {
+        // figure out which queries are affected (and thus must be removed and added again in a
+        // migration)
         let affected_queries = self.recipe.queries_for_nodes(affected_nodes);
         let (recovery, mut original) = self.recipe.make_recovery(affected_queries);
 
         // activate recipe
         self.apply_recipe(recovery.clone())
-            .expect("failed to apply recovery recipe");
+            .map_err(|e| format!("failed to apply recovery recipe: {}", e))?;
 
         // we must do this *after* the migration, since the migration itself modifies the recipe in
         // `recovery`, and we currently need to clone it here.
@@ -397,11 +522,154 @@ impl ControllerInner {
 
         // back to original recipe, which should add the query again
         self.apply_recipe(original)
-            .expect("failed to activate original recipe");
+            .map_err(|e| format!("failed to activate original recipe: {}", e))?;
+        Ok(())
+    }
+
+    /// Move all domains off the given worker, and then remove the worker from the deployment.
+    ///
+    /// Domains on the worker are moved by removing and re-adding the queries they are a part of,
+    /// which rebuilds their state elsewhere through replay. Base tables can't be rebuilt that way,
+    /// since their rows only exist in the worker's own state. So inputs to the base tables on the
+    /// worker are paused, and their rows are copied out before the tables are re-created elsewhere
+    /// and then loaded into the new tables. Writes to those tables that arrive after inputs were
+    /// paused are never applied.
+    fn decommission_worker(&mut self, wi: WorkerIdentifier) -> Result<(), String> {
+        match self.workers.get(&wi) {
+            None => return Err(format!("no worker at {:?}", wi)),
+            Some(w) if !w.healthy => return Err(format!("worker at {:?} has failed", wi)),
+            Some(_) => {}
+        }
+        if self.workers.len() <= self.quorum {
+            return Err(format!(
+                "decommissioning {:?} would leave fewer than {} workers",
+                wi, self.quorum
+            ));
+        }
+        if !self
+            .workers
+            .iter()
+            .any(|(&other, w)| other != wi && w.healthy && !w.draining)
+        {
+            return Err(format!("no other worker can take over from {:?}", wi));
+        }
+
+        let bases: BTreeMap<_, _> = self
+            .nodes_on_worker(Some(&wi))
+            .into_iter()
+            .filter(|&ni| self.ingredients[ni].is_base())
+            .map(|ni| (self.ingredients[ni].name().to_owned(), ni))
+            .collect();
+        let base_domains: HashSet<_> = bases
+            .values()
+            .map(|&ni| self.ingredients[ni].domain())
+            .collect();
+
+        // the base tables are re-created empty, so take their rows along. their inputs stay
+        // paused until the domains are shut down, so that no write is acknowledged after the copy.
+        let rows = self
+            .set_inputs_paused(&base_domains, true)
+            .and_then(|_| self.copy_base_rows(&bases))
+            .and_then(|_| {
+                bases
+                    .iter()
+                    .map(|(name, &ni)| {
+                        self.take_base_rows(name, ni)
+                            .map(|rows| (name.clone(), rows))
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+            });
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => {
+                self.set_inputs_paused(&base_domains, false)?;
+                return Err(e);
+            }
+        };
+
+        // make sure none of the domains we're about to (re-)create end up on this worker
+        info!(self.log, "draining worker {:?}", wi);
+        self.workers.get_mut(&wi).unwrap().draining = true;
+        if let Err(e) = self.drain_worker(&wi) {
+            // the worker stays, so it may host new domains again
+            self.workers.get_mut(&wi).unwrap().draining = false;
+            let paused: HashSet<_> = base_domains
+                .into_iter()
+                .filter(|di| self.domains.contains_key(di))
+                .collect();
+            self.set_inputs_paused(&paused, false)?;
+            return Err(e);
+        }
+
+        self.workers.remove(&wi);
+        self.read_addrs.remove(&wi);
+        self.decommissioned.insert(wi);
+
+        // the worker's domains are gone, so there is no going back if this fails
+        for (name, rows) in rows {
+            info!(self.log, "loading moved base table rows";
+                  "table" => &name, "rows" => rows.len());
+            self.load_base_rows(&name, rows)?;
+        }
+        info!(self.log, "decommissioned worker {:?}", wi);
+        Ok(())
+    }
+
+    /// Moves all nodes off the given (draining) worker, and shuts down its domains.
+    fn drain_worker(&mut self, wi: &WorkerIdentifier) -> Result<(), String> {
+        let affected_nodes = self.get_failed_nodes(wi);
+        self.rebuild_queries(affected_nodes)?;
+
+        let remaining = self.nodes_on_worker(Some(wi));
+        if !remaining.is_empty() {
+            return Err(format!(
+                "worker at {:?} still hosts nodes {:?} after draining",
+                wi, remaining
+            ));
+        }
+
+        // the worker's domains are all empty now, so we can shut them down
+        let empty: Vec<_> = self
+            .domains
+            .values()
+            .filter(|dh| dh.assigned_to_worker(wi))
+            .map(DomainHandle::index)
+            .collect();
+        for di in empty {
+            self.remove_domain(di)?;
+        }
+        Ok(())
+    }
+
+    /// Shuts down a domain that no longer hosts any nodes.
+    fn remove_domain(&mut self, di: DomainIndex) -> Result<(), String> {
+        let mut dh = self.domains.remove(&di).unwrap();
+        self.domain_nodes.remove(&di);
+        dh.send_to_healthy(Box::new(Packet::Quit), &self.workers)
+            .map_err(|e| format!("failed to stop domain {}: {:?}", di.index(), e))?;
+
+        for shard in 0..dh.shards() {
+            self.channel_coordinator.remove(&(di, shard));
+            for w in self.workers.values_mut() {
+                let src = w.sender.local_addr().unwrap();
+                w.sender
+                    .send(CoordinationMessage {
+                        epoch: self.epoch,
+                        source: src,
+                        payload: CoordinationPayload::RemoveDomain { domain: di, shard },
+                    })
+                    .map_err(|e| format!("failed to remove domain {}: {:?}", di.index(), e))?;
+            }
+        }
+        info!(self.log, "removed domain {}", di.index());
+        Ok(())
     }
 
     pub(super) fn handle_heartbeat(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
         match self.workers.get_mut(&msg.source) {
+            None if self.decommissioned.contains(&msg.source) => {
+                // the worker just hasn't shut down yet
+            }
             None => crit!(
                 self.log,
                 "got heartbeat for unknown worker {:?}",
@@ -446,6 +714,9 @@ impl ControllerInner {
 
         let mut recipe = Recipe::blank(Some(log.clone()));
         recipe.enable_reuse(state.config.reuse);
+        if let Some(ref config) = state.security_config {
+            recipe.set_security_config(config);
+        }
 
         ControllerInner {
             ingredients: g,
@@ -459,6 +730,13 @@ impl ControllerInner {
             heartbeat_every: state.config.heartbeat_every,
             healthcheck_every: state.config.healthcheck_every,
             recipe,
+            security_config: state.security_config,
+            change_feeds: state.change_feeds,
+            next_transaction: 0,
+            #[cfg(test)]
+            fail_sends_after: None,
+            #[cfg(test)]
+            abandon_next_transaction: false,
             quorum: state.config.quorum,
             log,
 
@@ -471,6 +749,7 @@ impl ControllerInner {
 
             read_addrs: HashMap::default(),
             workers: HashMap::default(),
+            decommissioned: HashSet::default(),
 
             pending_recovery,
             last_checked_workers: Instant::now(),
@@ -481,13 +760,15 @@ impl ControllerInner {
 
     /// Controls the persistence mode, and parameters related to persistence.
     ///
-    /// Three modes are available:
+    /// Four modes are available:
     ///
     ///  1. `DurabilityMode::Permanent`: all writes to base nodes should be written to disk.
     ///  2. `DurabilityMode::DeleteOnExit`: all writes are written to disk, but the log is
     ///     deleted once the `Controller` is dropped. Useful for tests.
     ///  3. `DurabilityMode::MemoryOnly`: no writes to disk, store all writes in memory.
     ///     Useful for baseline numbers.
+    ///  4. `DurabilityMode::MemoryWithLog`: store all writes in memory, but also append them to
+    ///     a log on disk that is replayed on restart.
     ///
     /// `queue_capacity` indicates the number of packets that should be buffered until
     /// flushing, and `flush_timeout` indicates the length of time to wait before flushing
@@ -542,7 +823,7 @@ impl ControllerInner {
 
             let (identifier, w) = loop {
                 if let Some((i, w)) = wi.next() {
-                    if w.healthy {
+                    if w.healthy && !w.draining {
                         break (*i, w);
                     }
                 } else {
@@ -702,6 +983,8 @@ impl ControllerInner {
     fn inputs(&self) -> BTreeMap<String, NodeIndex> {
         self.ingredients
             .neighbors_directed(self.source, petgraph::EdgeDirection::Outgoing)
+            // tables that have been removed (or moved) leave their old base nodes behind
+            .filter(|&n| !self.ingredients[n].is_dropped())
             .map(|n| {
                 let base = &self.ingredients[n];
                 assert!(base.is_base());
@@ -749,6 +1032,49 @@ impl ControllerInner {
         None
     }
 
+    /// All nodes that `node` reads through, up to (but excluding) the bases.
+    fn upstream_of(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut upstream = Vec::new();
+        let mut bfs = Bfs::new(Reversed(&self.ingredients), node);
+        while let Some(ni) = bfs.next(Reversed(&self.ingredients)) {
+            let n = &self.ingredients[ni];
+            if !n.is_source() && !n.is_base() {
+                upstream.push(ni);
+            }
+        }
+        upstream
+    }
+
+    /// `node`, along with all nodes upstream of it that no other (live) node depends on.
+    fn owned_by(&self, node: NodeIndex) -> HashSet<NodeIndex> {
+        let mut owned = HashSet::new();
+        owned.insert(node);
+        let mut stack = vec![node];
+        while let Some(ni) = stack.pop() {
+            for pi in self
+                .ingredients
+                .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+            {
+                let p = &self.ingredients[pi];
+                if owned.contains(&pi) || p.is_source() || p.is_base() {
+                    continue;
+                }
+                // a node is only owned once all of its children are, so we may end up checking it
+                // once for every child; the last one to become owned is the one that adds it.
+                if self
+                    .ingredients
+                    .neighbors_directed(pi, petgraph::EdgeDirection::Outgoing)
+                    .filter(|&ci| !self.ingredients[ci].is_dropped())
+                    .all(|ci| owned.contains(&ci))
+                {
+                    owned.insert(pi);
+                    stack.push(pi);
+                }
+            }
+        }
+        owned
+    }
+
     /// Obtain a `ViewBuilder` that can be sent to a client and then used to query a given
     /// (already maintained) reader node called `name`.
     fn view_builder(&self, name: &str) -> Option<ViewBuilder> {
@@ -771,6 +1097,9 @@ impl ControllerInner {
             let domain = self.ingredients[r].domain();
             let columns = self.ingredients[r].fields().to_vec();
             let schema = self.view_schema(r);
+            let order = self.ingredients[r]
+                .with_reader(|r| r.order().to_vec())
+                .unwrap_or_default();
             let shards = (0..self.domains[&domain].shards())
                 .map(|i| self.read_addrs[&self.domains[&domain].assignment(i)])
                 .collect();
@@ -779,6 +1108,7 @@ impl ControllerInner {
                 node: r,
                 columns,
                 schema,
+                order,
                 shards,
             }
         })
@@ -885,6 +1215,496 @@ impl ControllerInner {
         GraphStats { domains }
     }
 
+    fn enable_table_changes<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        table: String,
+    ) -> Result<(), String> {
+        let ni = *self
+            .inputs()
+            .get(&table)
+            .ok_or_else(|| format!("no base table named {}", table))?;
+        if self.persistence.mode == DurabilityMode::MemoryOnly {
+            // the feed could not be resumed across restarts
+            return Err("change feeds need base tables to be persisted".to_owned());
+        }
+
+        if !self.change_feeds.contains(&table) {
+            let persisted = authority.read_modify_write(
+                STATE_KEY,
+                |state: Option<ControllerState>| match state {
+                    None => unreachable!(),
+                    Some(ref state) if state.epoch > self.epoch => Err(()),
+                    Some(mut state) => {
+                        state.change_feeds.insert(table.clone());
+                        Ok(state)
+                    }
+                },
+            );
+            if let Ok(Ok(_)) = persisted {
+                self.change_feeds.insert(table.clone());
+            } else {
+                return Err(format!("failed to persist change feed of {}", table));
+            }
+        }
+        // shards that already keep a feed carry on with it
+        self.keep_changes(ni)
+            .map_err(|e| format!("failed to enable changes of {}: {}", table, e))
+    }
+
+    /// Have every shard of base node `ni` keep a change feed.
+    fn keep_changes(&mut self, ni: NodeIndex) -> Result<(), String> {
+        // domains that are (re)created later get the base as it is in the graph
+        self.ingredients[ni].get_base_mut().unwrap().keep_changes();
+
+        let node = self.ingredients[ni].local_addr();
+        let di = self.ingredients[ni].domain();
+        let packets = (0..self.domains[&di].shards())
+            .map(|_| Packet::EnableChanges { node })
+            .collect();
+        self.send_to_shards(di, packets).1
+    }
+
+    fn table_changes(
+        &mut self,
+        (table, from, limit): (String, ChangeOffset, usize),
+    ) -> Result<(Vec<Change>, ChangeOffset), String> {
+        let ni = *self
+            .inputs()
+            .get(&table)
+            .ok_or_else(|| format!("no base table named {}", table))?;
+        if !self.change_feeds.contains(&table) {
+            return Err(format!("changes are not enabled for table {}", table));
+        }
+        let node = self.ingredients[ni].local_addr();
+        let di = self.ingredients[ni].domain();
+
+        let shards = self.domains[&di].shards();
+        let packets = (0..shards)
+            .map(|shard| Packet::ReadChanges {
+                node,
+                from: from.shard(shard),
+                limit,
+            })
+            .collect();
+        let (reached, sent) = self.try_send_to_shards(di, packets);
+
+        // the shards we did reach will reply either way, and those replies must not be mistaken
+        // for replies to whatever we ask the domains next.
+        let replies = futures_executor::block_on(self.replies.wait_for_changes(reached));
+        sent.map_err(|e| format!("failed to read changes to {}: {}", table, e))?;
+
+        let mut changes = Vec::new();
+        let mut next = vec![None; shards];
+        for (shard, read) in replies {
+            let (read, continue_from) = read.map_err(|e| format!("shard {}: {}", shard, e))?;
+            changes.extend(read);
+            next[shard] = Some(continue_from);
+        }
+        changes.sort_by_key(|c| (c.shard, c.seq));
+        Ok((changes, ChangeOffset(next)))
+    }
+
+    /// Pause or resume the processing of inputs in the given domains.
+    fn set_inputs_paused(
+        &mut self,
+        domains: &HashSet<DomainIndex>,
+        paused: bool,
+    ) -> Result<(), String> {
+        for di in domains {
+            let p = if paused {
+                Packet::PauseInputs
+            } else {
+                Packet::ResumeInputs
+            };
+            let domain = self.domains.get_mut(di).unwrap();
+            domain
+                .send_to_healthy(Box::new(p), &self.workers)
+                .map_err(|e| format!("failed to reach domain {:?}: {:?}", di, e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(domain));
+        }
+        Ok(())
+    }
+
+    /// Have every shard of every base table take a copy of its rows.
+    fn copy_base_rows(&mut self, tables: &BTreeMap<String, NodeIndex>) -> Result<(), String> {
+        for (name, &ni) in tables {
+            let node = self.ingredients[ni].local_addr();
+            let di = self.ingredients[ni].domain();
+            let domain = self.domains.get_mut(&di).unwrap();
+            for shard in 0..domain.shards() {
+                domain
+                    .send_to_healthy_shard(
+                        shard,
+                        Box::new(Packet::CopyBaseRows { node }),
+                        &self.workers,
+                    )
+                    .map_err(|e| format!("failed to reach shard {} of {}: {:?}", shard, name, e))?;
+                futures_executor::block_on(self.replies.wait_for_base_rows())
+                    .map_err(|e| format!("shard {} of {}: {}", shard, name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the next chunk of the rows that `copy_base_rows` copied for the given shard of the
+    /// base table `ni`, with the columns that have been dropped from the table left out.
+    ///
+    /// Returns no rows once all of them have been read.
+    fn read_base_rows(
+        &mut self,
+        name: &str,
+        ni: NodeIndex,
+        shard: usize,
+    ) -> Result<Vec<Vec<DataType>>, String> {
+        let node = self.ingredients[ni].local_addr();
+        let di = self.ingredients[ni].domain();
+        let dropped = self.ingredients[ni].get_base().unwrap().get_dropped();
+
+        self.domains
+            .get_mut(&di)
+            .unwrap()
+            .send_to_healthy_shard(
+                shard,
+                Box::new(Packet::ReadBaseRows {
+                    node,
+                    limit: snapshot::CHUNK_ROWS,
+                }),
+                &self.workers,
+            )
+            .map_err(|e| format!("failed to reach shard {} of {}: {:?}", shard, name, e))?;
+        let rows = futures_executor::block_on(self.replies.wait_for_base_rows())
+            .map_err(|e| format!("shard {} of {}: {}", shard, name, e))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .enumerate()
+                    .filter(|&(col, _)| !dropped.contains_key(col))
+                    .map(|(_, v)| v)
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Write the rows that `copy_base_rows` copied for every shard of the base table `ni` to the
+    /// snapshot in `dir`, a chunk at a time.
+    ///
+    /// Returns the number of shards written.
+    fn write_base_rows(&mut self, dir: &Path, name: &str, ni: NodeIndex) -> Result<usize, String> {
+        let shards = self.domains[&self.ingredients[ni].domain()].shards();
+        for shard in 0..shards {
+            let mut w = snapshot::RowsWriter::create(dir, name, shard)
+                .map_err(|e| format!("failed to write rows of {}: {}", name, e))?;
+            loop {
+                let rows = self.read_base_rows(name, ni, shard)?;
+                if rows.is_empty() {
+                    break;
+                }
+                w.write(&rows)
+                    .map_err(|e| format!("failed to write rows of {}: {}", name, e))?;
+            }
+            w.finish()
+                .map_err(|e| format!("failed to write rows of {}: {}", name, e))?;
+        }
+        Ok(shards)
+    }
+
+    /// Read all the rows that `copy_base_rows` copied for every shard of the base table `ni`.
+    fn take_base_rows(&mut self, name: &str, ni: NodeIndex) -> Result<Vec<Vec<DataType>>, String> {
+        let shards = self.domains[&self.ingredients[ni].domain()].shards();
+        let mut all = Vec::new();
+        for shard in 0..shards {
+            loop {
+                let rows = self.read_base_rows(name, ni, shard)?;
+                if rows.is_empty() {
+                    break;
+                }
+                all.extend(rows);
+            }
+        }
+        Ok(all)
+    }
+
+    /// Insert `rows` into the base table called `name`, a chunk at a time.
+    fn load_base_rows(&mut self, name: &str, rows: Vec<Vec<DataType>>) -> Result<(), String> {
+        let ni = *self
+            .inputs()
+            .get(name)
+            .ok_or_else(|| format!("base table {} no longer exists", name))?;
+        let dst = self.ingredients[ni].local_addr();
+        let shards = self.domains[&self.ingredients[ni].domain()].shards();
+        let key = match self.ingredients[ni].sharded_by() {
+            Sharding::ByColumn(col, _) => Some(col),
+            _ => None,
+        };
+
+        let mut rows = rows.into_iter().peekable();
+        while rows.peek().is_some() {
+            let mut data = vec![Vec::new(); shards];
+            for row in rows.by_ref().take(snapshot::CHUNK_ROWS) {
+                let shard = key.map(|col| dataflow::shard_by(&row[col], shards));
+                data[shard.unwrap_or(0)].push(TableOperation::Insert(row));
+            }
+            let inputs = data
+                .into_iter()
+                .map(|data| Input {
+                    dst,
+                    data,
+                    bulk: false,
+                })
+                .collect();
+            self.transaction(vec![(ni, inputs)])
+                .map_err(|e| format!("failed to load rows into {}: {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    /// Apply the writes of a transaction, given as the input for each shard of each base table it
+    /// writes to, such that readers observe all of them at once.
+    ///
+    /// Every domain the writes flow through is told which upstream domain shards will tell it
+    /// once the writes have passed through them, and holds back the readers the writes reach until
+    /// all of them have. The writes are then applied, after which each base domain passes that on
+    /// downstream in line with the writes themselves.
+    ///
+    /// If any domain shard can't be reached along the way, every shard that was told about the
+    /// transaction is told to abort it, so that its readers stop holding back writes. Should the
+    /// controller itself fail part-way, the domains give up on the transaction once their
+    /// transaction timeout passes.
+    fn transaction(&mut self, writes: Vec<(NodeIndex, Vec<Input>)>) -> Result<(), String> {
+        let mut bases = Vec::new();
+        let mut inputs: HashMap<DomainIndex, Vec<Vec<Input>>> = HashMap::new();
+        for (ni, shards) in writes {
+            let n = match self.ingredients.node_weight(ni) {
+                Some(n) if n.is_base() && !n.is_dropped() => n,
+                _ => return Err(format!("{:?} is not a base table", ni)),
+            };
+            let nshards = self.domains[&n.domain()].shards();
+            if shards.len() != nshards {
+                return Err(format!(
+                    "got writes for {} shards of {}, which has {}",
+                    shards.len(),
+                    n.name(),
+                    nshards
+                ));
+            }
+
+            let domain = inputs
+                .entry(n.domain())
+                .or_insert_with(|| vec![Vec::new(); nshards]);
+            for (shard, input) in shards.into_iter().enumerate() {
+                domain[shard].push(input);
+            }
+            bases.push(ni);
+        }
+
+        // every domain shard the writes flow through, and where it will hear from that they have
+        let mut awaiting: HashMap<DomainIndex, Vec<HashSet<Option<(DomainIndex, usize)>>>> =
+            HashMap::new();
+        for (&di, shards) in &inputs {
+            let from_controller: HashSet<_> = vec![None].into_iter().collect();
+            awaiting.insert(di, vec![from_controller; shards.len()]);
+        }
+        // and the readers in each of those domains that the writes reach
+        let mut readers: HashMap<DomainIndex, HashSet<LocalNodeIndex>> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut stack = bases;
+        while let Some(ni) = stack.pop() {
+            if !seen.insert(ni) || self.ingredients[ni].is_dropped() {
+                continue;
+            }
+            stack.extend(
+                self.ingredients
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing),
+            );
+
+            let n = &self.ingredients[ni];
+            if n.is_reader() {
+                readers
+                    .entry(n.domain())
+                    .or_default()
+                    .insert(n.local_addr());
+            }
+            if !n.is_ingress() {
+                continue;
+            }
+            let shards = self.domains[&n.domain()].shards();
+            let domain = awaiting
+                .entry(n.domain())
+                .or_insert_with(|| vec![HashSet::new(); shards]);
+            for sender in self
+                .ingredients
+                .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+            {
+                let s = &self.ingredients[sender];
+                let sender_shards = self.domains[&s.domain()].shards();
+                for (shard, from) in domain.iter_mut().enumerate() {
+                    if s.is_sharder() || shards == 1 {
+                        // every shard of the sender sends to every shard of this domain
+                        from.extend((0..sender_shards).map(|i| Some((s.domain(), i))));
+                    } else {
+                        // sharded the same way, so each shard only hears from its counterpart
+                        from.insert(Some((s.domain(), shard)));
+                    }
+                }
+            }
+        }
+
+        let id = self.next_transaction;
+        self.next_transaction += 1;
+        let mut begun = Vec::new();
+        let mut result = Ok(());
+        for (di, shards) in awaiting {
+            let readers = readers.remove(&di).unwrap_or_default();
+            let packets = shards
+                .into_iter()
+                .map(|awaiting| Packet::BeginTransaction {
+                    id,
+                    awaiting,
+                    readers: readers.clone(),
+                })
+                .collect();
+            let (reached, sent) = self.send_to_shards(di, packets);
+            begun.push((di, reached));
+            if let Err(e) = sent {
+                result = Err(format!("transaction aborted: {}", e));
+                break;
+            }
+        }
+        #[cfg(test)]
+        {
+            if result.is_ok() && self.abandon_next_transaction {
+                // as if the controller failed before the writes were applied
+                self.abandon_next_transaction = false;
+                return Ok(());
+            }
+        }
+        if result.is_ok() {
+            for (di, shards) in inputs {
+                let packets = shards
+                    .into_iter()
+                    .map(|inputs| Packet::CommitTransaction { id, inputs })
+                    .collect();
+                if let (_, Err(e)) = self.send_to_shards(di, packets) {
+                    result = Err(format!(
+                        "transaction aborted, and may have been partially applied: {}",
+                        e
+                    ));
+                    break;
+                }
+            }
+        }
+
+        if result.is_err() {
+            // don't leave readers holding back writes for a transaction that won't complete
+            for (di, reached) in begun {
+                let packets = (0..reached)
+                    .map(|_| Packet::AbortTransaction { id })
+                    .collect();
+                if let (_, Err(e)) = self.send_to_shards(di, packets) {
+                    error!(self.log, "failed to abort transaction {}: {}", id, e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Send the `i`th of `packets` to the `i`th shard of domain `di`, stopping at the first shard
+    /// that can't be reached, and wait for every shard that was reached to ack.
+    ///
+    /// Returns how many shards were reached, and whether that was all of them.
+    fn send_to_shards(
+        &mut self,
+        di: DomainIndex,
+        packets: Vec<Packet>,
+    ) -> (usize, Result<(), String>) {
+        let (reached, result) = self.try_send_to_shards(di, packets);
+        futures_executor::block_on(self.replies.wait_for_n_acks(reached));
+        (reached, result)
+    }
+
+    /// Like `send_to_shards`, but leaves it to the caller to collect the replies of the shards
+    /// that were reached.
+    fn try_send_to_shards(
+        &mut self,
+        di: DomainIndex,
+        packets: Vec<Packet>,
+    ) -> (usize, Result<(), String>) {
+        let domain = self.domains.get_mut(&di).unwrap();
+        let mut reached = 0;
+        let mut result = Ok(());
+        for (shard, p) in packets.into_iter().enumerate() {
+            #[cfg(test)]
+            {
+                if self.fail_sends_after == Some(0) {
+                    self.fail_sends_after = None;
+                    result = Err(format!(
+                        "failed to reach shard {} of domain {:?}",
+                        shard, di
+                    ));
+                    break;
+                }
+                if let Some(ref mut n) = self.fail_sends_after {
+                    *n -= 1;
+                }
+            }
+            if let Err(e) = domain.send_to_healthy_shard(shard, Box::new(p), &self.workers) {
+                result = Err(format!(
+                    "failed to reach shard {} of domain {:?}: {:?}",
+                    shard, di, e
+                ));
+                break;
+            }
+            reached += 1;
+        }
+        (reached, result)
+    }
+
+    /// Write a snapshot of the recipe, the security configuration, and the contents of every base
+    /// table to the directory `path` on the controller's machine.
+    ///
+    /// Inputs to all base tables are paused while each of their shards takes an in-memory copy of
+    /// its rows, and writes are not acknowledged while paused. So, if the snapshot includes a
+    /// write, it also includes every write that was acknowledged before that write was issued.
+    /// Inputs resume once the copies are taken, and the copies are then read out and written to
+    /// disk a chunk at a time.
+    fn snapshot<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        path: String,
+    ) -> Result<(), String> {
+        let state = read_state(authority)?;
+
+        let tables = self.inputs();
+        let base_domains: HashSet<_> = tables
+            .values()
+            .map(|&ni| self.ingredients[ni].domain())
+            .collect();
+
+        info!(self.log, "taking snapshot"; "path" => &path, "tables" => tables.len());
+        let copied = self
+            .set_inputs_paused(&base_domains, true)
+            .and_then(|_| self.copy_base_rows(&tables));
+        // we resume even if pausing failed part-way, as some domains may have been paused
+        self.set_inputs_paused(&base_domains, false)?;
+        copied?;
+
+        let dir = Path::new(&path);
+        let mut manifest = snapshot::Manifest {
+            recipes: state.recipes,
+            security_config: self.security_config.clone(),
+            tables: Vec::with_capacity(tables.len()),
+        };
+        for (name, ni) in tables {
+            let shards = self.write_base_rows(dir, &name, ni)?;
+            manifest.tables.push((name, shards));
+        }
+        snapshot::write_manifest(dir, &manifest)
+            .map_err(|e| format!("failed to write snapshot manifest: {}", e))
+    }
+
     fn get_instances(&self) -> Vec<(WorkerIdentifier, bool, Duration)> {
         self.workers
             .iter()
@@ -1001,9 +1821,27 @@ impl ControllerInner {
         Ok(())
     }
 
-    fn set_security_config(&mut self, p: String) -> Result<(), String> {
-        self.recipe.set_security_config(&p);
-        Ok(())
+    fn set_security_config<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        p: String,
+    ) -> Result<(), String> {
+        let persisted =
+            authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.security_config = Some(p.clone());
+                    Ok(state)
+                }
+            });
+        if let Ok(Ok(_)) = persisted {
+            self.recipe.set_security_config(&p);
+            self.security_config = Some(p);
+            Ok(())
+        } else {
+            Err("failed to persist security configuration".to_owned())
+        }
     }
 
     fn apply_recipe(&mut self, mut new: Recipe) -> Result<ActivationResult, String> {
@@ -1014,6 +1852,9 @@ impl ControllerInner {
 
         match r {
             Ok(ref ra) => {
+                // the new recipe knows which leaves remain in use, which node removal relies on
+                self.recipe = new;
+
                 let (removed_bases, removed_other): (Vec<_>, Vec<_>) = ra
                     .removed_leaves
                     .iter()
@@ -1054,7 +1895,7 @@ impl ControllerInner {
                     self.remove_nodes(vec![base].as_slice()).unwrap();
                 }
 
-                self.recipe = new;
+                self.apply_memory_budgets();
             }
             Err(ref e) => {
                 crit!(self.log, "failed to apply recipe: {}", e);
@@ -1067,6 +1908,86 @@ impl ControllerInner {
         r
     }
 
+    /// Bring the memory budgets of the nodes in the graph in line with the budgets that the
+    /// queries in the current recipe have been annotated with.
+    ///
+    /// Pinning a query pins every partially materialized node it reads through, since evicting
+    /// from any of them would also evict from the query's view. A byte budget is split evenly
+    /// across the query's reader and the partial materializations that no other query depends on.
+    fn apply_memory_budgets(&mut self) {
+        let mut budgets: HashMap<NodeIndex, MemoryBudget> = HashMap::new();
+        for (name, &budget) in self.recipe.memory_budgets() {
+            let reader = self.recipe.node_addr_for(name).ok().and_then(|leaf| {
+                let name = self.recipe.resolve_alias(name).unwrap_or(name);
+                self.find_view_for(leaf, name)
+            });
+            let reader = match reader {
+                Some(reader) => reader,
+                None => {
+                    warn!(self.log, "no view to apply memory budget to"; "query" => name);
+                    continue;
+                }
+            };
+
+            let nodes: Vec<_> = match budget {
+                MemoryBudget::Shared => continue,
+                MemoryBudget::Pinned => self.upstream_of(reader),
+                MemoryBudget::Bytes(_) => self.owned_by(reader).into_iter().collect(),
+            };
+            let nodes: Vec<_> = nodes
+                .into_iter()
+                .filter(
+                    |&ni| match self.materializations.get_status(ni, &self.ingredients[ni]) {
+                        MaterializationStatus::Partial { .. } => true,
+                        _ => false,
+                    },
+                )
+                .collect();
+
+            for &ni in &nodes {
+                let budget = match budget {
+                    MemoryBudget::Bytes(bytes) => {
+                        let shards = self.ingredients[ni].sharded_by().shards().unwrap_or(1);
+                        MemoryBudget::Bytes(bytes / nodes.len() / shards)
+                    }
+                    budget => budget,
+                };
+                // nodes that several queries read through get the most generous budget
+                let b = budgets.entry(ni).or_insert(budget);
+                *b = match (*b, budget) {
+                    (MemoryBudget::Bytes(b1), MemoryBudget::Bytes(b2)) => {
+                        MemoryBudget::Bytes(std::cmp::max(b1, b2))
+                    }
+                    _ => MemoryBudget::Pinned,
+                };
+            }
+        }
+
+        for ni in self.ingredients.node_indices() {
+            let n = &self.ingredients[ni];
+            if n.is_source() || n.is_dropped() {
+                continue;
+            }
+            let budget = budgets.get(&ni).cloned().unwrap_or_default();
+            if n.memory_budget == budget {
+                continue;
+            }
+
+            info!(self.log, "updating memory budget";
+                  "node" => ni.index(), "budget" => ?budget);
+            let domain = n.domain();
+            let node = n.local_addr();
+            self.ingredients[ni].memory_budget = budget;
+            if let Err(e) = self.domains.get_mut(&domain).unwrap().send_to_healthy(
+                Box::new(Packet::UpdateMemoryBudget { node, budget }),
+                &self.workers,
+            ) {
+                warn!(self.log, "failed to update memory budget: {:?}", e;
+                      "node" => ni.index());
+            }
+        }
+    }
+
     fn extend_recipe<A: Authority + 'static>(
         &mut self,
         authority: &Arc<A>,
@@ -1077,19 +1998,25 @@ impl ControllerInner {
         match new.extend(&add_txt) {
             Ok(new) => {
                 let activation_result = self.apply_recipe(new);
-                if authority
-                    .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| {
+                    match state {
                         None => unreachable!(),
                         Some(ref state) if state.epoch > self.epoch => Err(()),
                         Some(mut state) => {
                             state.recipe_version = self.recipe.version();
                             state.recipes.push(add_txt.clone());
+                            if activation_result.is_ok() {
+                                state.keep_recipe_version();
+                            }
                             Ok(state)
                         }
-                    })
-                    .is_err()
-                {
-                    return Err("Failed to persist recipe extension".to_owned());
+                    }
+                }) {
+                    Err(_) => return Err("Failed to persist recipe extension".to_owned()),
+                    Ok(Ok(state)) if activation_result.is_ok() => {
+                        state.write_recipe_version(&**authority)?
+                    }
+                    Ok(_) => {}
                 }
 
                 activation_result
@@ -1098,7 +2025,7 @@ impl ControllerInner {
                 // need to restore the old recipe
                 crit!(self.log, "failed to extend recipe: {:?}", e);
                 self.recipe = old;
-                Err("failed to extend recipe".to_owned())
+                Err(format!("failed to extend recipe: {}", e))
             }
         }
     }
@@ -1113,117 +2040,360 @@ impl ControllerInner {
                 let old = mem::replace(&mut self.recipe, Recipe::blank(None));
                 let new = old.replace(r).unwrap();
                 let activation_result = self.apply_recipe(new);
-                if authority
-                    .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| {
+                    match state {
                         None => unreachable!(),
                         Some(ref state) if state.epoch > self.epoch => Err(()),
                         Some(mut state) => {
                             state.recipe_version = self.recipe.version();
                             state.recipes = vec![r_txt.clone()];
+                            if activation_result.is_ok() {
+                                state.keep_recipe_version();
+                            }
                             Ok(state)
                         }
-                    })
-                    .is_err()
-                {
-                    return Err("Failed to persist recipe installation".to_owned());
+                    }
+                }) {
+                    Err(_) => return Err("Failed to persist recipe installation".to_owned()),
+                    Ok(Ok(state)) if activation_result.is_ok() => {
+                        state.write_recipe_version(&**authority)?
+                    }
+                    Ok(_) => {}
                 }
                 activation_result
             }
             Err(e) => {
                 crit!(self.log, "failed to parse recipe: {:?}", e);
-                Err("failed to parse recipe".to_owned())
+                Err(format!("failed to parse recipe: {}", e))
             }
         }
     }
 
+    /// Works out what installing (if `replace` is set) or extending the current recipe with
+    /// `r_txt` would do to the data-flow graph, without applying the change.
+    ///
+    /// The change is activated against a copy of the recipe in a `Migration` that is never
+    /// committed, and the nodes it adds to the graph are removed again before returning.
+    fn plan_recipe(&mut self, (r_txt, replace): (String, bool)) -> Result<RecipePlan, String> {
+        let current = self.recipe.deep_clone();
+        let mut new = if replace {
+            let r = Recipe::from_str(&r_txt, Some(self.log.clone()))
+                .map_err(|e| format!("failed to parse recipe: {}", e))?;
+            current.replace(r)?
+        } else {
+            current
+                .extend(&r_txt)
+                .map_err(|(_, e)| format!("failed to extend recipe: {}", e))?
+        };
+
+        // activate the new recipe against copies of the graph and its materializations, so that
+        // the running graph is left exactly as it was
+        let planning = self.ingredients.clone();
+        let ingredients = mem::replace(&mut self.ingredients, planning);
+        let planning = self.materializations.clone();
+        let materializations = mem::replace(&mut self.materializations, planning);
+        let nnodes = ingredients.node_count();
+
+        info!(self.log, "planning migration");
+        let miglog = self.log.new(o!());
+        let mut m = Migration {
+            mainline: self,
+            added: Default::default(),
+            columns: Default::default(),
+            readers: Default::default(),
+            context: Default::default(),
+            start: time::Instant::now(),
+            log: miglog,
+        };
+        let r = new.activate(&mut m);
+        let added = m.added;
+        let planned = mem::replace(&mut self.materializations, materializations);
+
+        let plan = r.map(|ra| {
+            let (new_materializations, new_indices) =
+                planned.plan(&self.materializations, &self.ingredients, &added);
+
+            let describe = |ni: NodeIndex| {
+                let n = &self.ingredients[ni];
+                let description = if n.is_internal() {
+                    n.description(true)
+                } else if n.is_base() {
+                    "Base table".to_owned()
+                } else if n.is_reader() {
+                    "Leaf view".to_owned()
+                } else {
+                    n.name().to_owned()
+                };
+                PlannedNode {
+                    node: ni,
+                    name: n.name().to_owned(),
+                    description,
+                }
+            };
+
+            let mut new_nodes: Vec<_> = added.iter().cloned().collect();
+            new_nodes.sort();
+
+            // existing nodes that new nodes hang off, and queries that were entirely reused
+            let mut reused: HashSet<_> = new_nodes
+                .iter()
+                .flat_map(|&ni| {
+                    self.ingredients
+                        .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                })
+                .filter(|&ni| ni.index() < nnodes && !self.ingredients[ni].is_source())
+                .collect();
+            reused.extend(
+                ra.new_nodes
+                    .values()
+                    .filter(|ni| ni.index() < nnodes)
+                    .cloned(),
+            );
+            let mut reused_nodes: Vec<_> = reused.into_iter().collect();
+            reused_nodes.sort();
+
+            // same as in `apply_recipe`: query leaves in reverse topological order, then bases
+            let (removed_bases, removed_other): (Vec<_>, Vec<_>) = ra
+                .removed_leaves
+                .iter()
+                .cloned()
+                .partition(|ni| self.ingredients[*ni].is_base());
+            let mut topo_removals = Vec::with_capacity(removed_other.len());
+            let mut topo = petgraph::visit::Topo::new(&self.ingredients);
+            while let Some(node) = topo.next(&self.ingredients) {
+                if removed_other.contains(&node) {
+                    topo_removals.push(node);
+                }
+            }
+            topo_removals.reverse();
+
+            let mut removed = HashSet::default();
+            for leaf in topo_removals {
+                self.leaf_removals(leaf, &new, &mut removed);
+            }
+            removed.extend(removed_bases);
+            let mut removed_nodes: Vec<_> = removed.into_iter().collect();
+            removed_nodes.sort();
+
+            RecipePlan {
+                new_nodes: new_nodes.into_iter().map(describe).collect(),
+                reused_nodes: reused_nodes.into_iter().map(describe).collect(),
+                removed_nodes: removed_nodes.into_iter().map(describe).collect(),
+                new_materializations,
+                new_indices,
+            }
+        });
+
+        self.ingredients = ingredients;
+
+        let mut plan = plan.map_err(|e| format!("failed to activate recipe: {}", e))?;
+        self.estimate_materializations(&mut plan.new_materializations);
+        Ok(plan)
+    }
+
+    /// Fills in how much state each planned materialization would be filled with, from the current
+    /// sizes of the ancestors it would be replayed from.
+    fn estimate_materializations(&mut self, materializations: &mut [PlannedMaterialization]) {
+        if materializations.iter().all(|m| m.replay_from.is_empty()) {
+            // only partial materializations, which start out empty
+            return;
+        }
+
+        let mut sizes: HashMap<NodeIndex, (u64, u64)> = HashMap::new();
+        for (_, (_, nodes)) in self.get_statistics().domains {
+            for (ni, stats) in nodes {
+                let size = sizes.entry(ni).or_default();
+                size.0 += stats.rows;
+                size.1 += stats.mem_size;
+            }
+        }
+
+        // materializations are in node order, so any new ancestor that a materialization is
+        // replayed from has already been estimated
+        for m in materializations {
+            for pi in &m.replay_from {
+                if let Some(&(rows, bytes)) = sizes.get(pi) {
+                    m.estimated_rows += rows;
+                    m.estimated_bytes += bytes;
+                }
+            }
+            sizes.insert(m.node, (m.estimated_rows, m.estimated_bytes));
+        }
+    }
+
+    fn recipe_versions<A: Authority + 'static>(
+        &self,
+        authority: &Arc<A>,
+    ) -> Result<Vec<RecipeVersion>, String> {
+        let state = read_state(authority)?;
+        // a version whose key has not been written yet is skipped
+        Ok(state
+            .recipe_versions
+            .iter()
+            .filter_map(|&v| state.recipe_at(&**authority, v).ok())
+            .collect())
+    }
+
+    fn diff_recipes<A: Authority + 'static>(
+        &self,
+        authority: &Arc<A>,
+        (from, to): (usize, usize),
+    ) -> Result<RecipeDiff, String> {
+        let state = read_state(authority)?;
+        let parse = |version| {
+            Recipe::from_str(&state.recipe_at(&**authority, version)?.recipe, None)
+                .map_err(|e| format!("failed to parse recipe version {}: {}", version, e))
+        };
+        let (added, removed) = parse(to)?.diff(&parse(from)?);
+        Ok(RecipeDiff { added, removed })
+    }
+
+    /// Installs the recipe that was activated as `version` again.
+    fn rollback_recipe<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        version: usize,
+    ) -> Result<ActivationResult, String> {
+        let r_txt = read_state(authority)?
+            .recipe_at(&**authority, version)?
+            .recipe;
+        info!(self.log, "rolling back recipe"; "to" => version);
+        self.install_recipe(authority, r_txt)
+    }
+
     fn graphviz(&self, detailed: bool) -> String {
         graphviz(&self.ingredients, detailed, &self.materializations)
     }
 
-    fn remove_leaf(&mut self, mut leaf: NodeIndex) -> Result<(), String> {
+    /// Works out which nodes removing the query leaf `leaf` tears down, given the `recipe` that
+    /// remains, and the nodes that are `removed` already. Also returns the edges from egress nodes
+    /// that stay to ingress nodes that go.
+    fn leaf_removals(
+        &self,
+        mut leaf: NodeIndex,
+        recipe: &Recipe,
+        removed: &mut HashSet<NodeIndex>,
+    ) -> (Vec<NodeIndex>, Vec<(NodeIndex, NodeIndex)>) {
         let mut removals = vec![];
         let start = leaf;
         assert!(!self.ingredients[leaf].is_source());
 
-        info!(
-            self.log,
-            "Computing removals for removing node {}",
-            leaf.index()
-        );
+        let nchildren = |ni: NodeIndex, removed: &HashSet<NodeIndex>| {
+            self.ingredients
+                .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                .filter(|child| !removed.contains(child))
+                .count()
+        };
 
-        let nchildren = self
-            .ingredients
-            .neighbors_directed(leaf, petgraph::EdgeDirection::Outgoing)
-            .count();
-        if nchildren > 0 {
+        if nchildren(leaf, removed) > 0 {
             // This query leaf node has children -- typically, these are readers, but they can also
             // include egress nodes or other, dependent queries. We need to find the actual reader,
-            // and remove that.
-            if nchildren != 1 {
-                crit!(
-                    self.log,
-                    "cannot remove node {}, as it still has multiple children",
-                    leaf.index()
-                );
-                unreachable!();
-            }
-
+            // and remove that. Any dependent queries keep the leaf itself alive.
             let mut readers = Vec::new();
             let mut bfs = Bfs::new(&self.ingredients, leaf);
             while let Some(child) = bfs.next(&self.ingredients) {
                 let n = &self.ingredients[child];
-                if n.with_reader(|r| r.is_for() == leaf) == Ok(true) {
+                if n.with_reader(|r| r.is_for() == leaf) == Ok(true) && !removed.contains(&child) {
                     readers.push(child);
                 }
             }
 
             // nodes can have only one reader attached
-            assert_eq!(readers.len(), 1);
-            let reader = readers[0];
+            assert!(readers.len() <= 1);
+            let reader = match readers.pop() {
+                Some(reader) => reader,
+                None => {
+                    // the leaf has no reader, and its children are all other queries' nodes
+                    debug!(
+                        self.log,
+                        "Keeping query leaf \"{}\" for dependent queries",
+                        self.ingredients[leaf].name();
+                        "node" => leaf.index(),
+                    );
+                    return (removals, vec![]);
+                }
+            };
             debug!(
                 self.log,
                 "Removing query leaf \"{}\"", self.ingredients[leaf].name();
                 "node" => leaf.index(),
                 "really" => reader.index(),
             );
-            removals.push(reader);
             leaf = reader;
         }
 
         // `node` now does not have any children any more
-        assert_eq!(
-            self.ingredients
-                .neighbors_directed(leaf, petgraph::EdgeDirection::Outgoing)
-                .count(),
-            0
-        );
+        assert_eq!(nchildren(leaf, removed), 0);
 
+        let mut egress_removals = vec![];
         let mut nodes = vec![leaf];
+        removed.insert(leaf);
         while let Some(node) = nodes.pop() {
-            let mut parents = self
+            for parent in self
                 .ingredients
                 .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
-                .detach();
-            while let Some(parent) = parents.next_node(&self.ingredients) {
-                let edge = self.ingredients.find_edge(parent, node).unwrap();
-                self.ingredients.remove_edge(edge);
+            {
+                if removed.contains(&parent) {
+                    continue;
+                }
 
                 if !self.ingredients[parent].is_source()
                     && !self.ingredients[parent].is_base()
                     // ok to remove original start leaf
-                    && (parent == start || !self.recipe.sql_inc().is_leaf_address(parent))
-                    && self
-                        .ingredients
-                        .neighbors_directed(parent, petgraph::EdgeDirection::Outgoing)
-                        .count() == 0
+                    && (parent == start || !recipe.sql_inc().is_leaf_address(parent))
+                    && nchildren(parent, removed) == 0
                 {
+                    removed.insert(parent);
                     nodes.push(parent);
+                } else if self.ingredients[parent].is_egress() {
+                    // the egress node stays, but must stop sending to the ingress we're removing
+                    egress_removals.push((parent, node));
                 }
             }
 
             removals.push(node);
         }
 
+        // egress nodes may have lost their last child only after we came across them
+        egress_removals.retain(|(egress, _)| !removed.contains(egress));
+        (removals, egress_removals)
+    }
+
+    fn remove_leaf(&mut self, leaf: NodeIndex) -> Result<(), String> {
+        info!(
+            self.log,
+            "Computing removals for removing node {}",
+            leaf.index()
+        );
+
+        let (removals, egress_removals) =
+            self.leaf_removals(leaf, &self.recipe, &mut HashSet::default());
+
+        for &node in &removals {
+            let parents: Vec<_> = self
+                .ingredients
+                .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
+                .collect();
+            for parent in parents {
+                while let Some(edge) = self.ingredients.find_edge(parent, node) {
+                    self.ingredients.remove_edge(edge);
+                }
+            }
+        }
+
+        for (egress, ingress) in egress_removals {
+            let domain = self.ingredients[egress].domain();
+            let node = self.ingredients[egress].local_addr();
+            self.domains
+                .get_mut(&domain)
+                .unwrap()
+                .send_to_healthy(
+                    Box::new(Packet::RemoveEgressTx { node, dst: ingress }),
+                    &self.workers,
+                )
+                .map_err(|e| format!("failed to update egress {}: {:?}", egress.index(), e))?;
+        }
+
         self.remove_nodes(removals.as_slice())
     }
 
@@ -1232,6 +2402,7 @@ impl ControllerInner {
         let mut domain_removals: HashMap<DomainIndex, Vec<LocalNodeIndex>> = HashMap::default();
         for ni in removals {
             self.ingredients[*ni].remove();
+            self.materializations.remove(*ni);
             debug!(self.log, "Removed node {}", ni.index());
             domain_removals
                 .entry(self.ingredients[*ni].domain())
@@ -1240,6 +2411,7 @@ impl ControllerInner {
         }
 
         // Send messages to domains
+        let domains: Vec<_> = domain_removals.keys().cloned().collect();
         for (domain, nodes) in domain_removals {
             trace!(
                 self.log,
@@ -1271,6 +2443,16 @@ impl ControllerInner {
             }
         }
 
+        // shut down the domains that no longer host any nodes
+        for domain in domains {
+            let empty = self.domain_nodes.get(&domain).map_or(false, |nodes| {
+                nodes.iter().all(|&ni| self.ingredients[ni].is_dropped())
+            });
+            if empty {
+                self.remove_domain(domain)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -130,9 +130,9 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
             let mut assignment = None;
             for &(_, ref p) in &parents {
                 if p.is_sharder() {
-                    // we're a child of a sharder (which currently has to be unsharded). we
-                    // can't be in the same domain as the sharder (because we're starting a new
-                    // sharding)
+                    // we're a child of a sharder (which has to be unsharded, since sharded
+                    // sharders always feed a shard merger). we can't be in the same domain as the
+                    // sharder (because we're starting a new sharding)
                     assert!(p.sharded_by().is_none());
                 } else if p.is_source() {
                     // the source isn't a useful source of truth
@@ -59,6 +59,23 @@ pub(in crate::controller) struct Materializations {
     tag_generator: AtomicUsize,
 }
 
+impl Clone for Materializations {
+    fn clone(&self) -> Self {
+        Materializations {
+            log: self.log.clone(),
+
+            have: self.have.clone(),
+            added: self.added.clone(),
+
+            partial: self.partial.clone(),
+            partial_enabled: self.partial_enabled,
+            frontier_strategy: self.frontier_strategy.clone(),
+
+            tag_generator: AtomicUsize::new(self.tag_generator.load(Ordering::SeqCst)),
+        }
+    }
+}
+
 impl Materializations {
     /// Create a new set of materializations.
     pub(in crate::controller) fn new(logger: &Logger) -> Self {
@@ -93,6 +110,86 @@ impl Materializations {
 }
 
 impl Materializations {
+    /// Add an index on the given columns to an existing, fully materialized node.
+    ///
+    /// The index is built when the next migration is committed.
+    pub(in crate::controller) fn add_index(&mut self, ni: NodeIndex, columns: Vec<usize>) {
+        assert!(!self.partial.contains(&ni));
+        if self.have.entry(ni).or_default().insert(columns.clone()) {
+            self.added.entry(ni).or_default().insert(columns);
+        }
+    }
+
+    /// Works out which materializations committing a migration that added the nodes in `new` would
+    /// add to `current`.
+    ///
+    /// `self` must be a copy of `current` that the migration was planned against, so that it also
+    /// holds any indices the migration added to existing nodes. Returns the new materializations,
+    /// and the indices that would be added to existing ones.
+    pub(in crate::controller) fn plan(
+        mut self,
+        current: &Materializations,
+        graph: &Graph,
+        new: &HashSet<NodeIndex>,
+    ) -> (
+        Vec<noria::PlannedMaterialization>,
+        Vec<(NodeIndex, Vec<usize>)>,
+    ) {
+        self.extend(graph, new);
+
+        let mut materializations = Vec::new();
+        let mut new_indices = Vec::new();
+        for (&ni, cols) in &self.added {
+            let mut cols: Vec<_> = cols.iter().cloned().collect();
+            cols.sort();
+            if current.have.contains_key(&ni) {
+                new_indices.extend(cols.into_iter().map(|cols| (ni, cols)));
+                continue;
+            }
+
+            // full materializations are filled from their nearest materialized ancestors
+            let mut replay_from = Vec::new();
+            if !self.partial.contains(&ni) {
+                let mut visited = HashSet::new();
+                let mut frontier: Vec<_> = graph
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                    .collect();
+                while let Some(pi) = frontier.pop() {
+                    if !visited.insert(pi) || graph[pi].is_source() {
+                        continue;
+                    }
+                    if self.have.contains_key(&pi) {
+                        replay_from.push(pi);
+                    } else {
+                        frontier.extend(
+                            graph.neighbors_directed(pi, petgraph::EdgeDirection::Incoming),
+                        );
+                    }
+                }
+                replay_from.sort();
+            }
+
+            materializations.push(noria::PlannedMaterialization {
+                node: ni,
+                indices: cols,
+                partial: self.partial.contains(&ni),
+                replay_from,
+                estimated_rows: 0,
+                estimated_bytes: 0,
+            });
+        }
+        materializations.sort_by_key(|m| m.node);
+        new_indices.sort();
+        (materializations, new_indices)
+    }
+
+    /// Forget about the materialization of a node that has been removed.
+    pub(in crate::controller) fn remove(&mut self, ni: NodeIndex) {
+        self.have.remove(&ni);
+        self.added.remove(&ni);
+        self.partial.remove(&ni);
+    }
+
     fn next_tag(&self) -> Tag {
         Tag::new(self.tag_generator.fetch_add(1, Ordering::SeqCst) as u32)
     }
@@ -238,6 +335,12 @@ impl Materializations {
         // it and the nearest full materialization (because the intermediate ones haven't been
         // marked as materialized yet).
         for (ni, mut indices) in lookup_obligations {
+            // some operators need to see all of a parent's rows, which they can't through
+            // query-through operators.
+            let must_materialize = graph
+                .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                .any(|c| graph[c].is_internal() && graph[c].must_materialize_parent(ni));
+
             // we want to find the closest materialization that allows lookups (i.e., counting
             // query-through operators).
             let mut mi = ni;
@@ -246,7 +349,7 @@ impl Materializations {
                 if self.have.contains_key(&mi) {
                     break;
                 }
-                if !m.is_internal() || !m.can_query_through() {
+                if !m.is_internal() || !m.can_query_through() || (must_materialize && mi == ni) {
                     break;
                 }
 
@@ -423,6 +526,26 @@ impl Materializations {
                     !graph[ni].purge,
                     "full materialization placed beyond materialization frontier"
                 );
+
+                // some operators need more indexes when they are full. all of them are on our
+                // ancestors, which we have yet to walk, so we can just add them as obligations.
+                if graph[ni].is_internal() {
+                    for (mi, columns) in graph[ni].suggest_indexes_if_full(ni) {
+                        if self.have.entry(mi).or_default().insert(columns.clone()) {
+                            info!(self.log,
+                                "adding lookup index to view for full materialization";
+                                "node" => ni.index(),
+                                "on" => mi.index(),
+                                "columns" => ?columns,
+                            );
+                            replay_obligations
+                                .entry(mi)
+                                .or_default()
+                                .insert(columns.clone());
+                            self.added.entry(mi).or_default().insert(columns);
+                        }
+                    }
+                }
             }
 
             // no matter what happens, we're going to have to fulfill our replay obligations.
@@ -390,8 +390,8 @@ impl<'a> Plan<'a> {
                                 //
                                 // if we are sharded:
                                 //
-                                //  - if there is a shuffle above us, a shard merger + sharder
-                                //    above us will ensure that we hear the replay response.
+                                //  - if there is a shuffle above us, the shard merger below its
+                                //    sharder will ensure that we hear the replay response.
                                 //
                                 //  - if there is not, we are sharded by the same column as the
                                 //    source. this also means that the replay key in the
@@ -225,6 +225,29 @@ impl<'a> Migration<'a> {
         self.columns.push((node, ColumnChange::Drop(column)));
     }
 
+    /// Rename a column of a base node.
+    // crate viz for tests
+    pub fn rename_column<S: ToString>(&mut self, node: NodeIndex, column: usize, field: S) {
+        // not allowed to rename columns of new nodes
+        assert!(!self.added.contains(&node));
+
+        let base = &mut self.mainline.ingredients[node];
+        assert!(base.is_base());
+
+        // domains address columns by index, so only the graph needs to know the new name
+        base.rename_column(column, &field.to_string());
+    }
+
+    /// Add an index on the given columns to a base node.
+    ///
+    /// The index is built from the rows the base already holds when the migration is committed.
+    // crate viz for tests
+    pub fn add_index(&mut self, node: NodeIndex, columns: Vec<usize>) {
+        assert!(self.mainline.ingredients[node].is_base());
+
+        self.mainline.materializations.add_index(node, columns);
+    }
+
     #[cfg(test)]
     pub(crate) fn graph(&self) -> &Graph {
         self.mainline.graph()
@@ -277,6 +300,17 @@ impl<'a> Migration<'a> {
             .unwrap();
     }
 
+    /// Set the order in which the rows for each key of the given node's reader are paginated.
+    ///
+    /// The node must already be maintained.
+    pub fn maintain_order(&mut self, n: NodeIndex, order: Vec<(usize, nom_sql::OrderType)>) {
+        let ri = self.readers[&n];
+
+        self.mainline.ingredients[ri]
+            .with_reader_mut(|r| r.set_order(order))
+            .unwrap();
+    }
+
     /// Commit the changes introduced by this `Migration` to the master `Soup`.
     ///
     /// This will spin up an execution thread for each new thread domain, and hook those new
@@ -579,30 +579,43 @@ pub fn shard(
         }
     }
 
-    // and finally, because we don't *currently* support sharded shuffles (i.e., going directly
-    // from one sharding to another), we replace such patterns with a merge + a shuffle. the merge
-    // will ensure that replays from the first sharding are turned into a single update before
-    // arriving at the second sharding, and the merged sharder will ensure that nshards is set
-    // correctly.
+    // and finally, sharded shuffles (i.e., going directly from one sharding to another) need a
+    // little extra help. every shard of the sharder's domain sends to every shard of the
+    // destination, so each destination shard will receive one replay piece from *each* source
+    // shard for every replay that passes through the sharder. we place a shard merger (sharded
+    // the same way as the sharder's output) directly below the sharder so that the destination
+    // shards count and merge those pieces before they're processed any further.
     let sharded_sharders: Vec<_> = new
         .iter()
         .filter(|&&n| graph[n].is_sharder() && !graph[n].sharded_by().is_none())
         .cloned()
         .collect();
     for n in sharded_sharders {
-        // sharding what?
-        let p = {
-            let mut ps = graph.neighbors_directed(n, petgraph::EdgeDirection::Incoming);
-            let p = ps.next().unwrap();
-            assert_eq!(ps.count(), 0);
-            p
-        };
-        error!(log, "preventing unsupported sharded shuffle"; "sharder" => ?n);
-        reshard(log, new, &mut swaps, graph, p, n, Sharding::ForcedNone);
-        graph
-            .node_weight_mut(n)
-            .unwrap()
-            .shard_by(Sharding::ForcedNone);
+        let col = graph[n].with_sharder(|s| s.sharded_by()).unwrap();
+        let merger: NodeOperator = ops::union::Union::new_deshard(n, graph[n].sharded_by()).into();
+        let mut merger = graph[n].mirror(merger);
+        merger.shard_by(Sharding::ByColumn(col, sharding_factor));
+        let merger = graph.add_node(merger);
+        debug!(log, "merging sharded shuffle in destination shards"; "sharder" => ?n, "merger" => ?merger);
+        new.insert(merger);
+
+        // move the sharder's children below the merger
+        let cs: Vec<_> = graph
+            .neighbors_directed(n, petgraph::EdgeDirection::Outgoing)
+            .collect();
+        for c in cs {
+            let e = graph.find_edge(n, c).unwrap();
+            graph.remove_edge(e).unwrap();
+            graph.add_edge(merger, c, ());
+        }
+        graph.add_edge(n, merger, ());
+
+        // any node that used to refer to the sharder must now refer to the merger instead
+        for instead in swaps.values_mut() {
+            if *instead == n {
+                *instead = merger;
+            }
+        }
     }
 
     // check that we didn't mess anything up
@@ -1,6 +1,4 @@
-use nom_sql::{
-    ArithmeticBase, ArithmeticExpression, ColumnConstraint, ColumnSpecification, Literal, OrderType,
-};
+use nom_sql::{ColumnConstraint, ColumnSpecification, Literal, OrderType};
 use std::collections::HashMap;
 
 use crate::controller::Migration;
@@ -8,7 +6,7 @@ use common::DataType;
 use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::join::{Join, JoinType};
 use dataflow::ops::latest::Latest;
-use dataflow::ops::project::{Project, ProjectExpression, ProjectExpressionBase};
+use dataflow::ops::project::{Project, ProjectExpression};
 use dataflow::{node, ops};
 use mir::node::{GroupedNodeType, MirNode, MirNodeType};
 use mir::query::{MirQuery, QueryFlowParts};
@@ -113,6 +111,7 @@ fn mir_node_to_flow_parts(
                         column_specs.as_mut_slice(),
                         &bna.columns_added,
                         &bna.columns_removed,
+                        &bna.columns_renamed,
                     ),
                 },
                 MirNodeType::Extremum {
@@ -245,10 +244,50 @@ fn mir_node_to_flow_parts(
                         mig,
                     )
                 }
+                MirNodeType::RightJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 2);
+                    let left = mir_node.ancestors[0].clone();
+                    let right = mir_node.ancestors[1].clone();
+                    make_join_node(
+                        &name,
+                        left,
+                        right,
+                        mir_node.columns.as_slice(),
+                        on_left,
+                        on_right,
+                        project,
+                        JoinType::Right,
+                        mig,
+                    )
+                }
+                MirNodeType::FullJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 2);
+                    let left = mir_node.ancestors[0].clone();
+                    let right = mir_node.ancestors[1].clone();
+                    make_join_node(
+                        &name,
+                        left,
+                        right,
+                        mir_node.columns.as_slice(),
+                        on_left,
+                        on_right,
+                        project,
+                        JoinType::Full,
+                        mig,
+                    )
+                }
                 MirNodeType::Project {
                     ref emit,
                     ref literals,
-                    ref arithmetic,
+                    ref expressions,
                 } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
@@ -257,7 +296,7 @@ fn mir_node_to_flow_parts(
                         parent,
                         mir_node.columns.as_slice(),
                         emit,
-                        arithmetic,
+                        expressions,
                         literals,
                         mig,
                         table_mapping,
@@ -287,10 +326,20 @@ fn mir_node_to_flow_parts(
                         table_mapping,
                     )
                 }
-                MirNodeType::Distinct { ref group_by } => {
+                MirNodeType::Distinct {
+                    ref group_by,
+                    ref key,
+                } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
-                    make_distinct_node(&name, parent, mir_node.columns.as_slice(), group_by, mig)
+                    make_distinct_node(
+                        &name,
+                        parent,
+                        mir_node.columns.as_slice(),
+                        group_by,
+                        key,
+                        mig,
+                    )
                 }
                 MirNodeType::TopK {
                     ref order,
@@ -351,6 +400,7 @@ fn adapt_base_node(
     column_specs: &mut [(ColumnSpecification, Option<usize>)],
     add: &[ColumnSpecification],
     remove: &[ColumnSpecification],
+    rename: &[(ColumnSpecification, ColumnSpecification)],
 ) -> FlowNode {
     let na = match over_node.borrow().flow_node {
         None => panic!("adapted base node must have a flow node already!"),
@@ -392,6 +442,14 @@ fn adapt_base_node(
             .expect("base column ID must be set to remove column");
         mig.drop_column(na, cid);
     }
+    for &(ref old, ref new) in rename.iter() {
+        let cid = column_specs
+            .iter()
+            .find(|&&(ref cs, _)| cs == new)
+            .and_then(|&(_, cid)| cid)
+            .unwrap_or_else(|| panic!("base column ID must be set to rename column {:?}", old));
+        mig.rename_column(na, cid, &new.column.name);
+    }
 
     FlowNode::Existing(na)
 }
@@ -553,7 +611,7 @@ fn make_grouped_node(
     });
 
     let parent_na = parent.borrow().flow_node_addr().unwrap();
-    let column_names = column_names(columns);
+    let mut column_names = column_names(columns);
 
     let over_col_indx = parent.borrow().column_id_for_column(on, table_mapping);
 
@@ -569,11 +627,16 @@ fn make_grouped_node(
     assert!(!group_col_indx.is_empty());
 
     let na = match kind {
-        GroupedNodeType::Aggregation(agg) => mig.add_ingredient(
-            String::from(name),
-            column_names.as_slice(),
-            agg.over(parent_na, over_col_indx, group_col_indx.as_slice()),
-        ),
+        GroupedNodeType::Aggregation(agg) => {
+            let agg = agg.over(parent_na, over_col_indx, group_col_indx.as_slice());
+            // the aggregation's running state follows the computed column, but is not part of
+            // the MIR node's columns, since nothing downstream refers to it
+            let state_names: Vec<_> = (0..agg.state_columns())
+                .map(|i| format!("{}_state{}", name, i))
+                .collect();
+            column_names.extend(state_names.iter().map(String::as_str));
+            mig.add_ingredient(String::from(name), column_names.as_slice(), agg)
+        }
         GroupedNodeType::Extremum(extr) => mig.add_ingredient(
             String::from(name),
             column_names.as_slice(),
@@ -734,6 +797,8 @@ fn make_join_node(
     let j = match kind {
         JoinType::Inner => Join::new(left_na, right_na, JoinType::Inner, join_config),
         JoinType::Left => Join::new(left_na, right_na, JoinType::Left, join_config),
+        JoinType::Right => Join::new(left_na, right_na, JoinType::Right, join_config),
+        JoinType::Full => Join::new(left_na, right_na, JoinType::Full, join_config),
     };
     let n = mig.add_ingredient(String::from(name), column_names.as_slice(), j);
 
@@ -765,28 +830,12 @@ fn make_latest_node(
     FlowNode::New(na)
 }
 
-// Converts a nom_sql::ArithmeticBase into a project::ProjectExpressionBase:
-fn generate_projection_base(parent: &MirNodeRef, base: &ArithmeticBase) -> ProjectExpressionBase {
-    match *base {
-        ArithmeticBase::Column(ref column) => {
-            let column_id = parent
-                .borrow()
-                .column_id_for_column(&Column::from(column), None);
-            ProjectExpressionBase::Column(column_id)
-        }
-        ArithmeticBase::Scalar(ref literal) => {
-            let data: DataType = literal.into();
-            ProjectExpressionBase::Literal(data)
-        }
-    }
-}
-
 fn make_project_node(
     name: &str,
     parent: MirNodeRef,
     columns: &[Column],
     emit: &[Column],
-    arithmetic: &[(String, ArithmeticExpression)],
+    expressions: &[(String, ProjectExpression<Column>)],
     literals: &[(String, DataType)],
     mig: &mut Migration,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
@@ -801,14 +850,10 @@ fn make_project_node(
 
     let (_, literal_values): (Vec<_>, Vec<_>) = literals.iter().cloned().unzip();
 
-    let projected_arithmetic: Vec<ProjectExpression> = arithmetic
+    let projected_expressions: Vec<ProjectExpression> = expressions
         .iter()
         .map(|&(_, ref e)| {
-            ProjectExpression::new(
-                e.op.clone(),
-                generate_projection_base(&parent, &e.left),
-                generate_projection_base(&parent, &e.right),
-            )
+            e.map_columns(&mut |c| parent.borrow().column_id_for_column(c, table_mapping))
         })
         .collect();
 
@@ -819,7 +864,7 @@ fn make_project_node(
             parent_na,
             projected_column_ids.as_slice(),
             Some(literal_values),
-            Some(projected_arithmetic),
+            Some(projected_expressions),
         ),
     );
     FlowNode::New(n)
@@ -830,10 +875,15 @@ fn make_distinct_node(
     parent: MirNodeRef,
     columns: &[Column],
     group_by: &[Column],
+    key: &[Column],
     mig: &mut Migration,
 ) -> FlowNode {
     let parent_na = parent.borrow().flow_node_addr().unwrap();
-    let column_names = column_names(columns);
+    // the number of records each distinct record stands for follows the parent's columns, but is
+    // not part of the MIR node's columns, since nothing downstream refers to it
+    let count_name = format!("{}_count", name);
+    let mut column_names = column_names(columns);
+    column_names.push(&count_name);
 
     let group_by_indx = if group_by.is_empty() {
         // no query parameters, so we index on the first column
@@ -847,12 +897,19 @@ fn make_distinct_node(
             .map(|c| parent.borrow().column_id_for_column(c, None))
             .collect::<Vec<_>>()
     };
+    let key_indx = if key.is_empty() {
+        group_by_indx.clone()
+    } else {
+        key.iter()
+            .map(|c| parent.borrow().column_id_for_column(c, None))
+            .collect::<Vec<_>>()
+    };
 
     // make the new operator and record its metadata
     let na = mig.add_ingredient(
         String::from(name),
         column_names.as_slice(),
-        ops::distinct::Distinct::new(parent_na, group_by_indx),
+        ops::distinct::Distinct::new(parent_na, group_by_indx, key_indx),
     );
     FlowNode::New(na)
 }
@@ -882,8 +939,6 @@ fn make_topk_node(
 
     let cmp_rows = match *order {
         Some(ref o) => {
-            assert_eq!(offset, 0); // Non-zero offset not supported
-
             let columns: Vec<_> = o
                 .iter()
                 .map(|&(ref c, ref order_type)| {
@@ -909,7 +964,7 @@ fn make_topk_node(
     let na = mig.add_ingredient(
         String::from(name),
         column_names.as_slice(),
-        ops::topk::TopK::new(parent_na, cmp_rows, group_by_indx, k),
+        ops::topk::TopK::new(parent_na, cmp_rows, group_by_indx, k, offset),
     );
     FlowNode::New(na)
 }
@@ -939,4 +994,43 @@ fn materialize_leaf_node(
         // if no key specified, default to the first column
         mig.maintain(name, na, &[0]);
     }
+
+    if let Some(order) = leaf_order(parent) {
+        mig.maintain_order(na, order);
+    }
+}
+
+/// Find the order that the query's ORDER BY imposes on the rows of the leaf below `parent`.
+///
+/// The order comes from the closest TopK node above the leaf, as long as we only pass through
+/// single-ancestor nodes that keep all of the order columns on the way down.
+fn leaf_order(parent: &MirNodeRef) -> Option<Vec<(usize, OrderType)>> {
+    let mut n = parent.clone();
+    loop {
+        let next = {
+            let node = n.borrow();
+            if let MirNodeType::TopK {
+                order: Some(ref order),
+                ..
+            } = node.inner
+            {
+                let leaf_parent = parent.borrow();
+                return order
+                    .iter()
+                    .map(|&(ref c, ref order_type)| {
+                        leaf_parent
+                            .columns()
+                            .iter()
+                            .position(|pc| pc == c)
+                            .map(|i| (i, order_type.clone()))
+                    })
+                    .collect();
+            }
+            match node.ancestors() {
+                [ancestor] => ancestor.clone(),
+                _ => return None,
+            }
+        };
+        n = next;
+    }
 }
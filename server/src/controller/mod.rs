@@ -16,7 +16,8 @@ use futures_util::{
 use hyper::{self, StatusCode};
 use noria::channel::TcpSender;
 use noria::consensus::{Authority, Epoch, STATE_KEY};
-use noria::ControllerDescriptor;
+use noria::{ControllerDescriptor, RecipeVersion};
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
@@ -41,10 +42,81 @@ pub(crate) struct ControllerState {
 
     recipe_version: usize,
     recipes: Vec<String>,
+    /// The recipe versions that have been activated and are still kept, oldest first. The
+    /// versions themselves are stored under keys of their own (see `recipe_version_key`), so that
+    /// the state stays small no matter how often the recipe changes.
+    #[serde(default)]
+    recipe_versions: Vec<usize>,
+    /// The text of the security configuration, if one has been set.
+    #[serde(default)]
+    security_config: Option<String>,
+    /// The base tables that keep a change feed.
+    #[serde(default)]
+    change_feeds: HashSet<String>,
+}
+
+/// How many of the most recent recipe versions are kept for listing, diffing and rolling back.
+const MAX_RECIPE_VERSIONS: usize = 100;
+
+/// The authority key that `version` of the recipe is stored under. Versions take turns over
+/// `MAX_RECIPE_VERSIONS` keys, so a version overwrites the one `MAX_RECIPE_VERSIONS` before it.
+fn recipe_version_key(version: usize) -> String {
+    format!("/recipe-version-{}", version % MAX_RECIPE_VERSIONS)
+}
+
+impl ControllerState {
+    /// Notes that the recipe was activated as `recipe_version`, and forgets the versions whose
+    /// keys it takes over.
+    fn keep_recipe_version(&mut self) {
+        let version = self.recipe_version;
+        self.recipe_versions
+            .retain(|&v| v < version && version - v < MAX_RECIPE_VERSIONS);
+        self.recipe_versions.push(version);
+    }
+
+    /// Stores the recipe that `recipes` make up as `recipe_version`.
+    ///
+    /// This must not be called from within a `read_modify_write` of the state, as some authorities
+    /// do not allow nesting those.
+    fn write_recipe_version<A: Authority>(&self, authority: &A) -> Result<(), String> {
+        let version = RecipeVersion {
+            version: self.recipe_version,
+            recipe: self.recipes.join("\n"),
+            activated: time::SystemTime::now(),
+        };
+        match authority.read_modify_write(
+            &recipe_version_key(version.version),
+            |_: Option<RecipeVersion>| Ok::<_, ()>(version.clone()),
+        ) {
+            Ok(Ok(_)) => Ok(()),
+            _ => Err(format!(
+                "failed to persist recipe version {}",
+                version.version
+            )),
+        }
+    }
+
+    fn recipe_at<A: Authority>(
+        &self,
+        authority: &A,
+        version: usize,
+    ) -> Result<RecipeVersion, String> {
+        if !self.recipe_versions.contains(&version) {
+            return Err(format!("no recipe version {}", version));
+        }
+        authority
+            .try_read(&recipe_version_key(version))
+            .map_err(|e| format!("failed to read recipe version {}: {:?}", version, e))?
+            .and_then(|v| serde_json::from_slice::<RecipeVersion>(&v).ok())
+            .filter(|v| v.version == version)
+            .ok_or_else(|| format!("recipe version {} has not been stored", version))
+    }
 }
 
 struct Worker {
     healthy: bool,
+    /// The worker is being decommissioned, so no new domains should be placed on it.
+    draining: bool,
     last_heartbeat: time::Instant,
     sender: TcpSender<CoordinationMessage>,
 }
@@ -53,6 +125,7 @@ impl Worker {
     fn new(sender: TcpSender<CoordinationMessage>) -> Self {
         Worker {
             healthy: true,
+            draining: false,
             last_heartbeat: time::Instant::now(),
             sender,
         }
@@ -155,6 +228,18 @@ pub(super) async fn main<A: Authority + 'static>(
                     )
                     .unwrap();
             }
+            #[cfg(test)]
+            Event::FailSendsAfter(n) => {
+                if let Some(ref mut ctrl) = controller {
+                    ctrl.fail_sends_after = Some(n);
+                }
+            }
+            #[cfg(test)]
+            Event::AbandonNextTransaction => {
+                if let Some(ref mut ctrl) = controller {
+                    ctrl.abandon_next_transaction = true;
+                }
+            }
             Event::WonLeaderElection(state) => {
                 let c = campaign.take().unwrap();
                 tokio::task::block_in_place(move || c.join().unwrap());
@@ -262,6 +347,9 @@ fn instance_campaign<A: Authority + 'static>(
                         epoch,
                         recipe_version: 0,
                         recipes: vec![],
+                        recipe_versions: vec![],
+                        security_config: None,
+                        change_feeds: HashSet::new(),
                     }),
                     Some(ref state) if state.epoch > epoch => Err(()),
                     Some(mut state) => {
@@ -303,3 +391,51 @@ fn instance_campaign<A: Authority + 'static>(
         })
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noria::consensus::LocalAuthority;
+
+    #[test]
+    fn it_keeps_only_recent_recipe_versions() {
+        let authority = LocalAuthority::new();
+        let epoch = authority.become_leader(vec![]).unwrap().unwrap();
+        let mut state = ControllerState {
+            config: Config::default(),
+            epoch,
+            recipe_version: 0,
+            recipes: vec![],
+            recipe_versions: vec![],
+            security_config: None,
+            change_feeds: HashSet::new(),
+        };
+
+        for version in 1..=MAX_RECIPE_VERSIONS + 10 {
+            state.recipe_version = version;
+            state
+                .recipes
+                .push(format!("QUERY q{}: SELECT a FROM b;", version));
+            state.keep_recipe_version();
+            state.write_recipe_version(&authority).unwrap();
+        }
+        assert_eq!(state.recipe_versions.len(), MAX_RECIPE_VERSIONS);
+        assert_eq!(state.recipe_versions[0], 11);
+        assert!(state.recipe_at(&authority, 10).is_err());
+        let latest = state
+            .recipe_at(&authority, MAX_RECIPE_VERSIONS + 10)
+            .unwrap();
+        assert_eq!(latest.recipe.lines().count(), MAX_RECIPE_VERSIONS + 10);
+        assert!(state
+            .recipe_at(&authority, 11)
+            .unwrap()
+            .recipe
+            .ends_with("q11: SELECT a FROM b;"));
+
+        // versions that were never activated leave gaps, whose keys later versions take over
+        state.recipe_version += MAX_RECIPE_VERSIONS / 2;
+        state.keep_recipe_version();
+        assert_eq!(state.recipe_versions.len(), MAX_RECIPE_VERSIONS / 2 + 1);
+        assert_eq!(state.recipe_versions[0], 61);
+    }
+}
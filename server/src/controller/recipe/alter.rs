@@ -0,0 +1,320 @@
+//! `ALTER TABLE` statements in recipes.
+//!
+//! nom_sql does not know about `ALTER TABLE`, so recipes parse these statements themselves. An
+//! `ALTER TABLE` is never added to the graph as such; instead, it is applied to the `CREATE TABLE`
+//! statement that currently defines the table, and the existing base node is adapted to the
+//! schema that results.
+
+use nom_sql::{Column, ColumnConstraint, ColumnSpecification, CreateTableStatement};
+use nom_sql::{SqlQuery, TableKey};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum AlterTableDefinition {
+    /// `ADD [COLUMN] <column definition>`
+    AddColumn(ColumnSpecification),
+    /// `DROP [COLUMN] <column>`
+    DropColumn(String),
+    /// `RENAME COLUMN <old> TO <new>`
+    RenameColumn(String, String),
+    /// `ADD {INDEX|KEY} [<name>] (<column>, ...)`
+    AddIndex(Option<String>, Vec<String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct AlterTableStatement {
+    pub(super) table: String,
+    pub(super) definitions: Vec<AlterTableDefinition>,
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// If `input` starts with the keyword `kw` (in any case), returns what follows it.
+fn keyword<'a>(input: &'a str, kw: &str) -> Option<&'a str> {
+    let input = input.trim_start();
+    if input.len() < kw.len()
+        || !input.is_char_boundary(kw.len())
+        || !input[..kw.len()].eq_ignore_ascii_case(kw)
+    {
+        return None;
+    }
+    let rest = &input[kw.len()..];
+    match rest.chars().next() {
+        Some(c) if is_ident_char(c) => None,
+        _ => Some(rest),
+    }
+}
+
+/// Strips the quotes from a possibly quoted identifier.
+fn unquote(ident: &str) -> Result<String, String> {
+    let ident = ident.trim();
+    let ident = if ident.len() >= 2 && ident.starts_with('`') && ident.ends_with('`') {
+        &ident[1..ident.len() - 1]
+    } else {
+        ident
+    };
+    if ident.is_empty() || !ident.chars().all(is_ident_char) {
+        return Err(format!("invalid column name \"{}\"", ident));
+    }
+    Ok(ident.to_owned())
+}
+
+/// Splits `input` at the commas that are not inside parentheses or quotes.
+fn split_top_level(input: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in input.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'') | (None, '"') | (None, '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&input[start..]);
+    parts
+}
+
+fn column_definition(table: &str, def: &str) -> Result<ColumnSpecification, String> {
+    // reuse nom_sql's parser for column definitions
+    match nom_sql::parse_query(format!("CREATE TABLE {} ({})", table, def)) {
+        Ok(SqlQuery::CreateTable(mut ctq)) if ctq.fields.len() == 1 && ctq.keys.is_none() => {
+            Ok(ctq.fields.remove(0))
+        }
+        _ => Err(format!("invalid column definition \"{}\"", def.trim())),
+    }
+}
+
+fn index_definition(def: &str) -> Result<AlterTableDefinition, String> {
+    let invalid = || format!("invalid index definition \"{}\"", def.trim());
+    let open = def.find('(').ok_or_else(invalid)?;
+    if !def.trim_end().ends_with(')') {
+        return Err(invalid());
+    }
+    let name = match def[..open].trim() {
+        "" => None,
+        name => Some(unquote(name)?),
+    };
+    let columns = def.trim_end();
+    let columns = columns[open + 1..columns.len() - 1]
+        .split(',')
+        .map(unquote)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(AlterTableDefinition::AddIndex(name, columns))
+}
+
+fn alter_definition(table: &str, def: &str) -> Result<AlterTableDefinition, String> {
+    if let Some(rest) = keyword(def, "add") {
+        if let Some(rest) = keyword(rest, "unique") {
+            let rest = keyword(rest, "index")
+                .or_else(|| keyword(rest, "key"))
+                .unwrap_or(rest);
+            return index_definition(rest);
+        }
+        if let Some(rest) = keyword(rest, "index").or_else(|| keyword(rest, "key")) {
+            return index_definition(rest);
+        }
+        let rest = keyword(rest, "column").unwrap_or(rest);
+        return column_definition(table, rest).map(AlterTableDefinition::AddColumn);
+    }
+    if let Some(rest) = keyword(def, "drop") {
+        let rest = keyword(rest, "column").unwrap_or(rest);
+        return unquote(rest).map(AlterTableDefinition::DropColumn);
+    }
+    if let Some(rest) = keyword(def, "rename") {
+        if let Some(rest) = keyword(rest, "column") {
+            let words: Vec<_> = rest.split_whitespace().collect();
+            if words.len() == 3 && words[1].eq_ignore_ascii_case("to") {
+                return Ok(AlterTableDefinition::RenameColumn(
+                    unquote(words[0])?,
+                    unquote(words[2])?,
+                ));
+            }
+        }
+    }
+    Err(format!("unsupported ALTER TABLE clause \"{}\"", def.trim()))
+}
+
+/// Parses an `ALTER TABLE` statement into the name of the table and the text of its clauses,
+/// which `AlterTableStatement::new` then makes sense of.
+pub(super) fn alter_table(input: &str) -> nom::IResult<&str, (&str, &str)> {
+    use nom::bytes::complete::{tag_no_case, take_till1};
+    use nom::character::complete::{char, multispace0, multispace1};
+    use nom::combinator::opt;
+    use nom::sequence::{delimited, tuple};
+
+    let (input, (_, _, _, _, table, _)) = tuple((
+        tag_no_case("alter"),
+        multispace1,
+        tag_no_case("table"),
+        multispace1,
+        nom::branch::alt((delimited(char('`'), super::ident, char('`')), super::ident)),
+        multispace1,
+    ))(input)?;
+    let (input, clauses) = take_till1(|c: char| c == ';')(input)?;
+    let (input, _) = opt(char(';'))(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, (table, clauses)))
+}
+
+fn key_columns(key: &TableKey) -> &[Column] {
+    match *key {
+        TableKey::PrimaryKey(ref cs)
+        | TableKey::UniqueKey(_, ref cs)
+        | TableKey::FulltextKey(_, ref cs)
+        | TableKey::Key(_, ref cs) => cs,
+    }
+}
+
+fn is_primary_key(ctq: &CreateTableStatement, column: &str) -> bool {
+    let inline = ctq
+        .fields
+        .iter()
+        .any(|f| f.column.name == column && f.constraints.contains(&ColumnConstraint::PrimaryKey));
+    let declared = ctq.keys.iter().flatten().any(|k| match *k {
+        TableKey::PrimaryKey(ref cs) => cs.iter().any(|c| c.name == column),
+        _ => false,
+    });
+    inline || declared
+}
+
+impl AlterTableStatement {
+    /// Makes an `ALTER TABLE` statement for `table` from the text of its comma-separated clauses,
+    /// each of which is one of `ADD [COLUMN]`, `DROP [COLUMN]`, `RENAME COLUMN` and `ADD INDEX`.
+    pub(super) fn new(table: &str, clauses: &str) -> Result<Self, String> {
+        let definitions = split_top_level(clauses)
+            .into_iter()
+            .map(|def| alter_definition(table, def))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(AlterTableStatement {
+            table: table.to_owned(),
+            definitions,
+        })
+    }
+
+    fn position(&self, ctq: &CreateTableStatement, column: &str) -> Result<usize, String> {
+        ctq.fields
+            .iter()
+            .position(|f| f.column.name == column)
+            .ok_or_else(|| format!("table {} has no column {}", self.table, column))
+    }
+
+    /// Applies the alterations to `ctq`, the current definition of the table, and returns the
+    /// definition that results.
+    pub(super) fn apply(&self, ctq: &CreateTableStatement) -> Result<CreateTableStatement, String> {
+        let mut ctq = ctq.clone();
+        for def in &self.definitions {
+            match *def {
+                AlterTableDefinition::AddColumn(ref cs) => {
+                    if self.position(&ctq, &cs.column.name).is_ok() {
+                        return Err(format!(
+                            "table {} already has a column {}",
+                            self.table, cs.column.name
+                        ));
+                    }
+                    if cs.constraints.contains(&ColumnConstraint::PrimaryKey) {
+                        return Err(format!(
+                            "cannot add primary key column {} to existing table {}",
+                            cs.column.name, self.table
+                        ));
+                    }
+                    ctq.fields.push(cs.clone());
+                }
+                AlterTableDefinition::DropColumn(ref column) => {
+                    let pos = self.position(&ctq, column)?;
+                    if is_primary_key(&ctq, column) {
+                        return Err(format!(
+                            "cannot drop column {} of table {}, as it is part of the primary key",
+                            column, self.table
+                        ));
+                    }
+                    if ctq.fields.len() == 1 {
+                        return Err(format!(
+                            "cannot drop column {}, the only column of table {}",
+                            column, self.table
+                        ));
+                    }
+                    ctq.fields.remove(pos);
+                    if let Some(ref mut keys) = ctq.keys {
+                        keys.retain(|k| key_columns(k).iter().all(|c| c.name != *column));
+                    }
+                }
+                AlterTableDefinition::RenameColumn(ref old, ref new) => {
+                    let pos = self.position(&ctq, old)?;
+                    if self.position(&ctq, new).is_ok() {
+                        return Err(format!("table {} already has a column {}", self.table, new));
+                    }
+                    if is_primary_key(&ctq, old) {
+                        return Err(format!(
+                            "cannot rename column {} of table {}, as it is part of the primary key",
+                            old, self.table
+                        ));
+                    }
+                    ctq.fields[pos].column.name = new.clone();
+                    for key in ctq.keys.iter_mut().flatten() {
+                        let columns = match *key {
+                            TableKey::PrimaryKey(ref mut cs)
+                            | TableKey::UniqueKey(_, ref mut cs)
+                            | TableKey::FulltextKey(_, ref mut cs)
+                            | TableKey::Key(_, ref mut cs) => cs,
+                        };
+                        for c in columns.iter_mut().filter(|c| c.name == *old) {
+                            c.name = new.clone();
+                        }
+                    }
+                }
+                AlterTableDefinition::AddIndex(ref name, ref columns) => {
+                    for column in columns {
+                        self.position(&ctq, column)?;
+                    }
+                    let key = TableKey::Key(
+                        name.clone().unwrap_or_else(|| columns[0].clone()),
+                        columns
+                            .iter()
+                            .map(|c| Column::from(format!("{}.{}", self.table, c).as_str()))
+                            .collect(),
+                    );
+                    ctq.keys.get_or_insert_with(Vec::new).push(key);
+                }
+            }
+        }
+        Ok(ctq)
+    }
+
+    /// Returns the columns of the table's current definition that queries can no longer read by
+    /// their name once the alterations have been applied.
+    pub(super) fn removed_columns(&self) -> Vec<&str> {
+        self.definitions
+            .iter()
+            .filter_map(|def| match *def {
+                AlterTableDefinition::DropColumn(ref column)
+                | AlterTableDefinition::RenameColumn(ref column, _) => Some(column.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the (old, new) name of each column that the alterations rename, with successive
+    /// renames of a column collapsed into one.
+    pub(super) fn renamed_columns(&self) -> Vec<(String, String)> {
+        let mut renamed: Vec<(String, String)> = Vec::new();
+        for def in &self.definitions {
+            if let AlterTableDefinition::RenameColumn(ref old, ref new) = *def {
+                match renamed.iter_mut().find(|r| r.1 == *old) {
+                    Some(r) => r.1 = new.clone(),
+                    None => renamed.push((old.clone(), new.clone())),
+                }
+            }
+        }
+        renamed
+    }
+}
@@ -5,6 +5,7 @@ use crate::ReuseConfigType;
 use dataflow::ops::trigger::Trigger;
 use dataflow::ops::trigger::TriggerEvent;
 use dataflow::prelude::DataType;
+use dataflow::MemoryBudget;
 use nom_sql::parser as sql_parser;
 use nom_sql::SqlQuery;
 use noria::ActivationResult;
@@ -16,6 +17,12 @@ use std::collections::HashMap;
 use std::str;
 use std::vec::Vec;
 
+use self::alter::{alter_table, AlterTableStatement};
+use self::outer_join::{display_outer_joins, rewrite_outer_joins};
+
+mod alter;
+mod outer_join;
+
 type QueryID = u64;
 
 /// Represents a Soup recipe.
@@ -28,8 +35,14 @@ pub(crate) struct Recipe {
     expression_order: Vec<QueryID>,
     /// Named read/write expression aliases, mapping to queries in `expressions`.
     aliases: HashMap<String, QueryID>,
+    /// Memory budgets that named queries have been annotated with.
+    budgets: HashMap<String, MemoryBudget>,
     /// Security configuration
     security_config: Option<SecurityConfig>,
+    /// `CREATE TABLE` expressions that `ALTER TABLE` statements turned an expression of the prior
+    /// recipe into, along with those statements. Activating the recipe adapts the existing base
+    /// table rather than adding a new one.
+    alterations: HashMap<QueryID, AlterTableStatement>,
 
     /// Recipe revision.
     version: usize,
@@ -50,6 +63,7 @@ impl PartialEq for Recipe {
         self.expressions == other.expressions
             && self.expression_order == other.expression_order
             && self.aliases == other.aliases
+            && self.budgets == other.budgets
             && self.version == other.version
             && self.prior == other.prior
     }
@@ -78,25 +92,75 @@ fn ident(input: &str) -> nom::IResult<&str, &str> {
     })
 }
 
-fn query_prefix(input: &str) -> nom::IResult<&str, (bool, Option<&str>)> {
+/// Parses a memory budget annotation, i.e., `PINNED` or `BUDGET <bytes>[K|M|G]`.
+fn memory_budget(input: &str) -> nom::IResult<&str, MemoryBudget> {
+    use nom::branch::alt;
+    use nom::bytes::complete::tag_no_case;
+    use nom::character::complete::{digit1, one_of, space1};
+    use nom::combinator::{map, map_res, opt};
+    use nom::sequence::{pair, preceded};
+    alt((
+        map(tag_no_case("pinned"), |_| MemoryBudget::Pinned),
+        map_res(
+            preceded(
+                pair(tag_no_case("budget"), space1),
+                pair(digit1, opt(one_of("kKmMgG"))),
+            ),
+            |(n, unit): (&str, Option<char>)| {
+                let unit = match unit {
+                    None => 1,
+                    Some('k') | Some('K') => 1 << 10,
+                    Some('m') | Some('M') => 1 << 20,
+                    Some(_) => 1 << 30,
+                };
+                n.parse::<usize>()
+                    .ok()
+                    .and_then(|n| n.checked_mul(unit))
+                    .map(MemoryBudget::Bytes)
+                    .ok_or(())
+            },
+        ),
+    ))(input)
+}
+
+type QueryPrefix<'a> = (bool, Option<&'a str>, Option<MemoryBudget>);
+
+fn query_prefix(input: &str) -> nom::IResult<&str, QueryPrefix> {
     use nom::branch::alt;
     use nom::bytes::complete::tag_no_case;
     use nom::character::complete::{char, multispace0, space1};
-    use nom::combinator::opt;
+    use nom::combinator::{map, opt, peek};
     use nom::sequence::{pair, terminated};
     let (input, public) = opt(pair(
         alt((tag_no_case("query"), tag_no_case("view"))),
         space1,
     ))(input)?;
     let (input, _) = multispace0(input)?;
-    let (input, name) = opt(terminated(ident, multispace0))(input)?;
-    let (input, _) = multispace0(input)?;
+    let (input, (name, budget)) = alt((
+        // a budget right before the colon belongs to an unnamed query, and is not its name
+        map(
+            terminated(memory_budget, pair(multispace0, peek(char(':')))),
+            |budget| (None, Some(budget)),
+        ),
+        pair(
+            opt(terminated(ident, multispace0)),
+            opt(terminated(memory_budget, multispace0)),
+        ),
+    ))(input)?;
     let (input, _) = char(':')(input)?;
     let (input, _) = multispace0(input)?;
-    Ok((input, (public.is_some(), name)))
+    Ok((input, (public.is_some(), name, budget)))
+}
+
+type QueryExpr<'a> = (bool, Option<&'a str>, Option<MemoryBudget>, SqlQuery);
+
+enum RecipeExpr<'a> {
+    Query(QueryExpr<'a>),
+    /// An `ALTER TABLE` statement, as the table name and the text of its clauses.
+    AlterTable(&'a str, &'a str),
 }
 
-fn query_expr(input: &str) -> nom::IResult<&str, (bool, Option<&str>, SqlQuery)> {
+fn query_expr(input: &str) -> nom::IResult<&str, QueryExpr> {
     use nom::character::complete::multispace0;
     use nom::combinator::opt;
     let (input, prefix) = opt(query_prefix)(input)?;
@@ -113,14 +177,72 @@ fn query_expr(input: &str) -> nom::IResult<&str, (bool, Option<&str>, SqlQuery)>
     Ok((
         input,
         match prefix {
-            None => (false, None, expr),
-            Some((public, name)) => (public, name, expr),
+            None => (false, None, None, expr),
+            Some((public, name, budget)) => (public, name, budget, expr),
         },
     ))
 }
 
-fn query_exprs(input: &str) -> nom::IResult<&str, Vec<(bool, Option<&str>, SqlQuery)>> {
-    nom::multi::many1(query_expr)(input)
+fn recipe_exprs(input: &str) -> nom::IResult<&str, Vec<RecipeExpr>> {
+    use nom::branch::alt;
+    use nom::combinator::map;
+    nom::multi::many1(alt((
+        map(alter_table, |(table, clauses)| {
+            RecipeExpr::AlterTable(table, clauses)
+        }),
+        map(query_expr, RecipeExpr::Query),
+    )))(input)
+}
+
+/// Checks that no query that `inc` has added to the graph reads a column that `alter` drops or
+/// renames.
+fn check_dependents(alter: &AlterTableStatement, inc: &SqlIncorporator) -> Result<(), String> {
+    let removed = alter.removed_columns();
+    if removed.is_empty() {
+        return Ok(());
+    }
+
+    match inc.query_reading(&alter.table, &removed) {
+        Some((query, column)) => Err(format!(
+            "cannot drop or rename column {} of table {}, as query {} depends on it",
+            column, alter.table, query
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Checks that none of `queries`, which are yet to be added to the graph, refer to the table
+/// whose columns `alter` drops or renames.
+///
+/// The columns that a query reads are only known once the query has been added to the graph, so
+/// this errs on the side of caution, and takes any mention of the table to be a dependency.
+fn check_pending_dependents<'a, I>(alter: &AlterTableStatement, queries: I) -> Result<(), String>
+where
+    I: IntoIterator<Item = (Option<&'a String>, &'a SqlQuery)>,
+{
+    if alter.removed_columns().is_empty() {
+        return Ok(());
+    }
+
+    for (name, q) in queries {
+        if let SqlQuery::CreateTable(_) = *q {
+            continue;
+        }
+
+        let text = q.to_string();
+        let mentions_table = text
+            .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .any(|w| w == alter.table);
+        if mentions_table {
+            return Err(format!(
+                "cannot drop or rename columns of table {} in the same recipe as query {}, \
+                 which refers to it",
+                alter.table,
+                name.cloned().unwrap_or_else(|| format!("\"{}\"", q))
+            ));
+        }
+    }
+    Ok(())
 }
 
 #[allow(unused)]
@@ -146,6 +268,7 @@ impl Recipe {
             expressions: HashMap::default(),
             expression_order: Vec::default(),
             aliases: HashMap::default(),
+            budgets: HashMap::default(),
             version: 0,
             prior: None,
             inc: match log {
@@ -157,6 +280,7 @@ impl Recipe {
                 Some(log) => log,
             },
             security_config: None,
+            alterations: HashMap::default(),
         }
     }
 
@@ -192,6 +316,11 @@ impl Recipe {
         })
     }
 
+    /// Returns the memory budgets that named queries have been annotated with.
+    pub(in crate::controller) fn memory_budgets(&self) -> &HashMap<String, MemoryBudget> {
+        &self.budgets
+    }
+
     /// Obtains the `NodeIndex` for the node corresponding to a named query or a write type.
     pub(in crate::controller) fn node_addr_for(&self, name: &str) -> Result<NodeIndex, String> {
         match self.inc {
@@ -239,6 +368,22 @@ impl Recipe {
     /// it.
     // crate viz for tests
     pub(crate) fn from_str(recipe_text: &str, log: Option<slog::Logger>) -> Result<Recipe, String> {
+        let (recipe, alters) = Recipe::from_str_with_alters(recipe_text, log)?;
+        match alters.first() {
+            None => Ok(recipe),
+            Some(alter) => Err(format!(
+                "ALTER TABLE of table {}, which the recipe does not create",
+                alter.table
+            )),
+        }
+    }
+
+    /// Like `from_str`, but also accepts `ALTER TABLE` statements for tables that the recipe text
+    /// does not create, and returns those separately, in order.
+    fn from_str_with_alters(
+        recipe_text: &str,
+        log: Option<slog::Logger>,
+    ) -> Result<(Recipe, Vec<AlterTableStatement>), String> {
         // remove comment lines
         let lines: Vec<String> = recipe_text
             .lines()
@@ -249,9 +394,11 @@ impl Recipe {
         let cleaned_recipe_text = lines.join("\n");
 
         // parse and compute differences to current recipe
-        let parsed_queries = Recipe::parse(&cleaned_recipe_text)?;
+        let (parsed_queries, budgets, alters) = Recipe::parse(&cleaned_recipe_text)?;
 
-        Ok(Recipe::from_queries(parsed_queries, log))
+        let mut recipe = Recipe::from_queries(parsed_queries, log);
+        recipe.budgets = budgets;
+        Ok((recipe, alters))
     }
 
     /// Creates a recipe from a set of pre-parsed `SqlQuery` structures.
@@ -304,7 +451,9 @@ impl Recipe {
             expressions,
             expression_order,
             aliases,
+            budgets: HashMap::default(),
             security_config: None,
+            alterations: HashMap::default(),
             version: 0,
             prior: None,
             inc: Some(inc),
@@ -454,12 +603,20 @@ impl Recipe {
         for qid in added {
             let (n, q, is_leaf) = self.expressions[&qid].clone();
 
-            // add the query
-            let qfp = self
-                .inc
-                .as_mut()
-                .unwrap()
-                .add_parsed_query(q, n.clone(), is_leaf, mig)?;
+            // add the query, or adapt the existing base table if it is an altered one
+            let qfp = match self.alterations.get(&qid) {
+                Some(alter) => {
+                    self.inc
+                        .as_mut()
+                        .unwrap()
+                        .alter_base(q, &alter.renamed_columns(), mig)?
+                }
+                None => self
+                    .inc
+                    .as_mut()
+                    .unwrap()
+                    .add_parsed_query(q, n.clone(), is_leaf, mig)?,
+            };
 
             // If the user provided us with a query name, use that.
             // If not, use the name internally used by the QFP.
@@ -476,6 +633,15 @@ impl Recipe {
             .filter_map(|qid| {
                 let (ref n, ref q, _) = self.prior.as_ref().unwrap().expressions[qid];
                 match q {
+                    SqlQuery::CreateTable(ref ctq)
+                        if self
+                            .alterations
+                            .values()
+                            .any(|alter| alter.table == ctq.table.name) =>
+                    {
+                        // the table was altered, and lives on in its new definition
+                        None
+                    }
                     SqlQuery::CreateTable(ref ctq) => {
                         // a base may have many dependent queries, including ones that also lost
                         // nodes; the code handling `removed_leaves` therefore needs to take care
@@ -527,6 +693,25 @@ impl Recipe {
         (added_queries, removed_queries)
     }
 
+    /// Returns the text of the expressions that are in `self` but not in `other`, followed by the
+    /// text of those that are in `other` but not in `self`.
+    pub(in crate::controller) fn diff(&self, other: &Recipe) -> (Vec<String>, Vec<String>) {
+        let text = |r: &Recipe, qid: QueryID| {
+            let (ref n, ref q, public) = r.expressions[&qid];
+            let q = display_outer_joins(&q.to_string());
+            match n {
+                Some(n) if public => format!("QUERY {}: {};", n, q),
+                Some(n) => format!("{}: {};", n, q),
+                None => format!("{};", q),
+            }
+        };
+        let (added, removed) = self.compute_delta(other);
+        (
+            added.into_iter().map(|qid| text(self, qid)).collect(),
+            removed.into_iter().map(|qid| text(other, qid)).collect(),
+        )
+    }
+
     /// Returns the query expressions in the recipe.
     // crate viz for tests
     pub(crate) fn expressions(&self) -> Vec<(Option<&String>, &SqlQuery)> {
@@ -539,11 +724,14 @@ impl Recipe {
     /// Append the queries in the `additions` argument to this recipe. This will attempt to parse
     /// `additions`, and if successful, will extend the recipe. No expressions are removed from the
     /// recipe; use `replace` if removal of unused expressions is desired.
+    ///
+    /// `additions` may also alter tables of this recipe, in which case the `CREATE TABLE`
+    /// expression for each altered table is replaced by its new definition.
     /// Consumes `self` and returns a replacement recipe.
     // crate viz for tests
     pub(crate) fn extend(mut self, additions: &str) -> Result<Recipe, (Recipe, String)> {
         // parse and compute differences to current recipe
-        let add_rp = match Recipe::from_str(additions, None) {
+        let (add_rp, alters) = match Recipe::from_str_with_alters(additions, None) {
             Ok(rp) => rp,
             Err(e) => return Err((self, e)),
         };
@@ -557,15 +745,25 @@ impl Recipe {
             expressions: self.expressions.clone(),
             expression_order: self.expression_order.clone(),
             aliases: self.aliases.clone(),
+            budgets: self.budgets.clone(),
             version: self.version + 1,
             inc: prior_inc,
             log: self.log.clone(),
             security_config: self.security_config.clone(),
+            alterations: HashMap::default(),
             // retain the old recipe for future reference
             prior: Some(Box::new(self)),
         };
 
         // apply changes
+        for alter in &alters {
+            if let Err(e) = new.alter_table(alter) {
+                // hand back the old recipe, along with its incorporator
+                let mut old = *new.prior.take().unwrap();
+                old.inc = new.inc.take();
+                return Err((old, e));
+            }
+        }
         for qid in added {
             let q = add_rp.expressions[&qid].clone();
             new.expressions.insert(qid, q);
@@ -580,11 +778,62 @@ impl Recipe {
             );
         }
         new.aliases.extend(add_rp.aliases);
+        new.budgets.extend(add_rp.budgets);
 
         // return new recipe as replacement for self
         Ok(new)
     }
 
+    /// Replaces the `CREATE TABLE` expression for the table that `alter` alters with the table's
+    /// altered definition, after checking that no query depends on a column it drops or renames.
+    fn alter_table(&mut self, alter: &AlterTableStatement) -> Result<(), String> {
+        let (pos, old_qid) = self
+            .expression_order
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|&(_, qid)| match self.expressions[qid].1 {
+                SqlQuery::CreateTable(ref ctq) => ctq.table.name == alter.table,
+                _ => false,
+            })
+            .map(|(pos, &qid)| (pos, qid))
+            .ok_or_else(|| format!("ALTER TABLE of unknown table {}", alter.table))?;
+
+        check_dependents(alter, self.sql_inc())?;
+
+        let (name, q, is_leaf) = self.expressions.remove(&old_qid).unwrap();
+        let ctq = match q {
+            SqlQuery::CreateTable(ref ctq) => alter.apply(ctq),
+            _ => unreachable!(),
+        };
+        let ctq = match ctq {
+            Ok(ctq) => ctq,
+            Err(e) => {
+                self.expressions.insert(old_qid, (name, q, is_leaf));
+                return Err(e);
+            }
+        };
+
+        let q = SqlQuery::CreateTable(ctq);
+        let qid = hash_query(&q);
+        self.expression_order[pos] = qid;
+        self.expressions.insert(qid, (name.clone(), q, is_leaf));
+        if let Some(name) = name {
+            self.aliases.insert(name, qid);
+        }
+
+        // several statements may alter the same table before the recipe is activated
+        let mut alter = alter.clone();
+        if let Some(mut earlier) = self.alterations.remove(&old_qid) {
+            earlier.definitions.extend(alter.definitions);
+            alter = earlier;
+        }
+        if qid != old_qid {
+            self.alterations.insert(qid, alter);
+        }
+        Ok(())
+    }
+
     /// Helper method to reparent a recipe. This is needed for the recovery logic to build
     /// recovery and original recipe (see `make_recovery`).
     pub(in crate::controller) fn set_prior(&mut self, new_prior: Recipe) {
@@ -596,12 +845,30 @@ impl Recipe {
         self.inc.as_ref().unwrap()
     }
 
+    /// Returns a copy of the recipe with its own copy of the `SqlIncorporator` and the MIR graph
+    /// it holds, so that activating the copy leaves this recipe untouched.
+    pub(in crate::controller) fn deep_clone(&self) -> Recipe {
+        let mut copy = self.clone();
+        copy.inc = self.inc.as_ref().map(SqlIncorporator::deep_clone);
+        copy
+    }
+
     /// Helper method to reparent a recipe. This is needed for some of t
     pub(in crate::controller) fn set_sql_inc(&mut self, new_inc: SqlIncorporator) {
         self.inc = Some(new_inc);
     }
 
-    fn parse(recipe_text: &str) -> Result<Vec<(Option<String>, SqlQuery, bool)>, String> {
+    #[allow(clippy::type_complexity)]
+    fn parse(
+        recipe_text: &str,
+    ) -> Result<
+        (
+            Vec<(Option<String>, SqlQuery, bool)>,
+            HashMap<String, MemoryBudget>,
+            Vec<AlterTableStatement>,
+        ),
+        String,
+    > {
         let lines: Vec<&str> = recipe_text
             .lines()
             .filter(|l| !l.is_empty() && !l.starts_with('#'))
@@ -632,37 +899,81 @@ impl Recipe {
             i += 1;
         }
 
-        let parsed_queries = query_strings.iter().fold(
-            Vec::new(),
-            |mut acc: Vec<Result<(bool, Option<&str>, SqlQuery), String>>, q| {
-                match query_exprs(q) {
-                    Result::Err(e) => {
-                        // we got a parse error
-                        acc.push(Err(format!("Query \"{}\", parse error: {}", q, e)));
+        // nom_sql doesn't parse right and full joins, so we rewrite those first
+        let query_strings = query_strings
+            .iter()
+            .map(|q| {
+                rewrite_outer_joins(q).map_err(|e| format!("Query \"{}\", parse error: {}", q, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let parsed_queries =
+            query_strings
+                .iter()
+                .fold(Vec::new(), |mut acc: Vec<Result<RecipeExpr, String>>, q| {
+                    match recipe_exprs(q) {
+                        Result::Err(e) => {
+                            // we got a parse error
+                            acc.push(Err(format!("Query \"{}\", parse error: {}", q, e)));
+                        }
+                        Result::Ok((remainder, parsed)) => {
+                            // should have consumed all input
+                            assert!(
+                                remainder.is_empty(),
+                                format!(
+                                    "failed to parse the complete recipe; left with: {}",
+                                    remainder
+                                )
+                            );
+                            acc.extend(parsed.into_iter().map(|p| Ok(p)).collect::<Vec<_>>());
+                        }
                     }
-                    Result::Ok((remainder, parsed)) => {
-                        // should have consumed all input
-                        assert!(
-                            remainder.is_empty(),
-                            format!(
-                                "failed to parse the complete recipe; left with: {}",
-                                remainder
-                            )
-                        );
-                        acc.extend(parsed.into_iter().map(|p| Ok(p)).collect::<Vec<_>>());
+                    acc
+                });
+
+        let mut budgets = HashMap::new();
+        let mut queries: Vec<(Option<String>, SqlQuery, bool)> = Vec::new();
+        let mut alters = Vec::new();
+        for pr in parsed_queries {
+            match pr.unwrap() {
+                RecipeExpr::Query((public, name, budget, q)) => {
+                    match (name, budget) {
+                        (Some(name), Some(budget)) => {
+                            budgets.insert(name.to_owned(), budget);
+                        }
+                        (None, Some(_)) => {
+                            // budgets are applied by query name
+                            return Err(format!(
+                                "Query \"{}\", only named queries can have a memory budget",
+                                display_outer_joins(&q.to_string())
+                            ));
+                        }
+                        _ => {}
                     }
+                    queries.push((name.map(String::from), q, public));
                 }
-                acc
-            },
-        );
-
-        Ok(parsed_queries
-            .into_iter()
-            .map(|pr| {
-                let pr = pr.unwrap();
-                (pr.1.map(String::from), pr.2, pr.0)
-            })
-            .collect::<Vec<_>>())
+                RecipeExpr::AlterTable(table, clauses) => {
+                    let alter = AlterTableStatement::new(table, clauses)
+                        .map_err(|e| format!("ALTER TABLE {}: {}", table, e))?;
+                    // a table created earlier in the same text is created altered right away
+                    check_pending_dependents(
+                        &alter,
+                        queries.iter().map(|(n, q, _)| (n.as_ref(), q)),
+                    )?;
+                    let ctq = queries.iter_mut().rev().find_map(|(_, q, _)| match *q {
+                        SqlQuery::CreateTable(ref mut ctq) => {
+                            Some(ctq).filter(|ctq| ctq.table.name == table)
+                        }
+                        _ => None,
+                    });
+                    match ctq {
+                        Some(ctq) => *ctq = alter.apply(ctq)?,
+                        None => alters.push(alter),
+                    }
+                }
+            }
+        }
+        Ok((queries, budgets, alters))
     }
 
     /// Returns the predecessor from which this `Recipe` was migrated to.
@@ -680,6 +991,7 @@ impl Recipe {
         let qid = qid.unwrap();
 
         self.aliases.remove(qname);
+        self.budgets.remove(qname);
         if self.expressions.remove(&qid).is_some() {
             if let Some(i) = self.expression_order.iter().position(|&q| q == qid) {
                 self.expression_order.remove(i);
@@ -896,4 +1208,120 @@ mod tests {
         let r1 = r0.replace(r1_t).unwrap();
         assert_eq!(r1.expressions.len(), 2);
     }
+
+    #[test]
+    fn it_parses_memory_budgets() {
+        let r_txt = "QUERY q_0 PINNED: SELECT a FROM b;\n\
+                     QUERY q_1 BUDGET 64M: SELECT x FROM y;\n\
+                     VIEW q_2 budget 1000 : SELECT z FROM y;\n\
+                     QUERY q_3: SELECT a, c FROM b;";
+        let r = Recipe::from_str(r_txt, None).unwrap();
+        assert_eq!(r.expressions.len(), 4);
+
+        let budgets = r.memory_budgets();
+        assert_eq!(budgets.len(), 3);
+        assert_eq!(budgets["q_0"], MemoryBudget::Pinned);
+        assert_eq!(budgets["q_1"], MemoryBudget::Bytes(64 << 20));
+        assert_eq!(budgets["q_2"], MemoryBudget::Bytes(1000));
+        assert!(r.resolve_alias("q_1").is_some());
+
+        // budgets are looked up by name, so unnamed queries can't have one
+        for r_txt in &[
+            "PINNED: SELECT a FROM b;",
+            "QUERY BUDGET 1K : SELECT a FROM b;",
+        ] {
+            let e = Recipe::from_str(r_txt, None).unwrap_err();
+            assert!(e.contains("only named queries"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn it_parses_right_and_full_joins() {
+        use nom_sql::JoinOperator;
+
+        let r_txt = "QUERY q_0: SELECT a.x FROM a RIGHT JOIN b ON (a.x = b.x);\n\
+                     QUERY q_1: SELECT a.x FROM a full outer join b ON (a.x = b.x) \
+                         WHERE a.y = 'right join';";
+        let r = Recipe::from_str(r_txt, None).unwrap();
+        let operator = |name: &str| match r.expressions[&r.aliases[name]].1 {
+            SqlQuery::Select(ref s) => s.join[0].operator.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(operator("q_0"), JoinOperator::StraightJoin);
+        assert_eq!(operator("q_1"), JoinOperator::CrossJoin);
+
+        // the join operators that stand in for right and full joins can't be used directly
+        assert!(Recipe::from_str("SELECT a.x FROM a CROSS JOIN b ON (a.x = b.x);", None).is_err());
+        assert!(
+            Recipe::from_str("SELECT a.x FROM a STRAIGHT_JOIN b ON (a.x = b.x);", None).is_err()
+        );
+
+        let (added, _) = r.diff(&Recipe::blank(None));
+        assert_eq!(added.len(), 2);
+        assert!(added.iter().any(|q| q.contains("RIGHT JOIN b")));
+        assert!(added
+            .iter()
+            .any(|q| q.contains("FULL JOIN b") && q.contains("'right join'")));
+    }
+
+    #[test]
+    fn it_extends_with_alter_table() {
+        let r0 = Recipe::from_str(
+            "CREATE TABLE b (a int, c int, d int, PRIMARY KEY(a));\n\
+             QUERY q_0: SELECT a, c FROM b;",
+            None,
+        )
+        .unwrap();
+
+        let r1 = r0
+            .extend("ALTER TABLE b ADD COLUMN e int DEFAULT 1, ADD INDEX (e), DROP COLUMN d;")
+            .unwrap();
+        assert_eq!(r1.version, 1);
+        assert_eq!(r1.expressions.len(), 2);
+        assert_eq!(r1.alterations.len(), 1);
+        let (added, removed) = r1.compute_delta(r1.prior().unwrap());
+        assert_eq!((added.len(), removed.len()), (1, 1));
+        match r1.expressions[&added[0]].1 {
+            SqlQuery::CreateTable(ref ctq) => {
+                let fields: Vec<_> = ctq.fields.iter().map(|f| f.column.name.as_str()).collect();
+                assert_eq!(fields, vec!["a", "c", "e"]);
+                assert_eq!(ctq.keys.as_ref().unwrap().len(), 2);
+            }
+            _ => unreachable!(),
+        }
+
+        // a is the primary key (which queries read columns is checked once they are in the graph)
+        let (r1, _) = r1
+            .extend("ALTER TABLE b RENAME COLUMN a TO f;")
+            .unwrap_err();
+        let (r1, _) = r1.extend("ALTER TABLE x DROP COLUMN c;").unwrap_err();
+        assert_eq!(r1.version, 1);
+        assert!(r1.extend("ALTER TABLE b RENAME COLUMN e TO f;").is_ok());
+    }
+
+    #[test]
+    fn it_applies_alter_table_to_tables_in_the_same_recipe() {
+        let r = Recipe::from_str(
+            "CREATE TABLE b (a int, c int);\n\
+             ALTER TABLE `b` RENAME COLUMN c TO d;",
+            None,
+        )
+        .unwrap();
+        assert_eq!(r.expressions.len(), 1);
+        assert!(r.alterations.is_empty());
+
+        assert!(Recipe::from_str("ALTER TABLE b DROP COLUMN c;", None).is_err());
+        assert!(Recipe::from_str("CREATE TABLE b (a int);\nALTER TABLE b DROP a;", None).is_err());
+        // queries in the same text are yet to be resolved, so any mention of the table counts
+        assert!(Recipe::from_str(
+            "CREATE TABLE b (a int, c int);\nQUERY q: SELECT a FROM b;\nALTER TABLE b DROP c;",
+            None
+        )
+        .is_err());
+        assert!(Recipe::from_str(
+            "CREATE TABLE b (a int, c int);\nQUERY q: SELECT a FROM b;\nALTER TABLE b ADD d int;",
+            None
+        )
+        .is_ok());
+    }
 }
@@ -0,0 +1,126 @@
+//! `RIGHT` and `FULL` outer joins in recipes.
+//!
+//! nom_sql only parses inner and left joins, so recipes rewrite `RIGHT [OUTER] JOIN` and
+//! `FULL [OUTER] JOIN` before parsing, into join operators that nom_sql does know but that Noria
+//! does not otherwise support: `STRAIGHT_JOIN` stands in for a right join, and `CROSS JOIN` for a
+//! full join. The query graph turns these back into right and full joins. Recipes that use
+//! `STRAIGHT_JOIN` or `CROSS JOIN` themselves are rejected, so the two can't be confused.
+
+/// The join operator that a `RIGHT JOIN` is rewritten to.
+const RIGHT_JOIN: &str = "STRAIGHT_JOIN";
+/// The join operator that a `FULL JOIN` is rewritten to.
+const FULL_JOIN: &str = "CROSS JOIN";
+
+/// A word in a query, or `None` for any other token.
+type Token<'a> = Option<(usize, usize, &'a str)>;
+
+fn tokens(query: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c == '\'' || c == '"' || c == '`' {
+            // skip over quoted strings and identifiers; a quote is escaped by doubling it, or by
+            // a backslash.
+            while let Some((_, d)) = chars.next() {
+                if d == '\\' && c != '`' {
+                    chars.next();
+                } else if d == c {
+                    match chars.peek() {
+                        Some(&(_, e)) if e == c => {
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            tokens.push(None);
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, d)) = chars.peek() {
+                if !(d.is_ascii_alphanumeric() || d == '_') {
+                    break;
+                }
+                end = i + d.len_utf8();
+                chars.next();
+            }
+            tokens.push(Some((start, end, &query[start..end])));
+        } else if !c.is_whitespace() {
+            tokens.push(None);
+        }
+    }
+    tokens
+}
+
+/// Rewrites the `RIGHT` and `FULL` joins in `query` into the join operators that stand in for
+/// them.
+pub(super) fn rewrite_outer_joins(query: &str) -> Result<String, String> {
+    let tokens = tokens(query);
+    let mut rewritten = String::with_capacity(query.len());
+    let mut copied = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let (_, end, word) = match *token {
+            Some(token) => token,
+            None => continue,
+        };
+        if word.eq_ignore_ascii_case("straight_join") {
+            return Err("STRAIGHT_JOIN is not supported".to_owned());
+        }
+        if !word.eq_ignore_ascii_case("join") {
+            continue;
+        }
+
+        // the join kind comes right before JOIN, or before OUTER JOIN
+        let word_at = |j: Option<usize>| j.and_then(|j| tokens[j]);
+        let mut kind = word_at(i.checked_sub(1));
+        if let Some((_, _, w)) = kind {
+            if w.eq_ignore_ascii_case("outer") {
+                kind = word_at(i.checked_sub(2));
+            }
+        }
+        let (start, kind) = match kind {
+            Some((start, _, kind)) => (start, kind),
+            None => continue,
+        };
+        let operator = if kind.eq_ignore_ascii_case("right") {
+            RIGHT_JOIN
+        } else if kind.eq_ignore_ascii_case("full") {
+            FULL_JOIN
+        } else if kind.eq_ignore_ascii_case("cross") {
+            return Err("CROSS JOIN is not supported".to_owned());
+        } else {
+            continue;
+        };
+        rewritten.push_str(&query[copied..start]);
+        rewritten.push_str(operator);
+        copied = end;
+    }
+    rewritten.push_str(&query[copied..]);
+    Ok(rewritten)
+}
+
+/// Undoes `rewrite_outer_joins` on the text of a parsed query, which shows the join operators that
+/// stand in for right and full joins.
+pub(super) fn display_outer_joins(text: &str) -> String {
+    let tokens = tokens(text);
+    let mut displayed = String::with_capacity(text.len());
+    let mut copied = 0;
+    for pair in tokens.windows(2) {
+        if let [Some((start, _, kind)), Some((_, end, join))] = *pair {
+            if !join.eq_ignore_ascii_case("join") {
+                continue;
+            }
+            let operator = if kind.eq_ignore_ascii_case("straight") {
+                "RIGHT JOIN"
+            } else if kind.eq_ignore_ascii_case("cross") {
+                "FULL JOIN"
+            } else {
+                continue;
+            };
+            displayed.push_str(&text[copied..start]);
+            displayed.push_str(operator);
+            copied = end;
+        }
+    }
+    displayed.push_str(&text[copied..]);
+    displayed
+}
@@ -52,7 +52,25 @@ fn type_for_internal_column(
                 to_sql_type(&emits.1[off])
             }
         }
-        ops::NodeOperator::Sum(_) | ops::NodeOperator::FilterSum(_) => {
+        ops::NodeOperator::Sum(ref o) => {
+            use dataflow::ops::grouped::aggregate::Aggregation;
+
+            // computed column is always emitted last, save for any state columns after it
+            if column_index == node.fields().len() - 1 - o.state_columns() {
+                match *o.kind() {
+                    // counts and sums always produce integral columns
+                    Aggregation::COUNT | Aggregation::SUM | Aggregation::COUNT_DISTINCT => {
+                        Some(SqlType::Bigint(64))
+                    }
+                    Aggregation::AVG => Some(SqlType::Double),
+                }
+            } else {
+                // no column that isn't the aggregation result column should ever trace
+                // back to an aggregation.
+                unreachable!();
+            }
+        }
+        ops::NodeOperator::FilterSum(_) => {
             // computed column is always emitted last
             if column_index == node.fields().len() - 1 {
                 // counts and sums always produce integral columns
@@ -109,7 +109,12 @@ pub(super) fn make_grouped(
                 .edges
                 .values()
                 .filter(|e| match **e {
-                    QueryGraphEdge::Join(_) | QueryGraphEdge::LeftJoin(_) => false,
+                    QueryGraphEdge::Join(_)
+                    | QueryGraphEdge::LeftJoin(_)
+                    | QueryGraphEdge::RightJoin(_)
+                    | QueryGraphEdge::FullJoin(_)
+                    | QueryGraphEdge::SemiJoin(_)
+                    | QueryGraphEdge::AntiJoin(_) => false,
                     QueryGraphEdge::GroupBy(_) => true,
                 })
                 .collect();
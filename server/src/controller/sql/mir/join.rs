@@ -1,8 +1,11 @@
 use crate::controller::sql::mir::SqlToMirConverter;
 use crate::controller::sql::query_graph::{JoinRef, QueryGraph, QueryGraphEdge};
+use dataflow::ops::grouped::aggregate::Aggregation;
 use dataflow::ops::join::JoinType;
-use mir::MirNodeRef;
-use nom_sql::ConditionTree;
+use mir::node::GroupedNodeType;
+use mir::{Column, MirNodeRef};
+use nom_sql::{ConditionBase, ConditionExpression, ConditionTree, Literal, Operator};
+use noria::DataType;
 use std::collections::{HashMap, HashSet};
 
 struct JoinChain {
@@ -42,35 +45,279 @@ pub(super) fn make_joins(
     let mut node_count = node_count;
 
     for jref in qg.join_order.iter() {
-        let (join_type, jp) = from_join_ref(jref, &qg);
         let (left_chain, right_chain) =
             pick_join_chains(&jref.src, &jref.dst, &mut join_chains, node_for_rel);
 
-        let jn = mir_converter.make_join_node(
-            &format!("{}_n{}", name, node_count),
-            jp,
-            left_chain.last_node.clone(),
-            right_chain.last_node.clone(),
-            join_type,
-        );
+        let nodes = match qg.edges[&(jref.src.clone(), jref.dst.clone())] {
+            QueryGraphEdge::SemiJoin(ref jps) => make_subquery_join(
+                mir_converter,
+                name,
+                &jps[jref.index],
+                left_chain.last_node.clone(),
+                right_chain.last_node.clone(),
+                false,
+                node_count,
+            ),
+            QueryGraphEdge::AntiJoin(ref jps) => make_subquery_join(
+                mir_converter,
+                name,
+                &jps[jref.index],
+                left_chain.last_node.clone(),
+                right_chain.last_node.clone(),
+                true,
+                node_count,
+            ),
+            _ => {
+                let (join_type, jp) = from_join_ref(jref, &qg);
+                vec![mir_converter.make_join_node(
+                    &format!("{}_n{}", name, node_count),
+                    jp,
+                    left_chain.last_node.clone(),
+                    right_chain.last_node.clone(),
+                    join_type,
+                )]
+            }
+        };
 
         // merge node chains
-        let new_chain = left_chain.merge_chain(right_chain, jn.clone());
+        let new_chain = left_chain.merge_chain(right_chain, nodes.last().unwrap().clone());
         join_chains.push(new_chain);
 
-        node_count += 1;
+        node_count += nodes.len();
 
-        join_nodes.push(jn);
+        join_nodes.extend(nodes);
     }
 
     join_nodes
 }
 
+// Semi- and anti-joins against the view that computes an IN subquery. We count how often each
+// non-NULL value occurs in the view and keep only the values that do occur, since a COUNT never
+// retracts a group once it exists. A semi-join then inner-joins against those values, so a NULL
+// never matches. An anti-join left-joins against them and keeps the rows that found no match, but
+// follows SQL's NULL semantics for `NOT IN`: every row is kept if the view is empty, and otherwise
+// a row is only kept if its value is not NULL and the view contains no NULL. To know whether the
+// view is empty or contains a NULL, the anti-join also counts all values and all NULLs of the
+// view, and left-joins those counts onto every row through a constant key.
+fn make_subquery_join(
+    mir_converter: &SqlToMirConverter,
+    name: &str,
+    jp: &ConditionTree,
+    left: MirNodeRef,
+    right: MirNodeRef,
+    anti: bool,
+    node_count: usize,
+) -> Vec<MirNodeRef> {
+    let node_name = |i: usize| format!("{}_n{}", name, node_count + i);
+    let field = |c: &str| {
+        Box::new(ConditionExpression::Base(ConditionBase::Field(
+            nom_sql::Column::from(c),
+        )))
+    };
+    let literal = |l: Literal| Box::new(ConditionExpression::Base(ConditionBase::Literal(l)));
+    let is = |left: Box<ConditionExpression>, op: Operator, value: Literal| ConditionTree {
+        operator: op,
+        left,
+        right: literal(value),
+    };
+    let count = |over: MirNodeRef, i: usize, on: &str, group_by: &str| {
+        mir_converter.make_grouped_node(
+            &node_name(i),
+            &Column::new(None, &format!("{}_count", node_name(i))),
+            (over, &Column::new(None, on), None),
+            vec![&Column::new(None, group_by)],
+            GroupedNodeType::Aggregation(Aggregation::COUNT),
+            None,
+        )
+    };
+    let count_of = |i: usize| field(&format!("{}_count", node_name(i)));
+
+    let (left_col, right_col) = match (&*jp.left, &*jp.right) {
+        (
+            ConditionExpression::Base(ConditionBase::Field(ref l)),
+            ConditionExpression::Base(ConditionBase::Field(ref r)),
+        ) => (l.clone(), r.clone()),
+        _ => unreachable!(),
+    };
+    let view_col = Column::from(&right_col);
+    let marker_col = format!("{}_marker", node_name(0));
+    let all_col = format!("{}_all", node_name(0));
+    let null_col = format!("{}_null", node_name(7));
+
+    let mut nodes = Vec::new();
+    let marked = mir_converter.make_project_node(
+        &node_name(0),
+        right,
+        vec![&view_col],
+        vec![],
+        vec![
+            (marker_col.clone(), DataType::from(1)),
+            (all_col.clone(), DataType::from(0)),
+        ],
+        false,
+    );
+    nodes.push(marked.clone());
+
+    let values = mir_converter.make_filter_node(
+        &node_name(1),
+        marked.clone(),
+        &is(
+            Box::new(ConditionExpression::Base(ConditionBase::Field(
+                right_col.clone(),
+            ))),
+            Operator::NotEqual,
+            Literal::Null,
+        ),
+    );
+    nodes.push(values.clone());
+
+    let counted = mir_converter.make_grouped_node(
+        &node_name(2),
+        &Column::new(None, &format!("{}_count", node_name(2))),
+        (values, &Column::new(None, &marker_col), None),
+        vec![&view_col],
+        GroupedNodeType::Aggregation(Aggregation::COUNT),
+        None,
+    );
+    nodes.push(counted.clone());
+
+    let present = mir_converter.make_filter_node(
+        &node_name(3),
+        counted,
+        &is(count_of(2), Operator::Greater, Literal::Integer(0)),
+    );
+    nodes.push(present.clone());
+
+    if !anti {
+        nodes.push(mir_converter.make_join_node(&node_name(4), jp, left, present, JoinType::Inner));
+        return nodes;
+    }
+
+    // whether the view has any values at all
+    let total = count(marked.clone(), 4, &marker_col, &all_col);
+    nodes.push(total.clone());
+    let nonempty = mir_converter.make_filter_node(
+        &node_name(5),
+        total,
+        &is(count_of(4), Operator::Greater, Literal::Integer(0)),
+    );
+    nodes.push(nonempty.clone());
+
+    // whether the view has any NULLs
+    let nulls = mir_converter.make_filter_node(
+        &node_name(6),
+        marked,
+        &is(
+            Box::new(ConditionExpression::Base(ConditionBase::Field(
+                right_col.clone(),
+            ))),
+            Operator::Equal,
+            Literal::Null,
+        ),
+    );
+    nodes.push(nulls.clone());
+    let nulls = mir_converter.make_project_node(
+        &node_name(7),
+        nulls,
+        vec![&Column::new(None, &marker_col)],
+        vec![],
+        vec![(null_col.clone(), DataType::from(0))],
+        false,
+    );
+    nodes.push(nulls.clone());
+    let nulls = count(nulls, 8, &marker_col, &null_col);
+    nodes.push(nulls.clone());
+    let has_nulls = mir_converter.make_filter_node(
+        &node_name(9),
+        nulls,
+        &is(count_of(8), Operator::Greater, Literal::Integer(0)),
+    );
+    nodes.push(has_nulls.clone());
+
+    // give every row the constant keys to join the view's counts on
+    let keys = (
+        format!("{}_all", node_name(10)),
+        format!("{}_null", node_name(10)),
+    );
+    let left_cols = left.borrow().columns().to_vec();
+    let keyed = mir_converter.make_project_node(
+        &node_name(10),
+        left,
+        left_cols.iter().collect(),
+        vec![],
+        vec![
+            (keys.0.clone(), DataType::from(0)),
+            (keys.1.clone(), DataType::from(0)),
+        ],
+        false,
+    );
+    nodes.push(keyed.clone());
+
+    let on = |l: &str, r: &str| ConditionTree {
+        operator: Operator::Equal,
+        left: field(l),
+        right: field(r),
+    };
+    let joined = mir_converter.make_join_node(&node_name(11), jp, keyed, present, JoinType::Left);
+    nodes.push(joined.clone());
+    let joined = mir_converter.make_join_node(
+        &node_name(12),
+        &on(&keys.0, &all_col),
+        joined,
+        nonempty,
+        JoinType::Left,
+    );
+    nodes.push(joined.clone());
+    let joined = mir_converter.make_join_node(
+        &node_name(13),
+        &on(&keys.1, &null_col),
+        joined,
+        has_nulls,
+        JoinType::Left,
+    );
+    nodes.push(joined.clone());
+
+    // keep every row if the view is empty...
+    let empty = mir_converter.make_filter_node(
+        &node_name(14),
+        joined.clone(),
+        &is(count_of(4), Operator::Equal, Literal::Null),
+    );
+    nodes.push(empty.clone());
+
+    // ...and otherwise only rows with a value that is not NULL and not in the view, and only if
+    // the view contains no NULL
+    let mut kept = joined.clone();
+    let conditions = vec![
+        is(count_of(4), Operator::NotEqual, Literal::Null),
+        is(count_of(2), Operator::Equal, Literal::Null),
+        is(count_of(8), Operator::Equal, Literal::Null),
+        is(
+            Box::new(ConditionExpression::Base(ConditionBase::Field(left_col))),
+            Operator::NotEqual,
+            Literal::Null,
+        ),
+    ];
+    for (i, cond) in conditions.iter().enumerate() {
+        kept = mir_converter.make_filter_node(&node_name(15 + i), kept, cond);
+        nodes.push(kept.clone());
+    }
+
+    let columns = joined.borrow().columns().to_vec();
+    nodes.push(mir_converter.make_union_from_same_base(&node_name(19), vec![empty, kept], columns));
+
+    nodes
+}
+
 fn from_join_ref<'a>(jref: &JoinRef, qg: &'a QueryGraph) -> (JoinType, &'a ConditionTree) {
     match qg.edges[&(jref.src.clone(), jref.dst.clone())] {
         QueryGraphEdge::Join(ref jps) => (JoinType::Inner, &jps[jref.index]),
         QueryGraphEdge::LeftJoin(ref jps) => (JoinType::Left, &jps[jref.index]),
-        QueryGraphEdge::GroupBy(_) => unreachable!(),
+        QueryGraphEdge::RightJoin(ref jps) => (JoinType::Right, &jps[jref.index]),
+        QueryGraphEdge::FullJoin(ref jps) => (JoinType::Full, &jps[jref.index]),
+        QueryGraphEdge::SemiJoin(_) | QueryGraphEdge::AntiJoin(_) | QueryGraphEdge::GroupBy(_) => {
+            unreachable!()
+        }
     }
 }
 
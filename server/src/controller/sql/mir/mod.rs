@@ -1,4 +1,4 @@
-use mir::node::{GroupedNodeType, MirNode, MirNodeType};
+use mir::node::{GroupedNodeType, MirGraphCopy, MirNode, MirNodeType};
 use mir::query::MirQuery;
 use mir::{Column, MirNodeRef};
 use noria::DataType;
@@ -6,11 +6,12 @@ use petgraph::graph::NodeIndex;
 // TODO(malte): remove if possible
 use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::join::JoinType;
+use dataflow::ops::project::ProjectExpression;
 
 use crate::controller::sql::query_graph::{OutputColumn, QueryGraph};
 use crate::controller::sql::query_signature::Signature;
 use nom_sql::{
-    ArithmeticExpression, CaseWhenExpression, ColumnOrLiteral, ColumnSpecification,
+    ArithmeticBase, ArithmeticExpression, CaseWhenExpression, ColumnOrLiteral, ColumnSpecification,
     CompoundSelectOperator, ConditionBase, ConditionExpression, ConditionTree, Literal, Operator,
     SqlQuery, TableKey,
 };
@@ -20,6 +21,7 @@ use slog;
 use std::collections::{HashMap, HashSet};
 
 use std::ops::Deref;
+use std::rc::Rc;
 use std::vec::Vec;
 
 use crate::controller::sql::security::Universe;
@@ -36,6 +38,16 @@ fn sanitize_leaf_column(c: &mut Column, view_name: &str) {
     c.aliases = vec![];
 }
 
+/// Lowers an arithmetic expression from the SQL parser into a projection expression over
+/// named columns.
+fn to_project_expression(e: &ArithmeticExpression) -> ProjectExpression<Column> {
+    let operand = |b: &ArithmeticBase| match *b {
+        ArithmeticBase::Column(ref c) => ProjectExpression::Column(Column::from(c)),
+        ArithmeticBase::Scalar(ref l) => ProjectExpression::Literal(l.into()),
+    };
+    ProjectExpression::arithmetic(e.op.clone(), operand(&e.left), operand(&e.right))
+}
+
 /// Returns all collumns used in a predicate
 fn predicate_columns(ce: &ConditionExpression) -> HashSet<Column> {
     use nom_sql::ConditionExpression::*;
@@ -52,6 +64,13 @@ fn predicate_columns(ce: &ConditionExpression) -> HashSet<Column> {
         Bracketed(ref ce) => {
             cols.extend(predicate_columns(&ce));
         }
+        Arithmetic(ref e) => {
+            for b in &[&e.left, &e.right] {
+                if let ArithmeticBase::Column(ref c) = **b {
+                    cols.insert(Column::from(c));
+                }
+            }
+        }
         NegationOp(_) => unreachable!("negations should have been eliminated"),
         _ => (),
     }
@@ -59,16 +78,18 @@ fn predicate_columns(ce: &ConditionExpression) -> HashSet<Column> {
     cols
 }
 
-fn value_columns_needed_for_predicates(
-    value_columns: &[OutputColumn],
-    predicates: &[ConditionExpression],
-) -> Vec<(Column, OutputColumn)> {
-    let pred_columns: HashSet<_> = predicates.iter().fold(HashSet::new(), |mut acc, p| {
-        acc.extend(predicate_columns(p));
-        acc
-    });
+/// Returns the arithmetic and literal output columns that the query's global predicates or its
+/// parameters depend on, which must therefore be computed before the query's final projection.
+fn value_columns_needed_for_predicates(qg: &QueryGraph) -> Vec<(Column, OutputColumn)> {
+    let mut pred_columns: HashSet<_> = qg
+        .global_predicates
+        .iter()
+        .flat_map(predicate_columns)
+        .collect();
+    // arithmetic compared against a query parameter is computed into a column of its own
+    pred_columns.extend(qg.parameters().into_iter().map(Column::from));
 
-    value_columns
+    qg.columns
         .iter()
         .filter_map(|oc| match *oc {
             OutputColumn::Arithmetic(ref ac) => Some((
@@ -196,6 +217,56 @@ impl SqlToMirConverter {
         }
     }
 
+    /// Converts a comparison that involves arithmetic into a filter condition whose operands are
+    /// evaluated over the columns of `n`.
+    fn to_expression_condition(
+        &self,
+        ct: &ConditionTree,
+        n: &MirNodeRef,
+    ) -> (usize, FilterCondition) {
+        use crate::controller::sql::query_utils::arithmetic_operand;
+        use dataflow::ops::filter::Value;
+
+        let column_id = |c: &Column| n.borrow().column_id_for_column(c, None);
+        let expression =
+            |e: &ArithmeticExpression| to_project_expression(e).map_columns(&mut |c| column_id(c));
+
+        // keep the arithmetic on the left-hand side, mirroring the comparison if need be
+        let (left, op, right) = match arithmetic_operand(&ct.left) {
+            Some(e) => (e, ct.operator.clone(), ct.right.as_ref()),
+            None => {
+                let op = match ct.operator {
+                    Operator::Greater => Operator::Less,
+                    Operator::GreaterOrEqual => Operator::LessOrEqual,
+                    Operator::Less => Operator::Greater,
+                    Operator::LessOrEqual => Operator::GreaterOrEqual,
+                    ref op => op.clone(),
+                };
+                (arithmetic_operand(&ct.right).unwrap(), op, ct.left.as_ref())
+            }
+        };
+
+        let value = match arithmetic_operand(right) {
+            Some(e) => Value::Expression(expression(e)),
+            None => match *right {
+                ConditionExpression::Base(ConditionBase::Field(ref f)) => {
+                    Value::Column(column_id(&Column::from(f)))
+                }
+                ConditionExpression::Base(ConditionBase::Literal(ref l)) => {
+                    Value::Constant(l.into())
+                }
+                // rejected by `check_arithmetic_comparisons` when the query graph was built
+                _ => unreachable!("can't compare arithmetic against {}", right),
+            },
+        };
+
+        // the condition is attached to the first column that the expression reads
+        let left = expression(left);
+        let col = left.columns().first().map(|&&c| c).unwrap_or(0);
+
+        (col, FilterCondition::Expression(left, op, value))
+    }
+
     /// Converts a condition tree stored in the `ConditionExpr` returned by the SQL parser
     /// and adds its to a vector of conditions.
     fn to_conditions(
@@ -204,8 +275,13 @@ impl SqlToMirConverter {
         columns: &mut Vec<Column>,
         n: &MirNodeRef,
     ) -> Vec<(usize, FilterCondition)> {
+        use crate::controller::sql::query_utils::arithmetic_operand;
         use std::cmp::max;
 
+        if arithmetic_operand(&ct.left).is_some() || arithmetic_operand(&ct.right).is_some() {
+            return vec![self.to_expression_condition(ct, n)];
+        }
+
         // TODO(malte): we only support one level of condition nesting at this point :(
         let l = match *ct.left.as_ref() {
             ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
@@ -304,7 +380,7 @@ impl SqlToMirConverter {
                 MirNodeType::Project {
                     emit: columns.clone(),
                     literals: vec![],
-                    arithmetic: vec![],
+                    expressions: vec![],
                 },
                 vec![parent.clone()],
                 vec![],
@@ -463,11 +539,19 @@ impl SqlToMirConverter {
         }
     }
 
-    pub(super) fn named_base_to_mir(&mut self, name: &str, query: &SqlQuery) -> MirQuery {
+    /// Produces the MIR for a base table. If a base of the same name already exists,
+    /// `renamed_columns` lists the (old, new) names of its columns that are to be renamed, rather
+    /// than dropped and added anew.
+    pub(super) fn named_base_to_mir(
+        &mut self,
+        name: &str,
+        query: &SqlQuery,
+        renamed_columns: &[(String, String)],
+    ) -> MirQuery {
         match *query {
             SqlQuery::CreateTable(ref ctq) => {
                 assert_eq!(name, ctq.table.name);
-                let n = self.make_base_node(&name, &ctq.fields, ctq.keys.as_ref());
+                let n = self.make_base_node(&name, &ctq.fields, ctq.keys.as_ref(), renamed_columns);
                 let node_id = (String::from(name), self.schema_version);
                 use std::collections::hash_map::Entry;
                 if let Entry::Vacant(e) = self.nodes.entry(node_id) {
@@ -480,40 +564,65 @@ impl SqlToMirConverter {
         }
     }
 
-    pub(super) fn remove_query(&mut self, name: &str, mq: &MirQuery) {
-        use std::collections::VecDeque;
-
-        let v = self
-            .current
-            .remove(name)
-            .unwrap_or_else(|| panic!("no query named \"{}\"?", name));
+    pub(super) fn remove_query(&mut self, name: &str) {
+        let v = match self.current.remove(name) {
+            Some(v) => v,
+            // queries that exactly match an existing query have no MIR nodes of their own
+            None => return,
+        };
 
         let nodeid = (name.to_owned(), v);
         let leaf_mn = self.nodes.remove(&nodeid).unwrap();
 
-        assert_eq!(leaf_mn.borrow().name, mq.leaf.borrow().name);
-
-        // traverse the MIR query backwards, removing any nodes that we still have registered.
-        let mut q = VecDeque::new();
-        q.push_back(leaf_mn);
-
-        while !q.is_empty() {
-            let mnr = q.pop_front().unwrap();
-            let n = mnr.borrow_mut();
-            q.extend(n.ancestors.clone());
-            // node may not be registered, so don't bother checking return
-            match n.inner {
-                MirNodeType::Reuse { .. } | MirNodeType::Base { .. } => (),
-                _ => {
-                    self.nodes.remove(&(n.name.to_owned(), v));
+        // traverse the MIR query backwards, removing every node that no other query uses any more.
+        // Removed nodes are unlinked from their ancestors, so that the MIR graph does not keep
+        // them alive. Bases are only removed by `remove_base`, and other queries that reuse a
+        // node do so either through a child of the node, or through a `Reuse` node of their own.
+        let mut q = vec![leaf_mn];
+        while let Some(mnr) = q.pop() {
+            let ancestors = {
+                let n = mnr.borrow();
+                if let MirNodeType::Base { .. } = n.inner {
+                    continue;
                 }
+                if !n.children.is_empty() {
+                    continue;
+                }
+                n.ancestors.clone()
+            };
+
+            self.nodes.retain(|_, n| !Rc::ptr_eq(n, &mnr));
+            for a in ancestors {
+                a.borrow_mut().remove_child(mnr.clone());
+                q.push(a);
+            }
+        }
+    }
+
+    /// Returns a copy of the converter whose nodes are copied using `copy`.
+    pub(super) fn deep_clone(&self, copy: &mut MirGraphCopy) -> Self {
+        let mut converter = self.clone();
+        for node in converter.nodes.values_mut() {
+            *node = copy.node(node);
+        }
+        converter
+    }
+
+    /// Hands the MIR nodes of query `name` over to query `to`, which uses the same leaf, so that
+    /// they are removed along with `to` rather than with `name`.
+    pub(super) fn rename_query(&mut self, name: &str, to: &str) {
+        if let Some(v) = self.current.remove(name) {
+            if !self.current.contains_key(to) {
+                let leaf_mn = self.nodes[&(name.to_owned(), v)].clone();
+                self.current.insert(to.to_owned(), v);
+                self.nodes.insert((to.to_owned(), v), leaf_mn);
             }
         }
     }
 
-    pub(super) fn remove_base(&mut self, name: &str, mq: &MirQuery) {
+    pub(super) fn remove_base(&mut self, name: &str) {
         info!(self.log, "Removing base {} from SqlTomirconverter", name);
-        self.remove_query(name, mq);
+        self.remove_query(name);
         if self.base_schemas.remove(name).is_none() {
             warn!(
                 self.log,
@@ -590,6 +699,7 @@ impl SqlToMirConverter {
         name: &str,
         cols: &[ColumnSpecification],
         keys: Option<&Vec<TableKey>>,
+        renamed_columns: &[(String, String)],
     ) -> MirNodeRef {
         // have we seen a base of this name before?
         if self.base_schemas.contains_key(name) {
@@ -626,34 +736,55 @@ impl SqlToMirConverter {
                         existing_sv
                     );
 
-                    // Find out if this is a simple case of adding or removing a column
+                    // Find out if this is a simple case of adding, removing or renaming a column
                     let mut columns_added = Vec::new();
                     let mut columns_removed = Vec::new();
+                    let mut columns_renamed = Vec::new();
                     let mut columns_unchanged = Vec::new();
                     for c in cols {
                         if !schema.contains(c) {
-                            // new column
-                            columns_added.push(c);
+                            let renamed_from = renamed_columns
+                                .iter()
+                                .filter(|&&(_, ref new)| *new == c.column.name)
+                                .filter_map(|&(ref old, _)| {
+                                    schema.iter().find(|ec| {
+                                        ec.column.name == *old
+                                            && !cols.contains(ec)
+                                            && ec.sql_type == c.sql_type
+                                            && ec.constraints == c.constraints
+                                    })
+                                })
+                                .next();
+                            match renamed_from {
+                                // renamed column
+                                Some(ec) => columns_renamed.push((ec, c)),
+                                // new column
+                                None => columns_added.push(c),
+                            }
                         } else {
                             columns_unchanged.push(c);
                         }
                     }
                     for c in schema {
-                        if !cols.contains(c) {
+                        if !cols.contains(c) && !columns_renamed.iter().any(|&(ec, _)| ec == c) {
                             // dropped column
                             columns_removed.push(c);
                         }
                     }
 
-                    if !columns_unchanged.is_empty()
-                        && (!columns_added.is_empty() || !columns_removed.is_empty())
+                    if (!columns_unchanged.is_empty() || !columns_renamed.is_empty())
+                        && (!columns_added.is_empty()
+                            || !columns_removed.is_empty()
+                            || !columns_renamed.is_empty())
                     {
                         error!(
                             self.log,
-                            "base {}: add columns {:?}, remove columns {:?} over v{}",
+                            "base {}: add columns {:?}, remove columns {:?}, rename columns {:?} \
+                             over v{}",
                             name,
                             columns_added,
                             columns_removed,
+                            columns_renamed,
                             existing_sv
                         );
                         let existing_node = self.nodes[&(String::from(name), existing_sv)].clone();
@@ -667,6 +798,10 @@ impl SqlToMirConverter {
                         for added in &columns_added {
                             columns.push((*added).clone());
                         }
+                        for &(old, new) in &columns_renamed {
+                            let pos = columns.iter().position(|cc| cc == old).unwrap();
+                            columns[pos] = new.clone();
+                        }
                         for removed in &columns_removed {
                             let pos =
                                 columns
@@ -691,7 +826,12 @@ impl SqlToMirConverter {
                         let base_schemas = self.base_schemas.entry(String::from(name)).or_default();
                         base_schemas.push((self.schema_version, columns.clone()));
 
-                        return MirNode::adapt_base(existing_node, columns_added, columns_removed);
+                        return MirNode::adapt_base(
+                            existing_node,
+                            columns_added,
+                            columns_removed,
+                            columns_renamed,
+                        );
                     } else {
                         info!(self.log, "base table has complex schema change");
                         break;
@@ -981,6 +1121,54 @@ impl SqlToMirConverter {
         )
     }
 
+    /// Checks that the columns summed or averaged in `qg` hold numbers, as far as their base
+    /// table's schema tells, so that a query over other types is rejected when it is installed.
+    fn check_aggregated_types(&self, qg: &QueryGraph) -> Result<(), String> {
+        use nom_sql::FunctionArguments;
+        use nom_sql::FunctionExpression::*;
+        use nom_sql::SqlType;
+
+        let computed = match qg.relations.get("computed_columns") {
+            Some(computed) => computed,
+            None => return Ok(()),
+        };
+        for ccol in &computed.columns {
+            let (name, col, reals) = match **ccol.function.as_ref().unwrap() {
+                // sums are always integral
+                Sum(FunctionArguments::Column(ref col), _) => ("SUM", col, false),
+                Avg(FunctionArguments::Column(ref col), _) => ("AVG", col, true),
+                _ => continue,
+            };
+            // columns of views have no declared type
+            let schema = match col.table.as_ref().and_then(|t| self.base_schemas.get(t)) {
+                Some(schemas) => schemas.iter().max_by_key(|&&(sv, _)| sv).unwrap(),
+                None => continue,
+            };
+            let spec = match schema.1.iter().find(|cs| cs.column.name == col.name) {
+                Some(spec) => spec,
+                None => continue,
+            };
+            let numeric = match spec.sql_type {
+                SqlType::Bool
+                | SqlType::Int(_)
+                | SqlType::UnsignedInt(_)
+                | SqlType::Bigint(_)
+                | SqlType::UnsignedBigint(_)
+                | SqlType::Tinyint(_)
+                | SqlType::UnsignedTinyint(_) => true,
+                SqlType::Double | SqlType::Float | SqlType::Real | SqlType::Decimal(..) => reals,
+                _ => false,
+            };
+            if !numeric {
+                return Err(format!(
+                    "can't compute {} over column {} of type {}",
+                    name, col.name, spec.sql_type
+                ));
+            }
+        }
+        Ok(())
+    }
+
     fn make_function_node(
         &self,
         name: &str,
@@ -1006,7 +1194,32 @@ impl SqlToMirConverter {
                 let mut dist_col = Vec::new();
                 dist_col.push(over);
                 dist_col.extend(group_cols.clone());
-                let node = self.make_distinct_node(&new_name, parent, dist_col.clone());
+                let node = self.make_distinct_node(&new_name, parent, dist_col.clone(), dist_col);
+                out_nodes.push(node.clone());
+                out_nodes.push(self.make_grouped_node(
+                    name,
+                    &func_col,
+                    (node, &over, over_else),
+                    group_cols,
+                    t,
+                    cond,
+                ));
+                out_nodes
+            } else if let GroupedNodeType::Aggregation(Aggregation::COUNT_DISTINCT) = t {
+                // count how often each value occurs in a group, and then the values that occur at
+                // all; the counts let the distinct count handle deletes without recomputation
+                let counts_name = name.to_owned() + "_values";
+                let counts_col = Column::new(None, &counts_name);
+                let mut value_cols = group_cols.clone();
+                value_cols.push(over);
+                let node = self.make_grouped_node(
+                    &counts_name,
+                    &counts_col,
+                    (parent, &over, None),
+                    value_cols,
+                    GroupedNodeType::Aggregation(Aggregation::COUNT),
+                    None,
+                );
                 out_nodes.push(node.clone());
                 out_nodes.push(self.make_grouped_node(
                     name,
@@ -1067,10 +1280,25 @@ impl SqlToMirConverter {
                 false,
                 Some(condition),
             ),
-            Count(FunctionArguments::Column(ref col), distinct) => mknode(
+            Count(FunctionArguments::Column(ref col), false) => mknode(
                 &Column::from(col),
                 None,
                 GroupedNodeType::Aggregation(Aggregation::COUNT),
+                false,
+                None,
+            ),
+            // the distinct values are counted through a per-value count, see `mknode`
+            Count(FunctionArguments::Column(ref col), true) => mknode(
+                &Column::from(col),
+                None,
+                GroupedNodeType::Aggregation(Aggregation::COUNT_DISTINCT),
+                false,
+                None,
+            ),
+            Avg(FunctionArguments::Column(ref col), distinct) => mknode(
+                &Column::from(col),
+                None,
+                GroupedNodeType::Aggregation(Aggregation::AVG),
                 distinct,
                 None,
             ),
@@ -1304,6 +1532,16 @@ impl SqlToMirConverter {
                 on_right: right_join_columns,
                 project: fields.clone(),
             },
+            JoinType::Right => MirNodeType::RightJoin {
+                on_left: left_join_columns,
+                on_right: right_join_columns,
+                project: fields.clone(),
+            },
+            JoinType::Full => MirNodeType::FullJoin {
+                on_left: left_join_columns,
+                on_right: right_join_columns,
+                project: fields.clone(),
+            },
         };
         trace!(self.log, "Added join node {:?}", inner);
         MirNode::new(
@@ -1337,13 +1575,13 @@ impl SqlToMirConverter {
         name: &str,
         parent_node: MirNodeRef,
         proj_cols: Vec<&Column>,
-        arithmetic: Vec<(String, ArithmeticExpression)>,
+        expressions: Vec<(String, ProjectExpression<Column>)>,
         literals: Vec<(String, DataType)>,
         is_leaf: bool,
     ) -> MirNodeRef {
         //assert!(proj_cols.iter().all(|c| c.table == parent_name));
 
-        let names: Vec<String> = arithmetic
+        let names: Vec<String> = expressions
             .iter()
             .map(|&(ref n, _)| n.clone())
             .chain(literals.iter().map(|&(ref n, _)| n.clone()))
@@ -1379,7 +1617,7 @@ impl SqlToMirConverter {
             MirNodeType::Project {
                 emit: emit_cols,
                 literals,
-                arithmetic,
+                expressions,
             },
             vec![parent_node.clone()],
             vec![],
@@ -1391,6 +1629,7 @@ impl SqlToMirConverter {
         name: &str,
         parent: MirNodeRef,
         group_by: Vec<&Column>,
+        key: Vec<&Column>,
     ) -> MirNodeRef {
         let combined_columns = parent.borrow().columns().to_vec();
 
@@ -1401,6 +1640,7 @@ impl SqlToMirConverter {
             combined_columns,
             MirNodeType::Distinct {
                 group_by: group_by.into_iter().cloned().collect(),
+                key: key.into_iter().cloned().collect(),
             },
             vec![parent.clone()],
             vec![],
@@ -1426,8 +1666,6 @@ impl SqlToMirConverter {
             None => None,
         };
 
-        assert_eq!(limit.offset, 0); // Non-zero offset not supported
-
         // make the new operator and record its metadata
         MirNode::new(
             name,
@@ -1437,7 +1675,7 @@ impl SqlToMirConverter {
                 order,
                 group_by: group_by.into_iter().cloned().collect(),
                 k: limit.limit as usize,
-                offset: 0,
+                offset: limit.offset as usize,
             },
             vec![parent.clone()],
             vec![],
@@ -1554,16 +1792,15 @@ impl SqlToMirConverter {
         node_count: usize,
         universe: &str,
     ) -> Option<MirNodeRef> {
-        let arith_and_lit_columns_needed =
-            value_columns_needed_for_predicates(&qg.columns, &qg.global_predicates);
+        let arith_and_lit_columns_needed = value_columns_needed_for_predicates(&qg);
 
         if !arith_and_lit_columns_needed.is_empty() {
-            let projected_arithmetic: Vec<(String, ArithmeticExpression)> =
+            let projected_arithmetic: Vec<(String, ProjectExpression<Column>)> =
                 arith_and_lit_columns_needed
                     .iter()
                     .filter_map(|&(_, ref oc)| match oc {
                         OutputColumn::Arithmetic(ref ac) => {
-                            Some((ac.name.clone(), ac.expression.clone()))
+                            Some((ac.name.clone(), to_project_expression(&ac.expression)))
                         }
                         OutputColumn::Data(_) => None,
                         OutputColumn::Literal(_) => None,
@@ -1625,6 +1862,8 @@ impl SqlToMirConverter {
         use crate::controller::sql::mir::grouped::make_predicates_above_grouped;
         use crate::controller::sql::mir::join::make_joins;
 
+        self.check_aggregated_types(qg)?;
+
         let mut nodes_added: Vec<MirNodeRef>;
         let mut new_node_count = 0;
 
@@ -1990,18 +2229,21 @@ impl SqlToMirConverter {
 
             // We may already have added some of the arithmetic and literal columns
             let (_, already_computed): (Vec<_>, Vec<_>) =
-                value_columns_needed_for_predicates(&qg.columns, &qg.global_predicates)
-                    .into_iter()
-                    .unzip();
-            let projected_arithmetic: Vec<(String, ArithmeticExpression)> = qg
+                value_columns_needed_for_predicates(&qg).into_iter().unzip();
+            let projected_arithmetic: Vec<(String, ProjectExpression<Column>)> = qg
                 .columns
                 .iter()
                 .filter_map(|oc| match *oc {
                     OutputColumn::Arithmetic(ref ac) => {
                         if !already_computed.contains(oc) {
-                            Some((ac.name.clone(), ac.expression.clone()))
+                            Some((ac.name.clone(), to_project_expression(&ac.expression)))
                         } else {
-                            projected_columns.push(Column::new(None, &ac.name));
+                            // arithmetic that the query is parameterized on is already projected
+                            // along with the other parameter columns
+                            let c = Column::new(None, &ac.name);
+                            if !projected_columns.contains(&c) {
+                                projected_columns.push(c);
+                            }
                             None
                         }
                     }
@@ -2037,6 +2279,49 @@ impl SqlToMirConverter {
                 false
             };
 
+            // for `SELECT DISTINCT`, project the output before making it distinct, so that rows
+            // are compared over exactly what the query returns. the distinct records are looked up
+            // by the leaf's key, so that replays for a key fill all the records it may change.
+            let (final_node, projected_columns, projected_arithmetic, projected_literals) =
+                if qg.distinct {
+                    let project_node = self.make_project_node(
+                        &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
+                        final_node,
+                        projected_columns.iter().collect(),
+                        projected_arithmetic,
+                        projected_literals,
+                        false,
+                    );
+                    new_node_count += 1;
+                    nodes_added.push(project_node.clone());
+
+                    let columns = project_node.borrow().columns().to_vec();
+                    let key: Vec<_> = if has_bogokey {
+                        vec![Column::new(None, "bogokey")]
+                    } else if has_leaf {
+                        qg.parameters().into_iter().map(Column::from).collect()
+                    } else {
+                        vec![]
+                    };
+                    let distinct_node = self.make_distinct_node(
+                        &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
+                        project_node,
+                        columns.iter().collect(),
+                        key.iter().collect(),
+                    );
+                    new_node_count += 1;
+                    nodes_added.push(distinct_node.clone());
+
+                    (distinct_node, columns, vec![], vec![])
+                } else {
+                    (
+                        final_node,
+                        projected_columns,
+                        projected_arithmetic,
+                        projected_literals,
+                    )
+                };
+
             let ident = if has_leaf {
                 format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat)
             } else {
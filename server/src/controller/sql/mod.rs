@@ -13,13 +13,14 @@ use self::reuse::ReuseConfig;
 use super::mir_to_flow::mir_query_to_flow_parts;
 use crate::controller::Migration;
 use crate::ReuseConfigType;
+use ::mir::node::{MirGraphCopy, MirNodeType};
 use ::mir::query::{MirQuery, QueryFlowParts};
 use ::mir::reuse as mir_reuse;
 use ::mir::Column;
 use ::mir::MirNodeRef;
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
-use nom_sql::{ArithmeticBase, CreateTableStatement, SqlQuery};
+use nom_sql::{ArithmeticBase, CreateTableStatement, SqlQuery, TableKey};
 use nom_sql::{CompoundSelectOperator, CompoundSelectStatement, SelectStatement};
 use petgraph::graph::NodeIndex;
 
@@ -102,6 +103,21 @@ impl SqlIncorporator {
         }
     }
 
+    /// Returns a copy of the incorporator that has its own copy of the MIR graph, so that adding
+    /// queries to the copy does not affect this incorporator.
+    pub(super) fn deep_clone(&self) -> Self {
+        let mut copy = MirGraphCopy::default();
+        let mut inc = self.clone();
+        inc.mir_converter = self.mir_converter.deep_clone(&mut copy);
+        for mq in inc.base_mir_queries.values_mut() {
+            *mq = mq.deep_copy(&mut copy);
+        }
+        for mq in inc.mir_queries.values_mut() {
+            *mq = mq.deep_copy(&mut copy);
+        }
+        inc
+    }
+
     /// Disable node reuse for future migrations.
     #[allow(unused)]
     pub(super) fn disable_reuse(&mut self) {
@@ -152,6 +168,65 @@ impl SqlIncorporator {
         }
     }
 
+    /// Adapts an existing base table to `query`, the `CREATE TABLE` statement that results from
+    /// applying an `ALTER TABLE` to the table's current definition. `renamed_columns` lists the
+    /// (old, new) names of any columns that were renamed.
+    ///
+    /// Columns are added to and dropped from the existing base node, and any keys that `query`
+    /// declares over and above those of the current definition are added as indices on it.
+    pub(super) fn alter_base(
+        &mut self,
+        query: SqlQuery,
+        renamed_columns: &[(String, String)],
+        mig: &mut Migration,
+    ) -> Result<QueryFlowParts, String> {
+        let name = match query {
+            SqlQuery::CreateTable(ref ctq) => ctq.table.name.clone(),
+            _ => unreachable!("ALTER TABLE must produce a CREATE TABLE statement"),
+        };
+        let old_keys = match self.base_schemas.get(&name) {
+            Some(ctq) => ctq.keys.clone().unwrap_or_default(),
+            None => return Err(format!("no base table named {} to alter", name)),
+        };
+
+        let q = self.rewrite_query(query, mig)?;
+        let qfp = self.add_base_via_mir(&name, &q, renamed_columns, mig);
+        self.leaf_addresses.insert(name.clone(), qfp.query_leaf);
+
+        let keys = match q {
+            SqlQuery::CreateTable(ref ctq) => ctq.keys.clone().unwrap_or_default(),
+            _ => unreachable!(),
+        };
+        let keys = keys.into_iter().filter(|k| !old_keys.contains(k));
+        self.index_keys(&name, keys, qfp.query_leaf, mig);
+
+        Ok(qfp)
+    }
+
+    /// Adds an index on the base node `node` of table `name` for each of `keys` that declares an
+    /// index, so that the table's rows can be matched on the key's columns.
+    fn index_keys<I>(&self, name: &str, keys: I, node: NodeIndex, mig: &mut Migration)
+    where
+        I: IntoIterator<Item = TableKey>,
+    {
+        let mir = &self.base_mir_queries[name];
+        for key in keys {
+            let columns = match key {
+                TableKey::Key(_, columns) | TableKey::UniqueKey(_, columns) => columns,
+                _ => continue,
+            };
+            let columns = columns
+                .iter()
+                .map(|c| {
+                    mir.leaf
+                        .borrow()
+                        .column_id_for_column(&Column::from(c), None)
+                })
+                .collect();
+            mig.add_index(node, columns);
+        }
+    }
+
     pub(super) fn get_base_schema(&self, name: &str) -> Option<CreateTableStatement> {
         self.base_schemas.get(name).cloned()
     }
@@ -178,6 +253,53 @@ impl SqlIncorporator {
         self.leaf_addresses.values().any(|nn| *nn == ni)
     }
 
+    /// Finds a query that reads one of the given `columns` of the table `table`, and returns the
+    /// query's name along with the column.
+    pub(super) fn query_reading(&self, table: &str, columns: &[&str]) -> Option<(String, String)> {
+        let reads = |t: &Option<String>| t.as_ref().map(String::as_str) == Some(table);
+        let mut named: Vec<_> = self.named_queries.iter().collect();
+        named.sort();
+        for (name, hash) in named {
+            let qg = match self.query_graphs.get(hash) {
+                Some(qg) => qg,
+                None => continue,
+            };
+            let mut read: Vec<_> = qg
+                .referenced_columns()
+                .into_iter()
+                .filter(|c| reads(&c.table))
+                .map(|c| c.name.clone())
+                .collect();
+
+            // ordering is not part of the query graph, but of the query's top-k node
+            for ((_, _), mq) in self.mir_queries.iter().filter(|((h, _), _)| h == hash) {
+                let mut nodes = vec![mq.leaf.clone()];
+                while let Some(n) = nodes.pop() {
+                    let n = n.borrow();
+                    match n.inner {
+                        MirNodeType::TopK {
+                            order: Some(ref order),
+                            ..
+                        } => read.extend(
+                            order
+                                .iter()
+                                .filter(|(c, _)| reads(&c.table))
+                                .map(|(c, _)| c.name.clone()),
+                        ),
+                        MirNodeType::Reuse { ref node } => nodes.push(node.clone()),
+                        _ => {}
+                    }
+                    nodes.extend(n.ancestors.iter().cloned());
+                }
+            }
+
+            if let Some(c) = columns.iter().find(|c| read.iter().any(|r| r == *c)) {
+                return Some((name.clone(), (*c).to_owned()));
+            }
+        }
+        None
+    }
+
     pub(super) fn get_queries_for_node(&self, ni: NodeIndex) -> Vec<String> {
         self.leaf_addresses
             .iter()
@@ -284,7 +406,9 @@ impl SqlIncorporator {
                         OutputColumn::Data(ref dc) => dc.function.is_none(),
                     });
 
-                    if predicates_match && no_grouped_columns {
+                    // a `SELECT DISTINCT` looks up its distinct rows by the existing reader's key,
+                    // so a reader on other parameters can't share it
+                    if predicates_match && no_grouped_columns && !qg.distinct {
                         // QGs are identical, except for parameters (or their order)
                         info!(
                             self.log,
@@ -412,10 +536,13 @@ impl SqlIncorporator {
         &mut self,
         query_name: &str,
         query: &SqlQuery,
+        renamed_columns: &[(String, String)],
         mut mig: &mut Migration,
     ) -> QueryFlowParts {
         // first, compute the MIR representation of the SQL query
-        let mut mir = self.mir_converter.named_base_to_mir(query_name, query);
+        let mut mir = self
+            .mir_converter
+            .named_base_to_mir(query_name, query, renamed_columns);
 
         trace!(self.log, "Base node MIR: {:#?}", mir);
 
@@ -571,49 +698,38 @@ impl SqlIncorporator {
             .leaf_addresses
             .remove(query_name)
             .expect("tried to remove unknown query");
+        self.view_schemas.remove(query_name);
+        let qg_hash = self.named_queries.remove(query_name);
 
-        let qg_hash = self
-            .named_queries
-            .remove(query_name)
-            .unwrap_or_else(|| panic!("missing query hash for named query \"{}\"", query_name));
-        let mir = &self.mir_queries[&(qg_hash, mig.universe())];
-
-        // traverse self.leaf__addresses
-        if self
+        let sharing = self
             .leaf_addresses
-            .values()
-            .find(|&id| *id == nodeid)
-            .is_none()
-        {
-            // ok to remove
-
-            // remove local state for query
-
-            // traverse and remove MIR nodes
-            // TODO(malte): implement this
-            self.mir_converter.remove_query(query_name, mir);
-
-            // clean up local state
-            self.mir_queries.remove(&(qg_hash, mig.universe())).unwrap();
-            self.query_graphs.remove(&qg_hash).unwrap();
-            self.view_schemas.remove(query_name).unwrap();
-
-            // trigger reader node removal
-            Some(nodeid)
-        } else {
-            // more than one query uses this leaf
-            // don't remove node yet!
+            .iter()
+            .find(|&(_, id)| *id == nodeid)
+            .map(|(name, _)| name.clone());
+        if let Some(other) = sharing {
+            // another query uses the same leaf, so none of the query's nodes can go yet. The
+            // other query inherits the query graph and the MIR nodes instead, so that they are
+            // removed along with it.
+            if let Some(qg_hash) = qg_hash {
+                self.named_queries.entry(other.clone()).or_insert(qg_hash);
+            }
+            self.mir_converter.rename_query(query_name, &other);
+            return None;
+        }
 
-            // TODO(malte): implement this
-            self.mir_converter.remove_query(query_name, mir);
+        if let Some(qg_hash) = qg_hash {
+            // the query's MIR must not be reused once its nodes are gone
+            self.mir_queries.remove(&(qg_hash, mig.universe()));
+            if !self.named_queries.values().any(|h| *h == qg_hash) {
+                self.query_graphs.remove(&qg_hash);
+            }
+        }
 
-            // clean up state for this query
-            self.mir_queries.remove(&(qg_hash, mig.universe())).unwrap();
-            self.query_graphs.remove(&qg_hash).unwrap();
-            self.view_schemas.remove(query_name).unwrap();
+        // remove the MIR nodes that no other query uses
+        self.mir_converter.remove_query(query_name);
 
-            None
-        }
+        // trigger removal of the query's data-flow nodes
+        Some(nodeid)
     }
 
     pub(super) fn remove_base(&mut self, name: &str) {
@@ -625,11 +741,11 @@ impl SqlIncorporator {
             );
         }
 
-        let mir = self
-            .base_mir_queries
-            .get(name)
+        self.leaf_addresses.remove(name);
+        self.base_mir_queries
+            .remove(name)
             .unwrap_or_else(|| panic!("tried to remove unknown base {}", name));
-        self.mir_converter.remove_base(name, mir)
+        self.mir_converter.remove_base(name)
     }
 
     fn register_query(
@@ -793,17 +909,26 @@ impl SqlIncorporator {
         let mut fq = q.clone();
         for sq in fq.extract_subqueries() {
             use self::passes::subqueries::{
-                field_with_table_name, query_from_condition_base, Subquery,
+                is_correlated, nested_select_for_view, query_from_condition_base, Subquery,
             };
             use nom_sql::{JoinRightSide, Table};
             match sq {
                 Subquery::InComparison(cond_base) => {
                     let (sq, column) = query_from_condition_base(&cond_base);
+                    if let SqlQuery::Select(ref st) = sq {
+                        if is_correlated(st) {
+                            // these would need the subquery to be evaluated per row of the
+                            // outer query. the usual rewrite, as a join against the subquery
+                            // grouped by the correlated columns, is left to the user.
+                            return Err(format!(
+                                "correlated subqueries are not supported, rewrite it as a join: {}",
+                                st
+                            ));
+                        }
+                    }
 
-                    let qfp = self
-                        .add_parsed_query(sq, None, false, mig)
-                        .expect("failed to add subquery");
-                    *cond_base = field_with_table_name(qfp.name.clone(), column);
+                    let qfp = self.add_parsed_query(sq, None, false, mig)?;
+                    *cond_base = nested_select_for_view(qfp.name.clone(), column);
                 }
                 Subquery::InJoin(join_right_side) => {
                     *join_right_side = match *join_right_side {
@@ -903,7 +1028,12 @@ impl SqlIncorporator {
                     .unwrap()
             }
             SqlQuery::Select(sq) => self.add_select_query(&query_name, &sq, is_leaf, mig)?.0,
-            ref q @ SqlQuery::CreateTable { .. } => self.add_base_via_mir(&query_name, &q, mig),
+            SqlQuery::CreateTable(ref ctq) => {
+                let qfp = self.add_base_via_mir(&query_name, &q, &[], mig);
+                let keys = ctq.keys.clone().unwrap_or_default();
+                self.index_keys(&query_name, keys, qfp.query_leaf, mig);
+                qfp
+            }
             q => panic!("unhandled query type in recipe: {:?}", q),
         };
 
@@ -1,6 +1,6 @@
 use nom_sql::{
-    Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
-    JoinConstraint, JoinRightSide, SqlQuery,
+    ArithmeticBase, Column, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, JoinConstraint, JoinRightSide, SqlQuery,
 };
 
 use std::collections::HashMap;
@@ -62,6 +62,19 @@ fn rewrite_conditional(
             };
             ConditionExpression::LogicalOp(rewritten_ct)
         }
+        ConditionExpression::Arithmetic(mut e) => {
+            for base in vec![&mut e.left, &mut e.right] {
+                if let ArithmeticBase::Column(ref mut c) = *base {
+                    if let Some(t) = c.table.as_ref().and_then(|t| table_aliases.get(t)) {
+                        c.table = Some(t.clone());
+                    }
+                }
+            }
+            ConditionExpression::Arithmetic(e)
+        }
+        ConditionExpression::Bracketed(inner) => {
+            ConditionExpression::Bracketed(Box::new(rewrite_conditional(table_aliases, *inner)))
+        }
         x => x,
     }
 }
@@ -1,6 +1,6 @@
 use nom_sql::{
-    Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
-    FunctionArguments, SqlQuery, Table,
+    ArithmeticBase, Column, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, FunctionArguments, SqlQuery, Table,
 };
 
 use std::collections::HashMap;
@@ -25,11 +25,14 @@ fn extract_condition_columns(ce: &ConditionExpression) -> Vec<Column> {
             ..
         }) => {
             let mut cols = vec![];
-            if let ConditionExpression::Base(ConditionBase::Field(ref f)) = **left {
-                cols.push(f.clone());
-            }
-            if let ConditionExpression::Base(ConditionBase::Field(ref f)) = **right {
-                cols.push(f.clone());
+            for side in &[left, right] {
+                match ***side {
+                    ConditionExpression::Base(ConditionBase::Field(ref f)) => cols.push(f.clone()),
+                    ConditionExpression::Arithmetic(_) | ConditionExpression::Bracketed(_) => {
+                        cols.extend(extract_condition_columns(side))
+                    }
+                    _ => (),
+                }
             }
 
             cols
@@ -37,7 +40,13 @@ fn extract_condition_columns(ce: &ConditionExpression) -> Vec<Column> {
         ConditionExpression::NegationOp(ref inner) => extract_condition_columns(inner),
         ConditionExpression::Bracketed(ref inner) => extract_condition_columns(inner),
         ConditionExpression::Base(_) => unreachable!(),
-        ConditionExpression::Arithmetic(_) => unimplemented!(),
+        ConditionExpression::Arithmetic(ref e) => [&e.left, &e.right]
+            .iter()
+            .filter_map(|b| match **b {
+                ArithmeticBase::Column(ref c) => Some(c.clone()),
+                ArithmeticBase::Scalar(_) => None,
+            })
+            .collect(),
     }
 }
 
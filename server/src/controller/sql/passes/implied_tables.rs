@@ -50,6 +50,19 @@ where
             left: Box::new(rewrite_conditional(expand_columns, *left, avail_tables)),
             right: Box::new(rewrite_conditional(expand_columns, *right, avail_tables)),
         }),
+        Arithmetic(mut e) => {
+            for base in vec![&mut e.left, &mut e.right] {
+                if let ArithmeticBase::Column(ref mut c) = *base {
+                    *c = expand_columns(c.clone(), avail_tables);
+                }
+            }
+            Arithmetic(e)
+        }
+        Bracketed(inner) => Bracketed(Box::new(rewrite_conditional(
+            expand_columns,
+            *inner,
+            avail_tables,
+        ))),
         x => x,
     }
 }
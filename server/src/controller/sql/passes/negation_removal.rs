@@ -31,6 +31,23 @@ fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
             ref mut left,
             ref mut right,
         }) => {
+            if *operator == Operator::In {
+                // `x NOT IN (...)` parses as an IN whose right-hand side is negated. There is no
+                // inverse operator to flip to, so we keep that form and fold any negation from
+                // above into it.
+                if negate {
+                    let rhs = mem::replace(
+                        &mut **right,
+                        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)),
+                    );
+                    **right = match rhs {
+                        ConditionExpression::NegationOp(inner) => *inner,
+                        rhs => ConditionExpression::NegationOp(Box::new(rhs)),
+                    };
+                }
+                return;
+            }
+
             if negate {
                 *operator = match *operator {
                     Operator::Equal => Operator::NotEqual,
@@ -61,8 +78,7 @@ fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
         ConditionExpression::Bracketed(ref mut inner) => {
             normalize_condition_expr(inner, negate);
         }
-        ConditionExpression::Base(_) => {}
-        ConditionExpression::Arithmetic(_) => unimplemented!(),
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => {}
     }
 }
 
@@ -122,4 +138,35 @@ mod tests {
         normalize_condition_expr(&mut expr, false);
         assert_eq!(expr, target);
     }
+
+    #[test]
+    fn it_keeps_negated_in() {
+        let in_list = |negated: bool| {
+            let list = ConditionExpression::Base(ConditionBase::LiteralList(vec![1.into()]));
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::In,
+                left: Box::new(ConditionExpression::Base(ConditionBase::Field("a".into()))),
+                right: Box::new(if negated {
+                    ConditionExpression::NegationOp(Box::new(list))
+                } else {
+                    list
+                }),
+            })
+        };
+
+        // a NOT IN (1)
+        let mut expr = in_list(true);
+        normalize_condition_expr(&mut expr, false);
+        assert_eq!(expr, in_list(true));
+
+        // NOT (a IN (1))
+        let mut expr = ConditionExpression::NegationOp(Box::new(in_list(false)));
+        normalize_condition_expr(&mut expr, false);
+        assert_eq!(expr, in_list(true));
+
+        // NOT (a NOT IN (1))
+        let mut expr = ConditionExpression::NegationOp(Box::new(in_list(true)));
+        normalize_condition_expr(&mut expr, false);
+        assert_eq!(expr, in_list(false));
+    }
 }
@@ -1,5 +1,8 @@
 use nom_sql::ConditionExpression::*;
-use nom_sql::{Column, ConditionBase, ConditionExpression, JoinRightSide, SqlQuery};
+use nom_sql::{
+    Column, ConditionBase, ConditionExpression, FieldDefinitionExpression, JoinRightSide,
+    SelectStatement, SqlQuery, Table,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum Subquery<'a> {
@@ -26,22 +29,70 @@ fn extract_subqueries_from_condition(ce: &mut ConditionExpression) -> Vec<Subque
             NestedSelect(_) => vec![Subquery::InComparison(cb)],
             _ => vec![],
         },
-        Arithmetic(_) => unimplemented!(),
+        Arithmetic(_) => vec![],
     }
 }
 
-pub fn field_with_table_name(name: String, column: Column) -> ConditionBase {
-    ConditionBase::Field(Column {
-        name: column.name.clone(),
-        alias: column.alias.clone(),
-        table: Some(name),
-        function: column.function.clone(),
+/// Replaces the body of an `IN` subquery with a trivial selection of `column` from the view
+/// `name` that now computes the subquery. The comparison stays in place so that the query graph
+/// can turn it into a semi- or anti-join against that view.
+pub fn nested_select_for_view(name: String, column: Column) -> ConditionBase {
+    ConditionBase::NestedSelect(Box::new(SelectStatement {
+        tables: vec![Table::from(name.as_str())],
+        fields: vec![FieldDefinitionExpression::Col(Column {
+            name: column.alias.clone().unwrap_or(column.name),
+            alias: None,
+            table: Some(name),
+            function: None,
+        })],
+        ..Default::default()
+    }))
+}
+
+fn collect_columns<'a>(ce: &'a ConditionExpression, columns: &mut Vec<&'a Column>) {
+    match *ce {
+        ComparisonOp(ref ct) | LogicalOp(ref ct) => {
+            collect_columns(&*ct.left, columns);
+            collect_columns(&*ct.right, columns);
+        }
+        NegationOp(ref bce) | Bracketed(ref bce) => collect_columns(&*bce, columns),
+        Base(ConditionBase::Field(ref c)) => columns.push(c),
+        Base(_) | Arithmetic(_) => (),
+    }
+}
+
+/// Returns true if the subquery's WHERE clause refers to a table that does not appear in the
+/// subquery itself, i.e., to a column of an enclosing query. References to the universe context
+/// tables (`UserContext`, `GroupContext`) are resolved per universe and thus don't count.
+///
+/// Only uncorrelated subqueries in `IN` and `NOT IN` comparisons are supported; `EXISTS` and
+/// `NOT EXISTS` can't be expressed since nom-sql does not parse them.
+pub fn is_correlated(st: &SelectStatement) -> bool {
+    let mut columns = Vec::new();
+    if let Some(ref ce) = st.where_clause {
+        collect_columns(ce, &mut columns);
+    }
+
+    let tables: Vec<&Table> = st
+        .tables
+        .iter()
+        .chain(st.join.iter().filter_map(|jc| match jc.right {
+            JoinRightSide::Table(ref t) => Some(t),
+            _ => None,
+        }))
+        .collect();
+
+    columns.into_iter().any(|c| match c.table {
+        Some(ref t) if t.starts_with("UserContext") || t.starts_with("GroupContext") => false,
+        Some(ref t) => !tables
+            .iter()
+            .any(|tbl| tbl.name == *t || tbl.alias.as_ref() == Some(t)),
+        None => false,
     })
 }
 
 pub fn query_from_condition_base(cond: &ConditionBase) -> (SqlQuery, Column) {
     use nom_sql::ConditionBase::NestedSelect;
-    use nom_sql::FieldDefinitionExpression;
     let (sq, column);
     match *cond {
         NestedSelect(ref bst) => {
@@ -126,6 +177,27 @@ mod tests {
         assert_eq!(res, vec![Subquery::InComparison(&mut expected)]);
     }
 
+    #[test]
+    fn it_detects_correlated_subqueries() {
+        let subquery = |other: &str| SelectStatement {
+            tables: vec![Table::from("role")],
+            fields: vec![FieldDefinitionExpression::Col(Column::from("role.userid"))],
+            where_clause: Some(ComparisonOp(ConditionTree {
+                operator: Operator::Equal,
+                left: wrap(Field(Column::from("role.userid"))),
+                right: wrap(Field(Column::from(other))),
+            })),
+            ..Default::default()
+        };
+
+        // select role.userid from role where role.userid = post.author
+        assert!(is_correlated(&subquery("post.author")));
+        // select role.userid from role where role.userid = role.delegate
+        assert!(!is_correlated(&subquery("role.delegate")));
+        // select role.userid from role where role.userid = UserContext.id
+        assert!(!is_correlated(&subquery("UserContext.id")));
+    }
+
     #[test]
     fn it_does_nothing_for_flat_queries() {
         // select userid from role where type=1
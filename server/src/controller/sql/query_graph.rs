@@ -1,10 +1,13 @@
 use nom_sql::SelectStatement;
 use nom_sql::{
-    ArithmeticBase, ArithmeticExpression, Column, ConditionBase, ConditionExpression,
-    ConditionTree, FieldDefinitionExpression, FieldValueExpression, JoinConstraint, JoinOperator,
-    JoinRightSide, Literal, Operator, Table,
+    ArithmeticBase, ArithmeticExpression, Column, ColumnOrLiteral, ConditionBase,
+    ConditionExpression, ConditionTree, FieldDefinitionExpression, FieldValueExpression,
+    FunctionArguments, FunctionExpression, JoinConstraint, JoinOperator, JoinRightSide, Literal,
+    Operator, Table,
 };
 
+use crate::controller::sql::query_utils::{arithmetic_operand, ReferredTables};
+
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
@@ -147,6 +150,12 @@ pub struct QueryGraphNode {
 pub enum QueryGraphEdge {
     Join(Vec<ConditionTree>),
     LeftJoin(Vec<ConditionTree>),
+    RightJoin(Vec<ConditionTree>),
+    FullJoin(Vec<ConditionTree>),
+    /// `x IN (SELECT ...)`, with the subquery turned into a view ahead of time.
+    SemiJoin(Vec<ConditionTree>),
+    /// `x NOT IN (SELECT ...)`, with the subquery turned into a view ahead of time.
+    AntiJoin(Vec<ConditionTree>),
     GroupBy(Vec<Column>),
 }
 
@@ -164,6 +173,8 @@ pub struct QueryGraph {
     pub join_order: Vec<JoinRef>,
     /// Global predicates (not associated with a particular relation)
     pub global_predicates: Vec<ConditionExpression>,
+    /// Whether the query only returns distinct rows (`SELECT DISTINCT`).
+    pub distinct: bool,
 }
 
 impl QueryGraph {
@@ -174,6 +185,7 @@ impl QueryGraph {
             columns: Vec::new(),
             join_order: Vec::new(),
             global_predicates: Vec::new(),
+            distinct: false,
         }
     }
 
@@ -188,6 +200,53 @@ impl QueryGraph {
             })
     }
 
+    /// Returns every column that the query refers to, whether it projects, filters on, joins on,
+    /// groups by or aggregates over the column.
+    ///
+    /// The query's SQL rewrite passes have resolved aliases and implied tables by the time its
+    /// query graph is built, so each column is qualified with the table it belongs to.
+    pub fn referenced_columns(&self) -> Vec<&Column> {
+        let mut columns = Vec::new();
+        for qgn in self.relations.values() {
+            for c in &qgn.columns {
+                column_references(c, &mut columns);
+            }
+            for p in &qgn.predicates {
+                condition_references(p, &mut columns);
+            }
+            columns.extend(&qgn.parameters);
+        }
+        for e in self.edges.values() {
+            match *e {
+                QueryGraphEdge::Join(ref jps)
+                | QueryGraphEdge::LeftJoin(ref jps)
+                | QueryGraphEdge::RightJoin(ref jps)
+                | QueryGraphEdge::FullJoin(ref jps)
+                | QueryGraphEdge::SemiJoin(ref jps)
+                | QueryGraphEdge::AntiJoin(ref jps) => {
+                    for jp in jps {
+                        condition_references(&jp.left, &mut columns);
+                        condition_references(&jp.right, &mut columns);
+                    }
+                }
+                QueryGraphEdge::GroupBy(ref cols) => columns.extend(cols),
+            }
+        }
+        for oc in &self.columns {
+            match *oc {
+                OutputColumn::Data(ref c) => column_references(c, &mut columns),
+                OutputColumn::Arithmetic(ref ac) => {
+                    arithmetic_references(&ac.expression, &mut columns)
+                }
+                OutputColumn::Literal(_) => {}
+            }
+        }
+        for p in &self.global_predicates {
+            condition_references(p, &mut columns);
+        }
+        columns
+    }
+
     pub fn exact_hash(&self) -> u64 {
         use std::collections::hash_map::DefaultHasher;
 
@@ -215,10 +274,64 @@ impl Hash for QueryGraph {
         self.columns.hash(state);
         self.join_order.hash(state);
         self.global_predicates.hash(state);
+        self.distinct.hash(state);
+    }
+}
+
+/// Adds `c` to `columns`, or, if `c` is an aggregation, the columns that it aggregates over.
+fn column_references<'a>(c: &'a Column, columns: &mut Vec<&'a Column>) {
+    let args = match c.function.as_ref().map(|f| &**f) {
+        None => {
+            columns.push(c);
+            return;
+        }
+        Some(FunctionExpression::CountStar) => return,
+        Some(FunctionExpression::Avg(ref args, _))
+        | Some(FunctionExpression::Count(ref args, _))
+        | Some(FunctionExpression::Sum(ref args, _))
+        | Some(FunctionExpression::Max(ref args))
+        | Some(FunctionExpression::Min(ref args))
+        | Some(FunctionExpression::GroupConcat(ref args, _)) => args,
+    };
+    match *args {
+        FunctionArguments::Column(ref c) => column_references(c, columns),
+        FunctionArguments::Conditional(ref cw) => {
+            condition_references(&cw.condition, columns);
+            for e in std::iter::once(&cw.then_expr).chain(cw.else_expr.iter()) {
+                if let ColumnOrLiteral::Column(ref c) = *e {
+                    column_references(c, columns);
+                }
+            }
+        }
+    }
+}
+
+fn arithmetic_references<'a>(e: &'a ArithmeticExpression, columns: &mut Vec<&'a Column>) {
+    for b in &[&e.left, &e.right] {
+        if let ArithmeticBase::Column(ref c) = **b {
+            column_references(c, columns);
+        }
     }
 }
 
-/// Splits top level conjunctions into multiple predicates
+/// Adds the columns that `ce` refers to to `columns`.
+fn condition_references<'a>(ce: &'a ConditionExpression, columns: &mut Vec<&'a Column>) {
+    match *ce {
+        ConditionExpression::ComparisonOp(ref ct) | ConditionExpression::LogicalOp(ref ct) => {
+            condition_references(&ct.left, columns);
+            condition_references(&ct.right, columns);
+        }
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            condition_references(inner, columns)
+        }
+        ConditionExpression::Arithmetic(ref e) => arithmetic_references(e, columns),
+        ConditionExpression::Base(ConditionBase::Field(ref c)) => column_references(c, columns),
+        // subqueries have been turned into views of their own by now
+        ConditionExpression::Base(_) => {}
+    }
+}
+
+/// Splits top level conjunctions into multiple predicates/// Splits top level conjunctions into multiple predicates
 fn split_conjunctions(ces: Vec<ConditionExpression>) -> Vec<ConditionExpression> {
     let mut new_ces = Vec::new();
     for ce in ces {
@@ -243,6 +356,89 @@ fn split_conjunctions(ces: Vec<ConditionExpression>) -> Vec<ConditionExpression>
     new_ces
 }
 
+fn contains_subquery(ce: &ConditionExpression) -> bool {
+    match *ce {
+        ConditionExpression::ComparisonOp(ref ct) | ConditionExpression::LogicalOp(ref ct) => {
+            contains_subquery(&ct.left) || contains_subquery(&ct.right)
+        }
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            contains_subquery(inner)
+        }
+        ConditionExpression::Base(ConditionBase::NestedSelect(_)) => true,
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => false,
+    }
+}
+
+/// Splits `IN` and `NOT IN` comparisons against subqueries off the top-level conjunction of a
+/// WHERE clause and returns what remains of it. By now, each subquery has been replaced by a
+/// selection of a single column from the view that computes it, so the comparison becomes a
+/// semi- or anti-join predicate between the outer table and that view.
+fn extract_subquery_joins(
+    ce: &ConditionExpression,
+    semi: &mut Vec<ConditionTree>,
+    anti: &mut Vec<ConditionTree>,
+) -> Result<Option<ConditionExpression>, String> {
+    if !contains_subquery(ce) {
+        return Ok(Some(ce.clone()));
+    }
+
+    match *ce {
+        ConditionExpression::LogicalOp(ref ct) if ct.operator == Operator::And => {
+            let left = extract_subquery_joins(&ct.left, semi, anti)?;
+            let right = extract_subquery_joins(&ct.right, semi, anti)?;
+            Ok(match (left, right) {
+                (Some(l), Some(r)) => Some(ConditionExpression::LogicalOp(ConditionTree {
+                    operator: Operator::And,
+                    left: Box::new(l),
+                    right: Box::new(r),
+                })),
+                (Some(ce), None) | (None, Some(ce)) => Some(ce),
+                (None, None) => None,
+            })
+        }
+        ConditionExpression::Bracketed(ref inner) => extract_subquery_joins(inner, semi, anti),
+        ConditionExpression::ComparisonOp(ref ct) if ct.operator == Operator::In => {
+            let (sq, negated) = match *ct.right {
+                ConditionExpression::Base(ConditionBase::NestedSelect(ref sq)) => (sq, false),
+                ConditionExpression::NegationOp(ref inner) => match **inner {
+                    ConditionExpression::Base(ConditionBase::NestedSelect(ref sq)) => (sq, true),
+                    _ => return Err(format!("unsupported subquery comparison: {}", ce)),
+                },
+                _ => return Err(format!("unsupported subquery comparison: {}", ce)),
+            };
+            match *ct.left {
+                ConditionExpression::Base(ConditionBase::Field(ref f)) if f.table.is_some() => (),
+                _ => {
+                    return Err(format!(
+                        "left-hand side of IN subquery must be a column: {}",
+                        ce
+                    ))
+                }
+            }
+            let column = match sq.fields[..] {
+                [FieldDefinitionExpression::Col(ref c)] => c.clone(),
+                _ => return Err(format!("IN subquery must select a single column: {}", ce)),
+            };
+
+            let jp = ConditionTree {
+                operator: Operator::Equal,
+                left: ct.left.clone(),
+                right: Box::new(ConditionExpression::Base(ConditionBase::Field(column))),
+            };
+            if negated {
+                anti.push(jp);
+            } else {
+                semi.push(jp);
+            }
+            Ok(None)
+        }
+        _ => Err(format!(
+            "subqueries can only be combined with other predicates using AND: {}",
+            ce
+        )),
+    }
+}
+
 // 1. Extract any predicates with placeholder parameters. We push these down to the edge
 //    nodes, since we cannot instantiate the parameters inside the data flow graph (except for
 //    non-materialized nodes).
@@ -357,6 +553,32 @@ fn classify_conditionals(
             join.extend(new_join);
             params.extend(new_params);
         }
+        ConditionExpression::ComparisonOp(ref ct)
+            if arithmetic_operand(&ct.left).is_some()
+                || arithmetic_operand(&ct.right).is_some() =>
+        {
+            // arithmetic compared against a placeholder makes the query parameterized on the
+            // arithmetic's value, which the query computes as an extra column
+            if let Some(e) = parameter_expression(ce) {
+                params.push(computed_parameter(e));
+                return;
+            }
+
+            // comparison involving arithmetic. If it only reads columns of a single table and
+            // compares against a literal, it is local to that table; otherwise it can only be
+            // evaluated once all the tables it reads have been joined.
+            let tables = ce.referred_tables();
+            let is_literal = |ce: &ConditionExpression| match *ce {
+                ConditionExpression::Base(ConditionBase::Literal(_)) => true,
+                _ => false,
+            };
+            if tables.len() == 1 && (is_literal(&ct.left) || is_literal(&ct.right)) {
+                let e = local.entry(tables[0].name.clone()).or_default();
+                e.push(ce.clone());
+            } else {
+                global.push(ce.clone());
+            }
+        }
         ConditionExpression::ComparisonOp(ref ct) => {
             // atomic selection predicate
             if let ConditionExpression::Base(ref l) = *ct.left.as_ref() {
@@ -423,7 +645,9 @@ fn classify_conditionals(
                             }
                         }
                         ConditionBase::LiteralList(_) => (),
-                        ConditionBase::NestedSelect(_) => unimplemented!(),
+                        ConditionBase::NestedSelect(_) => {
+                            unreachable!("subqueries should have been turned into joins earlier")
+                        }
                     }
                 };
             };
@@ -451,13 +675,154 @@ fn classify_conditionals(
         ConditionExpression::NegationOp(_) => {
             panic!("negation should have been removed earlier");
         }
-        ConditionExpression::Arithmetic(_) => unimplemented!(),
+        ConditionExpression::Arithmetic(_) => {
+            panic!("encountered unexpected standalone arithmetic expression");
+        }
+    }
+}
+
+/// Rejects comparisons of arithmetic expressions that filters can't evaluate.
+///
+/// Query parameters become the keys of the query's view, which is only looked up by exact key, so
+/// arithmetic can only be compared against a parameter for equality, and must read some column.
+/// Arithmetic can otherwise only be compared against columns, literals, and other arithmetic,
+/// using the ordering operators or `IS`.
+fn check_arithmetic_comparisons(ce: &ConditionExpression) -> Result<(), String> {
+    match *ce {
+        ConditionExpression::LogicalOp(ref ct) => {
+            check_arithmetic_comparisons(&ct.left)?;
+            check_arithmetic_comparisons(&ct.right)
+        }
+        ConditionExpression::Bracketed(ref inner) => check_arithmetic_comparisons(inner),
+        ConditionExpression::ComparisonOp(ref ct)
+            if arithmetic_operand(&ct.left).is_some()
+                || arithmetic_operand(&ct.right).is_some() =>
+        {
+            if let Some(e) = parameter_expression(ce) {
+                if ct.operator != Operator::Equal {
+                    return Err(format!(
+                        "can only compare arithmetic against a query parameter using =, not in \"{}\"",
+                        ce
+                    ));
+                }
+                let reads_column = |b: &ArithmeticBase| match *b {
+                    ArithmeticBase::Column(_) => true,
+                    ArithmeticBase::Scalar(_) => false,
+                };
+                if !reads_column(&e.left) && !reads_column(&e.right) {
+                    return Err(format!(
+                        "query parameters can't be compared against constant arithmetic in \"{}\"",
+                        ce
+                    ));
+                }
+                return Ok(());
+            }
+
+            let operand = |ce: &ConditionExpression| match *ce {
+                ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => {
+                    Err(format!("can't use a query parameter in \"{}\"", ce))
+                }
+                ConditionExpression::Base(ConditionBase::Field(_))
+                | ConditionExpression::Base(ConditionBase::Literal(_)) => Ok(()),
+                _ if arithmetic_operand(ce).is_some() => Ok(()),
+                _ => Err(format!("can't compare arithmetic against \"{}\"", ce)),
+            };
+            operand(&ct.left)?;
+            operand(&ct.right)?;
+            match ct.operator {
+                Operator::Equal
+                | Operator::NotEqual
+                | Operator::Greater
+                | Operator::GreaterOrEqual
+                | Operator::Less
+                | Operator::LessOrEqual
+                | Operator::Is => Ok(()),
+                ref op => Err(format!("can't apply {} to arithmetic in \"{}\"", op, ce)),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the arithmetic that `ce` compares against a query parameter, if it is such a comparison.
+fn parameter_expression(ce: &ConditionExpression) -> Option<&ArithmeticExpression> {
+    let is_placeholder = |ce: &ConditionExpression| match *ce {
+        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => true,
+        _ => false,
+    };
+    match *ce {
+        ConditionExpression::ComparisonOp(ref ct) if is_placeholder(&ct.right) => {
+            arithmetic_operand(&ct.left)
+        }
+        ConditionExpression::ComparisonOp(ref ct) if is_placeholder(&ct.left) => {
+            arithmetic_operand(&ct.right)
+        }
+        _ => None,
+    }
+}
+
+/// Collects the arithmetic that `ce` compares against query parameters.
+fn parameter_expressions<'a>(
+    ce: &'a ConditionExpression,
+    exprs: &mut Vec<&'a ArithmeticExpression>,
+) {
+    match *ce {
+        ConditionExpression::LogicalOp(ref ct) => {
+            parameter_expressions(&ct.left, exprs);
+            parameter_expressions(&ct.right, exprs);
+        }
+        ConditionExpression::Bracketed(ref inner) => parameter_expressions(inner, exprs),
+        _ => exprs.extend(parameter_expression(ce)),
+    }
+}
+
+/// Returns the column that holds the value of arithmetic compared against a query parameter. The
+/// query computes this column, and its view is keyed on it.
+fn computed_parameter(e: &ArithmeticExpression) -> Column {
+    Column {
+        name: e.to_string(),
+        alias: None,
+        table: None,
+        function: None,
+    }
+}
+
+/// Splits the equi-join predicate off a JOIN's ON clause and returns it along with any further
+/// conditions in the clause.
+fn split_join_condition(
+    on: &ConditionExpression,
+) -> (ConditionExpression, Vec<ConditionExpression>) {
+    if let ConditionExpression::Bracketed(ref inner) = *on {
+        return split_join_condition(inner);
+    }
+
+    let mut conds = split_conjunctions(vec![on.clone()]);
+    let is_equi_join = |ce: &ConditionExpression| match *ce {
+        ConditionExpression::ComparisonOp(ref ct) => {
+            ct.operator == Operator::Equal
+                && match (&*ct.left, &*ct.right) {
+                    (
+                        ConditionExpression::Base(ConditionBase::Field(_)),
+                        ConditionExpression::Base(ConditionBase::Field(_)),
+                    ) => true,
+                    _ => false,
+                }
+        }
+        _ => false,
+    };
+    match conds.iter().position(is_equi_join) {
+        Some(i) if conds.len() > 1 => {
+            let jp = conds.remove(i);
+            (jp, conds)
+        }
+        _ => (on.clone(), vec![]),
     }
 }
 
 #[allow(clippy::cognitive_complexity)]
 pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
     let mut qg = QueryGraph::new();
+    qg.distinct = st.distinct;
 
     // a handy closure for making new relation nodes
     let new_node =
@@ -533,6 +898,7 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         Box::new(ConditionExpression::Base(ConditionBase::Field(col)))
     };
     // 2a. Explicit joins
+    let mut join_conditions = Vec::new();
     // The table specified in the query is available for USING joins.
     let prev_table = Some(st.tables.last().as_ref().unwrap().name.clone());
     for jc in &st.join {
@@ -543,8 +909,24 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                 let right_table;
 
                 let join_pred = match jc.constraint {
-                    JoinConstraint::On(ref cond) => {
-                        use crate::controller::sql::query_utils::ReferredTables;
+                    JoinConstraint::On(ref on) => {
+                        // inner joins may carry conditions besides the equi-join predicate;
+                        // these are evaluated just like WHERE predicates.
+                        let (cond, rest) = split_join_condition(on);
+                        if !rest.is_empty() {
+                            match jc.operator {
+                                JoinOperator::Join | JoinOperator::InnerJoin => {
+                                    join_conditions.extend(rest)
+                                }
+                                _ => {
+                                    return Err(format!(
+                                        "outer joins only support a single equi-join condition: {}",
+                                        on
+                                    ))
+                                }
+                            }
+                        }
+                        let cond = &cond;
 
                         // find all distinct tables mentioned in the condition
                         // conditions for now.
@@ -619,18 +1001,74 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     .edges
                     .entry((left_table.clone(), right_table.clone()))
                     .or_insert_with(|| match jc.operator {
-                        JoinOperator::LeftJoin => QueryGraphEdge::LeftJoin(vec![join_pred]),
+                        JoinOperator::LeftJoin | JoinOperator::LeftOuterJoin => {
+                            QueryGraphEdge::LeftJoin(vec![join_pred])
+                        }
                         JoinOperator::Join | JoinOperator::InnerJoin => {
                             QueryGraphEdge::Join(vec![join_pred])
                         }
-                        _ => unimplemented!(),
+                        // recipes parse right and full joins into these (see `recipe::outer_join`)
+                        JoinOperator::StraightJoin => QueryGraphEdge::RightJoin(vec![join_pred]),
+                        JoinOperator::CrossJoin => QueryGraphEdge::FullJoin(vec![join_pred]),
                     });
             }
             _ => unimplemented!(),
         }
     }
 
-    if let Some(ref cond) = st.where_clause {
+    // 2b. Semi- and anti-joins for IN and NOT IN subqueries
+    let mut semi_join_predicates = Vec::new();
+    let mut anti_join_predicates = Vec::new();
+    let where_clause = match st.where_clause {
+        Some(ref cond) => {
+            extract_subquery_joins(cond, &mut semi_join_predicates, &mut anti_join_predicates)?
+        }
+        None => None,
+    };
+    // any further conditions from explicit joins' ON clauses join the WHERE predicates
+    let where_clause = join_conditions
+        .into_iter()
+        .fold(where_clause, |acc, cond| match acc {
+            None => Some(cond),
+            Some(acc) => Some(ConditionExpression::LogicalOp(ConditionTree {
+                operator: Operator::And,
+                left: Box::new(acc),
+                right: Box::new(cond),
+            })),
+        });
+    if let Some(ref cond) = where_clause {
+        check_arithmetic_comparisons(cond)?;
+    }
+    let subquery_joins = semi_join_predicates
+        .into_iter()
+        .map(|jp| (jp, false))
+        .chain(anti_join_predicates.into_iter().map(|jp| (jp, true)));
+    for (jp, anti) in subquery_joins {
+        let table_of = |ce: &ConditionExpression| match *ce {
+            ConditionExpression::Base(ConditionBase::Field(ref f)) => f.table.clone().unwrap(),
+            _ => unreachable!(),
+        };
+        let (outer, view) = (table_of(&jp.left), table_of(&jp.right));
+        qg.relations
+            .entry(view.clone())
+            .or_insert_with(|| new_node(view.clone(), Vec::new(), st));
+
+        let e = qg.edges.entry((outer, view)).or_insert_with(|| {
+            if anti {
+                QueryGraphEdge::AntiJoin(vec![])
+            } else {
+                QueryGraphEdge::SemiJoin(vec![])
+            }
+        });
+        match *e {
+            QueryGraphEdge::SemiJoin(ref mut preds) if !anti => preds.push(jp),
+            QueryGraphEdge::AntiJoin(ref mut preds) if anti => preds.push(jp),
+            _ => return Err(format!("conflicting subquery joins for {:?}", jp)),
+        }
+    }
+
+    let mut computed_parameters = Vec::new();
+    if let Some(ref cond) = where_clause {
         let mut local_predicates = HashMap::new();
         let mut global_predicates = Vec::new();
         let mut query_parameters = Vec::new();
@@ -692,9 +1130,32 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         //    node for this query. Such columns will be carried all the way through the operators
         //    implementing the query (unlike in a traditional query plan, where the predicates on
         //    parameters might be evaluated sooner).
+        let mut parameter_exprs = Vec::new();
+        parameter_expressions(cond, &mut parameter_exprs);
         for column in query_parameters.into_iter() {
             match column.table {
-                None => panic!("each parameter's column must have an associated table!"),
+                None => {
+                    let e = match parameter_exprs
+                        .iter()
+                        .find(|e| e.to_string() == column.name)
+                    {
+                        Some(e) => (*e).clone(),
+                        None => panic!("each parameter's column must have an associated table!"),
+                    };
+                    // the parameter is on arithmetic, which isn't a column of any one table. We
+                    // register it with a table it reads, so that it keeps its place among the
+                    // parameters, and compute it along with the query's other output columns.
+                    let table = ConditionExpression::Arithmetic(Box::new(e.clone()))
+                        .referred_tables()
+                        .remove(0);
+                    let rel = qg.relations.get_mut(&table.name).unwrap();
+                    rel.parameters.push(column.clone());
+                    computed_parameters.push(ArithmeticColumn {
+                        name: column.name.clone(),
+                        table: None,
+                        expression: e,
+                    });
+                }
                 Some(ref table) => {
                     let rel = qg.relations.get_mut(table).unwrap();
                     if !rel.columns.contains(&column) {
@@ -768,6 +1229,24 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         }
     }
 
+    // 4a. Add the arithmetic that the query is parameterized on, unless it already projects it
+    if !computed_parameters.is_empty()
+        && (st.group_by.is_some() || qg.relations.contains_key("computed_columns"))
+    {
+        return Err(String::from(
+            "aggregated queries can't compare arithmetic against query parameters",
+        ));
+    }
+    for ac in computed_parameters {
+        let projected = qg.columns.iter().any(|oc| match *oc {
+            OutputColumn::Arithmetic(ref c) => c.name == ac.name,
+            _ => false,
+        });
+        if !projected {
+            qg.columns.push(OutputColumn::Arithmetic(ac));
+        }
+    }
+
     match st.group_by {
         None => (),
         Some(ref clause) => {
@@ -813,7 +1292,11 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                         })
                         .collect::<Vec<_>>(),
                 ),
-                QueryGraphEdge::LeftJoin(ref jps) => qg.join_order.extend(
+                QueryGraphEdge::LeftJoin(ref jps)
+                | QueryGraphEdge::RightJoin(ref jps)
+                | QueryGraphEdge::FullJoin(ref jps)
+                | QueryGraphEdge::SemiJoin(ref jps)
+                | QueryGraphEdge::AntiJoin(ref jps) => qg.join_order.extend(
                     jps.iter()
                         .enumerate()
                         .map(|(idx, _)| JoinRef {
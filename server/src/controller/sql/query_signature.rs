@@ -117,7 +117,11 @@ impl Signature for QueryGraph {
         for e in self.edges.values() {
             match *e {
                 QueryGraphEdge::Join(ref join_predicates)
-                | QueryGraphEdge::LeftJoin(ref join_predicates) => {
+                | QueryGraphEdge::LeftJoin(ref join_predicates)
+                | QueryGraphEdge::RightJoin(ref join_predicates)
+                | QueryGraphEdge::FullJoin(ref join_predicates)
+                | QueryGraphEdge::SemiJoin(ref join_predicates)
+                | QueryGraphEdge::AntiJoin(ref join_predicates) => {
                     for p in join_predicates {
                         for c in &p.contained_columns() {
                             attrs_vec.push(c);
@@ -162,6 +166,8 @@ impl Signature for QueryGraph {
         for c in proj_columns {
             c.hash(&mut hasher);
         }
+        // a `SELECT DISTINCT` query returns different rows than the same query without it
+        self.distinct.hash(&mut hasher);
 
         QuerySignature {
             relations: rels,
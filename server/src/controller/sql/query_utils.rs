@@ -1,4 +1,6 @@
-use nom_sql::{ConditionBase, ConditionExpression, SqlQuery, Table};
+use nom_sql::{
+    ArithmeticBase, ArithmeticExpression, ConditionBase, ConditionExpression, SqlQuery, Table,
+};
 
 pub trait ReferredTables {
     fn referred_tables(&self) -> Vec<Table>;
@@ -47,8 +49,32 @@ impl ReferredTables for ConditionExpression {
                     }
                 }
             }
+            ConditionExpression::Arithmetic(ref e) => {
+                for b in &[&e.left, &e.right] {
+                    if let ArithmeticBase::Column(ref c) = **b {
+                        if let Some(ref t) = c.table {
+                            let t = Table::from(t.as_ref());
+                            if !tables.contains(&t) {
+                                tables.push(t);
+                            }
+                        }
+                    }
+                }
+            }
+            ConditionExpression::Bracketed(ref inner) => return inner.referred_tables(),
+            ConditionExpression::Base(ConditionBase::Literal(_))
+            | ConditionExpression::Base(ConditionBase::LiteralList(_)) => (),
             _ => unimplemented!(),
         }
         tables
     }
 }
+
+/// Returns the arithmetic expression that forms one side of a comparison, if any.
+pub fn arithmetic_operand(ce: &ConditionExpression) -> Option<&ArithmeticExpression> {
+    match *ce {
+        ConditionExpression::Arithmetic(ref e) => Some(e),
+        ConditionExpression::Bracketed(ref inner) => arithmetic_operand(inner),
+        _ => None,
+    }
+}
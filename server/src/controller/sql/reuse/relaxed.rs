@@ -93,6 +93,50 @@ impl Relaxed {
                         _ => return None,
                     }
                 }
+                QueryGraphEdge::RightJoin(_) => {
+                    if !new_qg.edges.contains_key(srcdst) {
+                        return None;
+                    }
+                    let new_qge = &new_qg.edges[srcdst];
+                    match *new_qge {
+                        QueryGraphEdge::RightJoin(_) => {}
+                        // If there is no matching RightJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
+                QueryGraphEdge::FullJoin(_) => {
+                    if !new_qg.edges.contains_key(srcdst) {
+                        return None;
+                    }
+                    let new_qge = &new_qg.edges[srcdst];
+                    match *new_qge {
+                        QueryGraphEdge::FullJoin(_) => {}
+                        // If there is no matching FullJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
+                QueryGraphEdge::SemiJoin(_) => {
+                    if !new_qg.edges.contains_key(srcdst) {
+                        return None;
+                    }
+                    let new_qge = &new_qg.edges[srcdst];
+                    match *new_qge {
+                        QueryGraphEdge::SemiJoin(_) => {}
+                        // If there is no matching SemiJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
+                QueryGraphEdge::AntiJoin(_) => {
+                    if !new_qg.edges.contains_key(srcdst) {
+                        return None;
+                    }
+                    let new_qge = &new_qg.edges[srcdst];
+                    match *new_qge {
+                        QueryGraphEdge::AntiJoin(_) => {}
+                        // If there is no matching AntiJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
                 _ => continue,
             }
         }
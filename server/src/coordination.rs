@@ -34,7 +34,12 @@ pub enum CoordinationPayload {
     /// Assign a new domain for a worker to run.
     AssignDomain(DomainBuilder),
     /// Remove a running domain from a worker.
-    RemoveDomain,
+    RemoveDomain {
+        /// The domain being removed.
+        domain: DomainIndex,
+        /// The shard of the domain being removed.
+        shard: usize,
+    },
     /// Domain connectivity gossip.
     DomainBooted(DomainDescriptor),
     /// Create a new security universe.
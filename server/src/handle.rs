@@ -1,10 +1,12 @@
 use crate::controller::migrate::Migration;
+use crate::snapshot;
 use crate::startup::Event;
 use dataflow::prelude::*;
 use noria::consensus::Authority;
 use noria::prelude::*;
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
 use std::sync::Arc;
 use stream_cancel::Trigger;
 
@@ -62,6 +64,27 @@ impl<A: Authority + 'static> Handle<A> {
         }
     }
 
+    /// Make the controller fail to reach the domain shard it sends to after `n` more sends to
+    /// domain shards have succeeded.
+    #[cfg(test)]
+    pub(super) fn fail_sends_after(&mut self, n: usize) {
+        self.event_tx
+            .as_mut()
+            .unwrap()
+            .send(Event::FailSendsAfter(n))
+            .unwrap();
+    }
+
+    /// Make the controller tell domains about the next transaction, but never apply its writes.
+    #[cfg(test)]
+    pub(super) fn abandon_next_transaction(&mut self) {
+        self.event_tx
+            .as_mut()
+            .unwrap()
+            .send(Event::AbandonNextTransaction)
+            .unwrap();
+    }
+
     #[doc(hidden)]
     pub async fn migrate<F, T>(&mut self, f: F) -> T
     where
@@ -131,6 +154,51 @@ impl<A: Authority + 'static> Handle<A> {
             .map_err(|e| format_err!("failed to make table: {:?}", e))
     }
 
+    /// Restore a snapshot taken with `ControllerHandle::snapshot` from the directory `path`.
+    ///
+    /// The deployment must not have any base tables yet. The snapshot's rows are loaded with
+    /// `Table::bulk_load`, and may be restored into a deployment with different sharding.
+    pub async fn restore<P: AsRef<Path>>(&mut self, path: P) -> Result<(), failure::Error> {
+        let dir = path.as_ref();
+        let manifest = snapshot::read_manifest(dir)?;
+        if !self.inputs().await?.is_empty() {
+            bail!("can only restore into a deployment without base tables");
+        }
+
+        if let Some(config) = manifest.security_config {
+            self.set_security_config(config).await?;
+        }
+        let mut recipes = manifest.recipes.iter();
+        if let Some(recipe) = recipes.next() {
+            self.install_recipe(recipe).await?;
+        }
+        for recipe in recipes {
+            self.extend_recipe(recipe).await?;
+        }
+
+        for (name, parts) in &manifest.tables {
+            let mut table = self.table(name).await?;
+            for part in 0..*parts {
+                let mut bad_chunk = None;
+                let rows = snapshot::read_rows(dir, name, part)?
+                    .scan(&mut bad_chunk, |bad_chunk, chunk| match chunk {
+                        Ok(rows) => Some(rows),
+                        Err(e) => {
+                            **bad_chunk = Some(e);
+                            None
+                        }
+                    })
+                    .fuse()
+                    .flatten();
+                table.bulk_load(rows).await?;
+                if let Some(e) = bad_chunk {
+                    bail!("failed to read rows of {}: {}", name, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Inform the local instance that it should exit.
     pub fn shutdown(&mut self) {
         if let Some(kill) = self.kill.take() {
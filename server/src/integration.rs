@@ -13,6 +13,7 @@ use noria::consensus::LocalAuthority;
 use noria::DataType;
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use std::{env, thread};
@@ -328,6 +329,90 @@ async fn broad_recursing_upquery() {
     }
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn sharded_shuffle_between_joins() {
+    let mut g = start_simple("sharded_shuffle_between_joins").await;
+
+    // every base is sharded by its key, but each join needs its left input sharded by the join
+    // column, so both joins sit below a shuffle from one sharding directly to another:
+    //
+    //  a [by id]   b [by id]
+    //      |           |
+    //      +-----+-----+ [a shuffled to b_id]
+    //            |
+    //           j1        c [by id]
+    //            |            |
+    //            +-----+------+ [j1 shuffled to c_id]
+    //                  |
+    //                 j2
+    //                  |
+    //              reader [by c_id]
+    g.migrate(|mig| {
+        let a = mig.add_base(
+            "a",
+            &["id", "b_id", "c_id"],
+            Base::new(vec![]).with_key(vec![0]),
+        );
+        let b = mig.add_base("b", &["id", "x"], Base::new(vec![]).with_key(vec![0]));
+        let c = mig.add_base("c", &["id", "y"], Base::new(vec![]).with_key(vec![0]));
+        let j1 = mig.add_ingredient(
+            "j1",
+            &["id", "b_id", "c_id", "x"],
+            Join::new(a, b, JoinType::Inner, vec![L(0), B(1, 0), L(2), R(1)]),
+        );
+        let j2 = mig.add_ingredient(
+            "j2",
+            &["id", "b_id", "c_id", "x", "y"],
+            Join::new(
+                j1,
+                c,
+                JoinType::Inner,
+                vec![L(0), L(1), B(2, 0), L(3), R(1)],
+            ),
+        );
+        mig.maintain("reader".to_string(), j2, &[2]);
+    })
+    .await;
+
+    let mut a = g.table("a").await.unwrap();
+    let mut b = g.table("b").await.unwrap();
+    let mut c = g.table("c").await.unwrap();
+    let mut reader = g.view("reader").await.unwrap();
+
+    b.perform_all((0..3).map(|i| vec![DataType::Int(i), DataType::Int(i * 10)]))
+        .await
+        .unwrap();
+    c.perform_all((0..5).map(|i| vec![DataType::Int(i), DataType::Int(i * 100)]))
+        .await
+        .unwrap();
+    a.perform_all(
+        (0..30).map(|i| vec![DataType::Int(i), DataType::Int(i % 3), DataType::Int(i % 5)]),
+    )
+    .await
+    .unwrap();
+
+    sleep().await;
+
+    // the upquery has to go through both shuffles, and the pieces from every shard of each
+    // shuffled node have to be merged before the requesting shard can fill the key
+    let rows = reader.lookup(&[DataType::Int(2)], true).await.unwrap();
+    assert_eq!(rows.len(), 6);
+    for row in rows.iter() {
+        let b_id: i32 = row.get("b_id").unwrap();
+        assert_eq!(row.get::<i32>("c_id").unwrap(), 2);
+        assert_eq!(row.get::<i32>("x").unwrap(), b_id * 10);
+        assert_eq!(row.get::<i32>("y").unwrap(), 200);
+    }
+
+    // and later writes make it through both shuffles to the filled key
+    a.insert(vec![30.into(), 1.into(), 2.into()]).await.unwrap();
+    sleep().await;
+
+    let rows = reader.lookup(&[DataType::Int(2)], true).await.unwrap();
+    assert_eq!(rows.len(), 7);
+    assert!(rows.iter().any(|row| row.get::<i32>("id").unwrap() == 30));
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn base_mutation() {
     use noria::{Modification, Operation};
@@ -409,6 +494,76 @@ async fn base_mutation() {
     );
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn base_mutation_where() {
+    use noria::error::TableError;
+    use noria::{Modification, Operation};
+
+    let mut g = start_simple("base_mutation_where").await;
+    g.migrate(|mig| {
+        let a = mig.add_base(
+            "a",
+            &["id", "user", "n"],
+            Base::new(vec![]).with_key(vec![0]),
+        );
+        mig.add_index(a, vec![1]);
+        mig.maintain_anonymous(a, &[1]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+
+    for (id, user) in &[(1, 10), (2, 10), (3, 20)] {
+        write
+            .insert(vec![(*id).into(), (*user).into(), 0.into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    // update all of a user's rows
+    write
+        .update_where(
+            1,
+            10,
+            vec![(2, Modification::Apply(Operation::Add, 5.into()))],
+        )
+        .await
+        .unwrap();
+    sleep().await;
+    let mut rows: Vec<Vec<DataType>> = read.lookup(&[10.into()], true).await.unwrap().into();
+    rows.sort();
+    assert_eq!(
+        rows,
+        vec![
+            vec![1.into(), 10.into(), 5.into()],
+            vec![2.into(), 10.into(), 5.into()],
+        ]
+    );
+
+    // the primary key cannot be modified
+    match write
+        .update_where(1, 20, vec![(0, Modification::Set(4.into()))])
+        .await
+    {
+        Err(TableError::PrimaryKeyModification(0)) => {}
+        r => panic!(
+            "expected primary key modification to be rejected, got {:?}",
+            r
+        ),
+    }
+
+    // delete all of a user's rows without knowing their keys
+    write.delete_where(1, 10).await.unwrap();
+    sleep().await;
+    assert!(read.lookup(&[10.into()], true).await.unwrap().is_empty());
+    assert_eq!(
+        read.lookup(&[20.into()], true).await.unwrap(),
+        vec![vec![3.into(), 20.into(), 0.into()]]
+    );
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn shared_interdomain_ancestor() {
     // set up graph
@@ -702,6 +857,138 @@ async fn it_works_with_sql_recipe() {
     assert_eq!(result[0][0], 2.into());
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_range_lookups() {
+    let mut g = start_simple_unsharded("it_works_with_range_lookups").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        QUERY CarsById: SELECT id, brand FROM Car WHERE id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("CarsById").await.unwrap();
+
+    let brands = vec!["Volvo", "Saab", "Volkswagen", "Skoda"];
+    for (i, &brand) in brands.iter().enumerate() {
+        mutator.insert(vec![i.into(), brand.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    // the range is filled by a single replay
+    let result = getter
+        .lookup_range(vec![1.into()]..vec![3.into()], true)
+        .await
+        .unwrap();
+    assert_eq!(
+        result,
+        vec![
+            vec![1.into(), "Saab".into()],
+            vec![2.into(), "Volkswagen".into()]
+        ]
+    );
+
+    // and later writes to keys in the range, including new ones, show up in it
+    mutator.insert(vec![5.into(), "Audi".into()]).await.unwrap();
+    mutator.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+
+    let result = getter.lookup_range(vec![1.into()].., true).await.unwrap();
+    assert_eq!(
+        result,
+        vec![
+            vec![2.into(), "Volkswagen".into()],
+            vec![3.into(), "Skoda".into()],
+            vec![5.into(), "Audi".into()]
+        ]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_rejects_range_lookups_through_sharded_paths() {
+    let mut g = start_simple("it_rejects_range_lookups_through_sharded_paths").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("CarsByBrand").await.unwrap();
+
+    mutator.insert(vec![1.into(), "Saab".into()]).await.unwrap();
+    mutator
+        .insert(vec![2.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // the view is keyed on a different column than the table is sharded by, so a range would
+    // have to be replayed from every shard of the table. rather than wait forever, the lookup
+    // fails.
+    let result = getter
+        .lookup_range(vec!["A".into()]..vec!["Z".into()], true)
+        .await;
+    match result {
+        Err(noria::error::ViewError::RangeNotSupported) => {}
+        r => panic!("expected range lookup to be rejected, got {:?}", r),
+    }
+
+    // lookups of single keys still work
+    let result = getter.lookup(&["Saab".into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), "Saab".into()]]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_pages_through_topk() {
+    let mut g = start_simple_unsharded("it_pages_through_topk").await;
+    let sql = "
+        CREATE TABLE Post (id int, author int, score int, PRIMARY KEY(id));
+        QUERY TopPosts: SELECT id, author, score FROM Post WHERE author = ? \
+                        ORDER BY score DESC LIMIT 3 OFFSET 1;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Post").await.unwrap();
+    let mut getter = g.view("TopPosts").await.unwrap();
+
+    for (id, &score) in [10, 40, 30, 20, 50].iter().enumerate() {
+        mutator
+            .insert(vec![id.into(), 1.into(), score.into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    let scores =
+        |rs: &noria::results::Results| rs.iter().map(|r| r[2].clone()).collect::<Vec<DataType>>();
+
+    // the highest score is skipped, and only three posts are kept
+    let (page, cursor) = getter
+        .lookup_page(&[1.into()], None, 2, true)
+        .await
+        .unwrap();
+    assert_eq!(scores(&page), vec![40.into(), 30.into()]);
+    assert!(cursor.is_some());
+
+    let (page, cursor) = getter
+        .lookup_page(&[1.into()], cursor.as_ref(), 2, true)
+        .await
+        .unwrap();
+    assert_eq!(scores(&page), vec![20.into()]);
+    assert_eq!(cursor, None);
+
+    // removing the top post pulls the next one into the window
+    mutator.delete(vec![4.into()]).await.unwrap();
+    sleep().await;
+
+    let (page, _) = getter
+        .lookup_page(&[1.into()], None, 10, true)
+        .await
+        .unwrap();
+    assert_eq!(scores(&page), vec![30.into(), 20.into(), 10.into()]);
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_with_vote() {
     let mut g = start_simple("it_works_with_vote").await;
@@ -809,6 +1096,217 @@ async fn it_works_with_double_query_through() {
     assert_eq!(empty.len(), 0);
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_in_subqueries() {
+    let mut g = start_simple_unsharded("it_works_with_in_subqueries").await;
+    let sql = "
+        # base tables
+        CREATE TABLE Post (pid int, author int, PRIMARY KEY(pid));
+        CREATE TABLE Role (uid int, role int, PRIMARY KEY(uid));
+
+        # read queries
+        QUERY StaffPosts: SELECT Post.pid, Post.author FROM Post \
+            WHERE Post.author IN (SELECT Role.uid FROM Role WHERE Role.role = 1) \
+            AND Post.author = ?;
+        QUERY OtherPosts: SELECT Post.pid, Post.author FROM Post \
+            WHERE Post.author NOT IN (SELECT Role.uid FROM Role WHERE Role.role = 1) \
+            AND Post.author = ?;
+    ";
+
+    g.install_recipe(sql).await.unwrap();
+    let mut post = g.table("Post").await.unwrap();
+    let mut role = g.table("Role").await.unwrap();
+    let mut staff = g.view("StaffPosts").await.unwrap();
+    let mut other = g.view("OtherPosts").await.unwrap();
+
+    post.insert(vec![1.into(), 10.into()]).await.unwrap();
+    post.insert(vec![2.into(), 20.into()]).await.unwrap();
+    role.insert(vec![10.into(), 1.into()]).await.unwrap();
+    role.insert(vec![20.into(), 0.into()]).await.unwrap();
+    sleep().await;
+
+    let rs = staff.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0], vec![1.into(), 10.into()]);
+    assert!(staff.lookup(&[20.into()], true).await.unwrap().is_empty());
+    assert!(other.lookup(&[10.into()], true).await.unwrap().is_empty());
+    let rs = other.lookup(&[20.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0], vec![2.into(), 20.into()]);
+
+    // once the author leaves the staff, their post moves over
+    role.delete(vec![10.into()]).await.unwrap();
+    sleep().await;
+
+    assert!(staff.lookup(&[10.into()], true).await.unwrap().is_empty());
+    let rs = other.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0], vec![1.into(), 10.into()]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_follows_null_semantics_for_in_subqueries() {
+    let mut g = start_simple_unsharded("it_follows_null_semantics_for_in_subqueries").await;
+    let sql = "
+        # base tables
+        CREATE TABLE Post (pid int, author int, PRIMARY KEY(pid));
+        CREATE TABLE Role (rid int, uid int, PRIMARY KEY(rid));
+
+        # read queries
+        QUERY StaffPost: SELECT Post.pid, Post.author FROM Post \
+            WHERE Post.author IN (SELECT Role.uid FROM Role) AND Post.pid = ?;
+        QUERY OtherPost: SELECT Post.pid, Post.author FROM Post \
+            WHERE Post.author NOT IN (SELECT Role.uid FROM Role) AND Post.pid = ?;
+    ";
+
+    g.install_recipe(sql).await.unwrap();
+    let mut post = g.table("Post").await.unwrap();
+    let mut role = g.table("Role").await.unwrap();
+    let mut staff = g.view("StaffPost").await.unwrap();
+    let mut other = g.view("OtherPost").await.unwrap();
+
+    post.insert(vec![1.into(), 10.into()]).await.unwrap();
+    post.insert(vec![2.into(), DataType::None]).await.unwrap();
+    sleep().await;
+
+    // nothing is in an empty subquery, not even NULL
+    assert_eq!(
+        other.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 10.into()]]
+    );
+    assert_eq!(
+        other.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), DataType::None]]
+    );
+
+    // but whether NULL is in a non-empty one is unknown
+    role.insert(vec![1.into(), 20.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        other.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 10.into()]]
+    );
+    assert!(other.lookup(&[2.into()], true).await.unwrap().is_empty());
+    assert!(staff.lookup(&[2.into()], true).await.unwrap().is_empty());
+
+    // and so is whether anything is not in a subquery that contains NULL
+    role.insert(vec![2.into(), DataType::None]).await.unwrap();
+    sleep().await;
+    assert!(other.lookup(&[1.into()], true).await.unwrap().is_empty());
+    assert!(other.lookup(&[2.into()], true).await.unwrap().is_empty());
+    assert!(staff.lookup(&[1.into()], true).await.unwrap().is_empty());
+    assert!(staff.lookup(&[2.into()], true).await.unwrap().is_empty());
+
+    role.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        other.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 10.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_arithmetic_predicates() {
+    let mut g = start_simple_unsharded("it_works_with_arithmetic_predicates").await;
+    let sql = "
+        # base tables
+        CREATE TABLE Item (iid int, price int, qty int, PRIMARY KEY(iid));
+        CREATE TABLE Login (uid int, ts int, PRIMARY KEY(uid));
+        CREATE TABLE Purchase (pid int, uid int, ts int, PRIMARY KEY(pid));
+
+        # read queries
+        QUERY BigItems: SELECT Item.iid FROM Item \
+            WHERE Item.price * Item.qty > 100 AND Item.iid = ?;
+        QUERY QuickPurchases: SELECT Purchase.pid, Purchase.uid FROM Purchase \
+            JOIN Login ON (Purchase.uid = Login.uid AND Purchase.ts < Login.ts + 3600) \
+            WHERE Purchase.uid = ?;
+    ";
+
+    g.install_recipe(sql).await.unwrap();
+    let mut item = g.table("Item").await.unwrap();
+    let mut login = g.table("Login").await.unwrap();
+    let mut purchase = g.table("Purchase").await.unwrap();
+    let mut big = g.view("BigItems").await.unwrap();
+    let mut quick = g.view("QuickPurchases").await.unwrap();
+
+    item.insert(vec![1.into(), 30.into(), 4.into()])
+        .await
+        .unwrap();
+    item.insert(vec![2.into(), 30.into(), 3.into()])
+        .await
+        .unwrap();
+    login.insert(vec![1.into(), 1000.into()]).await.unwrap();
+    purchase
+        .insert(vec![1.into(), 1.into(), 2000.into()])
+        .await
+        .unwrap();
+    purchase
+        .insert(vec![2.into(), 1.into(), 5000.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let rs = big.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0], vec![1.into()]);
+    assert!(big.lookup(&[2.into()], true).await.unwrap().is_empty());
+
+    let rs = quick.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0], vec![1.into(), 1.into()]);
+
+    // dividing by zero gives NULL, which matches nothing
+    g.extend_recipe(
+        "QUERY CheapItems: SELECT Item.iid FROM Item WHERE Item.price / Item.qty < 20 \
+            AND Item.iid = ?;",
+    )
+    .await
+    .unwrap();
+    let mut cheap = g.view("CheapItems").await.unwrap();
+    item.insert(vec![3.into(), 30.into(), 0.into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(cheap.lookup(&[1.into()], true).await.unwrap().len(), 1);
+    assert!(cheap.lookup(&[3.into()], true).await.unwrap().is_empty());
+
+    // arithmetic compared against a query parameter keys the view on the arithmetic's value
+    g.extend_recipe(
+        "QUERY ItemsByTotal: SELECT Item.iid FROM Item WHERE Item.price * Item.qty = ?;",
+    )
+    .await
+    .unwrap();
+    let mut by_total = g.view("ItemsByTotal").await.unwrap();
+    let rs = by_total.lookup(&[90.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0][0], 2.into());
+    let rs = by_total.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0][0], 3.into());
+    assert!(by_total
+        .lookup(&[100.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // but views are only looked up by exact key, so other comparisons against parameters are
+    // rejected, as are comparisons that filters can't evaluate over arithmetic
+    assert!(g
+        .extend_recipe(
+            "QUERY PricyItems: SELECT Item.iid FROM Item WHERE Item.price * Item.qty > ?;"
+        )
+        .await
+        .is_err());
+    assert!(g
+        .extend_recipe("QUERY InItems: SELECT Item.iid FROM Item WHERE Item.qty + 1 IN (1, 2);")
+        .await
+        .is_err());
+    assert!(g
+        .extend_recipe("QUERY LikeItems: SELECT Item.iid FROM Item WHERE Item.qty + 1 LIKE '1%';")
+        .await
+        .is_err());
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_with_reads_before_writes() {
     let mut g = start_simple("it_works_with_reads_before_writes").await;
@@ -933,70 +1431,1206 @@ async fn it_works_with_arithmetic_aliases() {
         .insert(vec![pid.into(), price.into()])
         .await
         .unwrap();
-
-    // Let writes propagate:
-    sleep().await;
-
-    // Retrieve the result of the count query:
-    let result = getter.lookup(&[pid.into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0][1], (price / 100).into());
+
+    // Let writes propagate:
+    sleep().await;
+
+    // Retrieve the result of the count query:
+    let result = getter.lookup(&[pid.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][1], (price / 100).into());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_recovers_persisted_bases() {
+    let authority = Arc::new(LocalAuthority::new());
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("it_recovers_persisted_bases");
+    let persistence_params = PersistenceParameters::new(
+        DurabilityMode::Permanent,
+        Duration::from_millis(1),
+        Some(path.to_string_lossy().into()),
+        1,
+    );
+
+    {
+        let mut g = Builder::default();
+        g.set_persistence(persistence_params.clone());
+        let (mut g, done) = g.start(authority.clone()).await.unwrap();
+
+        {
+            let sql = "
+            CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+            QUERY CarPrice: SELECT price FROM Car WHERE id = ?;
+        ";
+            g.install_recipe(sql).await.unwrap();
+
+            let mut mutator = g.table("Car").await.unwrap();
+
+            for i in 1..10 {
+                let price = i * 10;
+                mutator.insert(vec![i.into(), price.into()]).await.unwrap();
+            }
+        }
+
+        // Let writes propagate:
+        sleep().await;
+        drop(g);
+        done.await;
+    }
+
+    let mut g = Builder::default();
+    g.set_persistence(persistence_params);
+    let (mut g, done) = g.start(authority.clone()).await.unwrap();
+    {
+        let mut getter = g.view("CarPrice").await.unwrap();
+
+        // Make sure that the new graph contains the old writes
+        for i in 1..10 {
+            let price = i * 10;
+            let result = getter.lookup(&[i.into()], true).await.unwrap();
+            assert_eq!(result.len(), 1);
+            assert_eq!(result[0][0], price.into());
+        }
+    }
+    drop(g);
+    done.await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_recovers_logged_bases() {
+    use noria::Modification;
+
+    let authority = Arc::new(LocalAuthority::new());
+    let dir = tempfile::tempdir().unwrap();
+    let mut persistence_params = PersistenceParameters::new(
+        DurabilityMode::MemoryWithLog,
+        Duration::from_millis(1),
+        Some(String::from("it_recovers_logged_bases")),
+        1,
+    );
+    persistence_params.log_dir = Some(dir.path().to_path_buf());
+
+    {
+        let mut g = Builder::default();
+        g.set_persistence(persistence_params.clone());
+        let (mut g, done) = g.start(authority.clone()).await.unwrap();
+
+        {
+            let sql = "
+            CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+            QUERY CarPrice: SELECT price FROM Car WHERE id = ?;
+        ";
+            g.install_recipe(sql).await.unwrap();
+
+            let mut mutator = g.table("Car").await.unwrap();
+            for i in 1..10 {
+                let price = i * 10;
+                mutator.insert(vec![i.into(), price.into()]).await.unwrap();
+            }
+            mutator
+                .update(vec![1.into()], vec![(1, Modification::Set(1000.into()))])
+                .await
+                .unwrap();
+            mutator.delete(vec![2.into()]).await.unwrap();
+        }
+
+        // Let writes propagate:
+        sleep().await;
+        drop(g);
+        done.await;
+    }
+
+    // Restart the worker, which has to replay the log to get its rows back
+    let mut g = Builder::default();
+    g.set_persistence(persistence_params);
+    let (mut g, done) = g.start(authority.clone()).await.unwrap();
+    {
+        let mut getter = g.view("CarPrice").await.unwrap();
+
+        let result = getter.lookup(&[1.into()], true).await.unwrap();
+        assert_eq!(result, vec![vec![1000.into()]]);
+        let result = getter.lookup(&[2.into()], true).await.unwrap();
+        assert!(result.is_empty());
+        for i in 3..10 {
+            let price = i * 10;
+            let result = getter.lookup(&[i.into()], true).await.unwrap();
+            assert_eq!(result, vec![vec![price.into()]]);
+        }
+    }
+    drop(g);
+    done.await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_keeps_table_changes_across_restarts() {
+    use noria::{ChangeOffset, Delta};
+
+    let authority = Arc::new(LocalAuthority::new());
+    let dir = tempfile::tempdir().unwrap();
+    let mut persistence_params = PersistenceParameters::new(
+        DurabilityMode::MemoryWithLog,
+        Duration::from_millis(1),
+        Some(String::from("it_keeps_table_changes_across_restarts")),
+        1,
+    );
+    persistence_params.log_dir = Some(dir.path().to_path_buf());
+
+    let offset = {
+        let mut g = Builder::default();
+        g.set_sharding(None);
+        g.set_persistence(persistence_params.clone());
+        let (mut g, done) = g.start(authority.clone()).await.unwrap();
+
+        g.install_recipe("CREATE TABLE Car (id int, price int, PRIMARY KEY(id));")
+            .await
+            .unwrap();
+        g.enable_table_changes("Car").await.unwrap();
+        let mut mutator = g.table("Car").await.unwrap();
+        mutator.insert(vec![1.into(), 10.into()]).await.unwrap();
+        mutator.insert(vec![2.into(), 20.into()]).await.unwrap();
+        sleep().await;
+
+        let (changes, offset) = g
+            .table_changes("Car", ChangeOffset::default(), 1)
+            .await
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].seq, 0);
+
+        drop(mutator);
+        drop(g);
+        done.await;
+        offset
+    };
+
+    // the restarted table still keeps changes, and reading resumes where it left off
+    let mut g = Builder::default();
+    g.set_sharding(None);
+    g.set_persistence(persistence_params);
+    let (mut g, done) = g.start(authority.clone()).await.unwrap();
+    {
+        let mut mutator = g.table("Car").await.unwrap();
+        mutator.insert(vec![3.into(), 30.into()]).await.unwrap();
+        sleep().await;
+
+        let (changes, next) = g.table_changes("Car", offset, 10).await.unwrap();
+        assert_eq!(
+            changes.iter().map(|c| c.seq).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            changes[0].records,
+            vec![Delta::Positive(vec![2.into(), 20.into()])]
+        );
+        assert_eq!(
+            changes[1].records,
+            vec![Delta::Positive(vec![3.into(), 30.into()])]
+        );
+        assert_eq!(next.shard(0), Some(3));
+    }
+    drop(g);
+    done.await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_decommissions_workers() {
+    let authority = Arc::new(LocalAuthority::new());
+
+    let mut g = Builder::default();
+    g.set_persistence(get_persistence_params("it_decommissions_workers"));
+    let (mut g, done) = g.start(authority.clone()).await.unwrap();
+
+    // a second instance, which will just run a worker for the first one
+    let mut w = Builder::default();
+    w.set_persistence(get_persistence_params("it_decommissions_workers_2"));
+    let (w, w_done) = w.start(authority.clone()).await.unwrap();
+
+    while g.instances().await.unwrap().len() < 2 {
+        sleep().await;
+    }
+
+    g.install_recipe(
+        "
+        CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+        QUERY CarPrice: SELECT price FROM Car WHERE id = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    for i in 1..10 {
+        mutator
+            .insert(vec![i.into(), (i * 10).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    // either worker can go, but once one has, there's nowhere left to move things to
+    let mut decommissioned = 0;
+    let mut errors = Vec::new();
+    for (addr, healthy, _) in g.instances().await.unwrap() {
+        assert!(healthy);
+        match g.decommission_worker(addr).await {
+            Ok(()) => decommissioned += 1,
+            Err(e) => errors.push(e.find_root_cause().to_string()),
+        }
+    }
+    assert_eq!(decommissioned, 1);
+    assert_eq!(g.instances().await.unwrap().len(), 1);
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].contains("fewer than"),
+        "unexpected error: {}",
+        errors[0]
+    );
+
+    // the remaining worker now hosts the base table, whose rows move along with it
+    let (base_worker, _, _) = g.instances().await.unwrap()[0];
+    let mut v = Builder::default();
+    v.set_persistence(get_persistence_params("it_decommissions_workers_3"));
+    let (v, v_done) = v.start(authority.clone()).await.unwrap();
+    while g.instances().await.unwrap().len() < 2 {
+        sleep().await;
+    }
+    g.decommission_worker(base_worker).await.unwrap();
+    assert_eq!(g.instances().await.unwrap().len(), 1);
+    assert_ne!(g.instances().await.unwrap()[0].0, base_worker);
+
+    // the table was re-created, so writes need a new handle
+    drop(mutator);
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator.insert(vec![10.into(), 100.into()]).await.unwrap();
+    sleep().await;
+
+    let mut getter = g.view("CarPrice").await.unwrap();
+    for i in 1..=10 {
+        let result = getter.lookup(&[i.into()], true).await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0][0], (i * 10).into());
+    }
+
+    drop(g);
+    done.await;
+    drop(w);
+    w_done.await;
+    drop(v);
+    v_done.await;
+}
+
+/// Total size of all partially materialized state in the graph.
+async fn partial_state_size(g: &mut Handle<LocalAuthority>) -> u64 {
+    use noria::internal::MaterializationStatus;
+    let stats = g.statistics().await.unwrap();
+    stats
+        .values()
+        .flat_map(|(_, nodes)| nodes.values())
+        .filter(|n| match n.materialized {
+            MaterializationStatus::Partial { .. } => true,
+            _ => false,
+        })
+        .map(|n| n.mem_size)
+        .sum()
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_evicts_views_over_their_memory_budget() {
+    let mut g = start_simple_unsharded("it_evicts_views_over_their_memory_budget").await;
+    g.install_recipe(
+        "
+        CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+        QUERY CarPrice BUDGET 1: SELECT price FROM Car WHERE id = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    for i in 1..=10 {
+        mutator
+            .insert(vec![i.into(), (i * 10).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    let mut getter = g.view("CarPrice").await.unwrap();
+    for i in 1..=10 {
+        let result = getter.lookup(&[i.into()], true).await.unwrap();
+        assert_eq!(result.len(), 1);
+    }
+
+    // the view is way over budget, and is emptied out once the domain notices
+    tokio::time::delay_for(Duration::from_secs(2)).await;
+    assert_eq!(partial_state_size(&mut g).await, 0);
+
+    // but reads still work, they just need replays
+    let result = getter.lookup(&[3.into()], true).await.unwrap();
+    let prices: Vec<i32> = result.iter().map(|row| row.get("price").unwrap()).collect();
+    assert_eq!(prices, vec![30]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_keeps_partial_distinct_views_up_to_date() {
+    let mut g = start_simple_unsharded("it_keeps_partial_distinct_views_up_to_date").await;
+    g.install_recipe(
+        "
+        CREATE TABLE Post (id int, author int, tag text, PRIMARY KEY(id));
+        QUERY AuthorTags BUDGET 1: SELECT DISTINCT author, tag FROM Post WHERE author = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Post").await.unwrap();
+    for &(id, author, tag) in &[(1, 1, "a"), (2, 1, "a"), (3, 1, "b"), (4, 2, "a")] {
+        mutator
+            .insert(vec![id.into(), author.into(), tag.into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    async fn tags(getter: &mut noria::View) -> Vec<DataType> {
+        let result = getter.lookup(&[1.into()], true).await.unwrap();
+        let mut tags: Vec<DataType> = result.iter().map(|row| row[1].clone()).collect();
+        tags.sort();
+        tags
+    }
+
+    let mut getter = g.view("AuthorTags").await.unwrap();
+    assert_eq!(
+        tags(&mut getter).await,
+        vec![DataType::from("a"), DataType::from("b")]
+    );
+
+    // removing one of two duplicates keeps the row
+    mutator.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        tags(&mut getter).await,
+        vec![DataType::from("a"), DataType::from("b")]
+    );
+
+    // removing the last one removes it
+    mutator.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(tags(&mut getter).await, vec![DataType::from("b")]);
+
+    // once the view is evicted, writes to it are dropped, and reads replay the rows again,
+    // duplicates included
+    tokio::time::delay_for(Duration::from_secs(2)).await;
+    assert_eq!(partial_state_size(&mut g).await, 0);
+    mutator
+        .insert(vec![5.into(), 1.into(), "b".into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(tags(&mut getter).await, vec![DataType::from("b")]);
+
+    mutator.delete(vec![3.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(tags(&mut getter).await, vec![DataType::from("b")]);
+
+    mutator.delete(vec![5.into()]).await.unwrap();
+    sleep().await;
+    assert!(tags(&mut getter).await.is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_does_not_evict_pinned_views() {
+    let mut g = Builder::default();
+    g.set_sharding(None);
+    g.set_persistence(get_persistence_params("it_does_not_evict_pinned_views"));
+    // any partial state at all puts us over the limit
+    g.set_memory_limit(1, Duration::from_millis(100), crate::EvictionKind::Random);
+    let (mut g, _) = g.start_local().await.unwrap();
+    g.install_recipe(
+        "
+        CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+        QUERY CarPrice PINNED: SELECT price FROM Car WHERE id = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    for i in 1..=10 {
+        mutator
+            .insert(vec![i.into(), (i * 10).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    let mut getter = g.view("CarPrice").await.unwrap();
+    for i in 1..=10 {
+        let result = getter.lookup(&[i.into()], true).await.unwrap();
+        assert_eq!(result.len(), 1);
+    }
+
+    tokio::time::delay_for(Duration::from_secs(2)).await;
+    assert!(partial_state_size(&mut g).await > 0);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_streams_view_changes() {
+    use futures_util::StreamExt;
+    use noria::Delta;
+
+    let mut g = start_simple("it_streams_view_changes").await;
+    g.install_recipe(
+        "
+        CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+        QUERY CarPrice: SELECT price FROM Car WHERE id = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator.insert(vec![1.into(), 10.into()]).await.unwrap();
+    sleep().await;
+
+    let mut getter = g.view("CarPrice").await.unwrap();
+    let mut changes = getter
+        .subscribe(vec![vec![1.into()], vec![2.into()]])
+        .await
+        .unwrap();
+
+    // the current results come first (after being backfilled)
+    let deltas = changes.next().await.unwrap().unwrap();
+    assert_eq!(deltas.len(), 1);
+    assert!(deltas[0].is_positive());
+    assert_eq!(deltas[0].row()[0], 10.into());
+
+    // and then every change to them
+    mutator.insert(vec![2.into(), 20.into()]).await.unwrap();
+    let deltas = changes.next().await.unwrap().unwrap();
+    assert_eq!(deltas.len(), 1);
+    assert!(deltas[0].is_positive());
+    assert_eq!(deltas[0].row()[0], 20.into());
+
+    mutator
+        .update(
+            vec![1.into()],
+            vec![(1, noria::Modification::Set(11.into()))],
+        )
+        .await
+        .unwrap();
+    let mut deltas = changes.next().await.unwrap().unwrap();
+    deltas.sort();
+    assert_eq!(deltas.len(), 2);
+    match (&deltas[0], &deltas[1]) {
+        (Delta::Positive(added), Delta::Negative(removed)) => {
+            assert_eq!(added[0], 11.into());
+            assert_eq!(removed[0], 10.into());
+        }
+        d => panic!("{:?}", d),
+    }
+
+    // but not changes to other keys
+    mutator.insert(vec![3.into(), 30.into()]).await.unwrap();
+    mutator.delete(vec![2.into()]).await.unwrap();
+    let deltas = changes.next().await.unwrap().unwrap();
+    assert_eq!(deltas.len(), 1);
+    assert!(!deltas[0].is_positive());
+    assert_eq!(deltas[0].row()[0], 20.into());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_reads_table_changes() {
+    use noria::{ChangeOffset, Delta, Modification};
+
+    let mut g = start_simple_unsharded("it_reads_table_changes").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+         CREATE TABLE Bike (id int, price int, PRIMARY KEY(id));",
+    )
+    .await
+    .unwrap();
+    let mut mutator = g.table("Car").await.unwrap();
+
+    // changes are only kept once enabled
+    mutator.insert(vec![0.into(), 10.into()]).await.unwrap();
+    g.enable_table_changes("Car").await.unwrap();
+    mutator.insert(vec![1.into(), 10.into()]).await.unwrap();
+    sleep().await;
+    let (changes, offset) = g
+        .table_changes("Car", ChangeOffset::default(), 10)
+        .await
+        .unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(
+        changes[0].records,
+        vec![Delta::Positive(vec![1.into(), 10.into()])]
+    );
+    assert_eq!(changes[0].seq, 0);
+    let s = offset.shard(0).unwrap();
+    assert_eq!(s, 1);
+
+    mutator.insert(vec![2.into(), 20.into()]).await.unwrap();
+    mutator
+        .update(vec![1.into()], vec![(1, Modification::Set(11.into()))])
+        .await
+        .unwrap();
+    mutator.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+
+    let (changes, next) = g.table_changes("Car", offset.clone(), 10).await.unwrap();
+    assert_eq!(
+        changes.iter().map(|c| c.seq).collect::<Vec<_>>(),
+        vec![s, s + 1, s + 2]
+    );
+    assert_eq!(
+        changes[0].records,
+        vec![Delta::Positive(vec![2.into(), 20.into()])]
+    );
+    assert_eq!(
+        changes[1].records,
+        vec![
+            Delta::Negative(vec![1.into(), 10.into()]),
+            Delta::Positive(vec![1.into(), 11.into()])
+        ]
+    );
+    assert_eq!(
+        changes[2].records,
+        vec![Delta::Negative(vec![2.into(), 20.into()])]
+    );
+    assert_eq!(next.shard(0), Some(s + 3));
+
+    // reading can resume from any offset
+    let (changes, _) = g.table_changes("Car", next, 10).await.unwrap();
+    assert!(changes.is_empty());
+    let (changes, next) = g.table_changes("Car", offset, 1).await.unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].seq, s);
+    assert_eq!(next.shard(0), Some(s + 1));
+
+    // but not from changes that haven't been made
+    assert!(g
+        .table_changes("Car", ChangeOffset(vec![Some(s + 4)]), 10)
+        .await
+        .is_err());
+
+    // nor from tables that don't keep changes
+    assert!(g
+        .table_changes("Bike", ChangeOffset::default(), 10)
+        .await
+        .is_err());
+    assert!(g
+        .table_changes("Boat", ChangeOffset::default(), 10)
+        .await
+        .is_err());
+    assert!(g.enable_table_changes("Boat").await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_recovers_from_failed_table_change_reads() {
+    use noria::ChangeOffset;
+
+    let mut g = start_simple("it_recovers_from_failed_table_change_reads").await;
+    g.install_recipe("CREATE TABLE Car (id int, price int, PRIMARY KEY(id));")
+        .await
+        .unwrap();
+    g.enable_table_changes("Car").await.unwrap();
+    let mut mutator = g.table("Car").await.unwrap();
+
+    // reach the first shard, but not the second
+    g.fail_sends_after(1);
+    assert!(g
+        .table_changes("Car", ChangeOffset::default(), 10)
+        .await
+        .is_err());
+
+    // the first shard's reply must not be taken for a reply to anything that follows
+    for i in 0..4 {
+        mutator.insert(vec![i.into(), 10.into()]).await.unwrap();
+    }
+    sleep().await;
+    let (changes, _) = g
+        .table_changes("Car", ChangeOffset::default(), 10)
+        .await
+        .unwrap();
+    assert_eq!(changes.iter().map(|c| c.records.len()).sum::<usize>(), 4);
+    g.extend_recipe("QUERY CarPrice: SELECT price FROM Car WHERE id = ?;")
+        .await
+        .unwrap();
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_bulk_loads() {
+    let mut g = start_simple("it_bulk_loads").await;
+    g.install_recipe(
+        "
+        CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+        QUERY CarPrice: SELECT price FROM Car WHERE id = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator
+        .bulk_load((0..25_000).map(|i| vec![i.into(), (i * 10).into()]))
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mut getter = g.view("CarPrice").await.unwrap();
+    for &id in &[0, 9_999, 10_000, 24_999] {
+        let result = getter.lookup(&[id.into()], true).await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0][0], (id * 10).into());
+    }
+
+    // regular writes see the loaded rows
+    mutator.insert(vec![1.into(), 1.into()]).await.unwrap();
+    mutator
+        .update(
+            vec![2.into()],
+            vec![(1, noria::Modification::Set(2.into()))],
+        )
+        .await
+        .unwrap();
+    sleep().await;
+    let result = getter.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], 10.into());
+    let result = getter.lookup(&[2.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], 2.into());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_snapshots_and_restores() {
+    let recipe = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        CREATE TABLE Price (cid int, price int);
+        CREATE TABLE Visit (cid int, at int);
+        QUERY CarPrice: SELECT Car.brand, Price.price FROM Car
+                        JOIN Price ON Car.id = Price.cid WHERE Car.id = ?;
+        QUERY CarVisits: SELECT at FROM Visit WHERE cid = ?;
+    ";
+    // enough rows that they are snapshotted in more than one chunk
+    let visits = 2 * crate::snapshot::CHUNK_ROWS + 1;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("snapshot");
+    let path = path.to_str().unwrap();
+
+    let mut g = start_simple("it_snapshots_and_restores").await;
+    g.install_recipe(recipe).await.unwrap();
+    g.extend_recipe("QUERY Brands: SELECT brand FROM Car;")
+        .await
+        .unwrap();
+    let mut car = g.table("Car").await.unwrap();
+    let mut price = g.table("Price").await.unwrap();
+    for id in 0..10 {
+        car.insert(vec![id.into(), format!("brand{}", id).into()])
+            .await
+            .unwrap();
+        price
+            .insert(vec![id.into(), (id * 100).into()])
+            .await
+            .unwrap();
+    }
+    car.delete(vec![9.into()]).await.unwrap();
+    let mut visit = g.table("Visit").await.unwrap();
+    visit
+        .bulk_load((0..visits).map(|at| vec![1.into(), at.into()]))
+        .await
+        .unwrap();
+    sleep().await;
+    g.snapshot(path).await.unwrap();
+
+    // the snapshot is restored into a deployment with different sharding
+    let mut r = start_simple_unsharded("it_snapshots_and_restores_restored").await;
+    r.restore(path).await.unwrap();
+    sleep().await;
+
+    let mut getter = r.view("CarPrice").await.unwrap();
+    let result = getter.lookup(&[3.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], "brand3".into());
+    assert_eq!(result[0][1], 300.into());
+    let result = getter.lookup(&[9.into()], true).await.unwrap();
+    assert!(result.is_empty());
+    let mut brands = r.view("Brands").await.unwrap();
+    assert_eq!(brands.lookup(&[0.into()], true).await.unwrap().len(), 9);
+    let mut car_visits = r.view("CarVisits").await.unwrap();
+    assert_eq!(
+        car_visits.lookup(&[1.into()], true).await.unwrap().len(),
+        visits
+    );
+
+    // writes keep working after the restore
+    let mut car = r.table("Car").await.unwrap();
+    car.insert(vec![9.into(), "brand9".into()]).await.unwrap();
+    sleep().await;
+    let result = getter.lookup(&[9.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][1], 900.into());
+
+    // and a deployment with tables can't be restored into
+    assert!(r.restore(path).await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_persists_security_config() {
+    let authority = Arc::new(LocalAuthority::new());
+    let config = r#"{"policies": []}"#;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("snapshot");
+
+    {
+        let mut g = Builder::default();
+        g.set_persistence(get_persistence_params("it_persists_security_config"));
+        let (mut g, done) = g.start(authority.clone()).await.unwrap();
+        g.set_security_config(config.to_owned()).await.unwrap();
+        g.install_recipe("CREATE TABLE Car (id int, PRIMARY KEY(id));")
+            .await
+            .unwrap();
+        drop(g);
+        done.await;
+    }
+
+    // a new controller picks the configuration up along with the recipe
+    let mut g = Builder::default();
+    g.set_persistence(get_persistence_params("it_persists_security_config"));
+    let (mut g, done) = g.start(authority.clone()).await.unwrap();
+    g.snapshot(path.to_str().unwrap()).await.unwrap();
+    let manifest = crate::snapshot::read_manifest(&path).unwrap();
+    assert_eq!(manifest.security_config.as_deref(), Some(config));
+    drop(g);
+    done.await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_commits_transactions() {
+    let mut g = start_simple("it_commits_transactions").await;
+    g.install_recipe(
+        "
+        CREATE TABLE Orders (oid int, customer int, PRIMARY KEY(oid));
+        CREATE TABLE Items (iid int, oid int, qty int, PRIMARY KEY(iid));
+        QUERY OrderItems: SELECT Orders.oid, Items.iid, Items.qty FROM Orders \
+                          LEFT JOIN Items ON (Orders.oid = Items.oid) WHERE Orders.customer = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let orders = g.table("Orders").await.unwrap();
+    let items = g.table("Items").await.unwrap();
+    let mut getter = g.view("OrderItems").await.unwrap();
+
+    // a reader that never gets to see an order without its items, or the other way around
+    let stop = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let mut getter = g.view("OrderItems").await.unwrap();
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            let mut reads = 0;
+            while !stop.load(Ordering::SeqCst) {
+                let result = getter.lookup(&[42.into()], true).await.unwrap();
+                assert!(
+                    result.is_empty()
+                        || (result.len() == 2 && result.iter().all(|r| r[1] != DataType::None)),
+                    "saw part of a transaction: {:?}",
+                    result
+                );
+                reads += 1;
+            }
+            reads
+        })
+    };
+
+    let mut tx = g.transaction();
+    tx.insert(&orders, vec![1.into(), 42.into()]).unwrap();
+    tx.insert(&items, vec![1.into(), 1.into(), 2.into()])
+        .unwrap();
+    tx.insert(&items, vec![2.into(), 1.into(), 3.into()])
+        .unwrap();
+    tx.commit().await.unwrap();
+    sleep().await;
+
+    let mut result: Vec<Vec<DataType>> = getter.lookup(&[42.into()], true).await.unwrap().into();
+    result.sort();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result[0][1], 1.into());
+    assert_eq!(result[0][2], 2.into());
+    assert_eq!(result[1][1], 2.into());
+    assert_eq!(result[1][2], 3.into());
+
+    // deletes are applied together too
+    let mut tx = g.transaction();
+    tx.delete(&items, vec![1.into()]).unwrap();
+    tx.delete(&items, vec![2.into()]).unwrap();
+    tx.delete(&orders, vec![1.into()]).unwrap();
+    tx.commit().await.unwrap();
+    sleep().await;
+    assert!(getter.lookup(&[42.into()], true).await.unwrap().is_empty());
+
+    stop.store(true, Ordering::SeqCst);
+    assert!(reader.await.unwrap() > 0);
+
+    // malformed writes are rejected before anything is sent
+    let mut tx = g.transaction();
+    assert!(tx.insert(&orders, vec![2.into()]).is_err());
+
+    // and regular writes are still exposed once the transactions are done
+    let mut orders = orders;
+    orders.insert(vec![3.into(), 42.into()]).await.unwrap();
+    sleep().await;
+    let result = getter.lookup(&[42.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], 3.into());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_aborts_transactions_that_fail_part_way() {
+    let mut g = start_simple("it_aborts_transactions_that_fail_part_way").await;
+    g.install_recipe(
+        "
+        CREATE TABLE Orders (oid int, customer int, PRIMARY KEY(oid));
+        CREATE TABLE Items (iid int, oid int, qty int, PRIMARY KEY(iid));
+        QUERY OrderItems: SELECT Orders.oid, Items.iid, Items.qty FROM Orders \
+                          LEFT JOIN Items ON (Orders.oid = Items.oid) WHERE Orders.customer = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let mut orders = g.table("Orders").await.unwrap();
+    let items = g.table("Items").await.unwrap();
+    let mut getter = g.view("OrderItems").await.unwrap();
+    assert!(getter.lookup(&[42.into()], true).await.unwrap().is_empty());
+
+    // fail every send in turn, until the transaction gets through without reaching the failure
+    let mut committed = None;
+    for n in 0..100 {
+        let oid = 100 + n as i32;
+        g.fail_sends_after(n);
+        let mut tx = g.transaction();
+        tx.insert(&orders, vec![oid.into(), oid.into()]).unwrap();
+        tx.insert(&items, vec![oid.into(), oid.into(), 1.into()])
+            .unwrap();
+        if tx.commit().await.is_ok() {
+            committed = Some(oid);
+            break;
+        }
+
+        // readers must not keep holding back writes for the failed transaction
+        orders
+            .insert(vec![(1000 + oid).into(), 42.into()])
+            .await
+            .unwrap();
+        sleep().await;
+        let result = getter.lookup(&[42.into()], true).await.unwrap();
+        assert_eq!(result.len(), n + 1);
+    }
+
+    let oid = committed.expect("transaction never committed");
+    sleep().await;
+    let result = getter.lookup(&[oid.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], oid.into());
+    assert_eq!(result[0][1], oid.into());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_keeps_readers_live_while_transactions_are_pending() {
+    let mut builder = Builder::default();
+    builder.set_persistence(get_persistence_params(
+        "it_keeps_readers_live_while_transactions_are_pending",
+    ));
+    builder.set_transaction_timeout(Duration::from_secs(2));
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "
+        CREATE TABLE Orders (oid int, customer int, PRIMARY KEY(oid));
+        CREATE TABLE Items (iid int, oid int, qty int, PRIMARY KEY(iid));
+        CREATE TABLE Notes (nid int, customer int, PRIMARY KEY(nid));
+        QUERY OrderItems: SELECT Orders.oid, Items.iid, Items.qty FROM Orders \
+                          LEFT JOIN Items ON (Orders.oid = Items.oid) WHERE Orders.customer = ?;
+        QUERY CustomerNotes: SELECT nid FROM Notes WHERE customer = ?;
+    ",
+    )
+    .await
+    .unwrap();
+
+    let mut orders = g.table("Orders").await.unwrap();
+    let items = g.table("Items").await.unwrap();
+    let mut notes = g.table("Notes").await.unwrap();
+    let mut order_items = g.view("OrderItems").await.unwrap();
+    let mut customer_notes = g.view("CustomerNotes").await.unwrap();
+    assert!(order_items
+        .lookup(&[42.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(customer_notes
+        .lookup(&[42.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // a transaction that the domains hear about, but whose writes never arrive
+    g.abandon_next_transaction();
+    let mut tx = g.transaction();
+    tx.insert(&orders, vec![1.into(), 42.into()]).unwrap();
+    tx.insert(&items, vec![1.into(), 1.into(), 2.into()])
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    // readers that the transaction doesn't reach keep exposing writes
+    notes.insert(vec![1.into(), 42.into()]).await.unwrap();
+    orders.insert(vec![2.into(), 42.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        customer_notes.lookup(&[42.into()], true).await.unwrap(),
+        vec![vec![1.into()]]
+    );
+    assert!(order_items
+        .lookup(&[42.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // and the ones it does reach stop waiting for it once the timeout passes
+    tokio::time::delay_for(Duration::from_secs(2)).await;
+    sleep().await;
+    let result = order_items.lookup(&[42.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], 2.into());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_serves_mysql_clients() {
+    use mysql::prelude::Queryable;
+
+    let mut g = start_simple("it_serves_mysql_clients").await;
+    g.install_recipe(
+        "CREATE TABLE Article (aid int, title varchar(255), votes int, PRIMARY KEY(aid));",
+    )
+    .await
+    .unwrap();
+    let addr = crate::listen_mysql(
+        "127.0.0.1:0".parse().unwrap(),
+        (*g).clone(),
+        tokio::runtime::Handle::current(),
+    )
+    .unwrap();
+
+    // the client blocks, so keep it off the runtime's other tasks
+    tokio::task::block_in_place(|| {
+        let opts = mysql::Opts::from_url(&format!("mysql://{}", addr)).unwrap();
+        let mut conn = mysql::Conn::new(opts).unwrap();
+        conn.query_drop("INSERT INTO Article (aid, title, votes) VALUES (1, 'first', 0)")
+            .unwrap();
+        conn.exec_drop(
+            "INSERT INTO Article (aid, title) VALUES (?, ?)",
+            (2, "second"),
+        )
+        .unwrap();
+        thread::sleep(get_settle_time());
+
+        // the query is added to the recipe the first time it is prepared
+        let q = "SELECT aid, title FROM Article WHERE aid = ?";
+        let rows: Vec<(i64, String)> = conn.exec(q, (1,)).unwrap();
+        assert_eq!(rows, vec![(1, "first".to_string())]);
+        let rows: Vec<(i64, String)> = conn.exec(q, (2,)).unwrap();
+        assert_eq!(rows, vec![(2, "second".to_string())]);
+
+        // parameters take on the types of their columns
+        let rows: Vec<(i64, String)> = conn.exec(q, ("1",)).unwrap();
+        assert_eq!(rows, vec![(1, "first".to_string())]);
+
+        conn.exec_drop("UPDATE Article SET title = ? WHERE aid = ?", ("updated", 1))
+            .unwrap();
+        assert_eq!(conn.affected_rows(), 1);
+        conn.exec_drop("DELETE FROM Article WHERE aid = ?", (2,))
+            .unwrap();
+        assert_eq!(conn.affected_rows(), 1);
+        conn.exec_drop("DELETE FROM Article WHERE aid = ?", (3,))
+            .unwrap();
+        assert_eq!(conn.affected_rows(), 0);
+        thread::sleep(get_settle_time());
+
+        let rows: Vec<(i64, String)> = conn.exec(q, (1,)).unwrap();
+        assert_eq!(rows, vec![(1, "updated".to_string())]);
+        let rows: Vec<(i64, String)> = conn.exec(q, (2,)).unwrap();
+        assert!(rows.is_empty());
+
+        // queries without parameters are looked up by the bogokey
+        let rows: Vec<(i64,)> = conn.query("SELECT aid FROM Article").unwrap();
+        assert_eq!(rows, vec![(1,)]);
+
+        // unsupported statements are reported to the client
+        assert!(conn
+            .query_drop("DELETE FROM Article WHERE aid > 1")
+            .is_err());
+
+        // connections that see a new query at the same time add it only once
+        let clients: Vec<_> = (0..4)
+            .map(|_| {
+                let opts = mysql::Opts::from_url(&format!("mysql://{}", addr)).unwrap();
+                thread::spawn(move || {
+                    let mut conn = mysql::Conn::new(opts).unwrap();
+                    let rows: Vec<(String,)> = conn
+                        .exec("SELECT title FROM Article WHERE votes = ?", (0,))
+                        .unwrap();
+                    rows
+                })
+            })
+            .collect();
+        for client in clients {
+            assert_eq!(client.join().unwrap(), vec![("updated".to_string(),)]);
+        }
+    });
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_alters_tables_through_recipes() {
+    let mut g = start_simple("it_alters_tables_through_recipes").await;
+    g.install_recipe(
+        "CREATE TABLE Article (aid int, title varchar(255), url text, body text, PRIMARY KEY(aid));
+         QUERY ArticleTitle: SELECT aid, title FROM Article WHERE aid = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    article
+        .insert(vec![1.into(), "first".into(), "a.com".into(), "...".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    g.extend_recipe(
+        "ALTER TABLE Article ADD COLUMN votes int DEFAULT 0, ADD INDEX (votes),
+                             RENAME COLUMN url TO link, DROP COLUMN body;
+         QUERY ArticleVotes: SELECT aid, votes, link FROM Article WHERE aid = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    assert_eq!(article.columns(), &["aid", "title", "link", "votes"]);
+    article
+        .insert(vec![2.into(), "second".into(), "b.com".into(), 5.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // the existing row has the new column's default
+    let mut votes = g.view("ArticleVotes").await.unwrap();
+    assert_eq!(
+        votes.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 0.into(), "a.com".into()]]
+    );
+    assert_eq!(
+        votes.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), 5.into(), "b.com".into()]]
+    );
+    let mut titles = g.view("ArticleTitle").await.unwrap();
+    assert_eq!(
+        titles.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), "second".into()]]
+    );
+
+    // columns that queries read, or that make up the primary key, stay put
+    for alter in &[
+        "ALTER TABLE Article DROP COLUMN title;",
+        "ALTER TABLE Article RENAME COLUMN link TO url;",
+        "ALTER TABLE Article DROP COLUMN aid;",
+        "ALTER TABLE Article DROP COLUMN body;",
+        "ALTER TABLE Comment ADD COLUMN votes int;",
+    ] {
+        assert!(g.extend_recipe(alter).await.is_err(), "{}", alter);
+    }
+    assert_eq!(
+        titles.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "first".into()]]
+    );
+
+    // dependencies are resolved through table aliases, and by the table a column belongs to
+    g.extend_recipe(
+        "CREATE TABLE Comment (cid int, aid int, summary text, PRIMARY KEY(cid));
+         QUERY CommentsWithLinks: SELECT c.summary, a.link AS l FROM Comment AS c
+                                  JOIN Article AS a ON (c.aid = a.aid) WHERE c.cid = ?;",
+    )
+    .await
+    .unwrap();
+    assert!(g
+        .extend_recipe("ALTER TABLE Article RENAME COLUMN link TO url;")
+        .await
+        .is_err());
+    g.extend_recipe("ALTER TABLE Article ADD COLUMN summary text;")
+        .await
+        .unwrap();
+    g.extend_recipe("ALTER TABLE Article DROP COLUMN summary;")
+        .await
+        .unwrap();
+    assert!(g
+        .extend_recipe("ALTER TABLE Comment DROP COLUMN summary;")
+        .await
+        .is_err());
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_recovers_persisted_bases() {
-    let authority = Arc::new(LocalAuthority::new());
-    let dir = tempfile::tempdir().unwrap();
-    let path = dir.path().join("it_recovers_persisted_bases");
-    let persistence_params = PersistenceParameters::new(
-        DurabilityMode::Permanent,
-        Duration::from_millis(1),
-        Some(path.to_string_lossy().into()),
-        1,
-    );
-
-    {
-        let mut g = Builder::default();
-        g.set_persistence(persistence_params.clone());
-        let (mut g, done) = g.start(authority.clone()).await.unwrap();
-
-        {
-            let sql = "
-            CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
-            QUERY CarPrice: SELECT price FROM Car WHERE id = ?;
-        ";
-            g.install_recipe(sql).await.unwrap();
-
-            let mut mutator = g.table("Car").await.unwrap();
+async fn it_modifies_rows_after_dropping_columns() {
+    use noria::error::TableError;
+    use noria::Modification;
 
-            for i in 1..10 {
-                let price = i * 10;
-                mutator.insert(vec![i.into(), price.into()]).await.unwrap();
-            }
-        }
+    let mut g = start_simple("it_modifies_rows_after_dropping_columns").await;
+    g.install_recipe(
+        "CREATE TABLE Article (aid int, junk text, title varchar(255), votes int,
+                               PRIMARY KEY(aid), KEY title (title));
+         QUERY ArticleVotes: SELECT aid, title, votes FROM Article WHERE aid = ?;",
+    )
+    .await
+    .unwrap();
+    g.extend_recipe("ALTER TABLE Article DROP COLUMN junk;")
+        .await
+        .unwrap();
 
-        // Let writes propagate:
-        sleep().await;
-        drop(g);
-        done.await;
+    let mut article = g.table("Article").await.unwrap();
+    assert_eq!(article.columns(), &["aid", "title", "votes"]);
+    for (aid, title) in &[(1, "a"), (2, "b"), (3, "b"), (4, "c")] {
+        article
+            .insert(vec![(*aid).into(), (*title).into(), 0.into()])
+            .await
+            .unwrap();
     }
 
-    let mut g = Builder::default();
-    g.set_persistence(persistence_params);
-    let (mut g, done) = g.start(authority.clone()).await.unwrap();
-    {
-        let mut getter = g.view("CarPrice").await.unwrap();
-
-        // Make sure that the new graph contains the old writes
-        for i in 1..10 {
-            let price = i * 10;
-            let result = getter.lookup(&[i.into()], true).await.unwrap();
-            assert_eq!(result.len(), 1);
-            assert_eq!(result[0][0], price.into());
-        }
+    // column indices are those of the table's remaining columns
+    article
+        .update(vec![1.into()], vec![(2, 10.into())])
+        .await
+        .unwrap();
+    article
+        .update_where(1, "b", vec![(2, Modification::Set(20.into()))])
+        .await
+        .unwrap();
+    article.delete(vec![3.into()]).await.unwrap();
+    article.delete_where(1, "c").await.unwrap();
+    match article.delete_where(2, 0).await {
+        Err(TableError::UnindexedColumn(2)) => {}
+        r => panic!("expected matching on votes to be rejected, got {:?}", r),
     }
-    drop(g);
-    done.await;
+    sleep().await;
+
+    let mut votes = g.view("ArticleVotes").await.unwrap();
+    assert_eq!(
+        votes.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "a".into(), 10.into()]]
+    );
+    assert_eq!(
+        votes.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), "b".into(), 20.into()]]
+    );
+    assert!(votes.lookup(&[3.into()], true).await.unwrap().is_empty());
+    assert!(votes.lookup(&[4.into()], true).await.unwrap().is_empty());
 }
 
 #[tokio::test(threaded_scheduler)]
@@ -1207,6 +2841,130 @@ async fn it_recovers_persisted_bases_w_multiple_nodes() {
     done.await;
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_avg_and_count_distinct() {
+    let mut g = start_simple("it_works_with_avg_and_count_distinct").await;
+    let sql = "
+        CREATE TABLE Rating (id int, item int, rater int, stars int, PRIMARY KEY(id));
+        QUERY AvgStars: SELECT item, AVG(stars) FROM Rating WHERE item = ? GROUP BY item;
+        QUERY Raters: SELECT item, COUNT(DISTINCT rater) FROM Rating WHERE item = ? GROUP BY item;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Rating").await.unwrap();
+    let mut avg = g.view("AvgStars").await.unwrap();
+    let mut raters = g.view("Raters").await.unwrap();
+
+    mutator
+        .insert(vec![1.into(), 10.into(), 100.into(), 4.into()])
+        .await
+        .unwrap();
+    mutator
+        .insert(vec![2.into(), 10.into(), 101.into(), 2.into()])
+        .await
+        .unwrap();
+    mutator
+        .insert(vec![3.into(), 10.into(), 100.into(), 3.into()])
+        .await
+        .unwrap();
+    mutator
+        .insert(vec![4.into(), 20.into(), 100.into(), 5.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // the first lookups replay the groups
+    let result = avg.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![10.into(), 3.0.into()]]);
+    let result = raters.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![10.into(), 2.into()]]);
+
+    // later writes update them
+    mutator.delete(vec![2.into()]).await.unwrap();
+    mutator
+        .insert(vec![5.into(), 10.into(), 102.into(), DataType::None])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // NULLs don't count towards the average, but the rater still counts
+    let result = avg.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![10.into(), 3.5.into()]]);
+    let result = raters.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![10.into(), 2.into()]]);
+
+    // and removing one of a rater's ratings does not remove the rater
+    mutator.delete(vec![3.into()]).await.unwrap();
+    sleep().await;
+    let result = raters.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![10.into(), 2.into()]]);
+    let result = avg.lookup(&[20.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![20.into(), 5.0.into()]]);
+
+    // but removing the rater's last one does, and leaves only NULLs to average
+    mutator.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    let result = raters.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![10.into(), 1.into()]]);
+    let result = avg.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![10.into(), DataType::None]]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_rejects_aggregating_non_numeric_columns() {
+    let mut g = start_simple("it_rejects_aggregating_non_numeric_columns").await;
+    let sql = "
+        CREATE TABLE Review (id int, item int, body text, posted timestamp, score double, \
+                             PRIMARY KEY(id));
+        QUERY AvgScore: SELECT item, AVG(score) FROM Review WHERE item = ? GROUP BY item;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    // sums are integral, and neither text nor timestamps can be summed or averaged
+    for q in &[
+        "QUERY Bodies: SELECT item, SUM(body) FROM Review WHERE item = ? GROUP BY item;",
+        "QUERY Posted: SELECT item, AVG(posted) FROM Review WHERE item = ? GROUP BY item;",
+        "QUERY Scores: SELECT item, SUM(score) FROM Review WHERE item = ? GROUP BY item;",
+    ] {
+        assert!(g.extend_recipe(q).await.is_err(), "{} was accepted", q);
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_keeps_max_after_deleting_it() {
+    let mut g = start_simple("it_keeps_max_after_deleting_it").await;
+    let sql = "
+        CREATE TABLE Item (id int, category int, price int, PRIMARY KEY(id));
+        QUERY MaxPrice: SELECT category, MAX(price) FROM Item WHERE category = ? GROUP BY category;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Item").await.unwrap();
+    let mut getter = g.view("MaxPrice").await.unwrap();
+    for (id, price) in &[(1, 10), (2, 30), (3, 20), (4, 30)] {
+        mutator
+            .insert(vec![(*id).into(), 1.into(), (*price).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    let result = getter.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), 30.into()]]);
+
+    // the maximum stays while there is another copy of it
+    mutator.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+    let result = getter.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), 30.into()]]);
+
+    // and then falls back to the next largest price
+    mutator.delete(vec![4.into()]).await.unwrap();
+    sleep().await;
+    let result = getter.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), 20.into()]]);
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_with_simple_arithmetic() {
     let mut g = start_simple("it_works_with_simple_arithmetic").await;
@@ -1302,6 +3060,144 @@ async fn it_works_with_join_arithmetic() {
     assert_eq!(result[0][1], (f64::from(price) * fraction).into());
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_right_and_full_joins() {
+    let mut g = start_simple_unsharded("it_works_with_right_and_full_joins").await;
+    g.install_recipe(
+        "CREATE TABLE a (x int, c text);
+         CREATE TABLE b (y int, d text);",
+    )
+    .await
+    .unwrap();
+
+    let mut a = g.table("a").await.unwrap();
+    let mut b = g.table("b").await.unwrap();
+    a.insert(vec![1.into(), "a".into()]).await.unwrap();
+    a.insert(vec![3.into(), "c".into()]).await.unwrap();
+    b.insert(vec![1.into(), "x".into()]).await.unwrap();
+    b.insert(vec![2.into(), "y".into()]).await.unwrap();
+    b.insert(vec![2.into(), "w".into()]).await.unwrap();
+    sleep().await;
+
+    // the views are added once there is data, so their lookups have to be replayed
+    g.extend_recipe(
+        "QUERY qr: SELECT a.x, a.c, b.d FROM a RIGHT JOIN b ON (a.x = b.y) WHERE a.x = ?;
+         QUERY qf: SELECT a.x, a.c, b.d FROM a FULL OUTER JOIN b ON (a.x = b.y) WHERE a.x = ?;
+         QUERY qc: SELECT a.x, a.c, b.d FROM a FULL JOIN b ON (a.x = b.y) \
+                   WHERE a.x = ? AND a.c = ?;",
+    )
+    .await
+    .unwrap();
+    let mut qr = g.view("qr").await.unwrap();
+    let mut qf = g.view("qf").await.unwrap();
+    let mut qc = g.view("qc").await.unwrap();
+
+    let lookup = |rows: Vec<Vec<DataType>>| {
+        let mut rows: Vec<_> = rows.into_iter().map(|r| r[..3].to_vec()).collect();
+        rows.sort();
+        rows
+    };
+    let right_only = vec![
+        vec![2.into(), DataType::None, "w".into()],
+        vec![2.into(), DataType::None, "y".into()],
+    ];
+    let joined = vec![vec![1.into(), "a".into(), "x".into()]];
+    let left_only = vec![vec![3.into(), "c".into(), DataType::None]];
+
+    assert_eq!(
+        lookup(qr.lookup(&[1.into()], true).await.unwrap().into()),
+        joined
+    );
+    assert_eq!(
+        lookup(qr.lookup(&[2.into()], true).await.unwrap().into()),
+        right_only
+    );
+    assert!(qr.lookup(&[3.into()], true).await.unwrap().is_empty());
+
+    assert_eq!(
+        lookup(qf.lookup(&[1.into()], true).await.unwrap().into()),
+        joined
+    );
+    assert_eq!(
+        lookup(qf.lookup(&[2.into()], true).await.unwrap().into()),
+        right_only
+    );
+    assert_eq!(
+        lookup(qf.lookup(&[3.into()], true).await.unwrap().into()),
+        left_only
+    );
+
+    // right-only rows are NULL in the left's columns other than the join column
+    let key = |x: i32, c: DataType| vec![x.into(), c];
+    assert_eq!(
+        lookup(
+            qc.lookup(&key(2, DataType::None), true)
+                .await
+                .unwrap()
+                .into()
+        ),
+        right_only
+    );
+    assert_eq!(
+        lookup(qc.lookup(&key(1, "a".into()), true).await.unwrap().into()),
+        joined
+    );
+    assert!(qc
+        .lookup(&key(1, DataType::None), true)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // a matching row on the left replaces the right-only rows
+    a.insert(vec![2.into(), "b".into()]).await.unwrap();
+    sleep().await;
+    let rows = lookup(qf.lookup(&[2.into()], true).await.unwrap().into());
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().all(|r| r[1] == DataType::from("b")));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_fully_replays_full_joins() {
+    let mut builder = Builder::default();
+    builder.disable_partial();
+    builder.set_sharding(None);
+    builder.set_persistence(get_persistence_params("it_fully_replays_full_joins"));
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE a (x int, c text);
+         CREATE TABLE b (y int, d text, z int);",
+    )
+    .await
+    .unwrap();
+
+    let mut a = g.table("a").await.unwrap();
+    let mut b = g.table("b").await.unwrap();
+    a.insert(vec![1.into(), "a".into()]).await.unwrap();
+    b.insert(vec![1.into(), "x".into(), 1.into()])
+        .await
+        .unwrap();
+    b.insert(vec![2.into(), "y".into(), 1.into()])
+        .await
+        .unwrap();
+    b.insert(vec![2.into(), "w".into(), 0.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // the filter on b is the join's right parent, whose rows the replay has to see
+    g.extend_recipe(
+        "QUERY qf: SELECT a.x, a.c, b.d FROM a FULL JOIN b ON (a.x = b.y) WHERE b.z = 1;",
+    )
+    .await
+    .unwrap();
+    let mut qf = g.view("qf").await.unwrap();
+    let rows: Vec<Vec<DataType>> = qf.lookup(&[0.into()], true).await.unwrap().into();
+    let rows: Vec<_> = rows.into_iter().map(|r| r[..3].to_vec()).collect();
+    assert!(rows.contains(&vec![1.into(), "a".into(), "x".into()]));
+    assert!(rows.contains(&vec![2.into(), DataType::None, "y".into()]));
+    assert!(!rows.iter().any(|r| r[2] == DataType::from("w")));
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_with_function_arithmetic() {
     let mut g = start_simple("it_works_with_function_arithmetic").await;
@@ -2821,6 +4717,159 @@ async fn remove_query() {
     }
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn remove_query_reclaims_domains() {
+    let r_txt = "CREATE TABLE b (a int, c text, x text);\n
+                 QUERY qa: SELECT a FROM b;\n
+                 QUERY qb: SELECT a, c FROM b WHERE a = 42;";
+
+    let r2_txt = "CREATE TABLE b (a int, c text, x text);\n
+                  QUERY qa: SELECT a FROM b;";
+
+    let mut g = start_simple("remove_query_reclaims_domains").await;
+    g.install_recipe(r_txt).await.unwrap();
+    let ndomains = g.statistics().await.unwrap().len();
+
+    // removing qb tears down its reader, along with the domain that hosted it
+    g.install_recipe(r2_txt).await.unwrap();
+    assert!(g.statistics().await.unwrap().len() < ndomains);
+
+    // adding it back and removing it again does not leave anything behind
+    for _ in 0..3 {
+        g.install_recipe(r_txt).await.unwrap();
+        assert_eq!(g.statistics().await.unwrap().len(), ndomains);
+        g.install_recipe(r2_txt).await.unwrap();
+    }
+
+    let mut mutb = g.table("b").await.unwrap();
+    let mut qa = g.view("qa").await.unwrap();
+    mutb.insert(vec![42.into(), "2".into(), "3".into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(qa.lookup(&[0.into()], true).await.unwrap().len(), 1);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_plans_recipes() {
+    let r_txt = "CREATE TABLE b (a int, c text, x text);\n
+                 QUERY qa: SELECT a FROM b;";
+    let add_txt = "QUERY qb: SELECT a, c FROM b WHERE a = ?;";
+
+    let mut g = start_simple("it_plans_recipes").await;
+    g.install_recipe(r_txt).await.unwrap();
+    let graph = g.graphviz().await.unwrap();
+
+    // planning an extension reports what it would add, but leaves the graph alone
+    let plan = g.plan_recipe(add_txt).await.unwrap();
+    assert!(!plan.new_nodes.is_empty());
+    assert!(plan.removed_nodes.is_empty());
+    assert!(plan.reused_nodes.iter().any(|n| n.name == "b"));
+    assert!(!plan.new_materializations.is_empty());
+    assert_eq!(g.graphviz().await.unwrap(), graph);
+    assert!(!g.outputs().await.unwrap().contains_key("qb"));
+
+    // planning a replacement reports the nodes of the queries it would remove
+    let plan = g
+        .plan_recipe_installation("CREATE TABLE b (a int, c text, x text);")
+        .await
+        .unwrap();
+    assert!(plan.new_nodes.is_empty());
+    assert!(!plan.removed_nodes.is_empty());
+    assert!(plan.removed_nodes.iter().all(|n| n.name != "b"));
+    assert_eq!(g.graphviz().await.unwrap(), graph);
+
+    // the planned extension can still be applied as usual
+    g.extend_recipe(add_txt).await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+    let mut qb = g.view("qb").await.unwrap();
+    mutb.insert(vec![42.into(), "2".into(), "3".into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(qb.lookup(&[42.into()], true).await.unwrap().len(), 1);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_estimates_planned_materializations() {
+    let mut builder = Builder::default();
+    builder.disable_partial();
+    builder.set_sharding(None);
+    builder.set_persistence(get_persistence_params(
+        "it_estimates_planned_materializations",
+    ));
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe("CREATE TABLE b (a int, c text, x text);")
+        .await
+        .unwrap();
+
+    let mut mutb = g.table("b").await.unwrap();
+    for i in 0..3 {
+        mutb.insert(vec![i.into(), "2".into(), "3".into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    // a full materialization is filled with every row of the base it is replayed from
+    let plan = g
+        .plan_recipe("QUERY qb: SELECT a, c FROM b WHERE a = ?;")
+        .await
+        .unwrap();
+    let m = plan
+        .new_materializations
+        .iter()
+        .find(|m| !m.replay_from.is_empty())
+        .unwrap();
+    assert!(!m.partial);
+    assert_eq!(m.estimated_rows, 3);
+    assert!(m.estimated_bytes > 0);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_rolls_back_recipes() {
+    let r_txt = "CREATE TABLE b (a int, c text, x text);\n
+                 QUERY qa: SELECT a FROM b;";
+    let add_txt = "QUERY qb: SELECT a, c FROM b WHERE a = ?;";
+
+    let mut g = start_simple("it_rolls_back_recipes").await;
+    g.install_recipe(r_txt).await.unwrap();
+    g.extend_recipe(add_txt).await.unwrap();
+
+    let versions = g.recipe_versions().await.unwrap();
+    assert_eq!(versions.len(), 2);
+    let (v1, v2) = (versions[0].version, versions[1].version);
+    assert!(v1 < v2);
+    assert!(versions[1].recipe.contains("qb"));
+
+    let diff = g.diff_recipes(v1, v2).await.unwrap();
+    assert_eq!(diff.added.len(), 1);
+    assert!(diff.added[0].starts_with("QUERY qb:"));
+    assert!(diff.removed.is_empty());
+    assert!(g.diff_recipes(v1, v2 + 1).await.is_err());
+
+    // rolling back is a new migration, which removes qb again
+    g.rollback_recipe(v1).await.unwrap();
+    let versions = g.recipe_versions().await.unwrap();
+    assert_eq!(versions.len(), 3);
+    assert!(versions[2].version > v2);
+    assert!(!g.outputs().await.unwrap().contains_key("qb"));
+    assert!(g
+        .diff_recipes(v1, versions[2].version)
+        .await
+        .unwrap()
+        .added
+        .is_empty());
+
+    let mut mutb = g.table("b").await.unwrap();
+    let mut qa = g.view("qa").await.unwrap();
+    mutb.insert(vec![42.into(), "2".into(), "3".into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(qa.lookup(&[0.into()], true).await.unwrap().len(), 1);
+}
+
 macro_rules! get {
     ($private:ident, $public:ident, $uid:expr, $aid:expr) => {{
         // combine private and public results
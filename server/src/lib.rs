@@ -361,6 +361,8 @@ mod builder;
 mod controller;
 mod coordination;
 mod handle;
+mod mysql;
+mod snapshot;
 mod startup;
 mod worker;
 
@@ -378,8 +380,9 @@ pub enum ReuseConfigType {
 
 pub use crate::builder::Builder;
 pub use crate::handle::Handle;
+pub use crate::mysql::listen_mysql;
 pub use controller::migrate::materialization::FrontierStrategy;
-pub use dataflow::{DurabilityMode, PersistenceParameters};
+pub use dataflow::{DurabilityMode, EvictionKind, PersistenceParameters};
 pub use noria::consensus::LocalAuthority;
 pub use noria::*;
 pub use petgraph::graph::NodeIndex;
@@ -419,6 +422,8 @@ impl Default for Config {
             domain_config: DomainConfig {
                 concurrent_replays: 512,
                 replay_batch_timeout: time::Duration::new(0, 100_000),
+                transaction_timeout: time::Duration::from_secs(10),
+                eviction_policy: Default::default(),
             },
             persistence: Default::default(),
             heartbeat_every: time::Duration::from_secs(1),
@@ -27,7 +27,7 @@ fn main() {
             Arg::with_name("durability")
                 .long("durability")
                 .takes_value(true)
-                .possible_values(&["persistent", "ephemeral", "memory"])
+                .possible_values(&["persistent", "ephemeral", "memory", "logged-memory"])
                 .default_value("persistent")
                 .help("How to maintain base logs."),
         )
@@ -75,6 +75,15 @@ fn main() {
                 .requires("memory")
                 .help("Frequency at which to check the state size against the memory limit [in seconds]."),
         )
+        .arg(
+            Arg::with_name("eviction_policy")
+                .long("eviction-policy")
+                .takes_value(true)
+                .possible_values(&["random", "lru", "lfu", "size-weighted"])
+                .default_value("random")
+                .requires("memory")
+                .help("How to pick the state to evict when exceeding the memory limit."),
+        )
         .arg(
             Arg::with_name("noreuse")
                 .long("no-reuse")
@@ -100,6 +109,18 @@ fn main() {
                 .default_value("0")
                 .help("Shard the graph this many ways (0 = disable sharding)."),
         )
+        .arg(
+            Arg::with_name("restore")
+                .long("restore")
+                .takes_value(true)
+                .help("Restore a snapshot from this directory into the (empty) deployment."),
+        )
+        .arg(
+            Arg::with_name("mysql-address")
+                .long("mysql-address")
+                .takes_value(true)
+                .help("Also accept MySQL clients on this address (e.g., 127.0.0.1:3306)."),
+        )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
@@ -116,6 +137,7 @@ fn main() {
     let zookeeper_addr = matches.value_of("zookeeper").unwrap();
     let memory = value_t_or_exit!(matches, "memory", usize);
     let memory_check_freq = value_t_or_exit!(matches, "memory_check_freq", u64);
+    let eviction_policy = value_t_or_exit!(matches, "eviction_policy", noria_server::EvictionKind);
     let quorum = value_t_or_exit!(matches, "quorum", usize);
     let persistence_threads = value_t_or_exit!(matches, "persistence-threads", i32);
     let flush_ns = value_t_or_exit!(matches, "flush-timeout", u32);
@@ -131,7 +153,11 @@ fn main() {
     let mut builder = Builder::default();
     builder.set_listen_addr(listen_addr);
     if memory > 0 {
-        builder.set_memory_limit(memory, Duration::from_secs(memory_check_freq));
+        builder.set_memory_limit(
+            memory,
+            Duration::from_secs(memory_check_freq),
+            eviction_policy,
+        );
     }
     builder.set_sharding(sharding);
     builder.set_quorum(quorum);
@@ -147,6 +173,7 @@ fn main() {
             "persistent" => noria_server::DurabilityMode::Permanent,
             "ephemeral" => noria_server::DurabilityMode::DeleteOnExit,
             "memory" => noria_server::DurabilityMode::MemoryOnly,
+            "logged-memory" => noria_server::DurabilityMode::MemoryWithLog,
             _ => unreachable!(),
         },
         Duration::new(0, flush_ns),
@@ -171,7 +198,23 @@ fn main() {
         rt.core_threads(threads);
     }
     let mut rt = rt.build().unwrap();
-    let (_server, done) = rt.block_on(builder.start(Arc::new(authority))).unwrap();
+    let (mut server, done) = rt.block_on(builder.start(Arc::new(authority))).unwrap();
+    if let Some(path) = matches.value_of("restore") {
+        if let Err(e) = rt.block_on(server.restore(path)) {
+            eprintln!("failed to restore snapshot from {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(addr) = matches.value_of("mysql-address") {
+        let addr = addr.parse().unwrap_or_else(|e| {
+            eprintln!("invalid MySQL address {}: {}", addr, e);
+            std::process::exit(1);
+        });
+        if let Err(e) = noria_server::listen_mysql(addr, (*server).clone(), rt.handle().clone()) {
+            eprintln!("failed to listen for MySQL clients on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    }
     rt.block_on(done);
     drop(rt);
 }
@@ -0,0 +1,888 @@
+//! A frontend that lets MySQL clients talk to Noria directly.
+//!
+//! The listener speaks just enough of the MySQL protocol for applications that use prepared
+//! statements against Noria's supported subset of SQL:
+//!
+//!  - `SELECT` statements become lookups in a view. The first time a query is seen, it is added
+//!    to the recipe under a name derived from its text, and the values of its `?` parameters
+//!    form the key of each lookup. Queries without parameters are looked up by the bogokey.
+//!  - `INSERT` statements become inserts into the named base table, with any columns that are
+//!    not mentioned set to `NULL`.
+//!  - `UPDATE` and `DELETE` statements must have a `WHERE` clause that compares a single indexed
+//!    column to a value, and become `Table::update_where` and `Table::delete_where` respectively.
+//!
+//! Parameters take on the types of the columns they are compared to or assigned to, and the values
+//! clients send for them are converted to those types.
+//!
+//! Each client connection is served by a thread from a fixed pool, which blocks on the futures it
+//! issues to the controller, views, and tables. Once every thread is busy, new connections wait to
+//! be accepted until an earlier one closes.
+
+use msql_srv::{
+    Column, ColumnFlags, ColumnType, ErrorKind, MysqlIntermediary, MysqlShim, ParamParser,
+    QueryResultWriter, RowWriter, StatementMetaWriter, ValueInner,
+};
+use nom_sql::{
+    ColumnConstraint, ConditionBase, ConditionExpression, ConditionTree, CreateTableStatement,
+    DeleteStatement, FieldDefinitionExpression, FieldValueExpression, InsertStatement, Literal,
+    Operator, SelectStatement, SqlQuery, SqlType, TableKey, UpdateStatement,
+};
+use noria::consensus::Authority;
+use noria::{ControllerHandle, DataType, Modification, Table, View};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// The number of client connections that are served at the same time.
+const MAX_CONNECTIONS: usize = 64;
+
+/// Start accepting MySQL client connections on `addr`.
+///
+/// Requests from clients are issued to the controller through `ch`, and the futures they produce
+/// are driven by the runtime behind `rt`. Returns the address the listener ended up bound to.
+pub fn listen_mysql<A: Authority + 'static>(
+    addr: SocketAddr,
+    ch: ControllerHandle<A>,
+    rt: tokio::runtime::Handle,
+) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+
+    // the listener hands each connection to the next idle thread, and waits for one otherwise.
+    let (tx, rx) = mpsc::sync_channel::<TcpStream>(0);
+    let rx = Arc::new(Mutex::new(rx));
+    let recipe = Arc::new(Mutex::new(()));
+    for i in 0..MAX_CONNECTIONS {
+        let rx = rx.clone();
+        let ch = ch.clone();
+        let rt = rt.clone();
+        let recipe = recipe.clone();
+        thread::Builder::new()
+            .name(format!("mysql-conn-{}", i))
+            .spawn(move || loop {
+                let stream = match rx.lock().unwrap().recv() {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                let backend = Backend::new(ch.clone(), rt.clone(), recipe.clone());
+                let _ = MysqlIntermediary::run_on_tcp(backend, stream);
+            })?;
+    }
+
+    thread::Builder::new()
+        .name("mysql-listener".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let _ = stream.set_nodelay(true);
+                if tx.send(stream).is_err() {
+                    break;
+                }
+            }
+        })?;
+    Ok(local_addr)
+}
+
+/// A hash of a query's text that stays the same across builds and toolchains, so that the views
+/// that queries are added to the recipe as keep their names.
+///
+/// This is 64-bit FNV-1a.
+fn query_hash(query: &str) -> u64 {
+    query.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// A statement that a client has prepared, or sent as a plain query.
+#[derive(Clone)]
+enum Statement {
+    Select {
+        view: String,
+        query: SelectStatement,
+        params: usize,
+    },
+    Insert(InsertStatement),
+    Update(UpdateStatement),
+    Delete(DeleteStatement),
+}
+
+impl Statement {
+    fn new(q: SqlQuery) -> Result<Self, Error> {
+        Ok(match q {
+            SqlQuery::Select(query) => {
+                let params = query.where_clause.as_ref().map(placeholders).unwrap_or(0);
+                Statement::Select {
+                    view: format!("mysql_{:x}", query_hash(&query.to_string())),
+                    query,
+                    params,
+                }
+            }
+            SqlQuery::Insert(q) => Statement::Insert(q),
+            SqlQuery::Update(q) => Statement::Update(q),
+            SqlQuery::Delete(q) => Statement::Delete(q),
+            q => {
+                return Err((
+                    ErrorKind::ER_NOT_SUPPORTED_YET,
+                    format!("unsupported statement: {}", q),
+                ))
+            }
+        })
+    }
+
+    /// The number of `?` parameters in the statement.
+    fn params(&self) -> usize {
+        match *self {
+            Statement::Select { params, .. } => params,
+            Statement::Insert(ref q) => q
+                .data
+                .iter()
+                .flatten()
+                .filter(|l| **l == Literal::Placeholder)
+                .count(),
+            Statement::Update(ref q) => {
+                q.fields
+                    .iter()
+                    .filter(|(_, v)| match *v {
+                        FieldValueExpression::Literal(ref l) => l.value == Literal::Placeholder,
+                        _ => false,
+                    })
+                    .count()
+                    + q.where_clause.as_ref().map(placeholders).unwrap_or(0)
+            }
+            Statement::Delete(ref q) => q.where_clause.as_ref().map(placeholders).unwrap_or(0),
+        }
+    }
+}
+
+fn placeholders(ce: &ConditionExpression) -> usize {
+    match *ce {
+        ConditionExpression::ComparisonOp(ref ct) | ConditionExpression::LogicalOp(ref ct) => {
+            placeholders(&ct.left) + placeholders(&ct.right)
+        }
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            placeholders(inner)
+        }
+        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => 1,
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => 0,
+    }
+}
+
+/// The columns that the `?` parameters of a condition are compared to, in order, as table and
+/// column names. Parameters that aren't compared to a column have none.
+fn condition_params(
+    ce: &ConditionExpression,
+    tables: &[nom_sql::Table],
+    out: &mut Vec<Option<(String, String)>>,
+) {
+    let placeholder = |ce: &ConditionExpression| match *ce {
+        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => true,
+        _ => false,
+    };
+    match *ce {
+        ConditionExpression::ComparisonOp(ref ct) => match (&*ct.left, &*ct.right) {
+            (ConditionExpression::Base(ConditionBase::Field(ref c)), p) if placeholder(p) => {
+                out.push(resolve_column(c, tables))
+            }
+            (p, ConditionExpression::Base(ConditionBase::Field(ref c))) if placeholder(p) => {
+                out.push(resolve_column(c, tables))
+            }
+            (left, right) => {
+                condition_params(left, tables, out);
+                condition_params(right, tables, out);
+            }
+        },
+        ConditionExpression::LogicalOp(ref ct) => {
+            condition_params(&ct.left, tables, out);
+            condition_params(&ct.right, tables, out);
+        }
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            condition_params(inner, tables, out)
+        }
+        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => out.push(None),
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => {}
+    }
+}
+
+/// Find the table that a column of a statement over `tables` belongs to.
+fn resolve_column(c: &nom_sql::Column, tables: &[nom_sql::Table]) -> Option<(String, String)> {
+    let table = match c.table {
+        Some(ref t) => tables
+            .iter()
+            .find(|table| table.alias.as_ref() == Some(t))
+            .map(|table| table.name.clone())
+            .unwrap_or_else(|| t.clone()),
+        None if tables.len() == 1 => tables[0].name.clone(),
+        None => return None,
+    };
+    Some((table, c.name.clone()))
+}
+
+/// The result of executing a statement.
+enum Reply {
+    Rows(Vec<Column>, Vec<Vec<DataType>>),
+    Done(u64),
+}
+
+type Error = (ErrorKind, String);
+
+fn other<E: ToString>(e: E) -> Error {
+    (ErrorKind::ER_UNKNOWN_ERROR, e.to_string())
+}
+
+fn block_on<F: Future>(rt: &tokio::runtime::Handle, f: F) -> F::Output {
+    rt.enter(|| futures_executor::block_on(f))
+}
+
+struct Backend<A: Authority + 'static> {
+    ch: ControllerHandle<A>,
+    rt: tokio::runtime::Handle,
+    /// Held while adding a query to the recipe, so that connections that see the same query for
+    /// the first time don't all add it.
+    recipe: Arc<Mutex<()>>,
+    views: HashMap<String, View>,
+    tables: HashMap<String, Table>,
+    /// Prepared statements, along with the types of their parameters.
+    prepared: slab::Slab<(Statement, Vec<Option<SqlType>>)>,
+}
+
+impl<A: Authority + 'static> Backend<A> {
+    fn new(ch: ControllerHandle<A>, rt: tokio::runtime::Handle, recipe: Arc<Mutex<()>>) -> Self {
+        Backend {
+            ch,
+            rt,
+            recipe,
+            views: HashMap::new(),
+            tables: HashMap::new(),
+            prepared: slab::Slab::new(),
+        }
+    }
+
+    /// Get the view for a `SELECT` statement, adding the query to the recipe if need be.
+    fn view(&mut self, name: &str, query: &SelectStatement) -> Result<&mut View, Error> {
+        if !self.views.contains_key(name) {
+            let ch = &mut self.ch;
+            let recipe = &self.recipe;
+            let view = block_on(&self.rt, async move {
+                ch.ready().await?;
+                if let Ok(view) = ch.view(name).await {
+                    return Ok(view);
+                }
+
+                // another connection may have added the query while we waited for the lock
+                let _recipe = recipe.lock().unwrap_or_else(|e| e.into_inner());
+                ch.ready().await?;
+                if let Ok(view) = ch.view(name).await {
+                    return Ok(view);
+                }
+                ch.extend_recipe(&format!("QUERY {}: {};", name, query))
+                    .await?;
+                ch.ready().await?;
+                ch.view(name).await
+            })
+            .map_err(other)?;
+            self.views.insert(name.to_string(), view);
+        }
+        Ok(self.views.get_mut(name).unwrap())
+    }
+
+    fn table(&mut self, name: &str) -> Result<&mut Table, Error> {
+        if !self.tables.contains_key(name) {
+            let ch = &mut self.ch;
+            let table = block_on(&self.rt, async move {
+                ch.ready().await?;
+                ch.table(name).await
+            })
+            .map_err(|e| (ErrorKind::ER_NO_SUCH_TABLE, e.to_string()))?;
+            self.tables.insert(name.to_string(), table);
+        }
+        Ok(self.tables.get_mut(name).unwrap())
+    }
+
+    /// Describe the columns a `SELECT` statement returns, as far as the view's schema knows.
+    fn describe(&mut self, name: &str, query: &SelectStatement) -> Result<Vec<Column>, Error> {
+        let view = self.view(name, query)?;
+        let visible = visible_columns(query, view);
+        let schema = view.schema();
+        Ok(view.columns()[..visible]
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let (coltype, colflags) = match schema {
+                    Some(schema) => sql_type(&schema[i].sql_type),
+                    None => (ColumnType::MYSQL_TYPE_VAR_STRING, ColumnFlags::empty()),
+                };
+                Column {
+                    table: name.to_string(),
+                    column: c.clone(),
+                    coltype,
+                    colflags,
+                }
+            })
+            .collect())
+    }
+
+    /// The type of a table's column, if the table's schema is known.
+    fn column_type(&mut self, table: &str, column: &str) -> Option<SqlType> {
+        let schema = self.table(table).ok()?.schema()?;
+        schema
+            .fields
+            .iter()
+            .find(|f| f.column.name == column)
+            .map(|f| f.sql_type.clone())
+    }
+
+    /// The types of the columns that the `?` parameters of a statement stand in for, in order.
+    fn param_types(&mut self, stmt: &Statement) -> Result<Vec<Option<SqlType>>, Error> {
+        let mut columns = Vec::with_capacity(stmt.params());
+        match *stmt {
+            Statement::Select { ref query, .. } => {
+                if let Some(ref ce) = query.where_clause {
+                    let mut tables = query.tables.clone();
+                    tables.extend(query.join.iter().filter_map(|j| match j.right {
+                        nom_sql::JoinRightSide::Table(ref t) => Some(t.clone()),
+                        _ => None,
+                    }));
+                    condition_params(ce, &tables, &mut columns);
+                }
+            }
+            Statement::Insert(ref q) => {
+                let fields: Vec<_> = match q.fields {
+                    Some(ref fields) => fields.iter().map(|f| f.name.clone()).collect(),
+                    None => self.table(&q.table.name)?.columns().to_vec(),
+                };
+                for values in &q.data {
+                    for (f, v) in fields.iter().zip(values) {
+                        if *v == Literal::Placeholder {
+                            columns.push(Some((q.table.name.clone(), f.clone())));
+                        }
+                    }
+                }
+            }
+            Statement::Update(ref q) => {
+                for (c, v) in &q.fields {
+                    if let FieldValueExpression::Literal(ref l) = *v {
+                        if l.value == Literal::Placeholder {
+                            columns.push(Some((q.table.name.clone(), c.name.clone())));
+                        }
+                    }
+                }
+                if let Some(ref ce) = q.where_clause {
+                    condition_params(ce, &[q.table.clone()], &mut columns);
+                }
+            }
+            Statement::Delete(ref q) => {
+                if let Some(ref ce) = q.where_clause {
+                    condition_params(ce, &[q.table.clone()], &mut columns);
+                }
+            }
+        }
+
+        Ok(columns
+            .into_iter()
+            .map(|c| c.and_then(|(table, column)| self.column_type(&table, &column)))
+            .collect())
+    }
+
+    /// Parse a statement, and describe the columns it returns.
+    fn prepare(&mut self, query: &str) -> Result<(Statement, Vec<Column>), Error> {
+        let stmt = nom_sql::parse_query(query)
+            .map_err(|e| (ErrorKind::ER_PARSE_ERROR, e.to_string()))
+            .and_then(Statement::new)?;
+        let columns = match stmt {
+            Statement::Select {
+                ref view,
+                ref query,
+                ..
+            } => self.describe(view, query)?,
+            _ => Vec::new(),
+        };
+        Ok((stmt, columns))
+    }
+
+    /// The number of rows in `table` whose `column` holds `value`, which `UPDATE` and `DELETE`
+    /// report as the rows they affected.
+    ///
+    /// The base table finds the rows to change by itself, and doesn't say how many it found. So
+    /// instead, we look the rows up in a view over the table, which is added to the recipe the
+    /// first time a statement matches rows on that column. Rows written very recently may not have
+    /// reached the view yet.
+    fn count_where(&mut self, table: &str, column: &str, value: &DataType) -> Result<u64, Error> {
+        let query = format!("SELECT * FROM {0} WHERE {0}.{1} = ?", table, column);
+        let (view, query) = match nom_sql::parse_query(&query) {
+            Ok(SqlQuery::Select(query)) => {
+                let view = format!("mysql_{:x}", query_hash(&query.to_string()));
+                (view, query)
+            }
+            _ => return Err(other(format!("could not count rows in {}", table))),
+        };
+
+        let rt = self.rt.clone();
+        let v = self.view(&view, &query)?;
+        let rows = block_on(&rt, v.lookup(&[value.clone()], true)).map_err(other)?;
+        Ok(rows.len() as u64)
+    }
+
+    fn execute(&mut self, stmt: &Statement, params: Vec<DataType>) -> Result<Reply, Error> {
+        if params.len() != stmt.params() {
+            return Err((
+                ErrorKind::ER_WRONG_ARGUMENTS,
+                format!(
+                    "expected {} parameters, got {}",
+                    stmt.params(),
+                    params.len()
+                ),
+            ));
+        }
+
+        let rt = self.rt.clone();
+        let mut params = params.into_iter();
+        match *stmt {
+            Statement::Select {
+                ref view,
+                ref query,
+                ..
+            } => {
+                let mut columns = self.describe(view, query)?;
+                let key: Vec<_> = if stmt.params() == 0 {
+                    vec![0.into()]
+                } else {
+                    params.collect()
+                };
+                let v = self.view(view, query)?;
+                let mut rows: Vec<Vec<DataType>> =
+                    block_on(&rt, v.lookup(&key, true)).map_err(other)?.into();
+                for row in &mut rows {
+                    row.truncate(columns.len());
+                }
+
+                // prefer the types of the values we actually return over those in the schema
+                for (i, c) in columns.iter_mut().enumerate() {
+                    if let Some(v) = rows.iter().map(|r| &r[i]).find(|v| **v != DataType::None) {
+                        let (coltype, colflags) = value_type(v);
+                        c.coltype = coltype;
+                        c.colflags = colflags;
+                    }
+                }
+                Ok(Reply::Rows(columns, rows))
+            }
+            Statement::Insert(ref q) => {
+                let table = self.table(&q.table.name)?;
+                let fields = match q.fields {
+                    Some(ref fields) => fields
+                        .iter()
+                        .map(|f| column_index(table, &f.name))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    None => (0..table.columns().len()).collect(),
+                };
+
+                let mut rows = Vec::with_capacity(q.data.len());
+                for values in &q.data {
+                    if values.len() != fields.len() {
+                        return Err((
+                            ErrorKind::ER_WRONG_VALUE_COUNT_ON_ROW,
+                            format!("expected {} values, got {}", fields.len(), values.len()),
+                        ));
+                    }
+                    let mut row = vec![DataType::None; table.columns().len()];
+                    for (&i, v) in fields.iter().zip(values) {
+                        row[i] = literal(v, &mut params);
+                    }
+                    rows.push(row);
+                }
+
+                let n = rows.len() as u64;
+                block_on(&rt, table.perform_all(rows)).map_err(other)?;
+                Ok(Reply::Done(n))
+            }
+            Statement::Update(ref q) => {
+                let table = self.table(&q.table.name)?;
+                let mut set = Vec::with_capacity(q.fields.len());
+                for (c, v) in &q.fields {
+                    let v = match *v {
+                        FieldValueExpression::Literal(ref l) => literal(&l.value, &mut params),
+                        _ => {
+                            return Err((
+                                ErrorKind::ER_NOT_SUPPORTED_YET,
+                                format!("unsupported value for column {}", c.name),
+                            ))
+                        }
+                    };
+                    set.push((column_index(table, &c.name)?, Modification::Set(v)));
+                }
+
+                let (col, value) = single_equality(table, q.where_clause.as_ref(), &mut params)?;
+                check_primary_key(table)?;
+                let column = table.columns()[col].clone();
+
+                let n = self.count_where(&q.table.name, &column, &value)?;
+                let table = self.table(&q.table.name)?;
+                block_on(&rt, table.update_where(col, value, set)).map_err(other)?;
+                Ok(Reply::Done(n))
+            }
+            Statement::Delete(ref q) => {
+                let table = self.table(&q.table.name)?;
+                let (col, value) = single_equality(table, q.where_clause.as_ref(), &mut params)?;
+                check_primary_key(table)?;
+                let column = table.columns()[col].clone();
+
+                let n = self.count_where(&q.table.name, &column, &value)?;
+                let table = self.table(&q.table.name)?;
+                block_on(&rt, table.delete_where(col, value)).map_err(other)?;
+                Ok(Reply::Done(n))
+            }
+        }
+    }
+}
+
+/// The number of columns of a view that the query asked for.
+///
+/// Views may carry extra columns at the end, such as the bogokey or the columns of parameters
+/// that the query did not select.
+fn visible_columns(query: &SelectStatement, view: &View) -> usize {
+    let all = query.fields.iter().any(|f| match *f {
+        FieldDefinitionExpression::All | FieldDefinitionExpression::AllInTable(_) => true,
+        _ => false,
+    });
+    if all {
+        view.columns()
+            .iter()
+            .take_while(|c| *c != "bogokey")
+            .count()
+    } else {
+        std::cmp::min(query.fields.len(), view.columns().len())
+    }
+}
+
+fn column_index(table: &Table, name: &str) -> Result<usize, Error> {
+    table
+        .columns()
+        .iter()
+        .position(|c| c == name)
+        .ok_or_else(|| {
+            (
+                ErrorKind::ER_BAD_FIELD_ERROR,
+                format!("unknown column {} in {}", name, table.table_name()),
+            )
+        })
+}
+
+fn literal<I: Iterator<Item = DataType>>(l: &Literal, params: &mut I) -> DataType {
+    match *l {
+        // the number of parameters has already been checked
+        Literal::Placeholder => params.next().unwrap(),
+        ref l => l.into(),
+    }
+}
+
+/// Extract the column and value from a `WHERE column = value` clause.
+fn single_equality<I: Iterator<Item = DataType>>(
+    table: &Table,
+    where_clause: Option<&ConditionExpression>,
+    params: &mut I,
+) -> Result<(usize, DataType), Error> {
+    if let Some(ConditionExpression::ComparisonOp(ConditionTree {
+        operator: Operator::Equal,
+        ref left,
+        ref right,
+    })) = where_clause
+    {
+        if let (
+            ConditionExpression::Base(ConditionBase::Field(ref c)),
+            ConditionExpression::Base(ConditionBase::Literal(ref l)),
+        ) = (&**left, &**right)
+        {
+            return Ok((column_index(table, &c.name)?, literal(l, params)));
+        }
+    }
+
+    Err((
+        ErrorKind::ER_NOT_SUPPORTED_YET,
+        "only WHERE clauses of the form `column = value` are supported".to_string(),
+    ))
+}
+
+fn check_primary_key(table: &Table) -> Result<(), Error> {
+    let has_key = |schema: &CreateTableStatement| {
+        schema
+            .fields
+            .iter()
+            .any(|f| f.constraints.contains(&ColumnConstraint::PrimaryKey))
+            || schema.keys.iter().flatten().any(|k| match *k {
+                TableKey::PrimaryKey(_) => true,
+                _ => false,
+            })
+    };
+    if table.schema().map(has_key) == Some(true) {
+        Ok(())
+    } else {
+        Err((
+            ErrorKind::ER_REQUIRES_PRIMARY_KEY,
+            format!("{} has no primary key", table.table_name()),
+        ))
+    }
+}
+
+fn sql_type(ty: &SqlType) -> (ColumnType, ColumnFlags) {
+    match *ty {
+        SqlType::Int(_) | SqlType::Bigint(_) | SqlType::Tinyint(_) => {
+            (ColumnType::MYSQL_TYPE_LONGLONG, ColumnFlags::empty())
+        }
+        SqlType::UnsignedInt(_) | SqlType::UnsignedBigint(_) => {
+            (ColumnType::MYSQL_TYPE_LONGLONG, ColumnFlags::UNSIGNED_FLAG)
+        }
+        SqlType::Double | SqlType::Float | SqlType::Real => {
+            (ColumnType::MYSQL_TYPE_DOUBLE, ColumnFlags::empty())
+        }
+        SqlType::Date | SqlType::Timestamp => {
+            (ColumnType::MYSQL_TYPE_DATETIME, ColumnFlags::empty())
+        }
+        _ => (ColumnType::MYSQL_TYPE_VAR_STRING, ColumnFlags::empty()),
+    }
+}
+
+fn value_type(v: &DataType) -> (ColumnType, ColumnFlags) {
+    match *v {
+        DataType::UnsignedBigInt(_) => {
+            (ColumnType::MYSQL_TYPE_LONGLONG, ColumnFlags::UNSIGNED_FLAG)
+        }
+        DataType::Int(_) | DataType::UnsignedInt(_) | DataType::BigInt(_) => {
+            (ColumnType::MYSQL_TYPE_LONGLONG, ColumnFlags::empty())
+        }
+        DataType::Real(..) => (ColumnType::MYSQL_TYPE_DOUBLE, ColumnFlags::empty()),
+        DataType::Timestamp(_) => (ColumnType::MYSQL_TYPE_DATETIME, ColumnFlags::empty()),
+        DataType::None | DataType::Text(_) | DataType::TinyText(_) => {
+            (ColumnType::MYSQL_TYPE_VAR_STRING, ColumnFlags::empty())
+        }
+    }
+}
+
+/// Convert the value a client sent for a parameter to the type of the column it stands in for.
+///
+/// Clients are free to send parameters of any type, and many send everything as strings. Values
+/// that can't be converted are an error, and parameters without a known type are left alone.
+fn coerce(v: DataType, ty: Option<&SqlType>) -> Result<DataType, Error> {
+    let ty = match ty {
+        Some(ty) if !v.is_none() => ty,
+        _ => return Ok(v),
+    };
+    let invalid = |v: &DataType| {
+        (
+            ErrorKind::ER_WRONG_ARGUMENTS,
+            format!("{} is not a valid {}", v, ty),
+        )
+    };
+
+    match *ty {
+        SqlType::Bool
+        | SqlType::Int(_)
+        | SqlType::UnsignedInt(_)
+        | SqlType::Bigint(_)
+        | SqlType::UnsignedBigint(_)
+        | SqlType::Tinyint(_)
+        | SqlType::UnsignedTinyint(_) => {
+            if v.is_string() {
+                <&str>::from(&v)
+                    .trim()
+                    .parse::<i64>()
+                    .map(DataType::from)
+                    .map_err(|_| invalid(&v))
+            } else if v.is_integer() {
+                Ok(v)
+            } else {
+                Err(invalid(&v))
+            }
+        }
+        SqlType::Double | SqlType::Float | SqlType::Real | SqlType::Decimal(..) => {
+            if v.is_string() {
+                <&str>::from(&v)
+                    .trim()
+                    .parse::<f64>()
+                    .map(DataType::from)
+                    .map_err(|_| invalid(&v))
+            } else if v.is_integer() {
+                Ok(DataType::from(i128::from(&v) as f64))
+            } else {
+                Ok(v)
+            }
+        }
+        SqlType::Char(_)
+        | SqlType::Varchar(_)
+        | SqlType::Tinytext
+        | SqlType::Mediumtext
+        | SqlType::Longtext
+        | SqlType::Text => {
+            if v.is_integer() {
+                Ok(i128::from(&v).to_string().into())
+            } else if v.is_real() {
+                Ok(f64::from(&v).to_string().into())
+            } else {
+                Ok(v)
+            }
+        }
+        _ => Ok(v),
+    }
+}
+
+fn write_value<W: io::Write>(rw: &mut RowWriter<W>, v: &DataType) -> io::Result<()> {
+    match *v {
+        DataType::None => rw.write_col(None::<i64>),
+        DataType::Int(_) | DataType::UnsignedInt(_) | DataType::BigInt(_) => {
+            rw.write_col(i64::from(v))
+        }
+        DataType::UnsignedBigInt(n) => rw.write_col(n),
+        DataType::Real(..) => rw.write_col(f64::from(v)),
+        DataType::Text(_) | DataType::TinyText(_) => rw.write_col(<&str>::from(v)),
+        DataType::Timestamp(ts) => rw.write_col(ts),
+    }
+}
+
+fn reply<W: io::Write>(
+    r: Result<Reply, Error>,
+    results: QueryResultWriter<'_, W>,
+) -> io::Result<()> {
+    match r {
+        Ok(Reply::Rows(columns, rows)) => {
+            let mut rw = results.start(&columns)?;
+            for row in &rows {
+                for v in row {
+                    write_value(&mut rw, v)?;
+                }
+                rw.end_row()?;
+            }
+            rw.finish()
+        }
+        Ok(Reply::Done(n)) => results.completed(n, 0),
+        Err((kind, msg)) => results.error(kind, msg.as_bytes()),
+    }
+}
+
+/// Answer queries for system variables, which many clients issue when they connect.
+fn system_variables<W: io::Write>(
+    query: &str,
+    results: QueryResultWriter<'_, W>,
+) -> io::Result<()> {
+    let vars: Vec<_> = query["select ".len()..]
+        .split(',')
+        .map(|v| v.trim().trim_start_matches("@@").to_string())
+        .collect();
+    let columns: Vec<_> = vars
+        .iter()
+        .map(|v| Column {
+            table: String::new(),
+            column: format!("@@{}", v),
+            coltype: ColumnType::MYSQL_TYPE_LONGLONG,
+            colflags: ColumnFlags::empty(),
+        })
+        .collect();
+    let mut rw = results.start(&columns)?;
+    for v in &vars {
+        match &*v.to_lowercase() {
+            "max_allowed_packet" => rw.write_col(16 * 1024 * 1024i64)?,
+            "wait_timeout" => rw.write_col(28800i64)?,
+            _ => rw.write_col(None::<i64>)?,
+        }
+    }
+    rw.end_row()?;
+    rw.finish()
+}
+
+impl<A: Authority + 'static, W: io::Write> MysqlShim<W> for Backend<A> {
+    type Error = io::Error;
+
+    fn on_prepare(&mut self, query: &str, info: StatementMetaWriter<'_, W>) -> io::Result<()> {
+        match self.prepare(query) {
+            Ok((stmt, columns)) => {
+                let types = match self.param_types(&stmt) {
+                    Ok(types) => types,
+                    Err((kind, msg)) => return info.error(kind, msg.as_bytes()),
+                };
+                let params: Vec<_> = types
+                    .iter()
+                    .map(|ty| {
+                        let (coltype, colflags) = match *ty {
+                            Some(ref ty) => sql_type(ty),
+                            None => (ColumnType::MYSQL_TYPE_VAR_STRING, ColumnFlags::empty()),
+                        };
+                        Column {
+                            table: String::new(),
+                            column: "?".to_string(),
+                            coltype,
+                            colflags,
+                        }
+                    })
+                    .collect();
+                let id = self.prepared.insert((stmt, types));
+                info.reply(id as u32, &params, &columns)
+            }
+            Err((kind, msg)) => info.error(kind, msg.as_bytes()),
+        }
+    }
+
+    fn on_execute(
+        &mut self,
+        id: u32,
+        params: ParamParser<'_>,
+        results: QueryResultWriter<'_, W>,
+    ) -> io::Result<()> {
+        let (stmt, types) = match self.prepared.get(id as usize) {
+            Some(prepared) => prepared.clone(),
+            None => {
+                return results.error(
+                    ErrorKind::ER_UNKNOWN_STMT_HANDLER,
+                    &b"unknown prepared statement"[..],
+                )
+            }
+        };
+
+        let params = params
+            .into_iter()
+            .map(|p| match p.value.into_inner() {
+                ValueInner::NULL => Ok(DataType::None),
+                ValueInner::Bytes(b) => DataType::try_from(b).map_err(other),
+                ValueInner::Int(n) => Ok(n.into()),
+                ValueInner::UInt(n) => Ok(n.into()),
+                ValueInner::Double(f) => Ok(f.into()),
+                _ => Err((
+                    ErrorKind::ER_NOT_SUPPORTED_YET,
+                    "unsupported parameter type".to_string(),
+                )),
+            })
+            .enumerate()
+            .map(|(i, p)| p.and_then(|p| coerce(p, types.get(i).and_then(Option::as_ref))))
+            .collect::<Result<Vec<_>, _>>();
+        let r = params.and_then(|params| self.execute(&stmt, params));
+        reply(r, results)
+    }
+
+    fn on_close(&mut self, id: u32) {
+        if self.prepared.contains(id as usize) {
+            self.prepared.remove(id as usize);
+        }
+    }
+
+    fn on_query(&mut self, query: &str, results: QueryResultWriter<'_, W>) -> io::Result<()> {
+        let trimmed = query.trim();
+        let prefix = "select @@";
+        if trimmed.len() > prefix.len()
+            && trimmed
+                .get(..prefix.len())
+                .map_or(false, |p| p.eq_ignore_ascii_case(prefix))
+        {
+            return system_variables(trimmed, results);
+        }
+
+        let r = match nom_sql::parse_query(query) {
+            Ok(SqlQuery::Set(_)) => Ok(Reply::Done(0)),
+            Ok(q) => Statement::new(q).and_then(|stmt| self.execute(&stmt, Vec::new())),
+            Err(e) => Err((ErrorKind::ER_PARSE_ERROR, e.to_string())),
+        };
+        reply(r, results)
+    }
+}
@@ -0,0 +1,91 @@
+//! The on-disk format of deployment snapshots.
+//!
+//! A snapshot is a directory holding a manifest, which lists the recipes and security
+//! configuration of the deployment along with its base tables, and the rows of each base table.
+//! A table's rows are split across one file per shard of the table at the time of the snapshot,
+//! but can be restored into a deployment with any sharding. Each file is a sequence of chunks of
+//! rows, so that neither writing nor reading it needs to hold all of its rows at once.
+//!
+//! The manifest is written last, so a directory without one holds an incomplete snapshot.
+
+use noria::DataType;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "manifest.json";
+
+/// The number of rows read from a base table shard, and written to its file, at a time.
+pub(crate) const CHUNK_ROWS: usize = 10_000;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// The recipes installed in the deployment, starting with the last one installed, followed
+    /// by the extensions made to it since.
+    pub(crate) recipes: Vec<String>,
+    pub(crate) security_config: Option<String>,
+    /// Each base table along with the number of files its rows were written to.
+    pub(crate) tables: Vec<(String, usize)>,
+}
+
+fn rows_file(dir: &Path, table: &str, part: usize) -> PathBuf {
+    dir.join(format!("{}.{}.rows", table, part))
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Writes the rows of one part of a table, a chunk at a time.
+pub(crate) struct RowsWriter(BufWriter<File>);
+
+impl RowsWriter {
+    pub(crate) fn create(dir: &Path, table: &str, part: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(RowsWriter(BufWriter::new(File::create(rows_file(
+            dir, table, part,
+        ))?)))
+    }
+
+    pub(crate) fn write(&mut self, rows: &[Vec<DataType>]) -> io::Result<()> {
+        bincode::serialize_into(&mut self.0, rows).map_err(invalid)
+    }
+
+    /// Make sure all the rows written so far are on disk.
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.0.get_ref().sync_all()
+    }
+}
+
+/// Reads back the chunks of rows of one part of a table.
+pub(crate) struct RowsReader(BufReader<File>);
+
+impl Iterator for RowsReader {
+    type Item = io::Result<Vec<Vec<DataType>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.0.fill_buf() {
+            Ok(buf) if buf.is_empty() => None,
+            Ok(_) => Some(bincode::deserialize_from(&mut self.0).map_err(invalid)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+pub(crate) fn read_rows(dir: &Path, table: &str, part: usize) -> io::Result<RowsReader> {
+    Ok(RowsReader(BufReader::new(File::open(rows_file(
+        dir, table, part,
+    ))?)))
+}
+
+pub(crate) fn write_manifest(dir: &Path, manifest: &Manifest) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let f = BufWriter::new(File::create(dir.join(MANIFEST))?);
+    serde_json::to_writer_pretty(f, manifest).map_err(invalid)
+}
+
+pub(crate) fn read_manifest(dir: &Path) -> io::Result<Manifest> {
+    let f = BufReader::new(File::open(dir.join(MANIFEST))?);
+    serde_json::from_reader(f).map_err(invalid)
+}
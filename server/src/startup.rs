@@ -1,6 +1,7 @@
 use crate::controller::ControllerState;
 use crate::coordination::{CoordinationMessage, CoordinationPayload};
 use async_bincode::AsyncBincodeReader;
+use dataflow::EvictionKind;
 use futures_util::{
     future::FutureExt,
     future::TryFutureExt,
@@ -40,6 +41,10 @@ pub(crate) enum Event {
     CampaignError(failure::Error),
     #[cfg(test)]
     IsReady(tokio::sync::oneshot::Sender<bool>),
+    #[cfg(test)]
+    FailSendsAfter(usize),
+    #[cfg(test)]
+    AbandonNextTransaction,
     ManualMigration {
         f: Box<dyn FnOnce(&mut crate::controller::migrate::Migration) + Send + 'static>,
         done: tokio::sync::oneshot::Sender<()>,
@@ -57,6 +62,10 @@ impl fmt::Debug for Event {
             Event::CampaignError(ref e) => write!(f, "CampaignError({:?})", e),
             #[cfg(test)]
             Event::IsReady(..) => write!(f, "IsReady"),
+            #[cfg(test)]
+            Event::FailSendsAfter(n) => write!(f, "FailSendsAfter({})", n),
+            #[cfg(test)]
+            Event::AbandonNextTransaction => write!(f, "AbandonNextTransaction"),
             Event::ManualMigration { .. } => write!(f, "ManualMigration{{..}}"),
         }
     }
@@ -70,6 +79,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     config: Config,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    eviction_policy: EvictionKind,
     log: slog::Logger,
 ) -> Result<(Handle<A>, impl Future<Output = ()> + Unpin + Send), failure::Error> {
     let (trigger, valve) = Valve::new();
@@ -131,7 +141,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
             let snd = match e {
                 Event::InternalMessage(ref msg) => match msg.payload {
                     CoordinationPayload::Deregister => ctx.send(e),
-                    CoordinationPayload::RemoveDomain => wtx.send(e),
+                    CoordinationPayload::RemoveDomain { .. } => wtx.send(e),
                     CoordinationPayload::AssignDomain(..) => wtx.send(e),
                     CoordinationPayload::DomainBooted(..) => wtx.send(e),
                     CoordinationPayload::Register { .. } => ctx.send(e),
@@ -145,6 +155,10 @@ pub(super) async fn start_instance<A: Authority + 'static>(
                 Event::CampaignError(..) => ctx.send(e),
                 #[cfg(test)]
                 Event::IsReady(..) => ctx.send(e),
+                #[cfg(test)]
+                Event::FailSendsAfter(..) => ctx.send(e),
+                #[cfg(test)]
+                Event::AbandonNextTransaction => ctx.send(e),
             };
             // needed for https://gist.github.com/nikomatsakis/fee0e47e14c09c4202316d8ea51e50a0
             snd.unwrap();
@@ -175,6 +189,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         waddr,
         memory_limit,
         memory_check_frequency,
+        eviction_policy,
         log.clone(),
     ));
 
@@ -2,7 +2,7 @@ use crate::controller::ControllerState;
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
 use crate::startup::Event;
 use async_bincode::AsyncBincodeWriter;
-use dataflow::{DomainBuilder, Packet};
+use dataflow::{DomainBuilder, EvictionKind, EvictionPolicy, Packet};
 use futures_util::{future::FutureExt, future::TryFutureExt, sink::SinkExt, stream::StreamExt};
 use noria::channel;
 use noria::consensus::Epoch;
@@ -27,6 +27,7 @@ mod readers;
 mod replica;
 
 type ChannelCoordinator = channel::ChannelCoordinator<ReplicaAddr, Box<Packet>>;
+type StateSizes = Arc<Mutex<HashMap<(DomainIndex, usize), Arc<AtomicUsize>>>>;
 
 enum InstanceState {
     Pining,
@@ -34,6 +35,7 @@ enum InstanceState {
         epoch: Epoch,
         trigger: Trigger,
         add_domain: UnboundedSender<DomainBuilder>,
+        state_sizes: StateSizes,
     },
 }
 
@@ -49,6 +51,7 @@ pub(super) async fn main(
     waddr: SocketAddr,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    eviction_policy: EvictionKind,
     log: slog::Logger,
 ) {
     // shared df state
@@ -59,8 +62,28 @@ pub(super) async fn main(
     while let Some(e) = worker_rx.next().await {
         match e {
             Event::InternalMessage(msg) => match msg.payload {
-                CoordinationPayload::RemoveDomain => {
-                    unimplemented!();
+                CoordinationPayload::RemoveDomain { domain, shard } => {
+                    if let InstanceState::Active {
+                        epoch,
+                        ref state_sizes,
+                        ..
+                    } = worker_state
+                    {
+                        if epoch == msg.epoch {
+                            // the controller has already told the domain to quit, so all we need
+                            // to do is make sure nothing tries to reach it anymore.
+                            trace!(
+                                log,
+                                "forgetting about removed domain {}.{}",
+                                domain.index(),
+                                shard
+                            );
+                            tokio::task::block_in_place(|| {
+                                state_sizes.lock().unwrap().remove(&(domain, shard))
+                            });
+                            coord.remove(&(domain, shard));
+                        }
+                    }
                 }
                 CoordinationPayload::AssignDomain(d) => {
                     if let InstanceState::Active {
@@ -132,17 +155,19 @@ pub(super) async fn main(
 
                 // TODO: memory stuff should probably also be in config?
                 let (rep_tx, rep_rx) = tokio::sync::mpsc::unbounded_channel();
+                let state_sizes = Arc::new(Mutex::new(HashMap::new()));
                 let ctrl = listen_df(
                     alive.clone(),
                     valve,
                     log.clone(),
-                    (memory_limit, memory_check_frequency),
+                    (memory_limit, memory_check_frequency, eviction_policy),
                     &state,
                     &descriptor,
                     waddr,
                     coord.clone(),
                     listen_addr,
                     rep_rx,
+                    state_sizes.clone(),
                 )
                 .await;
 
@@ -155,6 +180,7 @@ pub(super) async fn main(
                         epoch: state.epoch,
                         add_domain: rep_tx,
                         trigger,
+                        state_sizes,
                     };
                     warn!(log, "Connected to new leader");
                 }
@@ -175,13 +201,14 @@ async fn listen_df<'a>(
     alive: tokio::sync::mpsc::Sender<()>,
     valve: Valve,
     log: slog::Logger,
-    (memory_limit, evict_every): (Option<usize>, Option<Duration>),
+    (memory_limit, evict_every, eviction_policy): (Option<usize>, Option<Duration>, EvictionKind),
     state: &'a ControllerState,
     desc: &'a ControllerDescriptor,
     waddr: SocketAddr,
     coord: Arc<ChannelCoordinator>,
     on: IpAddr,
     mut replicas: tokio::sync::mpsc::UnboundedReceiver<DomainBuilder>,
+    state_sizes: StateSizes,
 ) -> Result<(), failure::Error> {
     // first, try to connect to controller
     let ctrl = tokio::net::TcpStream::connect(&desc.worker_addr).await?;
@@ -263,7 +290,6 @@ async fn listen_df<'a>(
         }
     });
 
-    let state_sizes = Arc::new(Mutex::new(HashMap::new()));
     if let Some(evict_every) = evict_every {
         let log = log.clone();
         let coord = coord.clone();
@@ -280,6 +306,7 @@ async fn listen_df<'a>(
                 do_eviction(
                     &log,
                     memory_limit,
+                    eviction_policy.policy(),
                     &mut domain_senders,
                     &coord,
                     &state_sizes,
@@ -374,18 +401,17 @@ async fn listen_df<'a>(
 async fn do_eviction(
     log: &slog::Logger,
     memory_limit: Option<usize>,
+    policy: &dyn EvictionPolicy,
     domain_senders: &mut HashMap<
         (DomainIndex, usize),
         Box<dyn futures_sink::Sink<Box<Packet>, Error = Box<bincode::ErrorKind>> + Send + Unpin>,
     >,
     coord: &ChannelCoordinator,
-    state_sizes: &Arc<Mutex<HashMap<(DomainIndex, usize), Arc<AtomicUsize>>>>,
+    state_sizes: &StateSizes,
 ) {
-    use std::cmp;
-
     // 2. add current state sizes (could be out of date, as packet sent below is not
     //    necessarily received immediately)
-    let mut sizes: Vec<((DomainIndex, usize), usize)> = tokio::task::block_in_place(|| {
+    let sizes: Vec<((DomainIndex, usize), usize)> = tokio::task::block_in_place(|| {
         let state_sizes = state_sizes.lock().unwrap();
         state_sizes
             .iter()
@@ -409,7 +435,7 @@ async fn do_eviction(
         None => (),
         Some(limit) => {
             if total >= limit {
-                let mut over = total - limit;
+                let over = total - limit;
 
                 // we are! time to evict.
                 // here's how we're going to proceed.
@@ -417,40 +443,17 @@ async fn do_eviction(
                 // and we also need to be aware that evicting something from one place may cause a
                 // number of downstream evictions.
 
-                // we want to spread the eviction impact across multiple nodes where possible,
-                // so we distribute how much we're over the limit across the 3 largest nodes.
-                // -1* so we sort in descending order
-                // TODO: be smarter than 3 here
-                sizes.sort_unstable_by_key(|&(_, s)| -1 * (s as i64));
-                sizes.truncate(3);
-
-                // don't evict from tiny things (< 10% of max)
-                if let Some(too_small_i) = sizes.iter().position(|&(_, s)| s < sizes[0].1 / 10) {
-                    // everything beyond this is smaller, so also too small
-                    sizes.truncate(too_small_i);
-                }
-
-                // starting with the smallest of the n domains
-                let mut n = sizes.len();
-                for &(target, size) in sizes.iter().rev() {
-                    // TODO: should this be evenly divided, or weighted by the size of the domains?
-                    let share = (over + n - 1) / n;
-                    // we're only willing to evict at most half the state in each domain
-                    // unless this is the only domain left to evict from
-                    let evict = if n > 1 {
-                        cmp::min(size / 2, share)
-                    } else {
-                        assert_eq!(share, over);
-                        share
-                    };
-                    over -= evict;
-                    n -= 1;
-
+                // we want to spread the eviction impact across multiple domains where possible,
+                // so we let the eviction policy distribute how much we're over the limit.
+                let domain_sizes: Vec<_> = sizes.iter().map(|&(_, s)| s).collect();
+                for (i, evict) in policy.distribute(&domain_sizes, over) {
+                    let target = sizes[i].0;
                     debug!(
                             log,
-                            "memory footprint ({} bytes) exceeds limit ({} bytes); evicting from largest domain {}",
+                            "memory footprint ({} bytes) exceeds limit ({} bytes); evicting {} bytes from domain {}",
                             total,
                             limit,
+                            evict,
                             target.0.index(),
                         );
 
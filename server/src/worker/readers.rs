@@ -2,14 +2,14 @@ use async_bincode::AsyncBincodeStream;
 use dataflow::prelude::DataType;
 use dataflow::prelude::*;
 use dataflow::Readers;
-use dataflow::SingleReadHandle;
+use dataflow::{SingleReadHandle, SubscriptionPoll};
 use futures_util::{
     future,
     future::Either,
     future::{FutureExt, TryFutureExt},
     stream::{StreamExt, TryStreamExt},
 };
-use noria::{ReadQuery, ReadReply, Tagged};
+use noria::{KeyRange, PageRequest, ReadQuery, ReadReply, Tagged};
 use pin_project::pin_project;
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -176,13 +176,135 @@ where
     SerializedReadReplyBatch(v)
 }
 
+/// Read all rows for the keys in `range`, in key order.
+///
+/// Returns `Ok(None)` if the range is not (entirely) present in a partial reader.
+fn read_range(
+    reader: &SingleReadHandle,
+    range: &KeyRange,
+) -> Result<Option<SerializedReadReplyBatch>, ()> {
+    reader
+        .try_find_range_and(range, |rs| rs.iter().cloned().collect::<Vec<_>>())
+        .map(|found| found.map(|rows| serialize(&rows.concat())))
+}
+
+/// Read the rows for `key`, or only the rows in `page` if one is given.
+///
+/// Returns `Ok(None)` if the key is not present in a partial reader.
+fn read_key(
+    reader: &SingleReadHandle,
+    key: &[DataType],
+    page: Option<&PageRequest>,
+) -> Result<Option<SerializedReadReplyBatch>, ()> {
+    match page {
+        None => reader.try_find_and(key, |rs| serialize(rs)).map(|r| r.0),
+        Some(page) => reader
+            .try_find_and(key, |rs| serialize(page.select(rs.iter())))
+            .map(|r| r.0),
+    }
+}
+
 fn handle_message(
     m: Tagged<ReadQuery>,
     s: &Readers,
     wait: &mut tokio::sync::mpsc::UnboundedSender<(BlockingRead, Ack)>,
 ) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
-    let tag = m.tag;
     match m.v {
+        ReadQuery::Subscribe { .. } | ReadQuery::Poll { .. } | ReadQuery::Unsubscribe { .. } => {
+            Either::Right(handle_subscription(m, s))
+        }
+        _ => Either::Left(handle_read(m, s, wait)),
+    }
+}
+
+fn handle_subscription(
+    m: Tagged<ReadQuery>,
+    s: &Readers,
+) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
+    let tag = m.tag;
+    let target = match m.v {
+        ReadQuery::Subscribe { target, .. }
+        | ReadQuery::Poll { target, .. }
+        | ReadQuery::Unsubscribe { target, .. } => target,
+        _ => unreachable!("not a subscription request"),
+    };
+
+    let reply = READERS.with(|readers_cache| {
+        let mut readers_cache = readers_cache.borrow_mut();
+        let reader = readers_cache.entry(target).or_insert_with(|| {
+            let readers = s.lock().unwrap();
+            readers.get(&target).unwrap().clone()
+        });
+
+        match m.v {
+            ReadQuery::Subscribe { keys, .. } => {
+                let subscribed = reader.subscribe(keys).map(|(id, misses)| {
+                    if !misses.is_empty() {
+                        // the rows for missing keys will reach the subscriber through the replay
+                        reader.trigger(misses.iter().map(Vec::as_slice));
+                    }
+                    id
+                });
+                Ok(ReadReply::Subscribed(subscribed))
+            }
+            ReadQuery::Poll { subscription, .. } => match reader.poll_subscription(subscription) {
+                SubscriptionPoll::Ready(changes) => Ok(ReadReply::Deltas(Ok(changes
+                    .into_iter()
+                    .map(Into::into)
+                    .collect()))),
+                SubscriptionPoll::Pending(rx) => Err(rx),
+                SubscriptionPoll::Lagged => Ok(ReadReply::Lagged),
+                SubscriptionPoll::Closed => Ok(ReadReply::Deltas(Err(()))),
+            },
+            ReadQuery::Unsubscribe { subscription, .. } => {
+                reader.unsubscribe(subscription);
+                Ok(ReadReply::Deltas(Ok(Vec::new())))
+            }
+            _ => unreachable!(),
+        }
+    });
+
+    match reply {
+        Ok(v) => Either::Left(future::ready(Ok(Tagged { tag, v }))),
+        Err(rx) => Either::Right(rx.map(move |changes| {
+            // the receiver is only dropped if the subscription goes away
+            let changes = changes
+                .map(|changes| changes.into_iter().map(Into::into).collect())
+                .map_err(|_| ());
+            Ok(Tagged {
+                tag,
+                v: ReadReply::Deltas(changes),
+            })
+        })),
+    }
+}
+
+fn handle_read(
+    m: Tagged<ReadQuery>,
+    s: &Readers,
+    wait: &mut tokio::sync::mpsc::UnboundedSender<(BlockingRead, Ack)>,
+) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
+    let tag = m.tag;
+
+    // a page is just a read of a single key that only returns some of the key's rows
+    let (query, page) = match m.v {
+        ReadQuery::Page {
+            target,
+            key,
+            page,
+            block,
+        } => (
+            ReadQuery::Normal {
+                target,
+                keys: vec![key],
+                block,
+            },
+            Some(page),
+        ),
+        query => (query, None),
+    };
+
+    match query {
         ReadQuery::Normal {
             target,
             mut keys,
@@ -207,8 +329,7 @@ fn handle_message(
                         ret.push(SerializedReadReplyBatch::empty());
                         return false;
                     }
-                    let rs = reader.try_find_and(key, |rs| serialize(rs)).map(|r| r.0);
-                    match rs {
+                    match read_key(reader, key, page.as_ref()) {
                         Ok(Some(rs)) => {
                             // immediate hit!
                             ret.push(rs);
@@ -269,6 +390,9 @@ fn handle_message(
                                 target,
                                 keys,
                                 pending,
+                                page,
+                                ranges: Vec::new(),
+                                pending_ranges: Vec::new(),
                                 read: ret,
                                 truth: s.clone(),
                                 trigger_timeout: trigger,
@@ -289,6 +413,123 @@ fn handle_message(
                 }
             }
         }
+        ReadQuery::Range {
+            target,
+            mut ranges,
+            block,
+        } => {
+            let immediate = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                let mut ret = Vec::with_capacity(ranges.len());
+                let mut pending = Vec::new();
+                let mut ready = true;
+                let mut i = -1;
+                ranges.retain(|range| {
+                    i += 1;
+                    if !ready {
+                        ret.push(SerializedReadReplyBatch::empty());
+                        return false;
+                    }
+                    match read_range(reader, range) {
+                        Ok(Some(rs)) => {
+                            ret.push(rs);
+                            false
+                        }
+                        Err(()) => {
+                            // map not yet ready
+                            ready = false;
+                            ret.push(SerializedReadReplyBatch::empty());
+                            false
+                        }
+                        Ok(None) => {
+                            // need to trigger partial replay for this range
+                            pending.push(i as usize);
+                            ret.push(SerializedReadReplyBatch::empty());
+                            true
+                        }
+                    }
+                });
+
+                if !ready {
+                    return Ok(Tagged {
+                        tag,
+                        v: ReadReply::Normal(Err(())),
+                    });
+                }
+
+                if ranges.is_empty() {
+                    return Ok(Tagged {
+                        tag,
+                        v: ReadReply::Normal(Ok(ret)),
+                    });
+                }
+
+                if reader.ranges_unsupported() {
+                    // the missing ranges would never be filled
+                    return Ok(Tagged {
+                        tag,
+                        v: ReadReply::RangeNotSupported,
+                    });
+                }
+
+                // trigger backfills for all the ranges we missed on
+                for range in &ranges {
+                    reader.trigger_range(range);
+                }
+
+                Err((ranges, ret, pending))
+            });
+
+            match immediate {
+                Ok(reply) => Either::Right(Either::Left(future::ready(Ok(reply)))),
+                Err((ranges, ret, pending)) => {
+                    if !block {
+                        Either::Right(Either::Left(future::ready(Ok(Tagged {
+                            tag,
+                            v: ReadReply::Normal(Ok(ret)),
+                        }))))
+                    } else {
+                        let (tx, rx) = tokio::sync::oneshot::channel();
+                        let trigger = time::Duration::from_millis(TRIGGER_TIMEOUT_MS);
+                        let now = time::Instant::now();
+                        let r = wait.send((
+                            BlockingRead {
+                                tag,
+                                target,
+                                keys: Vec::new(),
+                                pending: Vec::new(),
+                                page: None,
+                                ranges,
+                                pending_ranges: pending,
+                                read: ret,
+                                truth: s.clone(),
+                                trigger_timeout: trigger,
+                                next_trigger: now,
+                                first: now,
+                            },
+                            tx,
+                        ));
+                        if r.is_err() {
+                            // we're shutting down
+                            return Either::Right(Either::Left(future::ready(Err(()))));
+                        }
+                        Either::Right(Either::Right(rx.map(|r| match r {
+                            Err(_) => Err(()),
+                            Ok(r) => r,
+                        })))
+                    }
+                }
+            }
+        }
+        ReadQuery::Page { .. } => unreachable!("pages are read as normal reads"),
+        ReadQuery::Subscribe { .. } | ReadQuery::Poll { .. } | ReadQuery::Unsubscribe { .. } => {
+            unreachable!("subscriptions are handled separately")
+        }
         ReadQuery::Size { target } => {
             let size = READERS.with(|readers_cache| {
                 let mut readers_cache = readers_cache.borrow_mut();
@@ -300,10 +541,10 @@ fn handle_message(
                 reader.len()
             });
 
-            Either::Right(future::ready(Ok(Tagged {
+            Either::Right(Either::Left(future::ready(Ok(Tagged {
                 tag,
                 v: ReadReply::Size(size),
-            })))
+            }))))
         }
     }
 }
@@ -318,6 +559,12 @@ struct BlockingRead {
     keys: Vec<Vec<DataType>>,
     // index in self.read that each entyr in keys corresponds to
     pending: Vec<usize>,
+    // the page of rows to read for each key, if we are paginating
+    page: Option<PageRequest>,
+    // ranges we have yet to read
+    ranges: Vec<KeyRange>,
+    // index in self.read that each entry in ranges corresponds to
+    pending_ranges: Vec<usize>,
     truth: Readers,
 
     trigger_timeout: time::Duration,
@@ -333,6 +580,9 @@ impl std::fmt::Debug for BlockingRead {
             .field("read", &self.read)
             .field("keys", &self.keys)
             .field("pending", &self.pending)
+            .field("page", &self.page)
+            .field("ranges", &self.ranges)
+            .field("pending_ranges", &self.pending_ranges)
             .field("trigger_timeout", &self.trigger_timeout)
             .field("next_trigger", &self.next_trigger)
             .field("first", &self.first)
@@ -342,7 +592,7 @@ impl std::fmt::Debug for BlockingRead {
 
 impl BlockingRead {
     fn check(&mut self) -> Poll<Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> {
-        READERS.with(|readers_cache| {
+        let unsupported = READERS.with(|readers_cache| {
             let mut readers_cache = readers_cache.borrow_mut();
             let s = &self.truth;
             let target = &self.target;
@@ -364,7 +614,7 @@ impl BlockingRead {
 
             while let Some(read_i) = self.pending.pop() {
                 let key = self.keys.pop().expect("pending.len() == keys.len()");
-                match reader.try_find_and(&key, |rs| serialize(rs)).map(|r| r.0) {
+                match read_key(reader, &key, self.page.as_ref()) {
                     Ok(Some(rs)) => {
                         read[read_i] = rs;
                     }
@@ -384,32 +634,74 @@ impl BlockingRead {
             }
             debug_assert_eq!(self.pending.len(), self.keys.len());
 
-            if !self.keys.is_empty() && now > next_trigger {
+            // same trick for ranges
+            while let Some(read_i) = self.pending_ranges.pop() {
+                let range = self
+                    .ranges
+                    .pop()
+                    .expect("pending_ranges.len() == ranges.len()");
+                match read_range(reader, &range) {
+                    Ok(Some(rs)) => {
+                        read[read_i] = rs;
+                    }
+                    Err(()) => {
+                        // map has been deleted, so server is shutting down
+                        self.pending_ranges.clear();
+                        self.ranges.clear();
+                        return Err(());
+                    }
+                    Ok(None) => {
+                        // we still missed! restore range + pending
+                        self.pending_ranges.push(read_i);
+                        self.ranges.push(range);
+                        break;
+                    }
+                }
+            }
+            debug_assert_eq!(self.pending_ranges.len(), self.ranges.len());
+
+            if !self.ranges.is_empty() && reader.ranges_unsupported() {
+                // the domain found that it can't replay the ranges we're waiting for
+                return Ok(true);
+            }
+
+            if (!self.keys.is_empty() || !self.ranges.is_empty()) && now > next_trigger {
                 // maybe the key got filled, then evicted, and we missed it?
-                if !reader.trigger(self.keys.iter().map(Vec::as_slice)) {
+                if !self.keys.is_empty() && !reader.trigger(self.keys.iter().map(Vec::as_slice)) {
                     // server is shutting down and won't do the backfill
                     return Err(());
                 }
+                // range replays are not retried by the domain, so we have to re-request them
+                for range in &self.ranges {
+                    if !reader.trigger_range(range) {
+                        return Err(());
+                    }
+                }
 
                 self.trigger_timeout *= 2;
                 self.next_trigger = now + self.trigger_timeout;
             }
 
-            if !self.keys.is_empty() {
+            if !self.keys.is_empty() || !self.ranges.is_empty() {
                 let waited = now - self.first;
                 self.first = now;
                 if waited > time::Duration::from_secs(7) {
                     eprintln!(
-                        "warning: read has been stuck waiting on {:?} for {:?}",
-                        self.keys, waited
+                        "warning: read has been stuck waiting on {:?} {:?} for {:?}",
+                        self.keys, self.ranges, waited
                     );
                 }
             }
 
-            Ok(())
+            Ok(false)
         })?;
 
-        if self.keys.is_empty() {
+        if unsupported {
+            Poll::Ready(Ok(Tagged {
+                tag: self.tag,
+                v: ReadReply::RangeNotSupported,
+            }))
+        } else if self.keys.is_empty() && self.ranges.is_empty() {
             Poll::Ready(Ok(Tagged {
                 tag: self.tag,
                 v: ReadReply::Normal(Ok(mem::take(&mut self.read))),
@@ -553,6 +845,30 @@ mod readreply {
         ));
     }
 
+    #[test]
+    fn rtt_deltas() {
+        use noria::Delta;
+        let deltas = vec![
+            Delta::Positive(vec![DataType::from(1)]),
+            Delta::Negative(vec![DataType::from(2)]),
+        ];
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::Deltas::<SerializedReadReplyBatch>(Ok(deltas.clone())),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        match got {
+            Tagged {
+                v: ReadReply::Deltas(Ok(got)),
+                tag: 32,
+            } => assert_eq!(got, deltas),
+            r => panic!("{:?}", r),
+        }
+    }
+
     async fn async_bincode_rtt_ok(data: Vec<Vec<Vec<DataType>>>) {
         use futures_util::{SinkExt, StreamExt};
 